@@ -0,0 +1,141 @@
+//! Files and URLs attached to tasks, see `todo attach` and `todo open`.
+//!
+//! Attachments are references: a URL, or the absolute path of a file.
+//! Files can also be copied into `attachments/` next to the store, in a
+//! directory of their task, so they stay around if the original moves.
+//! The URLs of a task also include its link and those in its body.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::{Result, TodoError};
+use crate::ics::uid;
+use crate::task::Task;
+
+/// Whether an attachment is a URL rather than a file.
+pub fn is_url(target: &str) -> bool {
+    target.contains("://") || target.starts_with("mailto:")
+}
+
+/// The URLs of a task, in order: its link, the URLs attached and those
+/// written in its body, each once.
+pub fn urls(task: &Task) -> Vec<&str> {
+    let mut urls: Vec<&str> = Vec::new();
+    let attached = task.attachments.iter().map(String::as_str);
+    for url in task.link.as_deref().into_iter().chain(attached) {
+        if is_url(url) && !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    for url in urls_in(&task.body) {
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+/// The `http` and `https` URLs written in `text`, e.g. in
+/// `see <https://example.com>.` or a markdown link.
+fn urls_in(text: &str) -> impl Iterator<Item = &str> {
+    text.split_whitespace().filter_map(|word| {
+        let start = word.find("https://").or_else(|| word.find("http://"))?;
+        // Punctuation closing a sentence, or a bracket or quote around it
+        let url = word[start..]
+            .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '>', ']', '"', '\'']);
+        (!url.ends_with("://")).then_some(url)
+    })
+}
+
+/// The reference to store for `target`: URLs as they are, files as
+/// absolute paths. Files have to exist.
+pub fn reference(target: &str) -> Result<String> {
+    if is_url(target) {
+        return Ok(target.to_string());
+    }
+    let path = fs::canonicalize(target).map_err(|source| TodoError::ReadFile {
+        path: PathBuf::from(target),
+        source,
+    })?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Copies the file at `source` into the attachments of `task`, kept next
+/// to the store at `storage_path`, and returns the path of the copy. An
+/// attachment of the same name gets a number added.
+pub fn copy(source: &str, task: &Task, storage_path: &Path) -> Result<String> {
+    let source = PathBuf::from(source);
+    if !source.is_file() {
+        return Err(TodoError::Usage(format!(
+            "{} is not a file, only files can be copied",
+            source.display()
+        )));
+    }
+    let dir = storage_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("attachments")
+        .join(dir_name(task));
+    fs::create_dir_all(&dir).map_err(|err| TodoError::CreateFile {
+        path: dir.clone(),
+        source: err,
+    })?;
+
+    let name = source
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "attachment".to_string());
+    let mut destination = dir.join(&name);
+    let mut copies = 1;
+    while destination.exists() {
+        copies += 1;
+        destination = dir.join(format!("{copies}-{name}"));
+    }
+    fs::copy(&source, &destination).map_err(|err| TodoError::WriteFile {
+        path: destination.clone(),
+        source: err,
+    })?;
+    log::debug!("copied {} to {}", source.display(), destination.display());
+    reference(&destination.to_string_lossy())
+}
+
+/// Opens an attachment with the program the desktop uses for it.
+pub fn open(target: &str) -> Result<()> {
+    let (program, args): (&str, &[&str]) = if cfg!(target_os = "macos") {
+        ("open", &[])
+    } else if cfg!(windows) {
+        // Not `cmd /C start`, which would take `&`, `|` and `^` in a URL
+        // as its own
+        ("rundll32", &["url.dll,FileProtocolHandler"])
+    } else {
+        ("xdg-open", &[])
+    };
+    log::debug!("{program} {target}");
+    let status = Command::new(program)
+        .args(args)
+        .arg(target)
+        .status()
+        .map_err(|err| TodoError::Open(format!("cannot run {program}: {err}")))?;
+    if !status.success() {
+        return Err(TodoError::Open(format!(
+            "{program} could not open {target}"
+        )));
+    }
+    Ok(())
+}
+
+/// The directory of the copied attachments of `task`, named after its
+/// uid so that it follows the task when ids change.
+fn dir_name(task: &Task) -> String {
+    uid(task)
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,99 @@
+//! Backups and snapshots of the storage file.
+//!
+//! Both live next to the storage file: the backup made when a store
+//! can't be read is `<name>.bak`, snapshots are `<name>.<secs>.bak`.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn get_backup_path(storage_path: &Path) -> PathBuf {
+    let mut name = storage_path
+        .file_name()
+        .map(OsString::from)
+        .unwrap_or_default();
+    name.push(".bak");
+    storage_path.with_file_name(name)
+}
+
+/// Copies the file contents from the original storage path
+/// to a backup location, and returns the path of the backup.
+pub fn backup_data(storage_path: &Path) -> io::Result<PathBuf> {
+    let backup_file = get_backup_path(storage_path);
+    fs::copy(storage_path, &backup_file)?;
+    Ok(backup_file)
+}
+
+/// Copies the storage file to a timestamped snapshot next to it
+/// and returns the path of the snapshot.
+pub fn snapshot_data(storage_path: &Path) -> io::Result<PathBuf> {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let mut name = storage_path
+        .file_name()
+        .map(OsString::from)
+        .unwrap_or_default();
+    name.push(format!(".{secs}.bak"));
+    let snapshot = storage_path.with_file_name(name);
+
+    fs::copy(storage_path, &snapshot)?;
+    Ok(snapshot)
+}
+
+/// Returns the backups and snapshots of the storage file,
+/// newest first.
+pub fn list_backups(storage_path: &Path) -> io::Result<Vec<PathBuf>> {
+    let Some(name) = storage_path.file_name().and_then(|n| n.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let dir = match storage_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut backups = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|f| is_backup_of(name, f))
+        })
+        .filter_map(|entry| {
+            let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+            Some((modified, entry.path()))
+        })
+        .collect::<Vec<_>>();
+    backups.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    Ok(backups.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Whether `file` is the backup, `<name>.bak`, or a snapshot,
+/// `<name>.<secs>.bak`, of the storage file `name`, rather than of
+/// another store whose name starts the same, e.g. `<name>.bin`.
+fn is_backup_of(name: &str, file: &str) -> bool {
+    let Some(stamp) = file
+        .strip_prefix(name)
+        .and_then(|rest| rest.strip_suffix(".bak"))
+    else {
+        return false;
+    };
+    stamp.is_empty()
+        || stamp
+            .strip_prefix('.')
+            .is_some_and(|secs| !secs.is_empty() && secs.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Replaces the storage file with a backup.
+/// The current storage is snapshotted first, the path of that
+/// snapshot is returned. The caller holds the lock on the storage file,
+/// so no other process saves in between.
+pub fn restore(storage_path: &Path, backup: &Path) -> io::Result<PathBuf> {
+    let snapshot = snapshot_data(storage_path)?;
+    fs::copy(backup, storage_path)?;
+    Ok(snapshot)
+}
@@ -0,0 +1,93 @@
+//! Tasks laid out in columns, see `todo board`.
+//!
+//! Each column is a filter, and a task goes in the first column whose
+//! filter it matches, so a column can take what is left by those before
+//! it. Tasks matching no column are left out. Boards by tag are the
+//! exception: a task carrying several tags is in the column of each.
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+
+use crate::config::BoardColumn;
+use crate::error::Result;
+use crate::filter::{Filter, parse_filter};
+use crate::task::Task;
+
+/// A column of the board and the tasks in it.
+#[derive(Debug)]
+pub struct Column<'a> {
+    pub name: String,
+    pub tasks: Vec<&'a Task>,
+}
+
+/// The columns shown when none are set in the config: tasks not started,
+/// those with time tracked on them and those done.
+pub fn default_columns() -> Vec<BoardColumn> {
+    [
+        ("Backlog", "status:todo"),
+        ("In progress", "status:started"),
+        ("Done", "status:done"),
+    ]
+    .into_iter()
+    .map(|(name, filter)| BoardColumn {
+        name: name.to_string(),
+        filter: filter.to_string(),
+    })
+    .collect()
+}
+
+/// Puts each of `tasks` in the first of `definitions` whose filter it
+/// matches. Fails on a filter that can't be read.
+pub fn columns<'a>(
+    definitions: &[BoardColumn],
+    tasks: &[&'a Task],
+    today: NaiveDate,
+) -> Result<Vec<Column<'a>>> {
+    let filters = definitions
+        .iter()
+        .map(|column| parse_filter(&column.filter))
+        .collect::<Result<Vec<Filter>>>()?;
+    let mut columns = definitions
+        .iter()
+        .map(|column| Column {
+            name: column.name.clone(),
+            tasks: Vec::new(),
+        })
+        .collect::<Vec<_>>();
+    for task in tasks {
+        if let Some(i) = filters.iter().position(|f| f.matches(task, today)) {
+            columns[i].tasks.push(task);
+        }
+    }
+    Ok(columns)
+}
+
+/// Puts `tasks` in a column per tag, in the order of the tags' names,
+/// and those without tags in a last column.
+pub fn tag_columns<'a>(tasks: &[&'a Task]) -> Vec<Column<'a>> {
+    let mut by_tag = BTreeMap::<String, Vec<&Task>>::new();
+    let mut untagged = Vec::new();
+    for task in tasks {
+        if task.tags.is_empty() {
+            untagged.push(*task);
+        }
+        for tag in &task.tags {
+            by_tag.entry(tag.to_lowercase()).or_default().push(task);
+        }
+    }
+    let mut columns = by_tag
+        .into_iter()
+        .map(|(tag, tasks)| Column {
+            name: format!("#{tag}"),
+            tasks,
+        })
+        .collect::<Vec<_>>();
+    if !untagged.is_empty() {
+        columns.push(Column {
+            name: "No tag".to_string(),
+            tasks: untagged,
+        });
+    }
+    columns
+}
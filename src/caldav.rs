@@ -0,0 +1,419 @@
+//! Two-way sync of the tasks with a CalDAV task list.
+//!
+//! Tasks are matched with the to-dos on the server by their UID. What
+//! was last synced is kept in the store, see `SyncRecord`, so a task
+//! changed or deleted on one side can be told apart from one that is
+//! new on the other.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::Local;
+use quick_xml::Reader;
+use quick_xml::events::Event;
+
+use crate::config::CaldavConfig;
+use crate::error::{Result, TodoError};
+use crate::ics::{self, Todo};
+use crate::merge::{Conflict, merge};
+use crate::storage::{Storage, SyncRecord};
+use crate::task::{Id, Task};
+
+/// How long to wait for the server.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Asks for every to-do of a calendar with its version.
+const QUERY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+  <d:prop>
+    <d:getetag/>
+    <c:calendar-data/>
+  </d:prop>
+  <c:filter>
+    <c:comp-filter name="VCALENDAR">
+      <c:comp-filter name="VTODO"/>
+    </c:comp-filter>
+  </c:filter>
+</c:calendar-query>
+"#;
+
+/// What a sync changed, also used by the other kinds of sync.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// Tasks sent to the server
+    pub pushed: usize,
+    /// Tasks added or updated from the server
+    pub pulled: usize,
+    /// Tasks deleted on the server
+    pub deleted_remote: usize,
+    /// Tasks deleted here because they were deleted on the server
+    pub deleted_local: usize,
+    /// Tasks whose same fields were changed on both sides
+    pub conflicts: Vec<Conflict>,
+}
+
+/// A to-do as stored on the server.
+struct Remote {
+    href: String,
+    etag: String,
+    todo: Todo,
+}
+
+/// Syncs the tasks with the task list in `config`.
+///
+/// Changes made on one side since the last sync are made on the other.
+/// A task changed on both sides is merged field by field, see `merge`,
+/// against its copy as last synced, and fields changed on both sides
+/// keep the value changed last. A task
+/// deleted on one side is deleted on the other, unless it was changed
+/// there since.
+pub fn sync(data: &mut Storage, config: &CaldavConfig) -> Result<SyncReport> {
+    let client = Client::new(config);
+    let mut remotes: BTreeMap<String, Remote> = client
+        .fetch()?
+        .into_iter()
+        .map(|remote| (remote.todo.uid.clone(), remote))
+        .collect();
+    let records = data.caldav_records().clone();
+    let mut synced = BTreeMap::new();
+    let mut report = SyncReport::default();
+    let mut to_delete: Vec<Id> = Vec::new();
+    let mut to_add: Vec<Remote> = Vec::new();
+
+    let locals: Vec<(Id, String)> = data.list().map(|task| (task.id, ics::uid(task))).collect();
+    for (id, uid) in locals {
+        let Some(task) = data.get(id) else {
+            continue;
+        };
+        let record = records.get(&uid);
+        let changed_here = match record {
+            Some(record) => task.updated_at.is_none_or(|at| at > record.synced_at),
+            None => true,
+        };
+
+        let Some(remote) = remotes.remove(&uid) else {
+            if record.is_some() && !changed_here {
+                log::debug!("{uid} was deleted on the server");
+                to_delete.push(id);
+                report.deleted_local += 1;
+            } else {
+                // New here, or changed since it was deleted on the server
+                let href = client.task_url(&uid);
+                let etag = client.put(&href, &ics::task_calendar(task, Local::now()), None)?;
+                synced.insert(uid.clone(), (href, etag));
+                pin_uid(data, id, &uid);
+                report.pushed += 1;
+            }
+            continue;
+        };
+
+        let changed_there = match record {
+            // An unknown version was pushed last time
+            Some(record) => !record.etag.is_empty() && record.etag != remote.etag,
+            None => true,
+        };
+        let keep_remote = match (changed_here, changed_there) {
+            (false, false) => {
+                synced.insert(uid, (remote.href, remote.etag));
+                continue;
+            }
+            (true, false) => false,
+            (false, true) => true,
+            (true, true) => {
+                // The merged copy is sent back to the server
+                let mut theirs = task.clone();
+                remote.todo.apply_to(&mut theirs);
+                theirs.updated_at = remote.todo.last_modified;
+                let base = record.and_then(|record| record.base.as_ref());
+                let merged = merge(base, task, &theirs);
+                report.conflicts.extend(merged.conflict);
+                if let Some(task) = data.get_mut(id) {
+                    *task = merged.task;
+                }
+                report.pulled += 1;
+                false
+            }
+        };
+
+        if keep_remote {
+            if let Some(task) = data.get_mut(id) {
+                remote.todo.apply_to(task);
+            }
+            synced.insert(uid, (remote.href, remote.etag));
+            report.pulled += 1;
+        } else if let Some(task) = data.get(id) {
+            let calendar = ics::task_calendar(task, Local::now());
+            let etag = client.put(&remote.href, &calendar, Some(&remote.etag))?;
+            synced.insert(uid.clone(), (remote.href, etag));
+            pin_uid(data, id, &uid);
+            report.pushed += 1;
+        }
+    }
+
+    for (uid, remote) in remotes {
+        match records.get(&uid) {
+            Some(record) if record.etag.is_empty() || record.etag == remote.etag => {
+                log::debug!("{uid} was deleted here");
+                client.delete(&remote.href, &remote.etag)?;
+                report.deleted_remote += 1;
+            }
+            // New on the server, or changed since it was deleted here
+            _ => {
+                synced.insert(uid, (remote.href.clone(), remote.etag.clone()));
+                to_add.push(remote);
+            }
+        }
+    }
+
+    // Deleting renumbers the tasks, so it is done once everything else is
+    data.delete(&to_delete);
+    for remote in to_add {
+        if data.add(remote.todo.to_task()).is_some() {
+            report.pulled += 1;
+        }
+    }
+
+    let synced_at = Local::now();
+    let mut bases: BTreeMap<String, Task> = data
+        .list()
+        .map(|task| (ics::uid(task), held_fields(task)))
+        .collect();
+    *data.caldav_records_mut() = synced
+        .into_iter()
+        .map(|(uid, (href, etag))| {
+            let record = SyncRecord {
+                href,
+                etag,
+                synced_at,
+                base: bases.remove(&uid),
+            };
+            (uid, record)
+        })
+        .collect();
+    Ok(report)
+}
+
+/// The fields of `task` a to-do holds, kept to merge against.
+fn held_fields(task: &Task) -> Task {
+    Task {
+        uid: task.uid.clone(),
+        head: task.head.clone(),
+        body: task.body.clone(),
+        due: task.due,
+        tags: task.tags.clone(),
+        priority: task.priority,
+        done: task.done,
+        completed_at: task.completed_at,
+        ..Default::default()
+    }
+}
+
+/// Gives a task the UID it was synced under, so it keeps it if its
+/// creation time is ever lost.
+fn pin_uid(data: &mut Storage, id: Id, uid: &str) {
+    if data.get(id).is_some_and(|task| task.uid.is_none())
+        && let Some(task) = data.get_mut(id)
+    {
+        task.uid = Some(uid.to_string());
+    }
+}
+
+/// Talks to a CalDAV server.
+struct Client {
+    agent: ureq::Agent,
+    /// The URL of the calendar collection, ending with a slash
+    url: String,
+    /// The value of the `Authorization` header
+    auth: String,
+}
+
+impl Client {
+    fn new(config: &CaldavConfig) -> Self {
+        let mut url = config.url.trim().to_string();
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+        let credentials = format!("{}:{}", config.username, config.password);
+        Self {
+            agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build(),
+            url,
+            auth: format!("Basic {}", STANDARD.encode(credentials)),
+        }
+    }
+
+    /// Where a new to-do with `uid` is stored.
+    fn task_url(&self, uid: &str) -> String {
+        let mut url = self.url.clone();
+        for byte in uid.bytes() {
+            if byte.is_ascii_alphanumeric() || b"-._~@".contains(&byte) {
+                url.push(char::from(byte));
+            } else {
+                url.push_str(&format!("%{byte:02X}"));
+            }
+        }
+        url.push_str(".ics");
+        url
+    }
+
+    /// Reads every to-do in the calendar.
+    fn fetch(&self) -> Result<Vec<Remote>> {
+        let response = self
+            .agent
+            .request("REPORT", &self.url)
+            .set("Authorization", &self.auth)
+            .set("Depth", "1")
+            .set("Content-Type", "application/xml; charset=utf-8")
+            .send_string(QUERY)
+            .map_err(|err| request_failed(&self.url, err))?;
+        let body = response
+            .into_string()
+            .map_err(|err| TodoError::Sync(format!("{}: {err}", self.url)))?;
+
+        let mut remotes = Vec::new();
+        for (href, etag, calendar) in parse_multistatus(&body)? {
+            // A calendar object holds one to-do, or more for recurrences
+            if let Some(todo) = ics::parse_todos(&calendar).into_iter().next() {
+                remotes.push(Remote {
+                    href: self.resolve(&href),
+                    etag,
+                    todo,
+                });
+            }
+        }
+        log::debug!("fetched {} to-do(s) from {}", remotes.len(), self.url);
+        Ok(remotes)
+    }
+
+    /// Stores a calendar at `href`, only if it is still at version
+    /// `etag`, or if nothing is there yet without one.
+    /// Returns the new version, empty if the server didn't tell.
+    fn put(&self, href: &str, calendar: &str, etag: Option<&str>) -> Result<String> {
+        let request = self
+            .agent
+            .put(href)
+            .set("Authorization", &self.auth)
+            .set("Content-Type", "text/calendar; charset=utf-8");
+        let request = match etag {
+            Some(etag) if !etag.is_empty() => request.set("If-Match", etag),
+            Some(_) => request,
+            None => request.set("If-None-Match", "*"),
+        };
+        let response = request
+            .send_string(calendar)
+            .map_err(|err| request_failed(href, err))?;
+        log::debug!("pushed {href}");
+        Ok(response.header("ETag").unwrap_or_default().to_string())
+    }
+
+    /// Deletes the calendar at `href`, only if it is still at version
+    /// `etag`.
+    fn delete(&self, href: &str, etag: &str) -> Result<()> {
+        let mut request = self.agent.delete(href).set("Authorization", &self.auth);
+        if !etag.is_empty() {
+            request = request.set("If-Match", etag);
+        }
+        match request.call() {
+            // Already gone
+            Ok(_) | Err(ureq::Error::Status(404, _)) => Ok(()),
+            Err(err) => Err(request_failed(href, err)),
+        }
+    }
+
+    /// Turns an href from the server into a URL.
+    fn resolve(&self, href: &str) -> String {
+        if href.starts_with("http://") || href.starts_with("https://") {
+            return href.to_string();
+        }
+        if !href.starts_with('/') {
+            return format!("{}{href}", self.url);
+        }
+        // Keep the scheme and host of the calendar URL
+        let host_end = self
+            .url
+            .find("://")
+            .and_then(|at| self.url[at + 3..].find('/').map(|end| at + 3 + end))
+            .unwrap_or(self.url.len());
+        format!("{}{href}", &self.url[..host_end])
+    }
+}
+
+/// Describes a failed request.
+fn request_failed(url: &str, err: ureq::Error) -> TodoError {
+    match err {
+        ureq::Error::Status(401 | 403, _) => {
+            TodoError::Sync(format!("{url}: the server refused the credentials"))
+        }
+        ureq::Error::Status(412, _) => TodoError::Sync(format!(
+            "{url}: changed on the server while syncing, sync again"
+        )),
+        ureq::Error::Status(code, response) => {
+            TodoError::Sync(format!("{url}: {code} {}", response.status_text()))
+        }
+        ureq::Error::Transport(transport) => TodoError::Sync(format!("{url}: {transport}")),
+    }
+}
+
+/// Reads the href, ETag and calendar data of every response in a
+/// `multistatus` document.
+fn parse_multistatus(body: &str) -> Result<Vec<(String, String, String)>> {
+    let invalid = |err: &dyn std::fmt::Display| {
+        TodoError::Sync(format!("invalid response from the server: {err}"))
+    };
+    let mut reader = Reader::from_str(body);
+    let mut responses = Vec::new();
+    let (mut href, mut etag, mut calendar) = (String::new(), String::new(), String::new());
+    // The element whose text is being read
+    let mut field: Option<String> = None;
+
+    loop {
+        let text = match reader.read_event().map_err(|err| invalid(&err))? {
+            Event::Start(start) => {
+                let name = start.local_name().as_ref().to_string();
+                if name == "response" {
+                    (href, etag, calendar) = (String::new(), String::new(), String::new());
+                }
+                field = Some(name);
+                continue;
+            }
+            Event::End(end) => {
+                if end.local_name().as_ref() == "response" && !calendar.is_empty() {
+                    responses.push((
+                        std::mem::take(&mut href),
+                        std::mem::take(&mut etag),
+                        std::mem::take(&mut calendar),
+                    ));
+                }
+                field = None;
+                continue;
+            }
+            Event::Text(text) => text.xml10_content().into_owned(),
+            Event::CData(data) => data.into_inner().into_owned(),
+            Event::GeneralRef(reference) => {
+                if let Some(c) = reference.resolve_char_ref().map_err(|err| invalid(&err))? {
+                    c.to_string()
+                } else {
+                    match reference.as_ref() {
+                        "amp" => "&",
+                        "lt" => "<",
+                        "gt" => ">",
+                        "quot" => "\"",
+                        "apos" => "'",
+                        _ => "",
+                    }
+                    .to_string()
+                }
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+        match field.as_deref() {
+            Some("href") => href.push_str(text.trim()),
+            Some("getetag") => etag.push_str(text.trim()),
+            Some("calendar-data") => calendar.push_str(&text),
+            _ => {}
+        }
+    }
+    Ok(responses)
+}
@@ -0,0 +1,58 @@
+//! Reading the clipboard, see `todo new --from-clipboard`.
+//!
+//! The clipboard is read with the program the platform has for it:
+//! `pbpaste` on macOS, PowerShell on Windows, and elsewhere `wl-paste`
+//! under Wayland, or `xclip` or `xsel` under X11, whichever is
+//! installed.
+
+use std::process::Command;
+
+use crate::error::{Result, TodoError};
+
+/// The programs that print the clipboard, with their arguments, in the
+/// order they are tried.
+fn programs() -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        vec![("pbpaste", &[])]
+    } else if cfg!(windows) {
+        vec![(
+            "powershell",
+            &["-NoProfile", "-Command", "Get-Clipboard -Raw"],
+        )]
+    } else {
+        let mut programs: Vec<(&str, &[&str])> = Vec::new();
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            programs.push(("wl-paste", &["--no-newline"]));
+        }
+        programs.push(("xclip", &["-selection", "clipboard", "-out"]));
+        programs.push(("xsel", &["--clipboard", "--output"]));
+        programs
+    }
+}
+
+/// The text on the clipboard.
+pub fn read() -> Result<String> {
+    let mut tried = Vec::new();
+    for (program, args) in programs() {
+        log::debug!("{program} {}", args.join(" "));
+        let output = match Command::new(program).args(args).output() {
+            Ok(output) => output,
+            Err(err) => {
+                log::debug!("cannot run {program}: {err}");
+                tried.push(program);
+                continue;
+            }
+        };
+        if !output.status.success() {
+            return Err(TodoError::Clipboard(format!(
+                "{program} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+    }
+    Err(TodoError::Clipboard(format!(
+        "none of {} could be run, install one of them",
+        tried.join(", ")
+    )))
+}
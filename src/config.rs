@@ -0,0 +1,153 @@
+//! User settings.
+
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::TimeDelta;
+use serde::Deserialize;
+
+use crate::date::{deserialize_duration, deserialize_durations};
+use crate::storage::{Compression, DEFAULT_TRASH_RETENTION, StorageFormat};
+
+/// User settings read from the config file.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Roll tasks due yesterday forward to today when the store is loaded
+    pub rollover: bool,
+    /// The format the store is saved in
+    pub format: StorageFormat,
+    /// How binary stores are compressed, as the store was if unset
+    pub compression: Option<Compression>,
+    /// The level of `compression`, the default of the algorithm if unset
+    pub compression_level: Option<i32>,
+    /// Stamp each field of the tasks as it changes, so that copies synced
+    /// with git or over ssh merge without conflicts, see `crdt`
+    pub crdt: bool,
+    /// How long before a task is due the reminder daemon reminds of it
+    #[serde(deserialize_with = "deserialize_durations")]
+    pub reminder_leads: Vec<TimeDelta>,
+    /// How much estimated work fits in a day, see `todo workload`
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub daily_capacity: TimeDelta,
+    /// How long deleted tasks are kept in the trash before they are
+    /// dropped for good
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub trash_retention: TimeDelta,
+    /// The editor to compose tasks in, e.g. `code --wait`, taking
+    /// precedence over `VISUAL` and `EDITOR`
+    pub editor: Option<String>,
+    /// The columns of `todo board`, Backlog, In progress and Done if
+    /// none are set
+    pub board: Vec<BoardColumn>,
+    /// The CalDAV task list to sync with, see `todo sync caldav`
+    pub caldav: Option<CaldavConfig>,
+    /// The Todoist account to sync with, see `todo sync todoist`
+    pub todoist: Option<TodoistConfig>,
+    /// How to reach GitHub, see `todo import github`
+    pub github: Option<GithubConfig>,
+    /// How `todo serve` lets clients in
+    pub server: Option<ServerConfig>,
+}
+
+/// A column of `todo board`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BoardColumn {
+    /// The heading of the column
+    pub name: String,
+    /// The tasks the column shows, written as for `with`, e.g.
+    /// `tag:doing`. Every task if unset
+    #[serde(default)]
+    pub filter: String,
+}
+
+/// Where and how to reach a CalDAV task list.
+#[derive(Debug, Deserialize)]
+pub struct CaldavConfig {
+    /// The URL of the calendar collection holding the tasks
+    pub url: String,
+    /// The user name to log in with
+    pub username: String,
+    /// The password to log in with
+    pub password: String,
+}
+
+/// How to reach a Todoist account.
+#[derive(Debug, Deserialize)]
+pub struct TodoistConfig {
+    /// The API token, from Settings > Integrations > Developer
+    pub token: String,
+}
+
+/// How to reach GitHub.
+#[derive(Debug, Deserialize)]
+pub struct GithubConfig {
+    /// A personal access token, needed for private repositories and
+    /// `--assignee me`. `GITHUB_TOKEN` is used if unset
+    pub token: String,
+}
+
+/// How `todo serve` lets clients in.
+#[derive(Debug, Deserialize)]
+pub struct ServerConfig {
+    /// The token clients must send as `Authorization: Bearer <token>`,
+    /// made up by `todo serve` when empty
+    pub token: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            rollover: false,
+            format: StorageFormat::default(),
+            compression: None,
+            compression_level: None,
+            crdt: false,
+            // The day before, and on the day
+            reminder_leads: vec![TimeDelta::days(1), TimeDelta::zero()],
+            daily_capacity: TimeDelta::hours(8),
+            trash_retention: DEFAULT_TRASH_RETENTION,
+            editor: None,
+            board: Vec::new(),
+            caldav: None,
+            todoist: None,
+            github: None,
+            server: None,
+        }
+    }
+}
+
+/// Returns the path to the config file, `~/.config/todo/config.toml`.
+/// On Windows it is `%APPDATA%\todo\config.toml`, unless
+/// `~/.config/todo` was set up before.
+pub fn get_config_path() -> PathBuf {
+    let home = home::home_dir().unwrap_or(PathBuf::from("."));
+    let dir = home.join(".config").join("todo");
+    match app_data_dir() {
+        Some(app_dir) if !dir.is_dir() => app_dir.join("config.toml"),
+        _ => dir.join("config.toml"),
+    }
+}
+
+/// Where files are kept on Windows, `%APPDATA%\todo`, rather than in the
+/// home directory. `None` on other systems.
+pub fn app_data_dir() -> Option<PathBuf> {
+    if !cfg!(windows) {
+        return None;
+    }
+    std::env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("todo"))
+}
+
+/// Loads the config file.
+/// A missing config file yields the defaults, an invalid one is
+/// reported and ignored.
+pub fn load_config() -> Config {
+    let config_path = get_config_path();
+    match fs::read_to_string(&config_path) {
+        Ok(content) => toml::from_str(&content).unwrap_or_else(|err| {
+            log::warn!("Invalid config file {}: {err}", config_path.display());
+            Config::default()
+        }),
+        Err(_) => Config::default(),
+    }
+}
@@ -0,0 +1,280 @@
+//! Merging copies of the store edited apart without conflicts, for
+//! stores kept as CRDTs, see `crdt` in the config.
+//!
+//! Each task carries a clock telling when each of its fields last
+//! changed. A field is a last-writer-wins register: merging takes the
+//! value changed last, so every device ends up with the same task
+//! whatever order the copies are merged in. The tags are an add-wins
+//! set: a tag stays unless it was removed after it was last added. Notes
+//! and the history only grow, and the copies' are put together.
+//!
+//! Fields are stamped when the store is saved or merged rather than as
+//! they change, by comparing each with a checksum of its value as last
+//! stamped.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::task::{Id, Task};
+
+/// The fields of a task merged as registers, besides its custom fields.
+const REGISTERS: [&str; 17] = [
+    "head",
+    "body",
+    "due",
+    "contexts",
+    "project",
+    "parent",
+    "priority",
+    "position",
+    "done",
+    "link",
+    "attachments",
+    "blocked_by",
+    "reminders",
+    "snoozed_until",
+    "intervals",
+    "estimate",
+    "rollovers",
+];
+
+/// The prefix of the custom fields in `Clock::fields`.
+const CUSTOM: &str = "field:";
+
+/// When the fields and tags of a task last changed.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct Clock {
+    /// When each field last changed, custom fields as `field:<key>`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, Stamp>,
+    /// When each tag was last added and removed
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, TagStamp>,
+}
+
+impl Clock {
+    /// Whether the task was never stamped.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.tags.is_empty()
+    }
+}
+
+/// When a field last changed, and a checksum of its value then.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Stamp {
+    pub at: DateTime<Local>,
+    pub checksum: u32,
+}
+
+/// When a tag was last added and removed.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct TagStamp {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub added: Option<DateTime<Local>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub removed: Option<DateTime<Local>>,
+}
+
+impl TagStamp {
+    /// Whether the tag is on the task. Adding wins over removing at the
+    /// same moment.
+    fn present(&self) -> bool {
+        match (self.added, self.removed) {
+            (Some(added), Some(removed)) => added >= removed,
+            (added, _) => added.is_some(),
+        }
+    }
+
+    /// The later of the moments of both stamps.
+    fn latest(&self, other: &TagStamp) -> TagStamp {
+        TagStamp {
+            added: self.added.max(other.added),
+            removed: self.removed.max(other.removed),
+        }
+    }
+}
+
+/// Stamps the fields and tags of `task` that changed since they were
+/// last stamped with `now`. A task never stamped is stamped as of its
+/// last change, so that copies stamped on different devices agree.
+/// `uid_of` gives the `ics::uid` of a task by its id, as parents and
+/// blockers are compared by theirs.
+pub fn stamp(task: &mut Task, uid_of: &dyn Fn(Id) -> String, now: DateTime<Local>) {
+    let first = task.clock.is_empty();
+    let new = if first {
+        task.updated_at.or(task.created_at).unwrap_or(now)
+    } else {
+        now
+    };
+
+    for name in field_names(task, task) {
+        let checksum = checksum(&value_of(task, &name, uid_of));
+        match task.clock.fields.get(&name) {
+            Some(stamp) if stamp.checksum == checksum => {}
+            stamped => {
+                let at = if stamped.is_some() { now } else { new };
+                task.clock.fields.insert(name, Stamp { at, checksum });
+            }
+        }
+    }
+
+    for tag in &task.tags {
+        let stamp = task.clock.tags.entry(tag.clone()).or_default();
+        if !stamp.present() {
+            stamp.added = Some(if stamp.removed.is_some() { now } else { new });
+        }
+    }
+    for (tag, stamp) in &mut task.clock.tags {
+        if stamp.present() && !task.tags.contains(tag) {
+            stamp.removed = Some(now);
+        }
+    }
+}
+
+/// Merges `ours` and `theirs`, two stamped copies of a task. The merged
+/// task keeps the id of ours, and the ids `theirs` points at, e.g. its
+/// parent, are taken to be those of the tasks of ours.
+pub fn merge(ours: &Task, theirs: &Task) -> Task {
+    let mut task = ours.clone();
+
+    for name in field_names(ours, theirs) {
+        let stamp = |copy: &Task| {
+            copy.clock
+                .fields
+                .get(&name)
+                .map(|stamp| (stamp.at, stamp.checksum))
+        };
+        // Stamps at the same moment are told apart by their checksums
+        if stamp(theirs) > stamp(ours) {
+            take(&mut task, theirs, &name);
+            if let Some(stamp) = theirs.clock.fields.get(&name) {
+                task.clock.fields.insert(name, *stamp);
+            }
+        }
+    }
+
+    for (tag, stamp) in &theirs.clock.tags {
+        let merged = task.clock.tags.entry(tag.clone()).or_default();
+        *merged = merged.latest(stamp);
+    }
+    task.tags = ours
+        .tags
+        .iter()
+        .chain(&theirs.tags)
+        .filter(|tag| task.clock.tags.get(*tag).is_some_and(TagStamp::present))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut seen = BTreeSet::new();
+    task.tags.retain(|tag| seen.insert(tag.clone()));
+
+    for note in &theirs.notes {
+        if !task.notes.contains(note) {
+            task.notes.push(note.clone());
+        }
+    }
+    task.notes.sort_by_key(|note| note.at);
+    for entry in &theirs.history {
+        if !task.history.contains(entry) {
+            task.history.push(entry.clone());
+        }
+    }
+    task.history.sort_by_key(|entry| entry.at);
+
+    task.created_at = [ours.created_at, theirs.created_at]
+        .into_iter()
+        .flatten()
+        .min();
+    task.updated_at = ours.updated_at.max(theirs.updated_at);
+    task
+}
+
+/// The names of the registers of either copy: the fields of every task
+/// and the custom fields of either.
+fn field_names(ours: &Task, theirs: &Task) -> BTreeSet<String> {
+    let mut names = REGISTERS
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<BTreeSet<_>>();
+    for task in [ours, theirs] {
+        names.extend(task.fields.keys().map(|key| format!("{CUSTOM}{key}")));
+        names.extend(task.clock.fields.keys().cloned());
+    }
+    names
+}
+
+/// The value of the register `name` of `task`, to take its checksum.
+fn value_of(task: &Task, name: &str, uid_of: &dyn Fn(Id) -> String) -> Value {
+    match name {
+        "head" => json!(task.head),
+        "body" => json!(task.body),
+        "due" => json!(task.due),
+        "contexts" => json!(task.contexts),
+        "project" => json!(task.project),
+        "parent" => json!(task.parent.map(uid_of)),
+        "priority" => json!(task.priority),
+        "position" => json!(task.position),
+        "done" => json!((task.done, task.completed_at)),
+        "link" => json!(task.link),
+        "attachments" => json!(task.attachments),
+        "blocked_by" => json!(
+            task.blocked_by
+                .iter()
+                .map(|&id| uid_of(id))
+                .collect::<Vec<_>>()
+        ),
+        "reminders" => json!(task.reminders),
+        "snoozed_until" => json!(task.snoozed_until),
+        "intervals" => json!(task.intervals),
+        "estimate" => json!(task.estimate.map(|estimate| estimate.num_minutes())),
+        "rollovers" => json!(task.rollovers),
+        name => json!(
+            name.strip_prefix(CUSTOM)
+                .and_then(|key| task.fields.get(key))
+        ),
+    }
+}
+
+/// Sets the register `name` of `task` to its value in `from`.
+fn take(task: &mut Task, from: &Task, name: &str) {
+    match name {
+        "head" => task.head = from.head.clone(),
+        "body" => task.body = from.body.clone(),
+        "due" => task.due = from.due,
+        "contexts" => task.contexts = from.contexts.clone(),
+        "project" => task.project = from.project.clone(),
+        "parent" => task.parent = from.parent,
+        "priority" => task.priority = from.priority,
+        "position" => task.position = from.position,
+        "done" => {
+            task.done = from.done;
+            task.completed_at = from.completed_at;
+        }
+        "link" => task.link = from.link.clone(),
+        "attachments" => task.attachments = from.attachments.clone(),
+        "blocked_by" => task.blocked_by = from.blocked_by.clone(),
+        "reminders" => task.reminders = from.reminders.clone(),
+        "snoozed_until" => task.snoozed_until = from.snoozed_until,
+        "intervals" => task.intervals = from.intervals.clone(),
+        "estimate" => task.estimate = from.estimate,
+        "rollovers" => task.rollovers = from.rollovers,
+        name => {
+            let Some(key) = name.strip_prefix(CUSTOM) else {
+                return;
+            };
+            match from.fields.get(key) {
+                Some(value) => task.fields.insert(key.to_string(), value.clone()),
+                None => task.fields.remove(key),
+            };
+        }
+    }
+}
+
+/// A checksum of a value, telling whether it changed.
+fn checksum(value: &Value) -> u32 {
+    crc32fast::hash(value.to_string().as_bytes())
+}
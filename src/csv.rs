@@ -0,0 +1,193 @@
+//! Reading tasks from CSV files written by other tools, e.g. Trello,
+//! Asana or a spreadsheet, see `todo import --format csv`.
+//!
+//! The first row names the columns. Which column fills which field of
+//! the tasks is given as `field=Column`, e.g. `due=Deadline`, and fields
+//! not given are read from the column of their own name, if any. Names
+//! other than those of the fields below set fields of the user's own.
+//! Values that can't be read are left out with a warning.
+
+use chrono::NaiveDate;
+
+use crate::date::{parse_date, parse_duration};
+use crate::error::{Result, TodoError};
+use crate::task::{Task, context_name, field, project_name, tag_name};
+use crate::tokens::parse_priority;
+
+/// The fields of a task columns can fill.
+const FIELDS: [&str; 10] = [
+    "head", "body", "due", "tags", "contexts", "project", "priority", "done", "link", "estimate",
+];
+
+/// The values of a `done` column that mean the task is done.
+const DONE: [&str; 8] = [
+    "true",
+    "yes",
+    "y",
+    "1",
+    "x",
+    "done",
+    "complete",
+    "completed",
+];
+
+/// Reads the tasks in CSV `text`, mapping columns to fields as given by
+/// `map`, pairs of a field and the name of a column. Fails if a column
+/// isn't there, or if no column gives the heads.
+pub fn parse_csv(text: &str, map: &[(String, String)]) -> Result<Vec<Task>> {
+    let invalid = |reason: String| TodoError::InvalidImport {
+        format: "CSV".to_string(),
+        reason,
+    };
+    let mut rows = parse_rows(text.trim_start_matches('\u{feff}')).into_iter();
+    let header = rows
+        .next()
+        .ok_or_else(|| invalid("the file is empty".to_string()))?;
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|title| title.trim().eq_ignore_ascii_case(name))
+    };
+
+    let mut columns = Vec::new();
+    for (target, name) in map {
+        let index = column(name).ok_or_else(|| {
+            invalid(format!(
+                "there is no column `{name}`, the columns are {}",
+                header.join(", ")
+            ))
+        })?;
+        if !FIELDS.contains(&target.as_str()) && field(&format!("{target}=")).is_none() {
+            return Err(invalid(format!("`{target}` is not a field")));
+        }
+        columns.push((target.to_lowercase(), index));
+    }
+    for target in FIELDS {
+        if !columns.iter().any(|(mapped, _)| mapped == target)
+            && let Some(index) = column(target)
+        {
+            columns.push((target.to_string(), index));
+        }
+    }
+    if !columns.iter().any(|(target, _)| target == "head") {
+        return Err(invalid(
+            "no column gives the heads of the tasks, map one with `--map head=<column>`"
+                .to_string(),
+        ));
+    }
+
+    let mut tasks = Vec::new();
+    // The header is row 1
+    for (row, values) in (2..).zip(rows) {
+        let mut task = Task::default();
+        for (target, index) in &columns {
+            let value = values.get(*index).map(|value| value.trim());
+            if let Some(value) = value.filter(|value| !value.is_empty()) {
+                set_field(&mut task, target, value, row);
+            }
+        }
+        tasks.push(task);
+    }
+    Ok(tasks)
+}
+
+/// Sets `target` of the task to `value`, read from row `row`.
+fn set_field(task: &mut Task, target: &str, value: &str, row: usize) {
+    match target {
+        "head" => task.head = value.to_string(),
+        "body" => task.body = value.to_string(),
+        "link" => task.link = Some(value.to_string()),
+        "due" => match parse_day(value) {
+            Some(due) => task.due = Some(due),
+            None => log::warn!("row {row}: `{value}` is not a date, left out"),
+        },
+        "tags" => task.tags = names(value, '#', tag_name, row),
+        "contexts" => task.contexts = names(value, '@', context_name, row),
+        "project" => match project_name(&format!("+{}", value.replace(' ', "-"))) {
+            Some(project) => task.project = Some(project.to_string()),
+            None => log::warn!("row {row}: `{value}` is not a project name, left out"),
+        },
+        "priority" => match parse_priority(value) {
+            Ok(priority) => task.priority = Some(priority),
+            Err(err) => log::warn!("row {row}: {err}, left out"),
+        },
+        "done" => task.done = DONE.iter().any(|done| value.eq_ignore_ascii_case(done)),
+        "estimate" => match parse_duration(value) {
+            Ok(estimate) => task.estimate = Some(estimate),
+            Err(err) => log::warn!("row {row}: {err}, left out"),
+        },
+        key => {
+            task.fields.insert(key.to_string(), value.to_string());
+        }
+    }
+}
+
+/// Reads a date, also as the start of a date and time such as
+/// `2025-03-05T12:00:00Z`.
+fn parse_day(value: &str) -> Option<NaiveDate> {
+    parse_date(value).ok().or_else(|| {
+        let day = value.get(..10)?;
+        NaiveDate::parse_from_str(day, "%Y-%m-%d").ok()
+    })
+}
+
+/// Reads names separated by commas or semicolons, e.g. `Bug, Needs review`
+/// as `bug` and `needs-review`. Names that can't be read are left out.
+fn names(value: &str, mark: char, name: fn(&str) -> Option<&str>, row: usize) -> Vec<String> {
+    let mut names = Vec::new();
+    for part in value.split([',', ';']).map(str::trim) {
+        if part.is_empty() {
+            continue;
+        }
+        let word = format!("{mark}{}", part.to_lowercase().replace(' ', "-"));
+        match name(&word) {
+            Some(name) if !names.iter().any(|n: &String| n == name) => names.push(name.to_string()),
+            Some(_) => {}
+            None => log::warn!("row {row}: `{part}` is not a name, left out"),
+        }
+    }
+    names
+}
+
+/// Splits CSV text into rows of values. Values may be quoted, with `""`
+/// for a quote, and then hold separators and line breaks. The separator
+/// is a comma, or a semicolon or tab if the first line has more of them,
+/// as spreadsheets write in some languages.
+fn parse_rows(text: &str) -> Vec<Vec<String>> {
+    let first_line = text.lines().next().unwrap_or_default();
+    let separator = [',', ';', '\t']
+        .into_iter()
+        .max_by_key(|separator| (first_line.matches(*separator).count(), *separator == ','))
+        .unwrap_or(',');
+
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                value.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if value.is_empty() => quoted = true,
+            c if quoted => value.push(c),
+            c if c == separator => row.push(std::mem::take(&mut value)),
+            '\r' => {}
+            '\n' => {
+                row.push(std::mem::take(&mut value));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => value.push(c),
+        }
+    }
+    if !value.is_empty() || !row.is_empty() {
+        row.push(value);
+        rows.push(row);
+    }
+    // Blank lines
+    rows.retain(|row| !(row.len() == 1 && row[0].trim().is_empty()));
+    rows
+}
@@ -0,0 +1,199 @@
+//! Serving the tasks on a Unix socket, for editor plugins, see
+//! `todo daemon`.
+//!
+//! Clients write one JSON request per line and read one JSON response
+//! per line, in order, on a connection kept open as long as they like:
+//!
+//! - `{"op": "list"}` lists the open tasks, `"all": true` the done ones
+//!   too, and `"filter"` selects some, written as for `--where`
+//! - `{"op": "get", "id": 3}` reads a task
+//! - `{"op": "add", "head": "..."}` creates a task, optionally with
+//!   `body`, `due`, `tags` and `priority`
+//! - `{"op": "complete", "id": 3}` marks a task done, `"undo": true`
+//!   not done
+//!
+//! Responses are `{"tasks": [...]}`, `{"task": {...}}` or
+//! `{"error": "..."}`, tasks being written as by `todo show --format
+//! json`.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::{fs, thread};
+
+use chrono::{Local, NaiveDate};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::config::{Config, get_config_path};
+use crate::error::{Result, TodoError};
+use crate::filter::parse_filter;
+use crate::storage::{Storage, update_locked};
+use crate::task::{Id, Priority, Task};
+
+/// A request of a client.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Message {
+    List {
+        #[serde(default)]
+        filter: String,
+        #[serde(default)]
+        all: bool,
+    },
+    Get {
+        id: Id,
+    },
+    Add {
+        head: String,
+        #[serde(default)]
+        body: String,
+        due: Option<NaiveDate>,
+        #[serde(default)]
+        tags: Vec<String>,
+        priority: Option<Priority>,
+    },
+    Complete {
+        id: Id,
+        #[serde(default)]
+        undo: bool,
+    },
+}
+
+/// Where the daemon listens unless told otherwise: `TODO_SOCKET`, else
+/// `todo.sock` in `XDG_RUNTIME_DIR`, else next to the config file.
+pub fn socket_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("TODO_SOCKET") {
+        return PathBuf::from(path);
+    }
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("todo.sock"),
+        None => get_config_path().with_file_name("todo.sock"),
+    }
+}
+
+/// Creates the socket at `socket`, replacing one left behind.
+pub fn listen(socket: &Path) -> Result<UnixListener> {
+    if UnixStream::connect(socket).is_ok() {
+        return Err(TodoError::Usage(format!(
+            "a daemon is already listening on {}",
+            socket.display()
+        )));
+    }
+    // Left behind by a daemon that was stopped; anything else there is
+    // not ours to remove
+    if let Ok(meta) = fs::symlink_metadata(socket) {
+        if !meta.file_type().is_socket() {
+            return Err(TodoError::Usage(format!(
+                "{} is not a socket, refusing to replace it",
+                socket.display()
+            )));
+        }
+        fs::remove_file(socket)?;
+    }
+    if let Some(dir) = socket.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let listener = UnixListener::bind(socket)?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Serves the store at `storage_path` on `listener`, until the process
+/// is stopped.
+pub fn serve(listener: UnixListener, storage_path: &Path, config: &Config) -> Result<()> {
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    log::warn!("cannot accept a connection: {err}");
+                    continue;
+                }
+            };
+            scope.spawn(move || {
+                if let Err(err) = converse(stream, storage_path, config) {
+                    log::warn!("a connection failed: {err}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Answers the requests of a client until it disconnects.
+fn converse(stream: UnixStream, storage_path: &Path, config: &Config) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(message) => answer(message, storage_path, config)
+                .unwrap_or_else(|err| json!({ "error": err.to_string() })),
+            Err(err) => json!({ "error": format!("invalid request: {err}") }),
+        };
+        log::debug!("{line} -> {response}");
+        writeln!(writer, "{response}")?;
+    }
+    Ok(())
+}
+
+/// Answers a request, changing the store as asked.
+fn answer(message: Message, storage_path: &Path, config: &Config) -> Result<Value> {
+    update_locked(storage_path, config, |data| match message {
+        Message::List { filter, all } => {
+            let filter = parse_filter(&filter)?;
+            let today = Local::now().date_naive();
+            let tasks = data
+                .list()
+                .filter(|task| all || !task.done)
+                .filter(|task| filter.matches(task, today))
+                .map(to_json)
+                .collect::<Result<Vec<_>>>()?;
+            Ok(json!({ "tasks": tasks }))
+        }
+        Message::Get { id } => task_json(data, id),
+        Message::Add {
+            head,
+            body,
+            due,
+            tags,
+            priority,
+        } => {
+            let id = data
+                .add(Task {
+                    head,
+                    body,
+                    due,
+                    tags,
+                    priority,
+                    ..Default::default()
+                })
+                .ok_or_else(|| TodoError::Usage("the task is empty".to_string()))?;
+            task_json(data, id)
+        }
+        Message::Complete { id, undo } => {
+            data.get(id).ok_or(TodoError::TaskNotFound(id))?;
+            let blockers = data.open_blockers(id);
+            if !undo && !blockers.is_empty() {
+                return Err(TodoError::Blocked { id, blockers });
+            }
+            data.set_done(&[id], !undo);
+            task_json(data, id)
+        }
+    })
+}
+
+/// The response carrying a task.
+fn task_json(data: &Storage, id: Id) -> Result<Value> {
+    let task = data.get(id).ok_or(TodoError::TaskNotFound(id))?;
+    Ok(json!({ "task": to_json(task)? }))
+}
+
+/// Writes a task as JSON.
+fn to_json(task: &Task) -> Result<Value> {
+    serde_json::to_value(task).map_err(|err| TodoError::Serialize(err.to_string()))
+}
@@ -0,0 +1,490 @@
+//! Parsing of dates, times and durations given on the command line,
+//! and writing dates relative to today.
+
+use chrono::{
+    DateTime, Datelike, Local, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Weekday,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::error::TodoError;
+
+/// Whether dates are written as they are rather than relative to today,
+/// see `set_absolute_dates`.
+static ABSOLUTE: AtomicBool = AtomicBool::new(false);
+
+/// Examples of dates, for error messages.
+const DATE_EXAMPLES: &str =
+    "expected e.g. `2025-03-01`, `tomorrow`, `friday`, `next week`, `in 3 days` or `march 5`";
+
+/// The time of reminders set for a day without a time of day.
+const DEFAULT_TIME: NaiveTime = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
+
+/// The names of the days of the week, which may be shortened to their
+/// first three letters or more.
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("monday", Weekday::Mon),
+    ("tuesday", Weekday::Tue),
+    ("wednesday", Weekday::Wed),
+    ("thursday", Weekday::Thu),
+    ("friday", Weekday::Fri),
+    ("saturday", Weekday::Sat),
+    ("sunday", Weekday::Sun),
+];
+
+/// The names of the months, which may be shortened like weekdays.
+const MONTHS: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+/// Numbers that may be written as words, e.g. `in two weeks`.
+const NUMBERS: [&str; 13] = [
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+    "eleven", "twelve",
+];
+
+/// Parses a date given on the command line: YYYY-MM-DD, or words such
+/// as `today`, `tomorrow`, `friday`, `next week`, `in 2 weeks` or
+/// `march 5`. A weekday is the first one after today. A time of day,
+/// e.g. `next friday 5pm`, is allowed and left out.
+pub fn parse_date(s: &str) -> Result<NaiveDate, TodoError> {
+    parse_when(s, Local::now().naive_local())
+        .map(|when| when.date)
+        .map_err(|reason| TodoError::InvalidDate {
+            input: s.to_string(),
+            reason,
+        })
+}
+
+/// Parses a month given on the command line as `YYYY-MM`, returning
+/// its first day.
+pub fn parse_month(s: &str) -> Result<NaiveDate, TodoError> {
+    NaiveDate::parse_from_str(&format!("{}-01", s.trim()), "%Y-%m-%d")
+        .map_err(|_| TodoError::InvalidMonth(s.to_string()))
+}
+
+/// Parses a moment given on the command line: a date as `parse_date`
+/// takes it, with a time of day such as `9am`, `5:30pm` or `17:30`, or
+/// `in 2 hours`. A time alone is the next time the clock shows it, a day
+/// alone is at 09:00.
+pub fn parse_moment(s: &str) -> Result<DateTime<Local>, TodoError> {
+    let invalid = |reason: String| TodoError::InvalidTime {
+        input: s.to_string(),
+        reason,
+    };
+    let now = Local::now().naive_local();
+    let when = parse_when(s, now).map_err(invalid)?;
+    let naive = match when.time {
+        Some(time) if !when.named_day && when.date.and_time(time) <= now => {
+            when.date.and_time(time) + TimeDelta::days(1)
+        }
+        Some(time) => when.date.and_time(time),
+        None => when.date.and_time(DEFAULT_TIME),
+    };
+    // The earliest of the two moments when clocks go back
+    naive
+        .and_local_timezone(Local)
+        .earliest()
+        .ok_or_else(|| invalid("the clocks skip that time".to_string()))
+}
+
+/// A day, and maybe a time of day, read from the command line.
+struct When {
+    date: NaiveDate,
+    time: Option<NaiveTime>,
+    /// Whether a day was named, rather than only a time of day
+    named_day: bool,
+}
+
+/// A unit of time, as in `in 2 weeks`.
+#[derive(Clone, Copy)]
+enum Unit {
+    Minute,
+    Hour,
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+/// Reads a day, and maybe a time of day, relative to `now`. The error
+/// says what is wrong.
+fn parse_when(s: &str, now: NaiveDateTime) -> Result<When, String> {
+    let lowercase = s.to_lowercase();
+    let words: Vec<&str> = lowercase.split_whitespace().collect();
+    let (mut rest, time) = split_time(&words)?;
+    if let [day @ .., "at"] = rest {
+        rest = day;
+    }
+    let today = now.date();
+    let on = |date: NaiveDate| {
+        Ok(When {
+            date,
+            time,
+            named_day: true,
+        })
+    };
+
+    match rest {
+        [] if time.is_some() => Ok(When {
+            date: today,
+            time,
+            named_day: false,
+        }),
+        [] => Err(format!("no date given, {DATE_EXAMPLES}")),
+        ["today"] => on(today),
+        ["tomorrow"] => on(today + TimeDelta::days(1)),
+        ["yesterday"] => on(today - TimeDelta::days(1)),
+        [day] | ["this" | "next" | "on", day] if weekday(day).is_some() => {
+            let weekday = weekday(day).unwrap_or(Weekday::Mon);
+            // Never today, the first one after it
+            let ahead =
+                (weekday.num_days_from_monday() + 6 - today.weekday().num_days_from_monday()) % 7
+                    + 1;
+            on(today + TimeDelta::days(ahead.into()))
+        }
+        ["next", unit] => shift(now, 1, unit_of(unit)?, time),
+        ["in", amount, unit] => shift(now, number(amount)?, unit_of(unit)?, time),
+        ["in", compact] => {
+            // As durations are written, e.g. `in 3d`
+            let split = compact
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(compact.len());
+            let (amount, unit) = compact.split_at(split);
+            shift(now, number(amount)?, unit_of(unit)?, time)
+        }
+        ["in", ..] => Err("expected a number and a unit after `in`, e.g. `in 3 days`".to_string()),
+        [date] if date.contains('-') => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| format!("`{date}` is not a date, expected YYYY-MM-DD"))
+            .and_then(on),
+        _ if rest.iter().any(|word| month(word).is_some()) => on(month_day(rest, today)?),
+        _ => Err(format!(
+            "`{}` is not understood, {DATE_EXAMPLES}",
+            rest.join(" ")
+        )),
+    }
+}
+
+/// Moves `now` forward by `amount` units.
+fn shift(
+    now: NaiveDateTime,
+    amount: u32,
+    unit: Unit,
+    time: Option<NaiveTime>,
+) -> Result<When, String> {
+    let too_far = || "that is too far ahead".to_string();
+    let date = now.date();
+    let (date, time) = match unit {
+        Unit::Minute | Unit::Hour => {
+            let minutes = match unit {
+                Unit::Hour => i64::from(amount) * 60,
+                _ => i64::from(amount),
+            };
+            let moment = now
+                .checked_add_signed(TimeDelta::try_minutes(minutes).ok_or_else(too_far)?)
+                .ok_or_else(too_far)?;
+            (moment.date(), time.or(Some(moment.time())))
+        }
+        Unit::Day => (
+            date.checked_add_signed(TimeDelta::try_days(amount.into()).ok_or_else(too_far)?)
+                .ok_or_else(too_far)?,
+            time,
+        ),
+        Unit::Week => (
+            date.checked_add_signed(TimeDelta::try_weeks(amount.into()).ok_or_else(too_far)?)
+                .ok_or_else(too_far)?,
+            time,
+        ),
+        Unit::Month => (
+            date.checked_add_months(Months::new(amount))
+                .ok_or_else(too_far)?,
+            time,
+        ),
+        Unit::Year => (
+            date.checked_add_months(Months::new(amount.saturating_mul(12)))
+                .ok_or_else(too_far)?,
+            time,
+        ),
+    };
+    Ok(When {
+        date,
+        time,
+        named_day: true,
+    })
+}
+
+/// Splits a time of day off the end of `words`, e.g. `5pm`, `5 pm`,
+/// `17:30` or `noon`.
+fn split_time<'a, 'w>(words: &'a [&'w str]) -> Result<(&'a [&'w str], Option<NaiveTime>), String> {
+    match words {
+        [rest @ .., clock, meridiem @ ("am" | "pm")] => {
+            let time = time_of_day(&format!("{clock}{meridiem}"))
+                .ok_or_else(|| format!("`{clock} {meridiem}` is not a time of day"))?;
+            Ok((rest, Some(time)))
+        }
+        [rest @ .., last] if time_of_day(last).is_some() => Ok((rest, time_of_day(last))),
+        [.., last]
+            if last.starts_with(|c: char| c.is_ascii_digit())
+                && (last.contains(':') || last.ends_with("am") || last.ends_with("pm")) =>
+        {
+            Err(format!("`{last}` is not a time of day"))
+        }
+        _ => Ok((words, None)),
+    }
+}
+
+/// Reads a time of day: `noon`, `midnight`, `5pm`, `5:30pm` or `17:30`.
+fn time_of_day(word: &str) -> Option<NaiveTime> {
+    match word {
+        "noon" => return NaiveTime::from_hms_opt(12, 0, 0),
+        "midnight" => return NaiveTime::from_hms_opt(0, 0, 0),
+        _ => {}
+    }
+    let (clock, pm) = match (word.strip_suffix("am"), word.strip_suffix("pm")) {
+        (Some(clock), _) => (clock, Some(false)),
+        (_, Some(clock)) => (clock, Some(true)),
+        _ => (word, None),
+    };
+    let (hour, minute) = match clock.split_once(':') {
+        Some((hour, minute)) if minute.len() == 2 => (hour.parse().ok()?, minute.parse().ok()?),
+        // A number alone is not a time, it may be a day of the month
+        None if pm.is_some() => (clock.parse().ok()?, 0),
+        _ => return None,
+    };
+    let hour: u32 = match pm {
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some(true) => hour % 12 + 12,
+        Some(false) => hour % 12,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+/// Reads a day of the year, e.g. `march 5`, `5th mar` or `march 5
+/// 2027`. Without a year, the next such day, today included, e.g. the
+/// next leap day for `feb 29`.
+fn month_day(words: &[&str], today: NaiveDate) -> Result<NaiveDate, String> {
+    let not_understood = || format!("`{}` is not understood, {DATE_EXAMPLES}", words.join(" "));
+    let (month_word, day_word, year) = match words {
+        [first, second] | [first, second, _] => match (month(first), month(second)) {
+            (Some(_), _) => (*first, *second, words.get(2)),
+            (_, Some(_)) => (*second, *first, words.get(2)),
+            _ => return Err(not_understood()),
+        },
+        _ => return Err(not_understood()),
+    };
+    let month = month(month_word).ok_or_else(not_understood)?;
+    let day: u32 = day_word
+        .trim_end_matches(|c: char| c.is_ascii_alphabetic())
+        .parse()
+        .map_err(|_| format!("`{day_word}` is not a day of the month"))?;
+    let name = MONTHS[month as usize - 1];
+    let name = format!("{}{}", name[..1].to_uppercase(), &name[1..]);
+    match year {
+        Some(year) => {
+            let year = year
+                .parse()
+                .map_err(|_| format!("`{year}` is not a year"))?;
+            NaiveDate::from_ymd_opt(year, month, day)
+                .ok_or_else(|| format!("{name} {year} has no day {day}"))
+        }
+        // Leap days are up to eight years apart
+        None => (today.year()..=today.year() + 8)
+            .filter_map(|year| NaiveDate::from_ymd_opt(year, month, day))
+            .find(|date| *date >= today)
+            .ok_or_else(|| format!("{name} has no day {day}")),
+    }
+}
+
+/// Reads the name of a day of the week.
+fn weekday(word: &str) -> Option<Weekday> {
+    WEEKDAYS
+        .iter()
+        .find(|(name, _)| word.len() >= 3 && name.starts_with(word))
+        .map(|&(_, weekday)| weekday)
+}
+
+/// Reads the name of a month, returning its number from 1.
+fn month(word: &str) -> Option<u32> {
+    MONTHS
+        .iter()
+        .position(|name| word.len() >= 3 && name.starts_with(word))
+        .map(|index| index as u32 + 1)
+}
+
+/// Reads an amount, as digits or a word: `3`, `a`, `two`.
+fn number(word: &str) -> Result<u32, String> {
+    match word {
+        "a" | "an" => Ok(1),
+        _ => NUMBERS
+            .iter()
+            .position(|name| *name == word)
+            .map(|n| n as u32)
+            .or_else(|| word.parse().ok())
+            .ok_or_else(|| format!("`{word}` is not a number")),
+    }
+}
+
+/// Reads a unit of time, singular, plural or short: `day`, `weeks`, `h`.
+fn unit_of(word: &str) -> Result<Unit, String> {
+    let singular = word
+        .strip_suffix('s')
+        .filter(|s| s.len() > 1)
+        .unwrap_or(word);
+    match singular {
+        "m" | "min" | "minute" => Ok(Unit::Minute),
+        "h" | "hr" | "hour" => Ok(Unit::Hour),
+        "d" | "day" => Ok(Unit::Day),
+        "w" | "wk" | "week" => Ok(Unit::Week),
+        "mo" | "month" => Ok(Unit::Month),
+        "y" | "yr" | "year" => Ok(Unit::Year),
+        _ => Err(format!(
+            "`{word}` is not a unit of time, expected minutes, hours, days, weeks, months or years"
+        )),
+    }
+}
+
+/// Turns writing dates relative to today, e.g. `in 3 days`, off or on.
+pub fn set_absolute_dates(absolute: bool) {
+    ABSOLUTE.store(absolute, Ordering::Relaxed);
+}
+
+/// Writes a day relative to `today`, e.g. `tomorrow`, `in 3 days` or
+/// `2 weeks ago`, or as YYYY-MM-DD if relative dates are off.
+pub fn show_day(day: NaiveDate, today: NaiveDate) -> String {
+    if ABSOLUTE.load(Ordering::Relaxed) {
+        return day.to_string();
+    }
+    match (day - today).num_days() {
+        0 => "today".to_string(),
+        1 => "tomorrow".to_string(),
+        -1 => "yesterday".to_string(),
+        days => relative(days.abs(), days > 0),
+    }
+}
+
+/// Writes a moment relative to `now`, e.g. `5 minutes ago`, `in 2
+/// hours` or `yesterday`, or as YYYY-MM-DD HH:MM if relative dates are
+/// off.
+pub fn show_moment(at: DateTime<Local>, now: DateTime<Local>) -> String {
+    if ABSOLUTE.load(Ordering::Relaxed) {
+        return at.format("%Y-%m-%d %H:%M").to_string();
+    }
+    let minutes = (at - now).num_minutes();
+    let future = minutes > 0;
+    match minutes.abs() {
+        0 => "just now".to_string(),
+        minutes @ 1..60 => in_or_ago(minutes, "minute", future),
+        minutes @ 60..1440 => in_or_ago(minutes / 60, "hour", future),
+        _ => show_day(at.date_naive(), now.date_naive()),
+    }
+}
+
+/// Writes a day as `show_day` does, followed by the date if that is
+/// relative, e.g. `in 3 days (2025-03-04)`.
+pub fn show_day_and_date(day: NaiveDate, today: NaiveDate) -> String {
+    if ABSOLUTE.load(Ordering::Relaxed) {
+        return day.to_string();
+    }
+    format!("{} ({day})", show_day(day, today))
+}
+
+/// Writes a moment as `show_moment` does, followed by the date and
+/// time if that is relative, e.g. `2 hours ago (2025-03-01 09:30)`.
+pub fn show_moment_and_date(at: DateTime<Local>, now: DateTime<Local>) -> String {
+    if ABSOLUTE.load(Ordering::Relaxed) {
+        return show_moment(at, now);
+    }
+    format!("{} ({})", show_moment(at, now), at.format("%Y-%m-%d %H:%M"))
+}
+
+/// Writes a number of days in the largest unit that fits.
+fn relative(days: i64, future: bool) -> String {
+    match days {
+        0..14 => in_or_ago(days, "day", future),
+        14..60 => in_or_ago(days / 7, "week", future),
+        60..365 => in_or_ago(days / 30, "month", future),
+        _ => in_or_ago(days / 365, "year", future),
+    }
+}
+
+/// Writes e.g. `in 3 days` or `1 week ago`.
+fn in_or_ago(amount: i64, unit: &str, future: bool) -> String {
+    let plural = if amount == 1 { "" } else { "s" };
+    if future {
+        format!("in {amount} {unit}{plural}")
+    } else {
+        format!("{amount} {unit}{plural} ago")
+    }
+}
+
+/// Parses a duration given as a number and a unit: `30m`, `2h`, `3d`
+/// or `1w`.
+pub fn parse_duration(s: &str) -> Result<TimeDelta, TodoError> {
+    let invalid = || TodoError::InvalidDuration(s.to_string());
+    let s = s.trim();
+    let unit_at = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (amount, unit) = s.split_at(unit_at);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+
+    let duration = match unit {
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => None,
+    };
+    duration.ok_or_else(invalid)
+}
+
+/// Deserialises a list of durations written like `parse_duration`
+/// expects them.
+pub fn deserialize_durations<'de, D>(deserializer: D) -> Result<Vec<TimeDelta>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| parse_duration(s).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Deserialises a duration written like `parse_duration` expects it.
+pub fn deserialize_duration<'de, D>(deserializer: D) -> Result<TimeDelta, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    parse_duration(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+/// Stores an optional duration as a whole number of minutes.
+pub mod option_minutes {
+    use chrono::TimeDelta;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(duration: &Option<TimeDelta>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        duration.map(|d| d.num_minutes()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<TimeDelta>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Option::<i64>::deserialize(deserializer)?.and_then(TimeDelta::try_minutes))
+    }
+}
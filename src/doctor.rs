@@ -0,0 +1,372 @@
+//! Checking the storage file for damage, and mending it, see `todo
+//! doctor`.
+//!
+//! The file is read as written, without migrating it or starting from an
+//! empty store when it can't be read, so every problem can be told
+//! apart. Stores of schema 2 and older kept the tasks in an array of
+//! slots, which are checked against the ids pointing into them.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::backup::snapshot_data;
+use crate::error::{LoadError, Result, TodoError};
+use crate::storage::{Compression, Document, StorageFormat, read_document, read_index};
+use crate::task::{Id, Task};
+
+/// A problem found in the storage file.
+#[derive(Debug)]
+pub enum Problem {
+    /// The file can't be read as a store, so nothing else is checked
+    Unreadable(LoadError),
+    /// An id points past the end of the slots
+    SlotOutOfRange { id: Id, slot: usize },
+    /// Two ids point at the same slot
+    SharedSlot { id: Id, other: Id, slot: usize },
+    /// The task in the slot of an id carries another id
+    SlotIdMismatch { id: Id, slot: usize, found: Id },
+    /// A slot holds a task no id points at, which loading drops
+    OrphanedSlot { slot: usize, head: String },
+    /// Tasks share an id
+    DuplicateId { id: Id, count: usize },
+    /// The ids don't run from 1 in order
+    IdsOutOfOrder,
+    /// A task has neither head nor body
+    EmptyTask { id: Id },
+    /// A task's parent doesn't exist
+    MissingParent { id: Id, parent: Id },
+    /// Tasks are each other's parents
+    ParentCycle { ids: Vec<Id> },
+    /// A task is blocked by a task that doesn't exist
+    MissingBlocker { id: Id, blocker: Id },
+    /// A task is blocked by itself
+    BlockedBySelf { id: Id },
+    /// The index at the front of the file can't be read
+    UnreadableIndex(LoadError),
+    /// The index at the front of the file doesn't list the tasks
+    StaleIndex,
+}
+
+impl Problem {
+    /// Whether `repair` can mend the problem.
+    pub fn is_fixable(&self) -> bool {
+        !matches!(self, Problem::Unreadable(_))
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Unreadable(err) => write!(f, "the file can't be read: {err}"),
+            Problem::SlotOutOfRange { id, slot } => {
+                write!(f, "task {id} points at slot {slot}, past the last slot")
+            }
+            Problem::SharedSlot { id, other, slot } => {
+                write!(f, "tasks {other} and {id} both point at slot {slot}")
+            }
+            Problem::SlotIdMismatch { id, slot, found } => {
+                write!(
+                    f,
+                    "task {id} points at slot {slot}, which holds task {found}"
+                )
+            }
+            Problem::OrphanedSlot { slot, head } => {
+                write!(f, "slot {slot} holds `{head}`, which no id points at")
+            }
+            Problem::DuplicateId { id, count } => write!(f, "{count} tasks have the id {id}"),
+            Problem::IdsOutOfOrder => write!(f, "the ids don't run from 1 in order"),
+            Problem::EmptyTask { id } => write!(f, "task {id} has neither head nor body"),
+            Problem::MissingParent { id, parent } => {
+                write!(
+                    f,
+                    "task {id} is a subtask of task {parent}, which doesn't exist"
+                )
+            }
+            Problem::ParentCycle { ids } => {
+                let ids = ids.iter().map(Id::to_string).collect::<Vec<_>>();
+                write!(f, "tasks {} are subtasks of each other", ids.join(", "))
+            }
+            Problem::MissingBlocker { id, blocker } => {
+                write!(
+                    f,
+                    "task {id} is blocked by task {blocker}, which doesn't exist"
+                )
+            }
+            Problem::BlockedBySelf { id } => write!(f, "task {id} is blocked by itself"),
+            Problem::UnreadableIndex(err) => write!(f, "the index can't be read: {err}"),
+            Problem::StaleIndex => write!(f, "the index doesn't list the tasks of the file"),
+        }
+    }
+}
+
+/// What `examine` found.
+#[derive(Debug)]
+pub struct Examination {
+    /// The file that was examined
+    pub path: PathBuf,
+    /// The file as read, `None` if it can't be read
+    pub document: Option<Document>,
+    /// The number of tasks in the file
+    pub tasks: usize,
+    /// What is wrong with the file, nothing if it is sound
+    pub problems: Vec<Problem>,
+}
+
+/// Checks the storage file at `storage_path`.
+pub fn examine(storage_path: &Path) -> Examination {
+    let mut examination = Examination {
+        path: storage_path.to_path_buf(),
+        document: None,
+        tasks: 0,
+        problems: Vec::new(),
+    };
+    let document = match read_document(storage_path) {
+        Ok(document) => document,
+        Err(err) => {
+            examination.problems.push(Problem::Unreadable(err));
+            return examination;
+        }
+    };
+
+    if document.version <= 2 {
+        examination.problems.extend(check_slots(&document.value));
+    }
+    let tasks = match document.tasks() {
+        Ok(tasks) => tasks,
+        Err(err) => {
+            examination.problems.push(Problem::Unreadable(err));
+            return examination;
+        }
+    };
+    examination.problems.extend(check_tasks(&tasks));
+
+    if matches!(
+        document.format,
+        StorageFormat::Binary | StorageFormat::Mapped
+    ) {
+        match read_index(storage_path) {
+            Ok(Some(index)) => {
+                let listed = index.len() == tasks.len()
+                    && index.iter().zip(&tasks).all(|(a, b)| a.head == b.head);
+                if !listed {
+                    examination.problems.push(Problem::StaleIndex);
+                }
+            }
+            Ok(None) => {}
+            Err(err) => examination.problems.push(Problem::UnreadableIndex(err)),
+        }
+    }
+    examination.tasks = tasks.len();
+    examination.document = Some(document);
+    examination
+}
+
+/// Mends the problems found by `examine`, and saves the store in
+/// `format`. The file as it was is kept in a snapshot next to it, whose
+/// path is returned.
+pub fn repair(
+    examination: Examination,
+    format: StorageFormat,
+    compression: (Option<Compression>, Option<i32>),
+) -> Result<PathBuf> {
+    let Some(mut document) = examination.document else {
+        return Err(TodoError::Usage(format!(
+            "{} can't be read, so it can't be repaired, see `todo restore-backup`",
+            examination.path.display()
+        )));
+    };
+    let snapshot = snapshot_data(&examination.path)?;
+
+    if document.version <= 2 {
+        repair_slots(&mut document.value);
+    }
+    let mut data = document.into_storage().map_err(|source| TodoError::Load {
+        path: examination.path.clone(),
+        source,
+    })?;
+    data.repair();
+    data.set_compression(compression.0, compression.1);
+    data.save(&examination.path, format)?;
+    Ok(snapshot)
+}
+
+/// Checks the ids of a store of schema 2 or older against the slots
+/// they point at.
+fn check_slots(value: &Value) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let (store, id_to_slot) = slots(value);
+    let mut taken = BTreeMap::new();
+    for (id, slot) in &id_to_slot {
+        match store.get(*slot) {
+            None => problems.push(Problem::SlotOutOfRange {
+                id: *id,
+                slot: *slot,
+            }),
+            Some(task) => {
+                let found = task.get("id").and_then(Value::as_u64).unwrap_or_default();
+                if found != *id {
+                    problems.push(Problem::SlotIdMismatch {
+                        id: *id,
+                        slot: *slot,
+                        found,
+                    });
+                }
+            }
+        }
+        if let Some(other) = taken.insert(*slot, *id) {
+            problems.push(Problem::SharedSlot {
+                id: *id,
+                other,
+                slot: *slot,
+            });
+        }
+    }
+    for (slot, task) in store.iter().enumerate() {
+        if !taken.contains_key(&slot) && !is_empty_slot(task) {
+            problems.push(Problem::OrphanedSlot {
+                slot,
+                head: text_of(task, "head"),
+            });
+        }
+    }
+    problems
+}
+
+/// Points the ids of a store of schema 2 or older at the slots they
+/// should: ids past the last slot or sharing one are dropped, tasks take
+/// the id pointing at them, and tasks no id points at get new ids.
+fn repair_slots(value: &mut Value) {
+    let (store, id_to_slot) = slots(value);
+    let mut by_slot = BTreeMap::new();
+    for (id, slot) in id_to_slot {
+        if slot < store.len() {
+            by_slot.entry(slot).or_insert(id);
+        }
+    }
+    let mut next_id = by_slot.values().max().copied().unwrap_or_default() + 1;
+    for (slot, task) in store.iter().enumerate() {
+        if !by_slot.contains_key(&slot) && !is_empty_slot(task) {
+            by_slot.insert(slot, next_id);
+            next_id += 1;
+        }
+    }
+
+    let Some(storage) = value.as_object_mut() else {
+        return;
+    };
+    if let Some(Value::Array(store)) = storage.get_mut("store") {
+        for (slot, id) in &by_slot {
+            if let Some(task) = store.get_mut(*slot).and_then(Value::as_object_mut) {
+                task.insert("id".to_string(), Value::from(*id));
+            }
+        }
+    }
+    let id_to_slot = by_slot
+        .into_iter()
+        .map(|(slot, id)| (id.to_string(), Value::from(slot)))
+        .collect();
+    storage.insert("id_to_slot".to_string(), Value::Object(id_to_slot));
+}
+
+/// The slots and the ids pointing into them, of a store of schema 2 or
+/// older.
+fn slots(value: &Value) -> (Vec<Value>, BTreeMap<Id, usize>) {
+    let store = value
+        .get("store")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let id_to_slot = value
+        .get("id_to_slot")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+        .filter_map(|(id, slot)| Some((id.parse().ok()?, slot.as_u64()? as usize)))
+        .collect();
+    (store, id_to_slot)
+}
+
+/// Whether a slot holds the default task, left by a deleted one.
+fn is_empty_slot(task: &Value) -> bool {
+    text_of(task, "head").is_empty() && text_of(task, "body").is_empty()
+}
+
+/// A text field of a task, as written.
+fn text_of(task: &Value, field: &str) -> String {
+    task.get(field)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+/// Checks the tasks of the file, and the references between them.
+fn check_tasks(tasks: &[Task]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut counts = BTreeMap::new();
+    for task in tasks {
+        *counts.entry(task.id).or_insert(0) += 1;
+    }
+    for (id, count) in &counts {
+        if *count > 1 {
+            problems.push(Problem::DuplicateId {
+                id: *id,
+                count: *count,
+            });
+        }
+    }
+    if counts.len() == tasks.len() && !tasks.iter().zip(1..).all(|(task, id)| task.id == id) {
+        problems.push(Problem::IdsOutOfOrder);
+    }
+
+    let parents = tasks
+        .iter()
+        .map(|task| (task.id, task.parent))
+        .collect::<BTreeMap<Id, Option<Id>>>();
+    let mut in_cycle = BTreeSet::new();
+    for task in tasks {
+        if task.head.trim().is_empty() && task.body.trim().is_empty() {
+            problems.push(Problem::EmptyTask { id: task.id });
+        }
+        if let Some(parent) = task.parent
+            && !parents.contains_key(&parent)
+        {
+            problems.push(Problem::MissingParent {
+                id: task.id,
+                parent,
+            });
+        }
+        for blocker in &task.blocked_by {
+            if *blocker == task.id {
+                problems.push(Problem::BlockedBySelf { id: task.id });
+            } else if !parents.contains_key(blocker) {
+                problems.push(Problem::MissingBlocker {
+                    id: task.id,
+                    blocker: *blocker,
+                });
+            }
+        }
+
+        // Following the parents back to the task makes a cycle
+        let mut ids = vec![task.id];
+        let mut parent = task.parent;
+        while let Some(id) = parent {
+            if id == task.id {
+                if !in_cycle.contains(&task.id) {
+                    in_cycle.extend(ids.iter().copied());
+                    problems.push(Problem::ParentCycle { ids });
+                }
+                break;
+            }
+            if ids.contains(&id) || ids.len() > parents.len() {
+                break;
+            }
+            ids.push(id);
+            parent = parents.get(&id).copied().flatten();
+        }
+    }
+    problems
+}
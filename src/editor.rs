@@ -0,0 +1,180 @@
+//! Composing tasks in an external editor.
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::{fs, process};
+
+use crate::error::{Result, TodoError};
+
+/// The editor used when `EDITOR` isn't set.
+const DEFAULT_EDITOR: &str = if cfg!(windows) { "notepad" } else { "nano" };
+
+/// Instructions shown when composing new tasks.
+pub const NEW_TASK_HELP: &str = "\
+The first line is the head of the task, the rest is its body.
+A line with --- next task --- starts another task.
+Leave the file empty to abort.";
+
+/// Instructions shown when composing a template.
+pub const TEMPLATE_HELP: &str = "\
+The first line is the head of the task, the rest is its body.
+{{date}}, {{time}} and {{weekday}} are filled in for each task made from it.
+Leave the file empty to abort.";
+
+/// Instructions shown when editing a task.
+pub const EDIT_TASK_HELP: &str = "\
+The first line is the head of the task, the rest is its body.
+Leave the file empty to delete the task, after confirming.";
+
+/// The editor to compose tasks in: the one given, e.g. by `--editor` or
+/// the config, else `VISUAL`, else `EDITOR`, else nano, or notepad on
+/// Windows.
+pub fn resolve_editor(given: Option<&str>) -> String {
+    let from_env = |var| std::env::var(var).ok();
+    given
+        .map(str::to_string)
+        .into_iter()
+        .chain(from_env("VISUAL"))
+        .chain(from_env("EDITOR"))
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string())
+}
+
+/// Opens `initial` in `editor`, followed by `help` below a scissors
+/// line, and returns the saved contents above that line.
+/// The editor may be given with arguments, e.g. `code --wait`.
+/// The temporary file is a markdown file, for editors to highlight it.
+pub fn edit_text(editor: &str, initial: &str, help: &str) -> Result<String> {
+    let mut temp_file = tempfile::Builder::new().suffix(".md").tempfile()?;
+    write!(temp_file, "{initial}\n\n{SCISSORS}\n")?;
+    writeln!(
+        temp_file,
+        "Do not modify or remove the line above.\nEverything below it is ignored."
+    )?;
+    writeln!(temp_file, "{help}")?;
+    temp_file.flush()?;
+
+    let launch_err = |source| TodoError::EditorLaunch {
+        editor: editor.to_string(),
+        source,
+    };
+    let words = split_command(editor).unwrap_or_default();
+    let (program, args) = words.split_first().ok_or_else(|| {
+        launch_err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not a valid command line",
+        ))
+    })?;
+    let temp_path = temp_file.path().to_path_buf();
+    let status = process::Command::new(program)
+        .args(args)
+        .arg(&temp_path)
+        .status()
+        .map_err(launch_err)?;
+
+    if !status.success() {
+        return Err(TodoError::EditorFailed(editor.to_string()));
+    }
+
+    Ok(cut_at_scissors(&fs::read_to_string(&temp_path)?))
+}
+
+/// Splits an editor command into the program and its arguments. The
+/// path of an existing program is taken whole, as `EDITOR` often holds
+/// one with spaces on Windows, e.g. `C:\Program Files\Vim\gvim.exe`.
+fn split_command(command: &str) -> Option<Vec<String>> {
+    let command = command.trim();
+    if Path::new(command).is_file() {
+        return Some(vec![command.to_string()]);
+    }
+    if cfg!(windows) {
+        split_windows(command)
+    } else {
+        shlex::split(command)
+    }
+}
+
+/// Splits a command line the way Windows programs read theirs, for the
+/// usual cases: double quotes group words, and backslashes are path
+/// separators rather than escapes. `None` if a quote is left open.
+fn split_windows(command: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let (mut quoted, mut in_word) = (false, false);
+    for c in command.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quoted {
+        return None;
+    }
+    if in_word {
+        words.push(word);
+    }
+    Some(words)
+}
+
+/// The line above the instructions in the editor, everything from it
+/// on is dropped from the saved text.
+const SCISSORS: &str = "# ------------------------ >8 ------------------------";
+
+/// Drops the scissors line and everything below it, and the blank
+/// lines left at the end.
+fn cut_at_scissors(content: &str) -> String {
+    let lines: Vec<&str> = content
+        .lines()
+        .take_while(|line| line.trim_end() != SCISSORS)
+        .collect();
+    lines.join("\n").trim_end().to_string()
+}
+
+/// Splits text into a head, its first line, and a body, the remaining
+/// lines. Returns `None` for empty text.
+pub fn split_head_body(content: &str) -> Option<(String, String)> {
+    let lines: Vec<&str> = content.lines().collect();
+    let head = lines.first()?.to_string();
+
+    let body = if lines.len() > 1 {
+        lines[1..].join("\n")
+    } else {
+        String::new()
+    };
+    Some((head, body))
+}
+
+/// The line that separates tasks composed together in the editor.
+/// Unlike `---`, it isn't Markdown, so bodies can hold rules.
+pub const TASK_SEPARATOR: &str = "--- next task ---";
+
+/// Splits text holding several tasks, separated by `TASK_SEPARATOR`
+/// lines, into the head and body of each. Empty tasks are skipped.
+pub fn split_tasks(content: &str) -> Vec<(String, String)> {
+    let mut chunks = vec![String::new()];
+    for line in content.lines() {
+        if line.trim() == TASK_SEPARATOR {
+            chunks.push(String::new());
+        } else if let Some(chunk) = chunks.last_mut() {
+            chunk.push_str(line);
+            chunk.push('\n');
+        }
+    }
+
+    chunks
+        .iter()
+        .filter_map(|chunk| split_head_body(chunk.trim_start()))
+        .collect()
+}
@@ -0,0 +1,231 @@
+//! The errors of the task manager.
+
+use std::io;
+use std::path::PathBuf;
+
+use crate::task::{Id, join_ids};
+
+/// The process exit code for a task that doesn't exist.
+pub const EXIT_NOT_FOUND: u8 = 1;
+/// The process exit code for a storage file that can't be read.
+pub const EXIT_STORAGE: u8 = 2;
+/// The process exit code for an editor that can't be run.
+pub const EXIT_EDITOR: u8 = 3;
+/// The process exit code for any other I/O failure.
+pub const EXIT_IO: u8 = 4;
+/// The process exit code for invalid command line arguments.
+pub const EXIT_USAGE: u8 = 5;
+
+/// A `Result` with a `TodoError`.
+pub type Result<T, E = TodoError> = std::result::Result<T, E>;
+
+/// Everything that can go wrong while managing tasks.
+#[derive(Debug, thiserror::Error)]
+pub enum TodoError {
+    /// No task has the given id
+    #[error("task {0} not found")]
+    TaskNotFound(Id),
+
+    /// No task has a head matching the given words
+    #[error("no task matches `{0}`")]
+    NoMatchingTask(String),
+
+    /// Several tasks have a head matching the given words, and there is
+    /// no terminal to pick one of them in
+    #[error("`{query}` matches tasks {}, give the id of one", join_ids(.ids))]
+    AmbiguousTask { query: String, ids: Vec<Id> },
+
+    /// The storage file could not be read
+    #[error("cannot read {}: {source}", .path.display())]
+    Load {
+        path: PathBuf,
+        #[source]
+        source: LoadError,
+    },
+
+    /// The store could not be serialised
+    #[error("failed to serialise tasks: {0}")]
+    Serialize(String),
+
+    /// A file could not be read
+    #[error("failed to read {}: {source}", .path.display())]
+    ReadFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// A file could not be created
+    #[error("failed to create {}: {source}", .path.display())]
+    CreateFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// A file could not be written
+    #[error("failed to write {}: {source}", .path.display())]
+    WriteFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// The editor could not be started
+    #[error("failed to launch `{editor}`: {source}")]
+    EditorLaunch {
+        editor: String,
+        #[source]
+        source: io::Error,
+    },
+
+    /// The editor exited with a failure
+    #[error("`{0}` exited with non zero status")]
+    EditorFailed(String),
+
+    /// A task can't be done while tasks it depends on are open
+    #[error("task {id} is blocked by open tasks {}", join_ids(.blockers))]
+    Blocked { id: Id, blockers: Vec<Id> },
+
+    /// A dependency would make tasks wait for each other
+    #[error("task {on} already waits for task {id}")]
+    DependencyCycle { id: Id, on: Id },
+
+    /// A desktop notification could not be shown
+    #[error("failed to show a notification: {0}")]
+    Notify(String),
+
+    /// An attachment could not be opened
+    #[error("failed to open the attachment: {0}")]
+    Open(String),
+
+    /// The clipboard could not be read
+    #[error("failed to read the clipboard: {0}")]
+    Clipboard(String),
+
+    /// The storage file could not be watched for changes
+    #[error("cannot watch the store for changes: {0}")]
+    Watch(String),
+
+    /// Syncing with a server failed
+    #[error("failed to sync: {0}")]
+    Sync(String),
+
+    /// Reading from a server failed
+    #[error("failed to fetch {0}")]
+    Fetch(String),
+
+    /// Only one timer can run at a time
+    #[error("a timer is already running on task {0}, stop it first")]
+    TimerRunning(Id),
+
+    /// There is no backup with the given number
+    #[error("backup {0} not found")]
+    BackupNotFound(usize),
+
+    /// The task has no body section with the given name
+    #[error("section `{0}` not found")]
+    SectionNotFound(String),
+
+    /// There is no template with the given name
+    #[error("template `{0}` not found, see `todo template list`")]
+    TemplateNotFound(String),
+
+    /// A date could not be parsed
+    #[error("invalid date `{input}`: {reason}")]
+    InvalidDate { input: String, reason: String },
+
+    /// A month could not be parsed
+    #[error("invalid month `{0}`, expected YYYY-MM")]
+    InvalidMonth(String),
+
+    /// A time could not be parsed
+    #[error("invalid time `{input}`: {reason}")]
+    InvalidTime { input: String, reason: String },
+
+    /// A duration could not be parsed
+    #[error("invalid duration `{0}`, expected a number and m, h, d or w, e.g. `3d`")]
+    InvalidDuration(String),
+
+    /// Task ids could not be parsed
+    #[error("invalid task ids `{0}`, expected ids and ranges such as `2-6,9`")]
+    InvalidIds(String),
+
+    /// A filter could not be parsed
+    #[error("invalid filter: {0}")]
+    InvalidFilter(String),
+
+    /// A file to import could not be read as the given format
+    #[error("cannot import {format}: {reason}")]
+    InvalidImport { format: String, reason: String },
+
+    /// The command line is valid but the combination of arguments isn't
+    #[error("{0}")]
+    Usage(String),
+
+    /// Any other I/O failure
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl TodoError {
+    /// The process exit code reporting this error.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            TodoError::TaskNotFound(_)
+            | TodoError::NoMatchingTask(_)
+            | TodoError::BackupNotFound(_)
+            | TodoError::SectionNotFound(_)
+            | TodoError::TemplateNotFound(_) => EXIT_NOT_FOUND,
+            TodoError::Load { .. } => EXIT_STORAGE,
+            TodoError::EditorLaunch { .. } | TodoError::EditorFailed(_) => EXIT_EDITOR,
+            TodoError::Blocked { .. }
+            | TodoError::TimerRunning(_)
+            | TodoError::DependencyCycle { .. }
+            | TodoError::InvalidDate { .. }
+            | TodoError::InvalidMonth(_)
+            | TodoError::InvalidTime { .. }
+            | TodoError::InvalidDuration(_)
+            | TodoError::InvalidIds(_)
+            | TodoError::AmbiguousTask { .. }
+            | TodoError::InvalidFilter(_)
+            | TodoError::InvalidImport { .. }
+            | TodoError::Usage(_) => EXIT_USAGE,
+            TodoError::Serialize(_)
+            | TodoError::Notify(_)
+            | TodoError::Open(_)
+            | TodoError::Clipboard(_)
+            | TodoError::Watch(_)
+            | TodoError::Sync(_)
+            | TodoError::Fetch(_)
+            | TodoError::ReadFile { .. }
+            | TodoError::CreateFile { .. }
+            | TodoError::WriteFile { .. }
+            | TodoError::Io(_) => EXIT_IO,
+        }
+    }
+}
+
+/// Reasons a storage file could not be read.
+#[derive(Debug, thiserror::Error)]
+pub enum LoadError {
+    /// The file ends before the data recorded in its header
+    #[error("the file is truncated: expected {expected} bytes, found {found}")]
+    Truncated { expected: usize, found: usize },
+
+    /// The data doesn't match the checksum recorded in the header
+    #[error("the file is corrupted: checksum {found:08x} does not match {expected:08x}")]
+    Corrupted { expected: u32, found: u32 },
+
+    /// The data is intact but isn't a store in the expected format
+    #[error("the data is not a valid store: {0}")]
+    Format(String),
+
+    /// The file was written by a newer version of the program
+    #[error("it was written by a newer version of todo (schema {found}, supported {supported})")]
+    NewerVersion { found: u32, supported: u32 },
+
+    /// The file could not be read at all
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
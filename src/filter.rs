@@ -0,0 +1,213 @@
+//! Filters selecting a subset of the tasks.
+
+use std::str::FromStr;
+
+use clap::ValueEnum;
+
+use chrono::{NaiveDate, TimeDelta};
+
+use crate::date::{parse_date, parse_duration};
+use crate::error::TodoError;
+use crate::task::{Priority, Task, context_name, field};
+
+/// A set of conditions that a task must all satisfy.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    terms: Vec<FilterTerm>,
+}
+
+/// A single condition of a `Filter`.
+#[derive(Clone, Debug)]
+enum FilterTerm {
+    /// The task carries the tag
+    Tag(String),
+    /// The task can be done in the context
+    Context(String),
+    /// The task is part of the project or one of its subprojects, `None`
+    /// for no project
+    Project(Option<String>),
+    /// The task's due date matches
+    Due(DueFilter),
+    /// The task has the priority, `None` for no priority
+    Priority(Option<Priority>),
+    /// The task is at this stage of its work
+    Status(Status),
+    /// The task has the field set to the value, ignoring case, `None`
+    /// for the field not being set
+    Field(String, Option<String>),
+    /// The head or body contains the text, ignoring case
+    Text(String),
+}
+
+/// How far along a task is.
+#[derive(Clone, Copy, Debug)]
+enum Status {
+    /// Open, without time tracked on it
+    Todo,
+    /// Open, with time tracked on it
+    Started,
+    Done,
+}
+
+/// Conditions on a task's due date.
+#[derive(Clone, Debug)]
+enum DueFilter {
+    On(NaiveDate),
+    Overdue,
+    /// Due within this long from today, or overdue
+    Within(TimeDelta),
+    None,
+}
+
+impl Filter {
+    /// Whether the filter has no terms and matches every task.
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Whether matching the filter looks at the body of tasks.
+    pub fn reads_body(&self) -> bool {
+        self.terms
+            .iter()
+            .any(|term| matches!(term, FilterTerm::Text(_)))
+    }
+
+    /// Adds the condition that the task carries `tag`.
+    pub fn tag(mut self, tag: String) -> Self {
+        self.terms.push(FilterTerm::Tag(tag));
+        self
+    }
+
+    /// Adds the condition that the task can be done in `context`.
+    pub fn context(mut self, context: String) -> Self {
+        self.terms.push(FilterTerm::Context(context));
+        self
+    }
+
+    /// Adds the condition that the task is overdue.
+    pub fn overdue(mut self) -> Self {
+        self.terms.push(FilterTerm::Due(DueFilter::Overdue));
+        self
+    }
+
+    /// Adds the condition that the task is due within `within` from
+    /// today, or overdue.
+    pub fn due_within(mut self, within: TimeDelta) -> Self {
+        self.terms.push(FilterTerm::Due(DueFilter::Within(within)));
+        self
+    }
+
+    /// Adds the conditions of `other`.
+    pub fn and(mut self, other: &Filter) -> Self {
+        self.terms.extend(other.terms.iter().cloned());
+        self
+    }
+
+    /// Whether the task satisfies every term of the filter.
+    pub fn matches(&self, task: &Task, today: NaiveDate) -> bool {
+        self.terms.iter().all(|term| match term {
+            FilterTerm::Tag(tag) => task.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)),
+            FilterTerm::Context(context) => task
+                .contexts
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(context)),
+            FilterTerm::Project(None) => task.project.is_none(),
+            FilterTerm::Project(Some(project)) => task.project.as_deref().is_some_and(|p| {
+                p.eq_ignore_ascii_case(project)
+                    || p.get(..project.len() + 1)
+                        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(&format!("{project}.")))
+            }),
+            FilterTerm::Due(DueFilter::On(date)) => task.due == Some(*date),
+            FilterTerm::Due(DueFilter::Overdue) => task.due.is_some_and(|due| due < today),
+            // A span past the last date there is takes in every date
+            FilterTerm::Due(DueFilter::Within(within)) => task.due.is_some_and(|due| {
+                today
+                    .checked_add_signed(TimeDelta::days(within.num_days()))
+                    .is_none_or(|until| due <= until)
+            }),
+            FilterTerm::Due(DueFilter::None) => task.due.is_none(),
+            FilterTerm::Priority(priority) => task.priority == *priority,
+            FilterTerm::Status(Status::Todo) => !task.done && task.intervals.is_empty(),
+            FilterTerm::Status(Status::Started) => !task.done && !task.intervals.is_empty(),
+            FilterTerm::Status(Status::Done) => task.done,
+            FilterTerm::Field(key, value) => {
+                let set = task
+                    .fields
+                    .iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                    .map(|(_, v)| v);
+                match value {
+                    Some(value) => set.is_some_and(|v| v.eq_ignore_ascii_case(value)),
+                    None => set.is_none(),
+                }
+            }
+            FilterTerm::Text(text) => {
+                let text = text.to_lowercase();
+                task.head.to_lowercase().contains(&text) || task.body.to_lowercase().contains(&text)
+            }
+        })
+    }
+}
+
+impl FromStr for Filter {
+    type Err = TodoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_filter(s)
+    }
+}
+
+/// Parses a filter given on the command line.
+/// Terms are separated by whitespace: `tag:<name>`, `@<context>`,
+/// `project:<name|none>`, `due:<date|overdue|none>`, `due:within:<duration>`,
+/// `priority:<low|medium|high|none>`, `status:<todo|started|done>`,
+/// `<field>=<value>` for a field of the user's own, `<field>=` for it
+/// not being set, or words to look for in the head and body.
+pub fn parse_filter(s: &str) -> Result<Filter, TodoError> {
+    let terms = s
+        .split_whitespace()
+        .map(|word| {
+            if let Some((key, value)) = field(word) {
+                let value = (!value.is_empty()).then(|| value.to_string());
+                return Ok(FilterTerm::Field(key.to_string(), value));
+            }
+            match word.split_once(':') {
+                _ if word.starts_with('@') => parse_context(word).map(FilterTerm::Context),
+                Some(("tag", tag)) => Ok(FilterTerm::Tag(tag.to_string())),
+                Some(("project", "none")) => Ok(FilterTerm::Project(None)),
+                Some(("project", project)) => Ok(FilterTerm::Project(Some(project.to_string()))),
+                Some(("due", "overdue")) => Ok(FilterTerm::Due(DueFilter::Overdue)),
+                Some(("due", "none")) => Ok(FilterTerm::Due(DueFilter::None)),
+                Some(("due", within)) if within.starts_with("within:") => {
+                    parse_duration(&within["within:".len()..])
+                        .map(|within| FilterTerm::Due(DueFilter::Within(within)))
+                }
+                Some(("due", date)) => parse_date(date).map(|d| FilterTerm::Due(DueFilter::On(d))),
+                Some(("priority", "none")) => Ok(FilterTerm::Priority(None)),
+                Some(("priority", priority)) => Priority::from_str(priority, true)
+                    .map(|p| FilterTerm::Priority(Some(p)))
+                    .map_err(|_| {
+                        TodoError::InvalidFilter(format!("unknown priority `{priority}`"))
+                    }),
+                Some(("status", "todo")) => Ok(FilterTerm::Status(Status::Todo)),
+                Some(("status", "started")) => Ok(FilterTerm::Status(Status::Started)),
+                Some(("status", "done")) => Ok(FilterTerm::Status(Status::Done)),
+                Some(("status", status)) => Err(TodoError::InvalidFilter(format!(
+                    "unknown status `{status}`, expected todo, started or done"
+                ))),
+                Some((key, _)) => Err(TodoError::InvalidFilter(format!("unknown key `{key}`"))),
+                None => Ok(FilterTerm::Text(word.to_string())),
+            }
+        })
+        .collect::<Result<Vec<FilterTerm>, TodoError>>()?;
+    Ok(Filter { terms })
+}
+
+/// Parses a context given on the command line, e.g. `@home`.
+pub fn parse_context(s: &str) -> Result<String, TodoError> {
+    context_name(s).map(str::to_string).ok_or_else(|| {
+        TodoError::Usage(format!(
+            "`{s}` is not a context, expected `@` and a name, e.g. `@home`"
+        ))
+    })
+}
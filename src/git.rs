@@ -0,0 +1,201 @@
+//! Keeping the store in a git repository, see `todo sync git`.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use crate::error::{Result, TodoError};
+use crate::storage::{Reconciled, Storage, StorageFormat};
+
+/// The message of the commits recording changes to the store.
+const COMMIT_MESSAGE: &str = "Update tasks";
+
+/// What a git sync did.
+#[derive(Debug, Default)]
+pub struct GitReport {
+    /// Changes to the store were committed
+    pub committed: bool,
+    /// Changes were pulled without a merge
+    pub fast_forwarded: bool,
+    /// Changes made elsewhere were merged in
+    pub merged: Option<Reconciled>,
+    /// The repository has no remote to pull from and push to
+    pub no_remote: bool,
+    /// Commits were pushed
+    pub pushed: bool,
+    /// There are commits to push, see `finish`
+    ahead: bool,
+}
+
+/// Commits the store at `storage_path` to the git repository holding
+/// it and pulls, for `finish` to push. Only the store is committed.
+///
+/// If the history diverged, the two copies of the store are reconciled
+/// task by task, see `Storage::reconcile`, rather than line by line, with
+/// the store of the commit both histories share as the base.
+/// `data` is the store as saved, and is reconciled in place; the merge
+/// is left for `finish` to commit once the reconciled store is saved.
+/// The store should stay locked until then.
+pub fn sync(storage_path: &Path, data: &mut Storage, format: StorageFormat) -> Result<GitReport> {
+    let git = Git::of(storage_path);
+    let mut report = GitReport::default();
+
+    // The default store is in the home directory, which is no place
+    // for a repository to be created unasked
+    if !git.succeeds(&["rev-parse", "--git-dir"])? {
+        return Err(TodoError::Usage(format!(
+            "{} is not in a git repository, run `git init` in a directory of its own \
+             and point TODO_STORAGE at a store in it",
+            storage_path.display()
+        )));
+    }
+    if matches!(format, StorageFormat::Binary | StorageFormat::Mapped) {
+        log::warn!(
+            "the store isn't plaintext, set `format = \"json\"` in the config to read its history"
+        );
+    }
+
+    // Paths are given relative to the repository, as `git show` wants
+    let prefix = git.output(&["rev-parse", "--show-prefix"])?;
+    let file_name = storage_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file = format!("{prefix}{file_name}");
+
+    git.run(&["add", "--", &file_name])?;
+    if !git.succeeds(&["diff", "--cached", "--quiet", "--", &file_name])? {
+        git.run(&["commit", "--quiet", "-m", COMMIT_MESSAGE, "--", &file_name])?;
+        report.committed = true;
+    }
+
+    if git.output(&["remote"])?.is_empty() {
+        report.no_remote = true;
+        return Ok(report);
+    }
+    git.run(&["fetch", "--quiet"])?;
+    if !git.succeeds(&["rev-parse", "--verify", "--quiet", "@{upstream}"])? {
+        // Nothing to pull yet, start tracking the remote branch
+        let remote = git.output(&["remote"])?;
+        let remote = remote.lines().next().unwrap_or("origin");
+        git.run(&["push", "--quiet", "--set-upstream", remote, "HEAD"])?;
+        report.pushed = true;
+        return Ok(report);
+    }
+
+    let behind = !git.succeeds(&["merge-base", "--is-ancestor", "@{upstream}", "HEAD"])?;
+    let ahead = !git.succeeds(&["merge-base", "--is-ancestor", "HEAD", "@{upstream}"])?;
+    if behind && !ahead {
+        git.run(&["merge", "--quiet", "--ff-only", "@{upstream}"])?;
+        *data = Storage::load(storage_path)?;
+        report.fast_forwarded = true;
+    } else if behind {
+        let theirs = git.load(&format!("@{{upstream}}:{file}"))?;
+        // The store as of the last commit both histories share, if it
+        // was there then
+        let base = git.output(&["merge-base", "HEAD", "@{upstream}"])?;
+        let base = git.load(&format!("{base}:{file}")).ok();
+        // The merge is only started for the other files of the repository,
+        // the store is replaced by the reconciled copy
+        let _ = git.succeeds(&["merge", "--quiet", "--no-commit", "--no-ff", "@{upstream}"])?;
+        let conflicts = git.output(&["diff", "--name-only", "--diff-filter=U"])?;
+        if conflicts.lines().any(|path| path != file) {
+            git.run(&["merge", "--abort"])?;
+            return Err(TodoError::Sync(format!(
+                "other files in {} conflict, merge them by hand",
+                git.dir.display()
+            )));
+        }
+        report.merged = Some(data.reconcile(base.as_ref(), &theirs));
+    }
+    report.ahead = ahead;
+    Ok(report)
+}
+
+/// Commits the merge `sync` started, with the reconciled store saved to
+/// `storage_path`, and pushes what there is to push.
+pub fn finish(storage_path: &Path, report: &mut GitReport) -> Result<()> {
+    let git = Git::of(storage_path);
+    if report.merged.is_some() {
+        let file_name = storage_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        git.run(&["add", "--", &file_name])?;
+        git.run(&["commit", "--quiet", "--no-edit"])?;
+    }
+
+    // A merge makes a commit to push as well
+    if report.ahead {
+        git.run(&["push", "--quiet"])?;
+        report.pushed = true;
+    }
+    Ok(())
+}
+
+/// Runs git in a directory.
+struct Git {
+    dir: PathBuf,
+}
+
+impl Git {
+    /// Runs git in the directory holding the store at `storage_path`.
+    fn of(storage_path: &Path) -> Self {
+        let dir = storage_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        Git {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Runs git with `args`.
+    fn command(&self, args: &[&str]) -> Result<Output> {
+        log::debug!("git {}", args.join(" "));
+        Command::new("git")
+            .arg("-C")
+            .arg(&self.dir)
+            .args(args)
+            .output()
+            .map_err(|err| TodoError::Sync(format!("cannot run git: {err}")))
+    }
+
+    /// Runs git with `args`, failing if git does.
+    fn run(&self, args: &[&str]) -> Result<()> {
+        self.output(args).map(|_| ())
+    }
+
+    /// Runs git with `args` and returns whether it succeeded.
+    fn succeeds(&self, args: &[&str]) -> Result<bool> {
+        Ok(self.command(args)?.status.success())
+    }
+
+    /// Runs git with `args` and returns what it printed, trimmed.
+    fn output(&self, args: &[&str]) -> Result<String> {
+        let output = self.command(args)?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(TodoError::Sync(format!(
+                "git {} failed: {}",
+                args.join(" "),
+                stderr.trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Reads a copy of the store from git, e.g. `@{upstream}:tasks`.
+    fn load(&self, object: &str) -> Result<Storage> {
+        let output = self.command(&["show", object])?;
+        if !output.status.success() {
+            return Err(TodoError::Sync(format!("cannot read {object} from git")));
+        }
+        let copy = tempfile::NamedTempFile::new()?;
+        std::fs::write(copy.path(), &output.stdout)?;
+        let data = Storage::load(copy.path())?;
+        if data.was_recovered() {
+            return Err(TodoError::Sync(format!("{object} is not a valid store")));
+        }
+        Ok(data)
+    }
+}
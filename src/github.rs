@@ -0,0 +1,147 @@
+//! Reading issues from GitHub.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::error::{Result, TodoError};
+
+/// Where the REST API is.
+const API_URL: &str = "https://api.github.com";
+
+/// How long to wait for the server.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many issues are asked for at a time, the most GitHub allows.
+const PAGE_SIZE: usize = 100;
+
+/// How many pages are read at most.
+const MAX_PAGES: usize = 20;
+
+/// A GitHub issue.
+#[derive(Debug, Deserialize)]
+pub struct Issue {
+    pub number: u64,
+    pub title: String,
+    #[serde(default)]
+    pub body: Option<String>,
+    /// The page of the issue
+    pub html_url: String,
+    #[serde(default)]
+    pub labels: Vec<Label>,
+    /// `open` or `closed`
+    pub state: String,
+    /// Set if the issue is a pull request
+    #[serde(default)]
+    pub pull_request: Option<serde_json::Value>,
+}
+
+/// A label of an issue.
+#[derive(Debug, Deserialize)]
+pub struct Label {
+    pub name: String,
+}
+
+/// The signed in user.
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+/// Talks to the GitHub REST API.
+pub struct Client {
+    agent: ureq::Agent,
+    token: Option<String>,
+}
+
+impl Client {
+    /// A client signing in with `token`, if given.
+    pub fn new(token: Option<String>) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build(),
+            token: token.filter(|token| !token.trim().is_empty()),
+        }
+    }
+
+    /// The login of the user the token belongs to.
+    pub fn login(&self) -> Result<String> {
+        if self.token.is_none() {
+            return Err(TodoError::Usage(
+                "`--assignee me` needs a GitHub token, set one under [github] in the config \
+                 or in GITHUB_TOKEN"
+                    .to_string(),
+            ));
+        }
+        let user: User = self.get("/user", &[])?;
+        Ok(user.login)
+    }
+
+    /// The open issues of `repo`, e.g. `owner/name`, assigned to
+    /// `assignee`. Pull requests are left out.
+    pub fn assigned_issues(&self, repo: &str, assignee: &str) -> Result<Vec<Issue>> {
+        let path = format!("/repos/{repo}/issues");
+        let per_page = PAGE_SIZE.to_string();
+        let mut issues = Vec::new();
+        for page in 1..=MAX_PAGES {
+            let page = page.to_string();
+            let batch: Vec<Issue> = self.get(
+                &path,
+                &[
+                    ("state", "open"),
+                    ("assignee", assignee),
+                    ("per_page", &per_page),
+                    ("page", &page),
+                ],
+            )?;
+            let last = batch.len() < PAGE_SIZE;
+            issues.extend(
+                batch
+                    .into_iter()
+                    .filter(|issue| issue.pull_request.is_none()),
+            );
+            if last {
+                break;
+            }
+        }
+        Ok(issues)
+    }
+
+    /// Issue `number` of `repo`, whatever its state.
+    pub fn issue(&self, repo: &str, number: u64) -> Result<Issue> {
+        self.get(&format!("/repos/{repo}/issues/{number}"), &[])
+    }
+
+    /// Reads the JSON at `path` of the API.
+    fn get<T: for<'de> Deserialize<'de>>(&self, path: &str, query: &[(&str, &str)]) -> Result<T> {
+        let url = format!("{API_URL}{path}");
+        let mut request = self
+            .agent
+            .get(&url)
+            .set("Accept", "application/vnd.github+json")
+            .set("User-Agent", "todo");
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {}", token.trim()));
+        }
+        for (name, value) in query {
+            request = request.query(name, value);
+        }
+        let response = request.call().map_err(|err| match err {
+            ureq::Error::Status(401, _) => {
+                TodoError::Fetch(format!("{url}: GitHub refused the token"))
+            }
+            // GitHub hides private repositories from strangers
+            ureq::Error::Status(404, _) => {
+                TodoError::Fetch(format!("{url}: not found, or private and no token given"))
+            }
+            ureq::Error::Status(code, response) => {
+                TodoError::Fetch(format!("{url}: {code} {}", response.status_text()))
+            }
+            ureq::Error::Transport(transport) => TodoError::Fetch(format!("{url}: {transport}")),
+        })?;
+        let body = response
+            .into_string()
+            .map_err(|err| TodoError::Fetch(format!("{url}: {err}")))?;
+        serde_json::from_str(&body)
+            .map_err(|err| TodoError::Fetch(format!("{url}: invalid response: {err}")))
+    }
+}
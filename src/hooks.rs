@@ -0,0 +1,141 @@
+//! User scripts run when the store changes, kept in
+//! `~/.config/todo/hooks/`.
+//!
+//! Once the store is saved, `on-add` runs for each task added and
+//! `on-done` for each task marked done, with the task as JSON on stdin,
+//! then `on-save` runs once with the tasks added or changed as a JSON
+//! array. Hooks are given `TODO_HOOK`, their name, and `TODO_STORAGE`.
+//! A hook that fails is reported, the change is kept either way.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use chrono::{DateTime, Local};
+
+use crate::config::get_config_path;
+use crate::ics::uid;
+use crate::storage::Storage;
+use crate::task::Task;
+
+/// What the tasks looked like when the store was loaded, to tell what
+/// changed when it is saved.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    /// Whether each task was done and when it last changed, by uid
+    tasks: BTreeMap<String, (bool, Option<DateTime<Local>>)>,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of the tasks of `data`.
+    pub fn of(data: &Storage) -> Self {
+        let mut snapshot = Self::default();
+        snapshot.absorb(data);
+        snapshot
+    }
+
+    /// Takes in the tasks of `data`, so that the changes made to them
+    /// elsewhere, e.g. merged in from another copy, don't run hooks.
+    pub fn absorb(&mut self, data: &Storage) {
+        for task in data.list() {
+            self.tasks.insert(uid(task), (task.done, task.updated_at));
+        }
+    }
+
+    /// Whether `task` changed since the snapshot was taken, or is new.
+    pub fn changed(&self, task: &Task) -> bool {
+        self.tasks
+            .get(&uid(task))
+            .is_none_or(|(_, updated_at)| *updated_at != task.updated_at)
+    }
+}
+
+/// Where the hooks are.
+pub fn hooks_dir() -> PathBuf {
+    get_config_path().with_file_name("hooks")
+}
+
+/// Runs the hooks for the changes from `before` to `after`, the store
+/// just saved at `storage_path`.
+pub fn run_after_save(before: &Snapshot, after: &Storage, storage_path: &Path) {
+    let dir = hooks_dir();
+    if !dir.is_dir() {
+        return;
+    }
+    let mut changed: Vec<&Task> = Vec::new();
+    for task in after.list() {
+        match before.tasks.get(&uid(task)) {
+            None => {
+                run(&dir, "on-add", &to_json(task), storage_path);
+                if task.done {
+                    run(&dir, "on-done", &to_json(task), storage_path);
+                }
+            }
+            Some(&(done, updated_at)) => {
+                if task.done && !done {
+                    run(&dir, "on-done", &to_json(task), storage_path);
+                }
+                if task.updated_at == updated_at {
+                    continue;
+                }
+            }
+        }
+        changed.push(task);
+    }
+    let changed = serde_json::to_string(&changed).unwrap_or_default();
+    run(&dir, "on-save", &changed, storage_path);
+}
+
+/// Writes a task as JSON, as `todo show --format json` does.
+fn to_json(task: &Task) -> String {
+    serde_json::to_string(task).unwrap_or_default()
+}
+
+/// Runs the hook `name` in `dir`, if there is one, with `input` on
+/// stdin.
+fn run(dir: &Path, name: &str, input: &str, storage_path: &Path) {
+    let path = dir.join(name);
+    if !is_executable(&path) {
+        return;
+    }
+    log::debug!("running {} hook", path.display());
+    let child = Command::new(&path)
+        .env("TODO_HOOK", name)
+        .env("TODO_STORAGE", storage_path)
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            log::warn!("cannot run {}: {err}", path.display());
+            return;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // The hook may not read its input
+        let _ = stdin.write_all(input.as_bytes());
+    }
+    match child.wait() {
+        Ok(status) if status.success() => {}
+        Ok(status) => log::warn!("{} failed: {status}", path.display()),
+        Err(err) => log::warn!("{} failed: {err}", path.display()),
+    }
+}
+
+/// Whether `path` is a file that can be run. Hooks are turned off by
+/// making them not executable.
+fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = path.metadata() else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
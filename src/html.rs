@@ -0,0 +1,202 @@
+//! Writing tasks as a web page, see `todo export --format html`.
+//!
+//! The page stands alone, its style inlined, so it can be sent or put
+//! anywhere to share the list with people who don't use `todo`. Open
+//! tasks come first, by project and then by due date, soonest first.
+//! Completed tasks follow, by project, last completed first.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Local, NaiveDate};
+
+use crate::task::Task;
+
+/// The style of the page.
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; max-width: 50rem; margin: 2rem auto;
+  padding: 0 1rem; color: #222; line-height: 1.4; }
+h1 { margin-bottom: 0; }
+.generated { color: #777; margin-top: 0.25rem; }
+h2 { border-bottom: 1px solid #ddd; padding-bottom: 0.25rem; margin-top: 2rem; }
+h3 { font-size: 1.1rem; margin: 1.5rem 0 0.5rem; }
+h4 { font-size: 0.9rem; color: #555; margin: 1rem 0 0.5rem; }
+h4.overdue { color: #b00020; }
+ul { list-style: none; padding: 0; margin: 0; }
+li { padding: 0.5rem 0.75rem; margin-bottom: 0.5rem; border-left: 3px solid #ccc;
+  background: #f7f7f7; }
+li.high { border-left-color: #b00020; }
+li.medium { border-left-color: #e6a100; }
+li.low { border-left-color: #2a7ab0; }
+li.done { color: #777; }
+li.done .head { text-decoration: line-through; }
+.head { font-weight: 600; }
+.meta { font-size: 0.85rem; color: #666; }
+.tag { display: inline-block; padding: 0 0.4rem; margin-right: 0.25rem;
+  border-radius: 0.6rem; background: #e3e8ef; font-size: 0.8rem; }
+.body { white-space: pre-wrap; margin: 0.4rem 0 0; font-size: 0.9rem; }
+";
+
+/// The heading of the tasks without a project.
+const NO_PROJECT: &str = "No project";
+
+/// Writes `tasks` as a web page as of `now`.
+pub fn to_html(tasks: &[&Task], now: DateTime<Local>) -> String {
+    let today = now.date_naive();
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n");
+    html.push_str("<meta charset=\"utf-8\">\n");
+    html.push_str("<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n");
+    html.push_str("<title>Tasks</title>\n");
+    html.push_str(&format!("<style>{STYLE}</style>\n</head>\n<body>\n"));
+    html.push_str("<h1>Tasks</h1>\n");
+    html.push_str(&format!(
+        "<p class=\"generated\">As of {}</p>\n",
+        now.format("%a %Y-%m-%d %H:%M")
+    ));
+
+    let (done, open): (Vec<&Task>, Vec<&Task>) = tasks.iter().partition(|task| task.done);
+
+    html.push_str(&format!("<h2>Open ({})</h2>\n", open.len()));
+    if open.is_empty() {
+        html.push_str("<p>Nothing left to do.</p>\n");
+    }
+    for (project, tasks) in by_project(open) {
+        html.push_str(&format!("<h3>{}</h3>\n", escape(project)));
+        let mut due_dates = BTreeMap::<Option<NaiveDate>, Vec<&Task>>::new();
+        for task in tasks {
+            due_dates.entry(task.due).or_default().push(task);
+        }
+        // Tasks without a due date last
+        let (undated, dated): (Vec<_>, Vec<_>) =
+            due_dates.into_iter().partition(|(due, _)| due.is_none());
+        for (due, tasks) in dated.into_iter().chain(undated) {
+            html.push_str(&due_heading(due, today));
+            html.push_str("<ul>\n");
+            for task in tasks {
+                html.push_str(&item(task));
+            }
+            html.push_str("</ul>\n");
+        }
+    }
+
+    html.push_str(&format!("<h2>Completed ({})</h2>\n", done.len()));
+    for (project, mut tasks) in by_project(done) {
+        tasks.sort_by_key(|task| std::cmp::Reverse(task.completed_at));
+        html.push_str(&format!("<h3>{}</h3>\n<ul>\n", escape(project)));
+        for task in tasks {
+            html.push_str(&item(task));
+        }
+        html.push_str("</ul>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Groups `tasks` by project, in the order of their names, the tasks
+/// without a project last.
+fn by_project(tasks: Vec<&Task>) -> Vec<(&str, Vec<&Task>)> {
+    let mut projects = BTreeMap::<Option<&str>, Vec<&Task>>::new();
+    for task in tasks {
+        projects
+            .entry(task.project.as_deref())
+            .or_default()
+            .push(task);
+    }
+    let none = projects.remove(&None);
+    projects
+        .into_iter()
+        .map(|(project, tasks)| (project.unwrap_or(NO_PROJECT), tasks))
+        .chain(none.map(|tasks| (NO_PROJECT, tasks)))
+        .collect()
+}
+
+/// The heading of the open tasks due on `due`, marked if they are
+/// overdue.
+fn due_heading(due: Option<NaiveDate>, today: NaiveDate) -> String {
+    let (class, text) = match due {
+        None => ("", "No due date".to_string()),
+        Some(due) if due < today => (" class=\"overdue\"", format!("Overdue, due {}", day(due))),
+        Some(due) if due == today => ("", format!("Due today, {}", day(due))),
+        Some(due) => ("", format!("Due {}", day(due))),
+    };
+    format!("<h4{class}>{text}</h4>\n")
+}
+
+/// A task as an item of a list.
+fn item(task: &Task) -> String {
+    let mut classes = Vec::new();
+    if let Some(priority) = task.priority {
+        classes.push(priority.to_string());
+    }
+    if task.done {
+        classes.push("done".to_string());
+    }
+    let class = if classes.is_empty() {
+        String::new()
+    } else {
+        format!(" class=\"{}\"", classes.join(" "))
+    };
+
+    let mut meta = Vec::new();
+    if let Some(priority) = task.priority {
+        meta.push(format!("{priority} priority"));
+    }
+    if let Some(due) = task.due.filter(|_| task.done) {
+        meta.push(format!("due {}", day(due)));
+    }
+    if let Some(completed) = task.completed_at {
+        meta.push(format!("completed {}", completed.format("%a %Y-%m-%d")));
+    }
+    if !task.contexts.is_empty() {
+        let contexts = task
+            .contexts
+            .iter()
+            .map(|context| format!("@{context}"))
+            .collect::<Vec<_>>();
+        meta.push(contexts.join(" "));
+    }
+
+    let mut html = format!(
+        "<li{class}><span class=\"head\">{}</span>",
+        escape(&task.head)
+    );
+    for tag in &task.tags {
+        html.push_str(&format!(" <span class=\"tag\">#{}</span>", escape(tag)));
+    }
+    if !meta.is_empty() {
+        html.push_str(&format!(
+            "<div class=\"meta\">{}</div>",
+            escape(&meta.join(" · "))
+        ));
+    }
+    if !task.body.trim().is_empty() {
+        html.push_str(&format!(
+            "<p class=\"body\">{}</p>",
+            escape(task.body.trim())
+        ));
+    }
+    html.push_str("</li>\n");
+    html
+}
+
+/// A date as written on the page, e.g. `Fri 2025-03-07`.
+fn day(date: NaiveDate) -> String {
+    date.format("%a %Y-%m-%d").to_string()
+}
+
+/// Escapes the characters of `text` that mean something in HTML.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,301 @@
+//! Reading and writing tasks as iCalendar (RFC 5545) to-dos.
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+use crate::task::{Priority, Task};
+
+/// The longest a content line may be, in bytes, before it is folded.
+const MAX_LINE_LEN: usize = 75;
+
+/// Writes the tasks that have a due date as a calendar of `VTODO`
+/// components. `now` is recorded as the time of the export.
+pub fn to_ics<'a>(tasks: impl IntoIterator<Item = &'a Task>, now: DateTime<Local>) -> String {
+    let mut out = String::new();
+    push_calendar_start(&mut out);
+    for task in tasks {
+        if task.due.is_some() {
+            push_todo(&mut out, task, now);
+        }
+    }
+    push_line(&mut out, "END:VCALENDAR");
+    out
+}
+
+/// Writes a single task as a calendar holding one `VTODO`, as stored
+/// on CalDAV servers.
+pub fn task_calendar(task: &Task, now: DateTime<Local>) -> String {
+    let mut out = String::new();
+    push_calendar_start(&mut out);
+    push_todo(&mut out, task, now);
+    push_line(&mut out, "END:VCALENDAR");
+    out
+}
+
+/// Writes the start of a calendar.
+fn push_calendar_start(out: &mut String) {
+    push_line(out, "BEGIN:VCALENDAR");
+    push_line(out, "VERSION:2.0");
+    push_line(
+        out,
+        &format!("PRODID:-//todo//todo {}//EN", env!("CARGO_PKG_VERSION")),
+    );
+}
+
+/// Writes a task as a `VTODO` component.
+fn push_todo(out: &mut String, task: &Task, now: DateTime<Local>) {
+    push_line(out, "BEGIN:VTODO");
+    push_line(out, &format!("UID:{}", uid(task)));
+    push_line(out, &format!("DTSTAMP:{}", utc_stamp(now)));
+    push_line(out, &format!("SUMMARY:{}", escape(&task.head)));
+    if !task.body.is_empty() {
+        push_line(out, &format!("DESCRIPTION:{}", escape(&task.body)));
+    }
+    if let Some(due) = task.due {
+        push_line(out, &format!("DUE;VALUE=DATE:{}", due.format("%Y%m%d")));
+    }
+    if !task.tags.is_empty() {
+        let tags = task.tags.iter().map(|t| escape(t)).collect::<Vec<_>>();
+        push_line(out, &format!("CATEGORIES:{}", tags.join(",")));
+    }
+    if let Some(priority) = task.priority {
+        // 1 is the highest priority, 9 the lowest
+        let level = match priority {
+            Priority::High => 1,
+            Priority::Medium => 5,
+            Priority::Low => 9,
+        };
+        push_line(out, &format!("PRIORITY:{level}"));
+    }
+    if let Some(created_at) = task.created_at {
+        push_line(out, &format!("CREATED:{}", utc_stamp(created_at)));
+    }
+    if let Some(updated_at) = task.updated_at {
+        push_line(out, &format!("LAST-MODIFIED:{}", utc_stamp(updated_at)));
+    }
+    if task.done {
+        push_line(out, "STATUS:COMPLETED");
+        if let Some(completed_at) = task.completed_at {
+            push_line(out, &format!("COMPLETED:{}", utc_stamp(completed_at)));
+        }
+    } else {
+        push_line(out, "STATUS:NEEDS-ACTION");
+    }
+    push_line(out, "END:VTODO");
+}
+
+/// A unique id for the task that survives the renumbering of ids: the
+/// one it was given by a calendar, or one based on when it was created.
+/// Tasks added by old versions use their id, until they are given one.
+pub fn uid(task: &Task) -> String {
+    if let Some(uid) = &task.uid {
+        return uid.clone();
+    }
+    match task.created_at {
+        Some(created_at) => format!(
+            "{}-{}@todo",
+            created_at.timestamp(),
+            created_at.timestamp_subsec_nanos()
+        ),
+        None => format!("task-{}@todo", task.id),
+    }
+}
+
+/// Formats a moment as a UTC date-time, e.g. `20250301T090000Z`.
+fn utc_stamp(at: DateTime<Local>) -> String {
+    at.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Escapes the characters with a meaning in text values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Appends a content line, folded so no line is longer than
+/// `MAX_LINE_LEN` bytes, and ended with CRLF.
+fn push_line(out: &mut String, line: &str) {
+    let mut len = 0;
+    for c in line.chars() {
+        // Continuation lines start with a space, which counts
+        if len + c.len_utf8() > MAX_LINE_LEN {
+            out.push_str("\r\n ");
+            len = 1;
+        }
+        out.push(c);
+        len += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+/// A to-do read from an iCalendar file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Todo {
+    pub uid: String,
+    pub summary: String,
+    pub description: String,
+    pub due: Option<NaiveDate>,
+    pub categories: Vec<String>,
+    pub priority: Option<Priority>,
+    pub completed: bool,
+    pub completed_at: Option<DateTime<Local>>,
+    pub created_at: Option<DateTime<Local>>,
+    pub last_modified: Option<DateTime<Local>>,
+}
+
+impl Todo {
+    /// Copies the fields a to-do shares with a task onto the task.
+    pub fn apply_to(&self, task: &mut Task) {
+        task.uid = Some(self.uid.clone());
+        task.head = self.summary.clone();
+        task.body = self.description.clone();
+        task.due = self.due;
+        task.tags = self.categories.clone();
+        task.priority = self.priority;
+        if task.done != self.completed {
+            task.done = self.completed;
+            task.completed_at = if self.completed {
+                self.completed_at.or(Some(Local::now()))
+            } else {
+                None
+            };
+        }
+    }
+
+    /// A new task with the fields of the to-do.
+    pub fn to_task(&self) -> Task {
+        let mut task = Task {
+            created_at: self.created_at,
+            ..Default::default()
+        };
+        self.apply_to(&mut task);
+        task
+    }
+}
+
+/// Reads the `VTODO` components of an iCalendar file.
+/// Unknown properties are ignored, and so are to-dos without a `UID`.
+pub fn parse_todos(text: &str) -> Vec<Todo> {
+    let mut todos = Vec::new();
+    let mut current: Option<Todo> = None;
+
+    for line in unfold(text) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        // Parameters, e.g. `DUE;VALUE=DATE`, don't matter here
+        let name = name.split(';').next().unwrap_or_default().to_uppercase();
+
+        match (name.as_str(), current.as_mut()) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VTODO") => {
+                current = Some(Todo::default());
+            }
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VTODO") => {
+                if let Some(todo) = current.take().filter(|todo| !todo.uid.is_empty()) {
+                    todos.push(todo);
+                }
+            }
+            ("UID", Some(todo)) => todo.uid = value.to_string(),
+            ("SUMMARY", Some(todo)) => todo.summary = unescape(value),
+            ("DESCRIPTION", Some(todo)) => todo.description = unescape(value),
+            ("DUE", Some(todo)) => todo.due = parse_date_value(value),
+            ("CATEGORIES", Some(todo)) => {
+                todo.categories
+                    .extend(split_list(value).into_iter().filter(|tag| !tag.is_empty()));
+            }
+            ("PRIORITY", Some(todo)) => {
+                todo.priority = match value.trim().parse::<u8>() {
+                    Ok(1..=4) => Some(Priority::High),
+                    Ok(5) => Some(Priority::Medium),
+                    Ok(6..=9) => Some(Priority::Low),
+                    _ => None,
+                };
+            }
+            ("STATUS", Some(todo)) => todo.completed = value.eq_ignore_ascii_case("COMPLETED"),
+            ("COMPLETED", Some(todo)) => todo.completed_at = parse_date_time(value),
+            ("CREATED", Some(todo)) => todo.created_at = parse_date_time(value),
+            ("LAST-MODIFIED", Some(todo)) => todo.last_modified = parse_date_time(value),
+            _ => {}
+        }
+    }
+    todos
+}
+
+/// Joins folded lines back together.
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Reverses `escape`.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Splits a list value at the commas that aren't escaped, unescaping
+/// each item.
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = vec![String::new()];
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            ',' if !escaped => items.push(String::new()),
+            _ => {
+                if let Some(item) = items.last_mut() {
+                    item.push(c);
+                }
+            }
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    items.iter().map(|item| unescape(item)).collect()
+}
+
+/// Reads the date of a `DATE` or `DATE-TIME` value, e.g. `20250301` or
+/// `20250301T090000Z`.
+fn parse_date_value(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.get(..8)?, "%Y%m%d").ok()
+}
+
+/// Reads a `DATE-TIME` value, in UTC if it ends with `Z`, otherwise in
+/// local time.
+pub fn parse_date_time(value: &str) -> Option<DateTime<Local>> {
+    let value = value.trim();
+    match value.strip_suffix('Z') {
+        Some(utc) => NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S")
+            .ok()
+            .map(|naive| Utc.from_utc_datetime(&naive).with_timezone(&Local)),
+        None => NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+            .ok()
+            .and_then(|naive| Local.from_local_datetime(&naive).earliest()),
+    }
+}
@@ -0,0 +1,86 @@
+//! A Minimalistic task manager
+//!
+//! This crate holds the task store behind the `todo` command-line
+//! application, so it can be embedded in other tools.
+//!
+//! ```no_run
+//! use todo::{Storage, StorageFormat, Task};
+//!
+//! let path = std::path::Path::new("tasks.bin");
+//! let mut store = Storage::load(path)?;
+//! store.add(Task {
+//!     head: "Fix the boiler".to_string(),
+//!     ..Default::default()
+//! });
+//! for task in store.list() {
+//!     println!("{}. {}", task.id, task.head);
+//! }
+//! store.save(path, StorageFormat::Binary)?;
+//! # Ok::<(), todo::TodoError>(())
+//! ```
+//!
+//! Implementation details
+//! The `Storage` struct holds the state of the program
+//!
+//! Tasks are kept in an ordered map keyed by their id, so getting,
+//! changing and listing them in id order goes straight through the map.
+//! A new task gets the id after the last one. Deleting a task renumbers
+//! the remaining ones so the ids run from 1 without gaps, and points the
+//! parents and blockers of the tasks at their new ids. Only the tasks are
+//! saved, in id order, see `storage`.
+
+pub mod attach;
+pub mod backup;
+pub mod board;
+pub mod caldav;
+pub mod clipboard;
+pub mod config;
+pub mod crdt;
+pub mod csv;
+#[cfg(unix)]
+pub mod daemon;
+pub mod date;
+pub mod doctor;
+pub mod editor;
+pub mod error;
+pub mod filter;
+pub mod git;
+pub mod github;
+pub mod hooks;
+pub mod html;
+pub mod ics;
+pub mod mail;
+pub mod markdown;
+pub mod merge;
+pub mod notify;
+pub mod org;
+pub mod pager;
+pub mod pick;
+pub mod prompt;
+pub mod remind;
+pub mod remote;
+pub mod report;
+pub mod scan;
+pub mod search;
+pub mod sections;
+pub mod select;
+pub mod server;
+pub mod stats;
+pub mod storage;
+pub mod style;
+pub mod task;
+pub mod taskwarrior;
+pub mod template;
+#[cfg(feature = "todoist")]
+pub mod todoist;
+pub mod tokens;
+pub mod urgency;
+pub mod watch;
+
+pub use config::{Config, load_config};
+pub use error::{LoadError, Result, TodoError};
+pub use filter::Filter;
+pub use storage::{
+    Compression, Placement, Storage, StorageFormat, get_storage, load_from_storage, save_to_storage,
+};
+pub use task::{Event, HistoryEntry, Id, Interval, Note, Priority, Task};
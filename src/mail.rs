@@ -0,0 +1,260 @@
+//! Reading email messages as tasks, see `todo import --format eml` and
+//! `todo import --maildir`.
+//!
+//! The subject becomes the head and the plain text of the message the
+//! body. Of a multipart message the first `text/plain` part is read,
+//! however deeply nested. Quoted-printable and base64 bodies, and encoded
+//! words in the subject, are decoded. Text in charsets other than UTF-8
+//! is read as UTF-8 as far as it goes.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use regex::{Captures, Regex};
+
+use crate::error::{Result, TodoError};
+use crate::task::Task;
+
+/// An encoded word, e.g. `=?UTF-8?Q?Caf=C3=A9?=`: a charset, `B` for base64
+/// or `Q` for quoted-printable, and the encoded text.
+static ENCODED_WORD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"=\?[^?]+\?([BbQq])\?([^?]*)\?=").expect("valid regular expression")
+});
+
+/// The blanks between two encoded words, which aren't part of the text.
+static BETWEEN_WORDS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(\?=)\s+(=\?)").expect("valid regular expression"));
+
+/// The custom field of the tasks made from messages holding the
+/// `Message-ID` of their message, to tell the messages imported before.
+pub const MESSAGE_ID_FIELD: &str = "message-id";
+
+/// What a message says.
+#[derive(Debug, Default, PartialEq)]
+pub struct Message {
+    pub subject: String,
+    /// The plain text, empty if the message has none
+    pub text: String,
+    /// The `Message-ID` header, without its angle brackets
+    pub message_id: Option<String>,
+}
+
+impl Message {
+    /// The task the message asks for.
+    pub fn to_task(&self) -> Task {
+        let head = if self.subject.trim().is_empty() {
+            "(no subject)".to_string()
+        } else {
+            self.subject.clone()
+        };
+        let mut task = Task {
+            head,
+            body: self.text.trim().to_string(),
+            ..Default::default()
+        };
+        if let Some(id) = &self.message_id {
+            task.fields.insert(MESSAGE_ID_FIELD.to_string(), id.clone());
+        }
+        task
+    }
+}
+
+/// Reads a message as stored in an `.eml` file or a maildir.
+pub fn parse_message(raw: &str) -> Message {
+    let (headers, body) = split_part(raw);
+    Message {
+        subject: headers
+            .get("subject")
+            .map(|subject| decode_words(subject))
+            .unwrap_or_default(),
+        text: plain_text(&headers, body).unwrap_or_default(),
+        message_id: headers.get("message-id").map(|id| {
+            id.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        }),
+    }
+}
+
+/// Reads the messages of the maildir `dir`, in `new` and `cur`, oldest
+/// first as their names start with when they were delivered.
+pub fn read_maildir(dir: &Path) -> Result<Vec<Message>> {
+    let folders = [dir.join("new"), dir.join("cur")];
+    if !folders.iter().any(|folder| folder.is_dir()) {
+        return Err(TodoError::Usage(format!(
+            "{} is not a maildir, it has no `new` or `cur` directory",
+            dir.display()
+        )));
+    }
+    let mut messages = Vec::new();
+    for folder in folders.iter().filter(|folder| folder.is_dir()) {
+        let entries = fs::read_dir(folder).map_err(|source| TodoError::ReadFile {
+            path: folder.clone(),
+            source,
+        })?;
+        messages.extend(
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file()),
+        );
+    }
+    messages.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    messages
+        .into_iter()
+        .map(|path| match fs::read(&path) {
+            Ok(raw) => Ok(parse_message(&String::from_utf8_lossy(&raw))),
+            Err(source) => Err(TodoError::ReadFile { path, source }),
+        })
+        .collect()
+}
+
+/// Splits a message, or a part of one, into its headers, by their names
+/// in lowercase, and its body. Headers folded over several lines are
+/// joined.
+fn split_part(text: &str) -> (BTreeMap<String, String>, &str) {
+    let (head, body) = text
+        .split_once("\r\n\r\n")
+        .or_else(|| text.split_once("\n\n"))
+        .unwrap_or((text, ""));
+    let mut headers = BTreeMap::new();
+    let mut current: Option<(String, String)> = None;
+    for line in head.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = &mut current {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some((name, value)) = current.take() {
+            headers.entry(name).or_insert(value);
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            current = Some((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+    if let Some((name, value)) = current {
+        headers.entry(name).or_insert(value);
+    }
+    (headers, body)
+}
+
+/// The first plain text of a part, looking into the parts of multipart
+/// ones.
+fn plain_text(headers: &BTreeMap<String, String>, body: &str) -> Option<String> {
+    let content_type = headers
+        .get("content-type")
+        .map(String::as_str)
+        .unwrap_or("text/plain");
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+
+    if mime.starts_with("multipart/") {
+        let boundary = parameter(content_type, "boundary")?;
+        let delimiter = format!("--{boundary}");
+        return body
+            .split(delimiter.as_str())
+            // The preamble
+            .skip(1)
+            .take_while(|part| !part.starts_with("--"))
+            .find_map(|part| {
+                let (headers, body) = split_part(part.trim_start_matches(['\r', '\n']));
+                plain_text(&headers, body)
+            });
+    }
+    if mime != "text/plain" {
+        return None;
+    }
+    let encoding = headers
+        .get("content-transfer-encoding")
+        .map(|encoding| encoding.trim().to_lowercase());
+    let bytes = match encoding.as_deref() {
+        Some("quoted-printable") => decode_quoted_printable(body, false),
+        Some("base64") => {
+            let data = body
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect::<String>();
+            STANDARD.decode(data).ok()?
+        }
+        _ => body.as_bytes().to_vec(),
+    };
+    Some(String::from_utf8_lossy(&bytes).replace("\r\n", "\n"))
+}
+
+/// The value of a parameter of a header, e.g. the boundary of
+/// `multipart/mixed; boundary="abc"`.
+fn parameter(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+/// Decodes the encoded words of a header, e.g.
+/// `=?UTF-8?B?Q2Fmw6k=?=` as `Café`. Blanks between encoded words are
+/// dropped.
+fn decode_words(value: &str) -> String {
+    let value = BETWEEN_WORDS.replace_all(value, "$1$2");
+    ENCODED_WORD
+        .replace_all(&value, |captures: &Captures| {
+            let bytes = if captures[1].eq_ignore_ascii_case("b") {
+                STANDARD.decode(&captures[2]).unwrap_or_default()
+            } else {
+                decode_quoted_printable(&captures[2], true)
+            };
+            String::from_utf8_lossy(&bytes).into_owned()
+        })
+        .into_owned()
+}
+
+/// Decodes quoted-printable text, in which `=` and two hex digits stand
+/// for a byte and `=` ends a line that goes on. In encoded words `_`
+/// stands for a space.
+fn decode_quoted_printable(text: &str, in_word: bool) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'=' if bytes.get(i + 1) == Some(&b'\r') && bytes.get(i + 2) == Some(&b'\n') => i += 3,
+            b'=' if bytes.get(i + 1) == Some(&b'\n') => i += 2,
+            b'=' => {
+                let byte = text
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match byte {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                    }
+                    None => {
+                        decoded.push(b'=');
+                        i += 1;
+                    }
+                }
+            }
+            b'_' if in_word => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
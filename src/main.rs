@@ -1,477 +1,5092 @@
 //! A Minimalistic task manager
-/// The main crate for the task manager application.
-/// It provides a command-line interface to manage tasks.
-///
-/// Implementation details
-/// The `Storage` struct holds the state of the program
-///
-/// Tasks are stored in a contiguous array of intial length
-/// `INTIAL_TASKS_ARRAY_LENGTH`
-/// To create a new task entry, you first get a `Slot` index
-/// into the tasks array.This is the position into which the
-/// newly created task with be inserted at. The provided slot has
-/// already been initialised with the default implementation of the
-/// `Task` object.
-///
-/// The storage model also contains a mapping of task id's to their slot
-/// indices in the tasks array. This mapping is used to retrive tasks
-/// from the array in constant time. To get a task by its Id, first
-/// look up the task id in the id_to_slot map, then index into the tasks
-/// array at the provided slot index.
-/// Deleting a task takes a similar approach to getting a task but proceeds
-/// to re-index the id_to_slot map and issue new Ids to the remaining tasks.
-/// The element at the removed index slot in the tasks array is replaced with
-/// the default value of `Task`.
-///
-use std::collections::BTreeMap;
-use std::fs::File;
-use std::io::{self, Write};
+//!
+//! The command-line front-end of the `todo` crate.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
-use std::{fs, process};
+use std::process::{Command, ExitCode};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Local, Months, NaiveDate, NaiveTime, TimeDelta, Weekday};
+use clap::{ArgGroup, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use clap_complete::engine::{ArgValueCandidates, CompletionCandidate};
+use clap_complete::env::{CompleteEnv, Shells};
+use crossterm::cursor::MoveTo;
+use crossterm::terminal::{Clear, ClearType};
+use todo::attach;
+use todo::backup::{list_backups, restore, snapshot_data};
+use todo::board;
+use todo::caldav::{self, SyncReport};
+use todo::clipboard;
+use todo::config::{BoardColumn, get_config_path};
+#[cfg(unix)]
+use todo::daemon;
+use todo::date::{
+    self, parse_date, parse_duration, parse_moment, parse_month, show_day, show_day_and_date,
+    show_moment, show_moment_and_date,
+};
+use todo::doctor::{self, Problem};
+use todo::editor::{
+    EDIT_TASK_HELP, NEW_TASK_HELP, TEMPLATE_HELP, edit_text, resolve_editor, split_head_body,
+    split_tasks,
+};
+use todo::error::{EXIT_STORAGE, EXIT_USAGE, LoadError};
+use todo::filter::{parse_context, parse_filter};
+use todo::git;
+use todo::github;
+use todo::hooks::{self, Snapshot};
+use todo::html;
+use todo::ics;
+use todo::mail;
+use todo::markdown;
+use todo::merge::Conflict;
+use todo::notify;
+use todo::org;
+use todo::pager::{self, page};
+use todo::pick::pick;
+use todo::prompt::{self, confirm};
+use todo::remind::{format_duration, reminders, reminders_between};
+use todo::remote;
+use todo::report;
+use todo::scan;
+use todo::search::{Field, Hit, Query};
+use todo::sections::parse_sections;
+use todo::select::{self, IdSet, Selection, TaskRef, parse_ids, parse_selection, parse_task_ref};
+use todo::server;
+use todo::stats::{Productivity, Stats};
+use todo::storage::{
+    Fingerprint, Reconciled, decode_storage, fingerprint, lock_storage, read_document, salvage,
+    set_global_store,
+};
+use todo::style::{self, ColorChoice};
+use todo::task::{field, join_ids, project_name, tag_name};
+use todo::taskwarrior;
+use todo::template;
+#[cfg(feature = "todoist")]
+use todo::todoist;
+use todo::tokens::{parse_priority, take_tokens};
+use todo::urgency;
+use todo::watch::StorageWatcher;
+use todo::{
+    Config, Event, Filter, HistoryEntry, Id, Interval, Note, Placement, Priority, Result, Storage,
+    StorageFormat, Task, TodoError, get_storage, load_config,
+};
 
-use clap::{Parser, Subcommand};
-use flate2::Compression;
-use flate2::write::{ZlibDecoder, ZlibEncoder};
-use serde::{Deserialize, Serialize};
+/// Prints a message confirming what a command did, unless `--quiet`
+/// is given.
+macro_rules! report {
+    ($($arg:tt)*) => {
+        if !todo::report::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
 
 /// The version of the application, retrieved from the Cargo.toml file.
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-/// The intial length of the tasks array in the storage
-/// It is intialiased with the default values of `Task`
-const INITIAL_TASKS_ARRAY_LENGTH: usize = 64;
+/// The environment variable through which shells request completions.
+const COMPLETE_VAR: &str = "COMPLETE";
+
+/// The width of a day in the calendar.
+const CALENDAR_CELL_WIDTH: usize = 6;
+
+/// How days are printed in headings.
+const DAY_FORMAT: &str = "%a %Y-%m-%d";
+
+/// How moments such as reminders are printed.
+const MOMENT_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// Seconds between the checks of `remind --daemon`.
+const DEFAULT_REMIND_INTERVAL: u64 = 60;
+
+/// The port `serve` listens on when none is given.
+const DEFAULT_SERVE_PORT: u16 = 8080;
+
+/// How far ahead `notify` looks for tasks due soon.
+const DEFAULT_NOTIFY_WITHIN: &str = "1d";
+
+/// The length of a pomodoro when none is given.
+const DEFAULT_POMODORO_LENGTH: &str = "25m";
+
+/// The number of tags `stats` lists.
+const BUSIEST_TAGS: usize = 5;
+
+/// How long a task goes unchanged before `review` goes over it, when not
+/// told.
+const DEFAULT_REVIEW_OLDER_THAN: &str = "14d";
+
+/// How long `review` snoozes a task for when not told.
+const DEFAULT_REVIEW_SNOOZE: &str = "1w";
+
+/// How far back `report` looks when not told.
+const DEFAULT_REPORT_SINCE: &str = "30d";
+
+/// The width of the longest bar of the chart of `report`.
+const REPORT_BAR_WIDTH: usize = 30;
+
+/// `done` and `rm` ask for confirmation when given more tasks than this.
+const CONFIRM_ABOVE: usize = 3;
+
+/// Sample tasks used to populate the throwaway `demo` store.
+const DEMO_TASKS: &[(&str, &str)] = &[
+    ("Buy groceries", "Milk, eggs, bread and coffee"),
+    ("Review pull requests", "The code looks good to me"),
+    ("Call the plumber", ""),
+    ("Write the weekly report", "Summarise progress and blockers"),
+    ("Fix the boiler", ""),
+];
 
 /// The main command-line interface for the task manager.
 #[derive(Parser)]
-#[command(version = VERSION, about = "A Minimalistic task manager", long_about = None)]
+#[command(
+    version = VERSION,
+    about = "A Minimalistic task manager",
+    long_about = None,
+    after_help = "Other commands run plugins: `todo foo` runs `todo-foo` from the PATH."
+)]
 struct Cli {
     /// The command to execute.
     #[command(subcommand)]
     command: Commands,
+
+    /// When to color the output
+    #[arg(long, global = true, value_enum, default_value_t)]
+    color: ColorChoice,
+
+    /// Never pipe long output through `$PAGER`
+    #[arg(long, global = true)]
+    no_pager: bool,
+
+    /// Write dates as YYYY-MM-DD rather than e.g. `in 3 days`
+    #[arg(long, global = true)]
+    absolute_dates: bool,
+
+    /// The editor to compose and edit tasks in, e.g. `code --wait`,
+    /// instead of the one in the config, `$VISUAL` or `$EDITOR`
+    #[arg(long, global = true, value_name = "COMMAND")]
+    editor: Option<String>,
+
+    /// Use the store in the home directory, even in a project with a
+    /// `.todo` store of its own
+    #[arg(long, short, global = true)]
+    global: bool,
+
+    /// Don't ask for confirmation before deleting or changing many tasks
+    #[arg(long, short, global = true)]
+    yes: bool,
+
+    /// Report what the command changes without saving the changes
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Don't print messages confirming changes, and log errors only
+    #[arg(long, short, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log more details to stderr, repeat for even more
+    #[arg(long, short, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
 }
 
 /// The available commands for the task manager.
 #[derive(Subcommand)]
 enum Commands {
     /// Create new task.
+    ///
+    /// Without a head, the editor is opened to compose the task.
+    /// With `-` as the head, the task is read from stdin.
     New {
         /// The Title of the task
         head: Option<String>,
         /// The Body section of the new task
         body: Option<String>,
+        /// Read the task from stdin, the first line being the head
+        #[arg(long, conflicts_with_all = ["head", "body"])]
+        stdin: bool,
+        /// Read the task from the clipboard, the first line being the
+        /// head and the rest the body, e.g. to keep a link or a snippet
+        #[arg(long, conflicts_with_all = ["head", "body", "stdin"])]
+        from_clipboard: bool,
+        /// Ask for the head, body, due date, priority and tags one by one,
+        /// the options given being the defaults
+        #[arg(
+            short,
+            long,
+            conflicts_with_all = ["head", "body", "stdin", "from_clipboard", "template"]
+        )]
+        interactive: bool,
+        /// Start from a template saved with `template save`, the head and
+        /// body given replacing its own
+        #[arg(long, conflicts_with_all = ["stdin", "from_clipboard"])]
+        template: Option<String>,
+        /// Due date: YYYY-MM-DD, or e.g. `tomorrow`, `friday`, `in 2 weeks`
+        #[arg(long, value_parser = parse_date)]
+        due: Option<NaiveDate>,
+        /// Tag the task, may be repeated
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Attach a URL, e.g. of a page to read, may be repeated
+        #[arg(long = "url", value_parser = parse_url)]
+        urls: Vec<String>,
+        /// Where the task can be done, e.g. `home`, may be repeated.
+        /// `@home` in the head does the same
+        #[arg(long = "context", value_parser = parse_context_name)]
+        contexts: Vec<String>,
+        /// The project the task is part of, that of the parent by default.
+        /// `+name` in the head does the same
+        #[arg(long, value_parser = parse_project_name)]
+        project: Option<String>,
+        /// Make the task a subtask of this one
+        #[arg(long, add = ArgValueCandidates::new(task_id_candidates))]
+        parent: Option<u64>,
+        /// How long the task is expected to take: e.g. `30m`, `2h`
+        #[arg(long, value_parser = parse_duration)]
+        estimate: Option<TimeDelta>,
+        /// How important the task is
+        #[arg(long)]
+        priority: Option<Priority>,
     },
 
     /// List all tasks heads
-    List,
+    ///
+    /// Subtasks are listed under their parent, with the parent showing
+    /// how many of them are done
+    List {
+        #[command(flatten)]
+        options: ListOptions,
+    },
+
+    /// Show the list, and show it again whenever the store changes
+    ///
+    /// Takes the same options as `list`, e.g. for a terminal pane kept
+    /// open beside others. Press Ctrl-C to stop
+    Watch {
+        #[command(flatten)]
+        options: ListOptions,
+    },
 
-    /// Get a task
-    Get {
+    /// Show the tasks in columns: Backlog, In progress and Done
+    ///
+    /// Started tasks are those with time tracked on them. Other columns
+    /// are set up under [[board]] in the config, each taking the tasks
+    /// its filter matches that no column before it took
+    Board {
+        /// One column per tag instead
+        #[arg(long)]
+        by_tag: bool,
+        /// Include every task done and snoozed, not only those done in
+        /// the last week
+        #[arg(short, long)]
+        all: bool,
+    },
+
+    /// Suggest what to work on next: the most pressing open tasks
+    ///
+    /// Tasks score higher the closer or further past their due date,
+    /// the more important and the older they are, when other tasks wait
+    /// on them and when their timer runs; blocked tasks score lower
+    Next {
+        /// Only suggest tasks that can be done in these contexts, e.g.
+        /// `@home`
+        #[arg(value_parser = parse_context)]
+        contexts: Vec<String>,
+        /// How many tasks to suggest
+        #[arg(long, short = 'n', default_value_t = 1)]
+        count: usize,
+    },
+
+    /// Pick an open task at random, for when choosing is the hard part
+    Random {
+        /// Only pick tasks that can be done in these contexts, e.g. `@home`
+        #[arg(value_parser = parse_context)]
+        contexts: Vec<String>,
+        /// Only pick tasks with this tag, may be repeated
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+
+    /// Search the heads, bodies, tags and notes of all tasks
+    ///
+    /// Each task found is listed with the lines that match
+    Search {
+        /// The text to look for, matching case unless `--ignore-case`
+        pattern: String,
+        /// Read the pattern as a regular expression
+        #[arg(short = 'e', long)]
+        regex: bool,
+        /// Match regardless of case
+        #[arg(short, long)]
+        ignore_case: bool,
+        /// How to print the tasks found
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+
+    /// Show the overdue tasks and the tasks due today
+    Today,
+
+    /// Show the overdue tasks and the tasks due in the coming seven days,
+    /// grouped by day
+    Week,
+
+    /// Show a month calendar with the number of open tasks due each day
+    ///
+    /// Days with overdue tasks are marked with `!`
+    Cal {
+        /// The month to show, as YYYY-MM, defaults to this month
+        #[arg(long, value_parser = parse_month)]
+        month: Option<NaiveDate>,
+    },
+
+    /// Print a task
+    #[command(visible_alias = "get")]
+    Show {
+        /// Task Id, or words of its head
+        #[arg(
+            value_parser = parse_task_ref,
+            add = ArgValueCandidates::new(task_id_candidates),
+            required_unless_present = "pick"
+        )]
+        task: Option<TaskRef>,
+        /// Pick the task with the fuzzy finder instead
+        #[arg(long, conflicts_with = "task")]
+        pick: bool,
+        /// Only print this section of the body
+        #[arg(long)]
+        section: Option<String>,
+        /// How to print the task
+        #[arg(long, value_enum, default_value_t, conflicts_with = "section")]
+        format: OutputFormat,
+        /// Print the body as written, without rendering its Markdown
+        #[arg(long)]
+        raw: bool,
+    },
+
+    /// Attach a file or URL to a task, listed by `show`
+    Attach {
+        /// Task Id
+        #[arg(add = ArgValueCandidates::new(task_id_candidates))]
+        id: u64,
+        /// The path of a file, or a URL
+        #[arg(required_unless_present = "remove")]
+        target: Option<String>,
+        /// Copy the file into `attachments/` next to the store rather
+        /// than refer to it where it is
+        #[arg(long)]
+        copy: bool,
+        /// Remove the attachment with this number, as listed by `show`,
+        /// instead. Copied files are left in place
+        #[arg(long, conflicts_with_all = ["target", "copy"])]
+        remove: Option<usize>,
+    },
+
+    /// Open the URLs of a task, or one of its attachments, with the
+    /// program the desktop uses for them
+    ///
+    /// The URLs are the task's link, the URLs attached to it and those
+    /// in its body. A task without URLs opens its only attachment
+    Open {
+        /// Task Id
+        #[arg(add = ArgValueCandidates::new(task_id_candidates))]
+        id: u64,
+        /// The number of the attachment to open, as listed by `show`
+        #[arg(long)]
+        attachment: Option<usize>,
+    },
+
+    /// Add a timestamped note to a task, listed by `show`
+    Note {
         /// Task Id
+        #[arg(add = ArgValueCandidates::new(task_id_candidates))]
         id: u64,
+        /// The text of the note
+        text: String,
+        /// Append the text as a line of this section of the body instead,
+        /// created if missing
+        #[arg(long)]
+        section: Option<String>,
     },
 
     /// Update a task
+    ///
+    /// Opens the editor, unless the new contents are given as flags
     Edit {
+        /// Task Id, or words of its head
+        #[arg(value_parser = parse_task_ref, add = ArgValueCandidates::new(task_id_candidates))]
+        task: TaskRef,
+        #[command(flatten)]
+        fields: EditFields,
+    },
+
+    /// Add a line to the end of a task's body
+    Append {
         /// Task Id
+        #[arg(add = ArgValueCandidates::new(task_id_candidates))]
         id: u64,
+        /// The line to add
+        text: String,
     },
 
-    /// Delete task(s) by their id
+    /// Add a line to the start of a task's body
+    Prepend {
+        /// Task Id
+        #[arg(add = ArgValueCandidates::new(task_id_candidates))]
+        id: u64,
+        /// The line to add
+        text: String,
+    },
+
+    /// Set fields of a task's own, e.g. `client=acme`
+    ///
+    /// `show` prints them, and filters match them as written, e.g.
+    /// `list --where client=acme`. A field given as `key=` is removed
+    Set {
+        /// Task Id, or words of its head
+        #[arg(value_parser = parse_task_ref, add = ArgValueCandidates::new(task_id_candidates))]
+        task: TaskRef,
+        /// The fields, as `key=value`
+        #[arg(required = true, value_parser = parse_field)]
+        fields: Vec<(String, String)>,
+    },
+
+    /// Mark task(s) as done
     Done {
-        /// Task id(s) to delete.
-        indices: Vec<u64>,
+        /// Task id(s) to mark as done, also as ranges: e.g. `2-6,9`, or
+        /// words of the head of one
+        #[arg(value_parser = parse_selection, add = ArgValueCandidates::new(task_id_candidates))]
+        indices: Vec<Selection>,
+        /// Also pick a task with the fuzzy finder
+        #[arg(long)]
+        pick: bool,
+        /// Mark the tasks as not done again
+        #[arg(long)]
+        undo: bool,
+        /// Mark tasks as done even if tasks blocking them are open
+        #[arg(long)]
+        force: bool,
     },
-}
 
-/// An alias for the task id's type
-type Id = u64;
-/// An Alias for an index in the `Storage` store array of tasks
-type Slot = usize;
+    /// Change fields of many tasks at once
+    ///
+    /// Tasks are selected by id, with a filter, or both, e.g.
+    /// `todo modify --where tag:work --priority high`
+    #[command(group(ArgGroup::new("select").required(true).multiple(true).args(["ids", "filter"])))]
+    Modify {
+        /// Task Ids, also as ranges: e.g. `2-6,9`
+        #[arg(value_parser = parse_ids, add = ArgValueCandidates::new(task_id_candidates))]
+        ids: Vec<IdSet>,
+        /// Only tasks matching these filter terms, as for `with`
+        #[arg(long = "where", value_parser = parse_filter)]
+        filter: Option<Filter>,
+        #[command(flatten)]
+        fields: ModifyFields,
+    },
 
-/// A task with an id, head, and body.
-#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
-struct Task {
-    /// A unique identifier for the task
-    id: Id,
-    /// The head of the task.
-    head: String,
-    /// The body of the task.
-    body: String,
-}
+    /// Merge a duplicate into a task and delete the duplicate
+    ///
+    /// Bodies are joined, tags combined and the earliest due date kept.
+    /// Without `--confirm` the merged task is only previewed.
+    Merge {
+        /// The task to keep
+        #[arg(add = ArgValueCandidates::new(task_id_candidates))]
+        id: u64,
+        /// The duplicate to merge into it
+        #[arg(add = ArgValueCandidates::new(task_id_candidates))]
+        duplicate: u64,
+        /// Actually merge the tasks, as does `--yes`
+        #[arg(long)]
+        confirm: bool,
+    },
 
-#[derive(Debug, Deserialize, Serialize)]
-struct Storage {
-    /// An array of `Task` elements
-    store: Vec<Task>,
-    /// A mapping of the task id `Id` to the index slot in the
-    /// tasks array
-    id_to_slot: BTreeMap<Id, Slot>,
-    /// The In-Memory storage has unsynched changes to the disk
-    is_dirty: bool,
-}
+    /// Move a task to another place in the list
+    #[command(group(ArgGroup::new("place").required(true).args(["before", "top", "bottom"])))]
+    Move {
+        /// Task Id
+        #[arg(add = ArgValueCandidates::new(task_id_candidates))]
+        id: u64,
+        /// Put it right before this task
+        #[arg(long, add = ArgValueCandidates::new(task_id_candidates))]
+        before: Option<u64>,
+        /// Put it first
+        #[arg(long)]
+        top: bool,
+        /// Put it last
+        #[arg(long)]
+        bottom: bool,
+    },
 
-impl Default for Storage {
-    fn default() -> Self {
-        Self {
-            store: vec![Task::default(); INITIAL_TASKS_ARRAY_LENGTH],
-            id_to_slot: BTreeMap::new(),
-            is_dirty: false,
-        }
-    }
-}
+    /// Make a task wait for other tasks to be done
+    Block {
+        /// Task Id
+        #[arg(add = ArgValueCandidates::new(task_id_candidates))]
+        id: u64,
+        /// The task(s) it waits for
+        #[arg(long, required = true, add = ArgValueCandidates::new(task_id_candidates))]
+        on: Vec<u64>,
+    },
 
-/// Returns the path to the storage file.
-/// If the storage file does not exist, it creates it.
-fn get_storage() -> Result<PathBuf, String> {
-    let home = home::home_dir().unwrap_or(PathBuf::from("."));
-    // Supports development mode;
-    let t_path = match std::env::var("ENVIRONMENT") {
-        Ok(p) => match p.to_uppercase().as_str() {
-            "DEVELOPMENT" => ".dev_tasks.bin",
-            _ => ".tasks.bin",
-        },
-        _ => ".tasks.bin",
-    };
-    let storage = home.join(t_path);
+    /// Stop a task waiting for other tasks
+    Unblock {
+        /// Task Id
+        #[arg(add = ArgValueCandidates::new(task_id_candidates))]
+        id: u64,
+        /// The task(s) it no longer waits for
+        #[arg(long, required = true, add = ArgValueCandidates::new(task_id_candidates))]
+        on: Vec<u64>,
+    },
 
-    if !storage.exists() {
-        File::create(&storage).map_err(|err| format!("Failed to create tasks.bin file: {err}"))?;
-    }
-    Ok(storage)
-}
+    /// Delete task(s) by their id
+    Rm {
+        /// Task id(s) to delete, also as ranges: e.g. `2-6,9`, or words
+        /// of the head of one
+        #[arg(value_parser = parse_selection, add = ArgValueCandidates::new(task_id_candidates))]
+        indices: Vec<Selection>,
+        /// Also pick a task with the fuzzy finder
+        #[arg(long)]
+        pick: bool,
+    },
 
-fn get_backup_path(storage_path: &Path) -> Result<PathBuf, String> {
-    let mut backup_path = storage_path.to_path_buf().clone();
-    backup_path.set_extension("bin.bak");
-    if !backup_path.exists() {
-        File::create(&backup_path).map_err(|err| format!("Failed to create backup file: {err}"))?;
-    }
-    Ok(backup_path)
-}
+    /// Pick a task with the fuzzy finder and print its id
+    ///
+    /// e.g. `todo get $(todo pick)`
+    Pick,
 
-/// Copies the file contents from the original storage path
-/// to a backup location.
-fn backup_data(storage_path: &PathBuf) {
-    let backup_file = get_backup_path(storage_path)
-        .map_err(|_err| eprintln!("Err Saving backup!"))
-        .unwrap();
+    /// Create tasks from a file, or from another service
+    ///
+    /// e.g. `task export | todo import --format taskwarrior`
+    #[command(args_conflicts_with_subcommands = true)]
+    Import {
+        /// Where to import from instead of a file
+        #[command(subcommand)]
+        source: Option<ImportSource>,
+        /// The format of the file, by default CSV for `.csv` files, Org
+        /// for `.org` files, email for `.eml` files and lines otherwise
+        #[arg(long, value_enum)]
+        format: Option<ImportFormat>,
+        /// The file to read, `-` for stdin
+        #[arg(value_name = "FILE", default_value = "-")]
+        file: PathBuf,
+        /// A file with one task head per line, the same as
+        /// `--format lines FILE`
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["format", "file"])]
+        lines: Option<PathBuf>,
+        /// A maildir, e.g. `~/Mail/todo`, each message in it becoming a
+        /// task as with `--format eml`. Messages imported before are
+        /// skipped, so emails forwarded to it can be imported again and
+        /// again
+        #[arg(
+            long,
+            value_name = "DIR",
+            conflicts_with_all = ["format", "file", "lines"]
+        )]
+        maildir: Option<PathBuf>,
+        /// Put the tasks, trash and sync state imported in place of those
+        /// of the store, for `--format json`
+        #[arg(long, conflicts_with = "merge")]
+        replace: bool,
+        /// Merge the tasks imported into the store as `sync` does, for
+        /// `--format json`: a task in both keeps the copy changed last
+        #[arg(long)]
+        merge: bool,
+        /// Which column of a CSV file fills which field, as
+        /// `field=Column`, e.g. `due=Deadline`; may be repeated. Fields
+        /// not mapped come from the column of their name. Names other
+        /// than head, body, due, tags, contexts, project, priority, done,
+        /// link and estimate set fields of your own
+        #[arg(long, value_parser = parse_field)]
+        map: Vec<(String, String)>,
+    },
 
-    match fs::copy(storage_path, &backup_file) {
-        Err(err) => eprintln!(
-            "Failed to save data to backup file: {}, {}",
-            backup_file.display(),
-            err
-        ),
-        Ok(_) => {
-            println!("Data saved to a backup file: {}", backup_file.display());
-        }
-    }
-}
+    /// Write the tasks in a format other programs read
+    Export {
+        /// The format to write
+        #[arg(long, value_enum)]
+        format: ExportFormat,
+        /// The file to write, defaults to stdout
+        #[arg(long, short, value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// With `--format json`, also write the trash and sync state, for
+        /// a backup `import --format json` restores in full
+        #[arg(long)]
+        all: bool,
+    },
 
-/// Loads tasks from the storage file.
-/// If the storage file is empty or the storage file is corrupted,
-/// it returns `Storage::default()`.
-fn load_from_storage(storage_path: &PathBuf) -> Storage {
-    match fs::read(storage_path) {
-        Ok(data) if data.is_empty() => Storage::default(),
-        Ok(data) => {
-            let data = decompress(&data)
-                .map_err(|_| backup_data(storage_path))
-                .unwrap_or_default();
-            bincode2::deserialize(&data)
-                .map_err(|_| backup_data(storage_path))
-                .unwrap_or_default()
-        }
-        Err(err) => {
-            eprintln!("ERROR: {err}");
-            // save the old data to a backup file
-            backup_data(storage_path);
+    /// Create tasks from the TODO and FIXME comments in source code
+    ///
+    /// Each comment is found again on later scans, wherever its line, so
+    /// its task is updated rather than added twice. Tasks of comments
+    /// since removed are marked done.
+    Scan {
+        /// The directory or file to scan
+        #[arg(default_value = ".")]
+        path: PathBuf,
+    },
 
-            Storage::default()
-        }
-    }
-}
+    /// Sync the tasks both ways with a server set up in the config
+    ///
+    /// A task changed on both sides since the last sync is merged field
+    /// by field; a field changed on both sides keeps the value changed
+    /// last
+    Sync {
+        /// What to sync with
+        #[command(subcommand)]
+        target: SyncTarget,
+    },
 
-/// Decompress the data from storage before deserialization
-fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
-    let mut decoder = ZlibDecoder::new(Vec::new());
-    decoder.write_all(data)?;
-    decoder.finish()
-}
+    /// List the deleted tasks, or restore one of them
+    ///
+    /// Deleted tasks are kept for `trash_retention` from the config
+    Trash {
+        /// The number of the deleted task to restore, as listed
+        #[arg(long)]
+        restore: Option<usize>,
+    },
 
-/// Saves tasks to the storage file.
-fn save_to_storage(storage_path: &PathBuf, data: &Storage) -> io::Result<()> {
-    let encoded = bincode2::serialize(&data).map_err(|err| {
-        io::Error::new(
-            io::ErrorKind::InvalidData,
-            format!("failed to serialise tasks: {err}"),
-        )
-    })?;
+    /// Drop the deleted tasks for good, without waiting for them to
+    /// expire
+    Purge,
 
-    let data = compress_data(&encoded)?;
-    fs::write(storage_path, data)
-}
+    /// Delete the tasks that are done, or all tasks, after asking for
+    /// confirmation and saving a snapshot of the store
+    #[command(group(ArgGroup::new("which").required(true).args(["completed", "all"])))]
+    Clear {
+        /// Delete the tasks that are done
+        #[arg(long)]
+        completed: bool,
+        /// Delete every task
+        #[arg(long)]
+        all: bool,
+    },
 
-/// Compress the data before saving to the storage file
-fn compress_data(data: &[u8]) -> io::Result<Vec<u8>> {
-    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-    encoder.write_all(data)?;
-    encoder.finish()
-}
+    /// Clear all tasks, after saving a snapshot of the store
+    Reset {
+        /// Actually clear the store, as does `--yes`
+        #[arg(long)]
+        confirm: bool,
+    },
 
-// Get the next available slot in the tasks array to insert a new entry
-fn get_next_slot(data: &mut Storage) -> usize {
-    let taken_slots = data.id_to_slot.values().cloned().collect::<Vec<usize>>();
-    let l = data.store.len();
-    let t = taken_slots.len();
-    if t == l {
-        let new_len = l * 2;
-        data.store.resize(new_len, Task::default());
-    }
+    /// Populate a throwaway store with sample tasks
+    Demo,
 
-    (0..data.store.len())
-        .find(|i| !taken_slots.contains(i))
-        .unwrap() // For Now I don't think would ever miss a slot
-}
+    /// List the tags in use, with the number of open tasks carrying each
+    Tags,
 
-/// Adds a new task with a head and body.
-fn add_one(head: Option<String>, body: Option<String>, data: &mut Storage) {
-    let new_id = (data.id_to_slot.len() + 1) as u64;
-    let head = head.unwrap_or_default().trim().to_string();
-    let body = body.unwrap_or_default().trim().to_string();
-    if head.is_empty() && body.is_empty() {
-        return;
-    }
+    /// Manage the tags of all tasks at once
+    Tag {
+        #[command(subcommand)]
+        action: TagAction,
+    },
 
-    let new_task = Task {
-        id: new_id,
-        head,
-        body,
-    };
-    let slot = get_next_slot(data);
-    data.store[slot] = new_task;
-    data.id_to_slot.insert(new_id, slot);
-    data.is_dirty = true;
-    println!("Task {new_id} added!");
-}
+    /// Manage the templates `new --template` starts from
+    Template {
+        #[command(subcommand)]
+        action: TemplateAction,
+    },
 
-/// Adds a new task by opening the default editor.
-fn add_new(data: &mut Storage) -> Result<(), io::Error> {
-    let file = tempfile::NamedTempFile::new().unwrap();
-    let temp_path = file.path().to_path_buf();
+    /// List the backups of the store, or restore one of them
+    RestoreBackup {
+        /// The number of the backup to restore, as listed
+        index: Option<usize>,
+    },
 
-    // I think everyone has at least nano
-    let editor = std::env::var("EDITOR").unwrap_or("nano".to_string());
+    /// Check the storage file for damage, and repair it
+    ///
+    /// The file is read as written, so problems are reported rather than
+    /// leaving an empty store. Repairing keeps a snapshot of the file
+    Doctor {
+        /// Repair the problems found
+        #[arg(long)]
+        fix: bool,
+        /// Check this file instead of the store, e.g. a backup
+        file: Option<PathBuf>,
+    },
 
-    let status = process::Command::new(&editor).arg(&temp_path).status()?;
+    /// Add the tasks that can still be read from a damaged storage file
+    ///
+    /// Works on backups too. Tasks already in the store are skipped
+    Recover {
+        /// The damaged file or backup
+        file: PathBuf,
+    },
 
-    if !status.success() {
-        return Err(io::Error::other(format!(
-            "{editor} exited with non zero status"
-        )));
-    }
+    /// Put a task out of sight for a while, pushing its due date forward
+    ///
+    /// The task is left out of `list` until then, `list --all` shows it
+    Snooze {
+        /// Task Id
+        #[arg(add = ArgValueCandidates::new(task_id_candidates))]
+        id: u64,
+        /// For how long: e.g. `2h`, `3d`, `1w`
+        #[arg(value_parser = parse_duration)]
+        duration: TimeDelta,
+    },
 
-    let content = fs::read_to_string(&temp_path)?;
-    let lines: Vec<&str> = content.lines().collect();
+    /// Go over the open tasks left unchanged for a while, one by one, as
+    /// in a weekly review
+    ///
+    /// Each task can be marked done, snoozed, given another priority,
+    /// deleted, or kept as it is, which counts as a change so it waits
+    /// for the next review. Tasks least recently changed come first
+    Review {
+        /// How long a task has gone unchanged: e.g. `7d`, `1w`
+        #[arg(long, default_value = DEFAULT_REVIEW_OLDER_THAN, value_parser = parse_duration)]
+        older_than: TimeDelta,
+    },
 
-    if lines.is_empty() {
-        println!("New Task aborted!");
-        return Ok(());
-    }
+    /// Start a timer on a task, to track the time worked on it
+    Start {
+        /// Task Id, or words of its head
+        #[arg(value_parser = parse_task_ref, add = ArgValueCandidates::new(task_id_candidates))]
+        task: TaskRef,
+    },
 
-    let head = lines[0].to_string();
+    /// Stop the running timer
+    Stop,
 
-    let body = if lines.len() > 1 {
-        lines[1..].join("\n")
-    } else {
-        String::new()
-    };
+    /// Work on a task for a pomodoro: count down, then log the time
+    /// worked on the task and notify
+    Pomo {
+        /// Task Id
+        #[arg(add = ArgValueCandidates::new(task_id_candidates))]
+        id: u64,
+        /// The length of the pomodoro: e.g. `25m`, `1h`
+        #[arg(long, default_value = DEFAULT_POMODORO_LENGTH, value_parser = parse_duration)]
+        length: TimeDelta,
+    },
 
-    add_one(Some(head), Some(body), data);
-    Ok(())
-}
+    /// Show the estimated work left, per day of the coming week and per
+    /// tag
+    ///
+    /// Days with more than `daily_capacity` from the config are marked
+    Workload,
 
-/// Get a task by it's Id
-fn get_task(id: u64, data: &Storage) {
-    let slot = data.id_to_slot.get(&id);
-    match slot {
-        Some(s) => {
-            let task = &data.store[*s];
-            println!("ID: {}\nHEAD: {}\nBODY: {}", task.id, task.head, task.body);
-        }
-        None => eprintln!("Task not Found!"),
-    }
-}
+    /// Show the time worked on each task
+    Timesheet {
+        /// Only count this week, and show the time worked per day
+        #[arg(long)]
+        week: bool,
+    },
 
-/// Lists all tasks.
-fn list_all(data: &Storage) {
-    let slots = data.id_to_slot.values().cloned().collect::<Vec<Slot>>();
-    if slots.is_empty() {
-        println!("No Tasks!");
-    }
+    /// Set reminders, or list the upcoming ones
+    ///
+    /// Reminders are given by `todo remind --daemon`, also ahead of due
+    /// dates as set by `reminder_leads` in the config
+    Remind {
+        /// Task Id
+        #[arg(add = ArgValueCandidates::new(task_id_candidates))]
+        id: Option<u64>,
+        /// Remind of the task at e.g. `17:30`, `tomorrow 9am` or `in 2 hours`
+        #[arg(long, requires = "id", value_parser = parse_moment)]
+        at: Option<DateTime<Local>>,
+        /// Remove the reminders of the task
+        #[arg(long, requires = "id", conflicts_with = "at")]
+        clear: bool,
+        /// Keep running and give reminders as they fall due
+        #[arg(long, conflicts_with = "id")]
+        daemon: bool,
+        /// Seconds between checks for reminders, with `--daemon`
+        #[arg(long, default_value_t = DEFAULT_REMIND_INTERVAL, requires = "daemon")]
+        interval: u64,
+    },
 
-    slots
-        .iter()
-        .map(|slot| &data.store[*slot])
-        .for_each(|task| {
-            if task.body.is_empty() {
-                println!("{}. {}", task.id, task.head);
-            } else {
-                println!("{}. HEAD: {}", task.id, task.head);
-            }
-        })
+    /// Show desktop notifications for overdue tasks and tasks due soon
+    ///
+    /// Meant to be run by cron, e.g. `0 9 * * * todo notify`
+    Notify {
+        /// How far ahead a task counts as due soon: e.g. `12h`, `1d`
+        #[arg(long, default_value = DEFAULT_NOTIFY_WITHIN, value_parser = parse_duration)]
+        within: TimeDelta,
+    },
+
+    /// Show statistics about the tasks
+    Stats,
+
+    /// Report what was done over a period: the tasks completed each day,
+    /// how long they took from creation to completion, and by tag
+    Report {
+        /// How far back to look: e.g. `7d`, `4w`
+        #[arg(long, default_value = DEFAULT_REPORT_SINCE, value_parser = parse_duration)]
+        since: TimeDelta,
+        /// How to print the report, JSON e.g. for dashboards
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+
+    /// Start an interactive shell that keeps the store loaded
+    Shell,
+
+    /// Serve the tasks over a JSON HTTP API, until stopped
+    ///
+    /// Clients must send the `token` under [server] in the config, or
+    /// else the one printed at start. The CLI can be used meanwhile
+    Serve {
+        /// The port to listen on
+        #[arg(long, default_value_t = DEFAULT_SERVE_PORT)]
+        port: u16,
+        /// The address to listen on, e.g. `0.0.0.0` for every network
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: String,
+    },
+
+    /// Serve the tasks on a Unix socket for editor plugins, until
+    /// stopped
+    ///
+    /// Requests and responses are JSON, one per line; see the readme
+    #[cfg(unix)]
+    Daemon {
+        /// The socket to listen on, defaults to `todo.sock` in
+        /// $XDG_RUNTIME_DIR or TODO_SOCKET if set
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+
+    /// Print the man page
+    ///
+    /// With `--out-dir`, pages for every subcommand are written there
+    Man {
+        /// Directory to write `todo.1` and the subcommand pages to
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+    },
+
+    /// Print a shell completion script
+    ///
+    /// e.g. `source <(todo completions bash)`
+    Completions {
+        /// The shell to complete for
+        shell: Shell,
+        /// Print a static script, without completion of task ids
+        #[arg(long = "static")]
+        static_script: bool,
+    },
+
+    /// Run a single command with a temporary filter applied
+    ///
+    /// e.g. `todo with "tag:work due:today" -- list`
+    With {
+        /// Filter terms: `tag:<name>`, `@<context>`, `project:<name|none>`,
+        /// `due:<date|overdue|none>`, `due:within:<duration>`, `priority:<low|medium|high|none>` or
+        /// words to look for in the head and body
+        #[arg(value_parser = parse_filter)]
+        filter: Filter,
+        /// The command to run
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+
+    /// Any other command `foo` runs `todo-foo` from the PATH
+    #[command(external_subcommand)]
+    External(Vec<String>),
 }
 
-/// Deletes todos by their indices.
-/// If a task is deleted, it re-indexes the mapping of task id
-/// to slots in the tasks array.
-fn delete_todos(indices: &[u64], data: &mut Storage) {
-    indices
-        .iter()
-        .filter(|id| data.id_to_slot.contains_key(id))
-        .cloned()
-        .collect::<Vec<Id>>()
-        .into_iter()
-        .for_each(|id| {
-            let slot = *data.id_to_slot.get(&id).unwrap();
-            let _ = std::mem::take(&mut data.store[slot]);
-            let _ = data.id_to_slot.remove(&id);
-            println!("Task {id} Deleted!");
-            data.is_dirty = true;
-        });
+/// Which tasks `list` and `watch` show, and how.
+#[derive(Args)]
+struct ListOptions {
+    /// Only list the tasks that can be done in these contexts, e.g.
+    /// `@home`
+    #[arg(value_parser = parse_context)]
+    contexts: Vec<String>,
+    /// Print the body and details of each task too
+    #[arg(long)]
+    long: bool,
+    /// Print the tasks in aligned columns: id, head, tags, due date,
+    /// priority and the start of the body
+    #[arg(long, conflicts_with = "long")]
+    table: bool,
+    /// Include tasks that are done
+    #[arg(long)]
+    all: bool,
+    /// The order of the tasks, subtasks stay under their parent
+    #[arg(long, value_enum, default_value_t)]
+    sort: SortKey,
+    /// Group the tasks under the name of their project
+    #[arg(long)]
+    by_project: bool,
+    /// Only list the overdue tasks
+    #[arg(long)]
+    overdue: bool,
+    /// Only list the tasks due within this long, e.g. `3d`, or overdue
+    #[arg(long, value_parser = parse_duration, conflicts_with = "overdue")]
+    due_within: Option<TimeDelta>,
+    /// Only list the tasks matching these filter terms, as for `with`,
+    /// e.g. `client=acme`
+    #[arg(long = "where", value_parser = parse_filter)]
+    matching: Option<Filter>,
+}
 
-    if !data.is_dirty {
-        return;
+impl ListOptions {
+    /// Narrows `filter` down to the contexts, due dates and terms asked
+    /// for.
+    fn filter(&self, filter: &Filter) -> Filter {
+        let mut filter = self
+            .contexts
+            .iter()
+            .cloned()
+            .fold(filter.clone(), Filter::context);
+        if let Some(matching) = &self.matching {
+            filter = filter.and(matching);
+        }
+        if self.overdue {
+            filter = filter.overdue();
+        }
+        if let Some(within) = self.due_within {
+            filter = filter.due_within(within);
+        }
+        filter
     }
 
-    // Reindex the map to fill in the gaps from deleted tasks.
-    let old_map = data.id_to_slot.clone();
-    data.id_to_slot.clear();
-
-    for (i, (_old_id, slot)) in old_map.iter().enumerate() {
-        let new_id = i as u64 + 1;
-        data.id_to_slot.insert(new_id, *slot);
-        if let Some(elem) = data.store.get_mut(*slot) {
-            elem.id = new_id;
+    /// How the tasks are written.
+    fn layout(&self) -> Layout {
+        if self.long {
+            Layout::Full
+        } else if self.table {
+            Layout::Table
         } else {
-            unreachable!("A bug in the slot allocation implementation!");
+            Layout::Lines
         }
     }
 }
 
-/// Gets a task by its index and opens it in the default editor.
-/// If the task is modified, it updates the task.
-/// If the task is empty, it deletes the task.
-fn edit_task(index: u64, data: &mut Storage) -> Result<(), io::Error> {
-    if !data.id_to_slot.contains_key(&index) {
-        return Err(io::Error::other(format!("Task with id {index} not found")));
-    }
-
-    let slot = data.id_to_slot.get(&index).unwrap();
-    let current_task = data.store.get(*slot).unwrap();
-
-    let mut temp_file = tempfile::NamedTempFile::new()?;
-    writeln!(temp_file, "{}", current_task.head)?;
-    write!(temp_file, "{}", current_task.body)?;
-    temp_file.flush()?;
+/// How `list` writes the tasks.
+#[derive(Clone, Copy)]
+enum Layout {
+    /// A line per task, subtasks indented under their parent
+    Lines,
+    /// Aligned columns, subtasks indented under their parent
+    Table,
+    /// Every detail of each task, as `show` prints it
+    Full,
+}
 
-    let temp_path = temp_file.path().to_path_buf();
+/// The changes `edit` makes without opening an editor.
+#[derive(Args, Default)]
+struct EditFields {
+    /// Replace the head
+    #[arg(long)]
+    head: Option<String>,
+    /// Replace the body
+    #[arg(long, conflicts_with = "append_body")]
+    body: Option<String>,
+    /// Add a line to the end of the body
+    #[arg(long)]
+    append_body: Option<String>,
+    /// Set how long the task is expected to take: e.g. `30m`, `2h`
+    #[arg(long, value_parser = parse_duration)]
+    estimate: Option<TimeDelta>,
+    /// Set how important the task is
+    #[arg(long)]
+    priority: Option<Priority>,
+}
 
-    let editor = match std::env::var("EDITOR") {
-        Ok(e) => e,
-        Err(_) => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "Env variable EDITOR not specified",
-            ));
-        }
-    };
+impl EditFields {
+    /// Whether no changes were given.
+    fn is_empty(&self) -> bool {
+        self.head.is_none()
+            && self.body.is_none()
+            && self.append_body.is_none()
+            && self.estimate.is_none()
+            && self.priority.is_none()
+    }
+}
 
-    let status = process::Command::new(&editor).arg(&temp_path).status()?;
+/// The changes `modify` makes to every selected task.
+#[derive(Args)]
+struct ModifyFields {
+    /// Set the due date: YYYY-MM-DD, or e.g. `tomorrow`, `friday`, `in 2 weeks`
+    #[arg(long, value_parser = parse_date, conflicts_with = "no_due")]
+    due: Option<NaiveDate>,
+    /// Remove the due date
+    #[arg(long)]
+    no_due: bool,
+    /// Set the priority
+    #[arg(long, conflicts_with = "no_priority")]
+    priority: Option<Priority>,
+    /// Remove the priority
+    #[arg(long)]
+    no_priority: bool,
+    /// Add a tag, may be repeated
+    #[arg(long)]
+    add_tag: Vec<String>,
+    /// Remove a tag, may be repeated
+    #[arg(long)]
+    remove_tag: Vec<String>,
+    /// Add a context, e.g. `home`, may be repeated
+    #[arg(long, value_parser = parse_context_name)]
+    add_context: Vec<String>,
+    /// Remove a context, may be repeated
+    #[arg(long, value_parser = parse_context_name)]
+    remove_context: Vec<String>,
+    /// Move the tasks to a project
+    #[arg(long, value_parser = parse_project_name, conflicts_with = "no_project")]
+    project: Option<String>,
+    /// Take the tasks out of their project
+    #[arg(long)]
+    no_project: bool,
+    /// Set how long each task is expected to take: e.g. `30m`, `2h`
+    #[arg(long, value_parser = parse_duration)]
+    estimate: Option<TimeDelta>,
+}
 
-    if !status.success() {
-        return Err(io::Error::other(format!(
-            "{editor} exited with non zero status"
-        )));
+impl ModifyFields {
+    /// Whether no changes were given.
+    fn is_empty(&self) -> bool {
+        self.due.is_none()
+            && !self.no_due
+            && self.priority.is_none()
+            && !self.no_priority
+            && self.add_tag.is_empty()
+            && self.remove_tag.is_empty()
+            && self.add_context.is_empty()
+            && self.remove_context.is_empty()
+            && self.project.is_none()
+            && !self.no_project
+            && self.estimate.is_none()
     }
 
-    let content = fs::read_to_string(&temp_path)?;
-    let lines: Vec<&str> = content.lines().collect();
-
-    if lines.is_empty() {
-        delete_todos(&[index], data);
-        return Ok(());
+    /// Applies the changes to a task.
+    fn apply(&self, task: &mut Task) {
+        if let Some(due) = self.due {
+            task.due = Some(due);
+        }
+        if self.no_due {
+            task.due = None;
+        }
+        if let Some(priority) = self.priority {
+            task.priority = Some(priority);
+        }
+        if self.no_priority {
+            task.priority = None;
+        }
+        for tag in &self.add_tag {
+            if !task.tags.contains(tag) {
+                task.tags.push(tag.clone());
+            }
+        }
+        task.tags.retain(|tag| !self.remove_tag.contains(tag));
+        for context in &self.add_context {
+            if !task.contexts.contains(context) {
+                task.contexts.push(context.clone());
+            }
+        }
+        task.contexts
+            .retain(|context| !self.remove_context.contains(context));
+        if let Some(project) = &self.project {
+            task.project = Some(project.clone());
+        }
+        if self.no_project {
+            task.project = None;
+        }
+        if let Some(estimate) = self.estimate {
+            task.estimate = Some(estimate);
+        }
     }
+}
 
-    let new_head = lines[0].to_string();
+/// How `show`, `search` and `report` print.
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum OutputFormat {
+    /// Labelled lines for reading
+    #[default]
+    Text,
+    /// JSON, for scripts
+    Json,
+}
 
-    let new_body = if lines.len() > 1 {
-        lines[1..].join("\n")
-    } else {
-        String::new()
-    };
+/// The services `import` can read from.
+#[derive(Subcommand)]
+enum ImportSource {
+    /// Open issues of a GitHub repository assigned to someone
+    ///
+    /// Private repositories and `--assignee me` need a token, under
+    /// `[github]` in the config or in `GITHUB_TOKEN`
+    Github {
+        /// The repository, e.g. `juanmilkah/todo`
+        #[arg(long, value_name = "OWNER/NAME")]
+        repo: String,
+        /// The login of the assignee, `me` for the owner of the token
+        #[arg(long, default_value = "me")]
+        assignee: String,
+        /// Refresh the tasks imported before from the issues, and mark
+        /// them done once their issue is closed
+        #[arg(long)]
+        update: bool,
+    },
+}
 
-    let updated_task = Task {
-        id: index,
-        head: new_head,
-        body: new_body,
-    };
+/// The formats `import` can read.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ImportFormat {
+    /// One task head per line
+    Lines,
+    /// The JSON written by Taskwarrior's `task export`
+    Taskwarrior,
+    /// Org-mode headlines, nested headlines become subtasks
+    Org,
+    /// Comma-separated values with a header row, e.g. exported from
+    /// Trello, Asana or a spreadsheet, see `--map`
+    Csv,
+    /// A store written by `export --format json`, or a storage file of any
+    /// format. Needs `--replace` or `--merge`
+    Json,
+    /// An email message, its subject becoming the head and its plain text
+    /// the body
+    Eml,
+}
 
-    if *current_task != updated_task {
-        println!("Task {} updated!", &updated_task.id);
-        data.store[*slot] = updated_task;
-        data.is_dirty = true;
-    } else {
-        println!("Task {} not updated!", &updated_task.id);
-    }
+/// The formats `export` can write.
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    /// iCalendar to-dos, for calendar apps. Only tasks with a due date
+    /// are written
+    Ics,
+    /// Org-mode headlines, with subtasks nested under their parents
+    Org,
+    /// Every task with all of its details, history included, as the
+    /// JSON format stores them
+    Json,
+    /// A web page styled for reading, with the open tasks by project and
+    /// due date, then those completed, e.g. to share with others
+    Html,
+}
 
-    Ok(())
+/// What `template` can do.
+#[derive(Subcommand)]
+enum TemplateAction {
+    /// Save a template, composing it in the editor
+    ///
+    /// `{{date}}`, `{{time}}` and `{{weekday}}` are filled in for each
+    /// task made from it. An existing template is opened for editing
+    Save {
+        /// The name of the template, e.g. `release`
+        name: String,
+        /// Copy the head and body of this task instead
+        #[arg(long, add = ArgValueCandidates::new(task_id_candidates))]
+        from: Option<u64>,
+    },
+    /// List the saved templates
+    List,
+    /// Print a template as saved
+    Show {
+        /// The name of the template
+        name: String,
+    },
+    /// Delete a template
+    Rm {
+        /// The name of the template
+        name: String,
+    },
 }
 
-/// The main function for the task manager.
-fn main() -> Result<(), io::Error> {
-    // Parse the cli arguments
-    let args = Cli::parse();
+/// What `tag` can do.
+#[derive(Subcommand)]
+enum TagAction {
+    /// Rename a tag on every task carrying it
+    Rename {
+        /// The tag to rename, with or without its `#`
+        #[arg(value_parser = parse_tag_name)]
+        old: String,
+        /// The new name
+        #[arg(value_parser = parse_tag_name)]
+        new: String,
+    },
+}
 
-    // Get filepath for the storage
-    // Create one if it does not exist
-    let storage_path = match get_storage() {
-        Ok(v) => v,
-        Err(err) => return Err(io::Error::other(err)),
-    };
+/// What `sync` can sync with.
+#[derive(Subcommand)]
+enum SyncTarget {
+    /// A CalDAV task list, set up under `[caldav]` in the config
+    Caldav,
+    /// The git repository holding the store, pulling from and pushing
+    /// to its remote
+    Git,
+    /// A copy of the store on another machine, over SSH
+    ///
+    /// The copy is created if there is none yet
+    Remote {
+        /// Where the copy is, e.g. `me@example.com:tasks.json`
+        #[arg(value_name = "[USER@]HOST:PATH")]
+        destination: String,
+    },
+    /// A Todoist account, set up under `[todoist]` in the config
+    #[cfg(feature = "todoist")]
+    Todoist,
+}
 
-    // Load data from the storage file
-    // If the data is corrupted, copy it to a backup file and start
-    // this session from a clean slate.
-    let mut data = load_from_storage(&storage_path);
+/// The orders `list` can show tasks in.
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum SortKey {
+    /// The order set with `move`
+    #[default]
+    Manual,
+    /// By id
+    Id,
+    /// Oldest first
+    Created,
+    /// Most recently modified first
+    Updated,
+    /// Soonest due first, tasks without a due date last
+    Due,
+    /// Most important first, tasks without a priority last
+    Priority,
+}
 
-    match args.command {
-        Commands::List => {
-            list_all(&data);
-            return Ok(());
+impl SortKey {
+    /// Sorts `tasks` by the key. Tasks with equal keys keep their order.
+    fn sort(self, tasks: &mut [&Task]) {
+        match self {
+            SortKey::Manual => tasks.sort_by_key(|task| (task.position, task.id)),
+            SortKey::Id => tasks.sort_by_key(|task| task.id),
+            SortKey::Created => tasks.sort_by_key(|task| task.created_at),
+            SortKey::Updated => tasks.sort_by_key(|task| Reverse(task.updated_at)),
+            SortKey::Due => tasks.sort_by_key(|task| (task.due.is_none(), task.due)),
+            SortKey::Priority => tasks.sort_by_key(|task| Reverse(task.priority)),
         }
+    }
+}
 
-        Commands::Edit { id } => edit_task(id, &mut data)?,
+/// The commands `Commands::accepts_filter` holds for, as named on the
+/// command line.
+const FILTERED_COMMANDS: &[&str] = &[
+    "list", "watch", "board", "next", "random", "search", "review",
+];
 
-        Commands::Get { id } => get_task(id, &data),
+/// The error for a filter given through `with` to a command that
+/// doesn't honour one.
+fn filter_not_accepted() -> TodoError {
+    let commands = FILTERED_COMMANDS
+        .iter()
+        .map(|name| format!("`{name}`"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    TodoError::Usage(format!("`with` only applies to {commands}"))
+}
 
-        Commands::New { head, body } => {
-            if head.is_none() && body.is_none() {
-                add_new(&mut data)?;
-            } else {
-                add_one(head, body, &mut data);
-            }
-        }
+impl Commands {
+    /// Whether the command honours a filter given through `with`.
+    /// Keep `FILTERED_COMMANDS` in step with it.
+    fn accepts_filter(&self) -> bool {
+        matches!(
+            self,
+            Commands::List { .. }
+                | Commands::Watch { .. }
+                | Commands::Board { .. }
+                | Commands::Next { .. }
+                | Commands::Random { .. }
+                | Commands::Search { .. }
+                | Commands::Review { .. }
+        )
+    }
 
-        Commands::Done { indices } => {
-            delete_todos(&indices, &mut data);
+    /// The tasks the command reads in full when it only needs what the
+    /// index of the store holds of the others, see `Storage::load_partial`.
+    /// `None` if it may need any task in full.
+    fn tasks_read_in_full(&self, filter: &Filter) -> Option<Vec<Id>> {
+        match self {
+            Commands::List { options } if !options.long && !options.filter(filter).reads_body() => {
+                Some(Vec::new())
+            }
+            Commands::Stats | Commands::Report { .. } | Commands::Tags => Some(Vec::new()),
+            Commands::Show {
+                task: Some(TaskRef::Id(id)),
+                pick: false,
+                ..
+            } => Some(vec![*id]),
+            _ => None,
         }
-    };
+    }
+}
 
-    // save the current state to disk
-    if data.is_dirty {
-        save_to_storage(&storage_path, &data)?;
+/// Reads a URL given to a flag.
+fn parse_url(s: &str) -> Result<String> {
+    if !attach::is_url(s) {
+        return Err(TodoError::Usage(format!(
+            "`{s}` is not a URL, expected e.g. `https://example.com`"
+        )));
     }
+    Ok(s.to_string())
+}
 
-    Ok(())
+/// Reads a context given to a flag, with or without its `@`.
+fn parse_context_name(s: &str) -> Result<String> {
+    parse_context(&format!("@{}", s.strip_prefix('@').unwrap_or(s)))
+}
+
+/// Reads a field given as `key=value`, the key in lower case.
+fn parse_field(s: &str) -> Result<(String, String)> {
+    field(s)
+        .map(|(key, value)| (key.to_lowercase(), value.to_string()))
+        .ok_or_else(|| {
+            TodoError::Usage(format!(
+                "`{s}` is not a field, expected `key=value`, e.g. `client=acme`"
+            ))
+        })
+}
+
+/// Reads a tag given as an argument, with or without its `#`.
+fn parse_tag_name(s: &str) -> Result<String> {
+    let name = s.strip_prefix('#').unwrap_or(s);
+    tag_name(&format!("#{name}"))
+        .map(str::to_string)
+        .ok_or_else(|| {
+            TodoError::Usage(format!(
+                "`{s}` is not a tag, expected a name such as `bills`"
+            ))
+        })
+}
+
+/// Reads a project given to a flag, with or without its `+`.
+fn parse_project_name(s: &str) -> Result<String> {
+    let name = s.strip_prefix('+').unwrap_or(s);
+    project_name(&format!("+{name}"))
+        .map(str::to_string)
+        .ok_or_else(|| {
+            TodoError::Usage(format!(
+                "`{s}` is not a project, expected a name such as `house` or `house.garden`"
+            ))
+        })
+}
+
+/// Adds a new task and reports its id. Details written in the head,
+/// e.g. `due:friday` or `+project`, are moved to their fields, see
+/// `tokens`.
+fn add_one(mut task: Task, data: &mut Storage) -> Result<()> {
+    take_tokens(&mut task)?;
+    if let Some(id) = data.add(task) {
+        report!("Task {id} added!");
+    }
+    Ok(())
+}
+
+/// Adds new tasks by opening the default editor, or a single task read
+/// from stdin if `from_stdin` is set.
+/// In the editor, tasks are separated by `--- next task ---` lines.
+/// In each task, the first line becomes the head and the rest the body.
+fn add_new(template: Task, from_stdin: bool, editor: &str, data: &mut Storage) -> Result<()> {
+    let tasks = if from_stdin {
+        let content = io::read_to_string(io::stdin())?;
+        split_head_body(content.trim_start()).into_iter().collect()
+    } else {
+        split_tasks(&edit_text(editor, "", NEW_TASK_HELP)?)
+    };
+    if tasks.is_empty() {
+        report!("New Task aborted!");
+        return Ok(());
+    }
+
+    for (head, body) in tasks {
+        add_one(
+            Task {
+                head,
+                body,
+                ..template.clone()
+            },
+            data,
+        )?;
+    }
+    Ok(())
+}
+
+/// Asks for the fields of a new task one by one, those of `template`
+/// being the defaults, until each answer is valid. Returns `None` if the
+/// input ends first.
+fn ask_task(template: Task) -> Result<Option<Task>> {
+    let Some(head) = ask_until("Head", None, |head| {
+        if head.is_empty() {
+            Err(TodoError::Usage("the head cannot be empty".to_string()))
+        } else {
+            Ok(head.to_string())
+        }
+    })?
+    else {
+        return Ok(None);
+    };
+    let Some(body) = ask_until("Body", None, |body| Ok(body.to_string()))? else {
+        return Ok(None);
+    };
+    let due = template.due.map(|due| due.format("%Y-%m-%d").to_string());
+    let Some(due) = ask_until("Due date, `-` for none", due, |due| match due {
+        "" | "-" => Ok(None),
+        due => parse_date(due).map(Some),
+    })?
+    else {
+        return Ok(None);
+    };
+    let priority = template.priority.map(|priority| priority.to_string());
+    let Some(priority) =
+        ask_until(
+            "Priority: low, medium or high",
+            priority,
+            |priority| match priority {
+                "" | "-" => Ok(None),
+                priority => parse_priority(priority).map(Some),
+            },
+        )?
+    else {
+        return Ok(None);
+    };
+    let tags = Some(template.tags.join(" "));
+    let Some(tags) = ask_until("Tags, separated by spaces", tags, |tags| {
+        let mut names = Vec::new();
+        for word in tags.split([' ', ',']).filter(|word| !word.is_empty()) {
+            if word == "-" {
+                continue;
+            }
+            let name = tag_name(&format!("#{}", word.trim_start_matches('#')))
+                .ok_or_else(|| TodoError::Usage(format!("`{word}` is not a tag name")))?
+                .to_string();
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    })?
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(Task {
+        head,
+        body,
+        due,
+        priority,
+        tags,
+        ..template
+    }))
+}
+
+/// Asks `question` until `parse` reads the answer, telling what is wrong
+/// with those it can't. Returns `None` if the input ends first.
+fn ask_until<T>(
+    question: &str,
+    default: Option<String>,
+    parse: impl Fn(&str) -> Result<T>,
+) -> Result<Option<T>> {
+    while let Some(answer) = prompt::ask(question, default.as_deref())? {
+        match parse(&answer) {
+            Ok(value) => return Ok(Some(value)),
+            Err(err) => eprintln!("{err}, try again"),
+        }
+    }
+    Ok(None)
+}
+
+/// Lists the tags carried by any task, done ones included, each with
+/// the number of open tasks carrying it. Tags differing only in case
+/// are counted as one, spelled as first seen.
+fn list_tags(data: &Storage) -> Result<()> {
+    let mut tags = BTreeMap::<String, (&str, usize)>::new();
+    for task in data.list() {
+        for tag in &task.tags {
+            let (_, open) = tags.entry(tag.to_lowercase()).or_insert((tag, 0));
+            if !task.done {
+                *open += 1;
+            }
+        }
+    }
+    let mut out = Vec::new();
+    if tags.is_empty() {
+        writeln!(out, "No Tags!")?;
+        return page(&out);
+    }
+    let width = tags
+        .values()
+        .map(|(tag, _)| tag.chars().count() + 1)
+        .max()
+        .unwrap_or_default();
+    for (tag, open) in tags.values() {
+        let line = format!("{:<width$}  {open}", format!("#{tag}"));
+        writeln!(out, "{}", if *open == 0 { style::dim(&line) } else { line })?;
+    }
+    page(&out)
+}
+
+/// Saves, lists, prints or deletes templates.
+fn manage_template(action: TemplateAction, editor: &str, data: &Storage) -> Result<()> {
+    match action {
+        TemplateAction::Save { name, from } => {
+            let text = match from {
+                Some(id) => {
+                    let task = data.get(id).ok_or(TodoError::TaskNotFound(id))?;
+                    // Written back into the head, to be taken out again
+                    let annotations = task
+                        .project
+                        .iter()
+                        .map(|p| format!(" +{p}"))
+                        .chain(task.contexts.iter().map(|c| format!(" @{c}")))
+                        .collect::<String>();
+                    format!("{}{annotations}\n{}", task.head, task.body)
+                }
+                None => {
+                    let current = match template::load(&name) {
+                        Err(TodoError::TemplateNotFound(_)) => String::new(),
+                        current => current?,
+                    };
+                    edit_text(editor, current.trim_end(), TEMPLATE_HELP)?
+                }
+            };
+            if text.trim().is_empty() {
+                report!("Template aborted!");
+                return Ok(());
+            }
+            template::save(&name, text.trim_start())?;
+            report!("Template {name} saved!");
+        }
+        TemplateAction::List => {
+            let names = template::names()?;
+            if names.is_empty() {
+                println!("No templates!");
+            }
+            for name in names {
+                let text = template::load(&name)?;
+                let head = text.lines().next().unwrap_or_default();
+                println!("{name}: {head}");
+            }
+        }
+        TemplateAction::Show { name } => print!("{}", template::load(&name)?),
+        TemplateAction::Rm { name } => {
+            template::remove(&name)?;
+            report!("Template {name} deleted!");
+        }
+    }
+    Ok(())
+}
+
+/// Prints a task by it's Id
+fn show_task(
+    id: u64,
+    section: Option<&str>,
+    format: OutputFormat,
+    raw: bool,
+    data: &Storage,
+) -> Result<()> {
+    let task = data.get(id).ok_or(TodoError::TaskNotFound(id))?;
+
+    let mut out = Vec::new();
+    match format {
+        OutputFormat::Text => write_task(&mut out, task, section, raw, data)?,
+        OutputFormat::Json => {
+            let json = serde_json::to_string_pretty(task)
+                .map_err(|err| TodoError::Serialize(err.to_string()))?;
+            writeln!(out, "{json}")?;
+        }
+    }
+    page(&out)
+}
+
+/// Writes the details of a task, one labelled line each, and its body,
+/// as Markdown unless `raw` is set.
+fn write_task(
+    out: &mut impl Write,
+    task: &Task,
+    section: Option<&str>,
+    raw: bool,
+    data: &Storage,
+) -> Result<()> {
+    let today = Local::now().date_naive();
+    writeln!(out, "ID: {}\nHEAD: {}", task.id, style::bold(&task.head))?;
+    if task.done {
+        writeln!(out, "DONE: yes")?;
+    }
+    if let Some(parent) = task.parent {
+        writeln!(out, "PARENT: {parent}")?;
+    }
+    if let Some((done, total)) = progress(task, data) {
+        writeln!(out, "PROGRESS: {done}/{total}")?;
+    }
+    let blockers = data.open_blockers(task.id);
+    if !blockers.is_empty() {
+        writeln!(out, "BLOCKED BY: {}", join_ids(&blockers))?;
+    }
+    if let Some(due) = task.due {
+        writeln!(
+            out,
+            "DUE: {}",
+            style_due(due, today, &show_day_and_date(due, today))
+        )?;
+    }
+    if let Some(priority) = task.priority {
+        writeln!(out, "PRIORITY: {priority}")?;
+    }
+    if let Some(estimate) = task.estimate {
+        let left = task.remaining_estimate(Local::now()).unwrap_or_default();
+        writeln!(
+            out,
+            "ESTIMATE: {} ({} left)",
+            format_time(estimate),
+            format_time(left)
+        )?;
+    }
+    if !task.intervals.is_empty() {
+        let spent = task.time_spent(DateTime::<Local>::MIN_UTC.into(), Local::now());
+        let running = if task.is_timing() { " (running)" } else { "" };
+        writeln!(out, "TIME: {}{running}", format_time(spent))?;
+    }
+    if let Some(until) = task.snoozed_until.filter(|_| task.is_snoozed(Local::now())) {
+        writeln!(out, "SNOOZED UNTIL: {}", until.format(MOMENT_FORMAT))?;
+    }
+    if !task.tags.is_empty() {
+        writeln!(out, "TAGS: {}", style::cyan(&task.tags.join(", ")))?;
+    }
+    if let Some(project) = &task.project {
+        writeln!(out, "PROJECT: {}", style::cyan(project))?;
+    }
+    if !task.contexts.is_empty() {
+        let contexts = task
+            .contexts
+            .iter()
+            .map(|c| format!("@{c}"))
+            .collect::<Vec<_>>();
+        writeln!(out, "CONTEXTS: {}", style::cyan(&contexts.join(", ")))?;
+    }
+    if let Some(link) = &task.link {
+        writeln!(out, "LINK: {link}")?;
+    }
+    for (i, attachment) in task.attachments.iter().enumerate() {
+        writeln!(out, "ATTACHMENT {}: {attachment}", i + 1)?;
+    }
+    for (key, value) in &task.fields {
+        writeln!(out, "FIELD {key}: {value}")?;
+    }
+    if let Some(created_at) = task.created_at {
+        writeln!(
+            out,
+            "CREATED: {}",
+            show_moment_and_date(created_at, Local::now())
+        )?;
+    }
+    if let Some(updated_at) = task.updated_at {
+        writeln!(
+            out,
+            "UPDATED: {}",
+            show_moment_and_date(updated_at, Local::now())
+        )?;
+    }
+    print_body(out, &task.body, section, raw)?;
+    // Labelled one by one, apart from a `## Notes` section of the body
+    for note in task.notes.iter().filter(|_| section.is_none()) {
+        let at = show_moment_and_date(note.at, Local::now());
+        let mut lines = note.text.lines();
+        writeln!(
+            out,
+            "NOTE {}: {}",
+            style::dim(&at),
+            lines.next().unwrap_or_default()
+        )?;
+        for line in lines {
+            writeln!(out, "  {line}")?;
+        }
+    }
+    Ok(())
+}
+
+/// How many subtasks of a task are done, out of how many.
+/// `None` if the task has no subtasks.
+fn progress(task: &Task, data: &Storage) -> Option<(usize, usize)> {
+    let (done, total) = data.children(task.id).fold((0, 0), |(done, total), child| {
+        (done + usize::from(child.done), total + 1)
+    });
+    (total > 0).then_some((done, total))
+}
+
+/// Prints a task body, labelling each of its sections, rendering its
+/// Markdown unless `raw` is set.
+/// If `only` is given, just that section is printed.
+fn print_body(out: &mut impl Write, body: &str, only: Option<&str>, raw: bool) -> Result<()> {
+    let render = |lines: &[&str]| {
+        let text = lines.join("\n");
+        if raw { text } else { markdown::render(&text) }
+    };
+    let sections = parse_sections(body);
+    if let Some(only) = only {
+        match sections
+            .iter()
+            .find(|s| s.name.is_some_and(|name| name.eq_ignore_ascii_case(only)))
+        {
+            Some(section) => writeln!(out, "{}", render(&section.lines).trim())?,
+            None => return Err(TodoError::SectionNotFound(only.to_string())),
+        }
+        return Ok(());
+    }
+
+    for section in sections {
+        let text = render(&section.lines);
+        match section.name {
+            None => writeln!(out, "BODY: {}", text.trim())?,
+            Some(name) => {
+                writeln!(out, "{}:", name.to_uppercase())?;
+                for line in text.trim().lines() {
+                    writeln!(out, "  {line}")?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The index of the attachment numbered `number` in `show`, from 1.
+fn attachment_index(task: &Task, number: usize) -> Result<usize> {
+    if number == 0 || number > task.attachments.len() {
+        return Err(TodoError::Usage(format!(
+            "task {} has no attachment {number}",
+            task.id
+        )));
+    }
+    Ok(number - 1)
+}
+
+/// Adds a timestamped note to a task, or appends the text as a line of
+/// a section of its body.
+fn add_note(id: Id, text: &str, section: Option<&str>, data: &mut Storage) -> Result<()> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(());
+    }
+
+    let task = data.get_mut(id).ok_or(TodoError::TaskNotFound(id))?;
+    match section {
+        Some(section) => {
+            task.body = todo::sections::append_to_section(&task.body, section, text);
+        }
+        None => task.notes.push(Note {
+            at: Local::now(),
+            text: text.to_string(),
+        }),
+    }
+    report!("Task {id} updated!");
+    Ok(())
+}
+
+/// The ids of the tasks selected on the command line, in order and each
+/// once. Tasks given by words of their head are looked up among those
+/// `eligible` allows, see `find_by_head`.
+fn select_tasks(
+    selections: &[Selection],
+    eligible: impl Fn(&Task) -> bool,
+    data: &Storage,
+) -> Result<Vec<Id>> {
+    let mut ids = Vec::new();
+    for selection in selections {
+        match selection {
+            Selection::Ids(set) => ids.extend(set.ids()),
+            Selection::Head(query) => ids.extend(find_by_head(query, &eligible, data)?),
+        }
+    }
+    let mut seen = BTreeSet::new();
+    ids.retain(|id| seen.insert(*id));
+    Ok(ids)
+}
+
+/// The id of the task given on the command line, see `select_tasks`.
+/// `None` if picking among several matches was cancelled.
+fn find_task(
+    task: &TaskRef,
+    eligible: impl Fn(&Task) -> bool,
+    data: &Storage,
+) -> Result<Option<Id>> {
+    match task {
+        TaskRef::Id(id) => Ok(Some(*id)),
+        TaskRef::Head(query) => find_by_head(query, eligible, data),
+    }
+}
+
+/// The task whose head `query` matches, among those `eligible` allows.
+/// When several match, the user picks one of them with the fuzzy finder,
+/// and `None` is returned if they cancel. Without a terminal to pick in
+/// that fails, as does finding no task.
+fn find_by_head(
+    query: &str,
+    eligible: impl Fn(&Task) -> bool,
+    data: &Storage,
+) -> Result<Option<Id>> {
+    let tasks = select::match_head(query, data.list().filter(|task| eligible(task)));
+    match tasks.as_slice() {
+        [] => Err(TodoError::NoMatchingTask(query.to_string())),
+        [task] => Ok(Some(task.id)),
+        _ if !(io::stdin().is_terminal() && io::stderr().is_terminal()) => {
+            Err(TodoError::AmbiguousTask {
+                query: query.to_string(),
+                ids: tasks.iter().map(|task| task.id).collect(),
+            })
+        }
+        _ => {
+            let items = tasks
+                .iter()
+                .map(|task| format!("{}. {}", task.id, task.head))
+                .collect::<Vec<_>>();
+            Ok(pick(&items)?.map(|index| tasks[index].id))
+        }
+    }
+}
+
+/// Lets the user pick a task by fuzzy matching its head.
+/// Returns `None` if there are no tasks or picking was cancelled.
+fn pick_task(data: &Storage) -> Result<Option<Id>> {
+    if data.is_empty() {
+        println!("No Tasks!");
+        return Ok(None);
+    }
+
+    let tasks: Vec<&Task> = data.list().collect();
+    let items: Vec<String> = tasks
+        .iter()
+        .map(|task| format!("{}. {}", task.id, task.head))
+        .collect();
+    Ok(pick(&items)?.map(|index| tasks[index].id))
+}
+
+/// Renders the tasks matching the filter as `list` prints them, leaving
+/// out the done and snoozed ones unless `all` is set.
+/// Subtasks are indented under their parent, with `long` every task
+/// is printed like `show` does instead. With `by_project` the tasks are
+/// grouped under the name of their project, those without one last.
+fn render_list(data: &Storage, filter: &Filter, options: &ListOptions) -> Result<Vec<u8>> {
+    let now = Local::now();
+    let today = now.date_naive();
+    let mut tasks = data
+        .list()
+        .filter(|task| options.all || !(task.done || task.is_snoozed(now)))
+        .filter(|task| filter.matches(task, today))
+        .collect::<Vec<&Task>>();
+    options.sort.sort(&mut tasks);
+    let mut out = Vec::new();
+    if tasks.is_empty() {
+        writeln!(out, "No Tasks!")?;
+        return Ok(out);
+    }
+
+    let layout = options.layout();
+    if !options.by_project {
+        write_list(&mut out, &tasks, data, layout, 0)?;
+        return Ok(out);
+    }
+
+    // Tasks without a project sort last
+    let mut by_project = BTreeMap::<(bool, Option<&str>), Vec<&Task>>::new();
+    for task in tasks {
+        let project = task.project.as_deref();
+        by_project
+            .entry((project.is_none(), project))
+            .or_default()
+            .push(task);
+    }
+    for (i, ((_, project), tasks)) in by_project.into_iter().enumerate() {
+        if i > 0 && matches!(layout, Layout::Full) {
+            writeln!(out)?;
+        }
+        let heading = project.map_or("No project".to_string(), |p| format!("+{p}"));
+        writeln!(out, "{}", style::bold(&heading))?;
+        write_list(&mut out, &tasks, data, layout, 1)?;
+    }
+    Ok(out)
+}
+
+/// Shows the list as `list` does, and again whenever the store is saved,
+/// until interrupted. The list is also shown again every minute, for due
+/// dates to stay current.
+fn watch(options: &ListOptions, filter: &Filter, session: &mut Session) -> Result<()> {
+    // Nothing is saved once watching starts, e.g. tasks rolled over
+    session.save()?;
+    let watcher = StorageWatcher::new(&session.storage_path)?;
+    let filter = options.filter(filter);
+    let mut stdout = io::stdout();
+    loop {
+        let list = render_list(&session.data, &filter, options)?;
+        if stdout.is_terminal() {
+            crossterm::execute!(stdout, Clear(ClearType::All), MoveTo(0, 0))?;
+        }
+        let now = Local::now().format("%H:%M:%S");
+        writeln!(
+            stdout,
+            "{}",
+            style::dim(&format!("As of {now}, Ctrl-C to stop"))
+        )?;
+        stdout.write_all(&list)?;
+        stdout.flush()?;
+
+        if watcher.wait(Duration::from_secs(60))?
+            && fingerprint(&session.storage_path) != session.loaded
+        {
+            session.reload()?;
+        }
+    }
+}
+
+/// Renders the board: the columns side by side, as wide as the terminal
+/// allows, heads cut short to fit. Done tasks show if they were done in
+/// the last week, unless `all` is set.
+fn render_board(
+    data: &Storage,
+    filter: &Filter,
+    definitions: &[BoardColumn],
+    by_tag: bool,
+    all: bool,
+) -> Result<Vec<u8>> {
+    let now = Local::now();
+    let today = now.date_naive();
+    let recent = now - TimeDelta::weeks(1);
+    let mut tasks = data
+        .list()
+        .filter(|task| {
+            all || if task.done {
+                task.completed_at.is_some_and(|at| at >= recent)
+            } else {
+                !task.is_snoozed(now)
+            }
+        })
+        .filter(|task| filter.matches(task, today))
+        .collect::<Vec<&Task>>();
+    SortKey::Manual.sort(&mut tasks);
+    let columns = if by_tag {
+        board::tag_columns(&tasks)
+    } else {
+        board::columns(definitions, &tasks, today)?
+    };
+    let mut out = Vec::new();
+    if columns.is_empty() {
+        writeln!(out, "No Tasks!")?;
+        return Ok(out);
+    }
+
+    let gap = "   ";
+    let screen = crossterm::terminal::size()
+        .ok()
+        .filter(|_| io::stdout().is_terminal())
+        .map_or(120, |(width, _)| usize::from(width));
+    let count = columns.len();
+    let width = (screen.saturating_sub(gap.len() * (count - 1)) / count).clamp(16, 40);
+    // Cut short to the width, and padded to it unless the cell is the
+    // last of its line, to leave no spaces at the end
+    let fit = |text: &str, last: bool| {
+        let pad = if last { 0 } else { width };
+        if text.chars().count() > width {
+            text.chars().take(width - 1).collect::<String>() + "…"
+        } else {
+            format!("{text:<pad$}")
+        }
+    };
+
+    let headings = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let heading = format!("{} ({})", column.name, column.tasks.len());
+            style::bold(&fit(&heading, i == count - 1))
+        })
+        .collect::<Vec<_>>();
+    writeln!(out, "{}", headings.join(gap))?;
+    let rule = vec!["─".repeat(width); count];
+    writeln!(out, "{}", rule.join(gap))?;
+
+    let rows = columns
+        .iter()
+        .map(|c| c.tasks.len())
+        .max()
+        .unwrap_or_default();
+    for row in 0..rows {
+        // Columns that have run out of tasks leave blanks
+        let last = columns
+            .iter()
+            .rposition(|column| row < column.tasks.len())
+            .unwrap_or_default();
+        let mut cells = Vec::new();
+        for (i, column) in columns[..=last].iter().enumerate() {
+            let Some(task) = column.tasks.get(row) else {
+                cells.push(" ".repeat(width));
+                continue;
+            };
+            let cell = fit(&format!("{}. {}", task.id, task.head), i == last);
+            let cell = match task.due {
+                _ if task.done => style::dim(&cell),
+                Some(due) => style_due(due, today, &cell),
+                None => cell,
+            };
+            cells.push(cell);
+        }
+        writeln!(out, "{}", cells.join(gap))?;
+    }
+    Ok(out)
+}
+
+/// Writes the tasks in `layout`, indented `depth` levels.
+fn write_list(
+    out: &mut impl Write,
+    tasks: &[&Task],
+    data: &Storage,
+    layout: Layout,
+    depth: usize,
+) -> Result<()> {
+    let now = Local::now();
+    match layout {
+        Layout::Lines => {
+            for (depth, task) in tree_rows(tasks, depth) {
+                writeln!(out, "{}{}", "  ".repeat(depth), list_line(task, data, now))?;
+            }
+        }
+        // The columns line up under a project heading as they are
+        Layout::Table => write_table(out, &tree_rows(tasks, 0), data, now)?,
+        Layout::Full => {
+            for (i, task) in tasks.iter().enumerate() {
+                if i > 0 {
+                    writeln!(out)?;
+                }
+                write_task(out, task, None, false, data)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Orders `tasks` as a tree, each followed by its subtasks among them,
+/// with how deep each is, from `depth`.
+fn tree_rows<'a>(tasks: &[&'a Task], depth: usize) -> Vec<(usize, &'a Task)> {
+    // Tasks whose parent isn't listed are roots of the tree
+    let listed = tasks.iter().map(|task| task.id).collect::<BTreeSet<Id>>();
+    let mut rows = Vec::new();
+    let mut added = BTreeSet::new();
+    for task in tasks {
+        if task.parent.is_none_or(|parent| !listed.contains(&parent)) {
+            add_subtree(tasks, task, depth, &mut added, &mut rows);
+        }
+    }
+    // Only tasks whose parents form a cycle are not reached from a root
+    for task in tasks {
+        add_subtree(tasks, task, depth, &mut added, &mut rows);
+    }
+    rows
+}
+
+/// Adds a task and, below it, its subtasks among `tasks` to `rows`.
+/// Tasks in `added` are skipped.
+fn add_subtree<'a>(
+    tasks: &[&'a Task],
+    task: &'a Task,
+    depth: usize,
+    added: &mut BTreeSet<Id>,
+    rows: &mut Vec<(usize, &'a Task)>,
+) {
+    if !added.insert(task.id) {
+        return;
+    }
+    rows.push((depth, task));
+    for child in tasks.iter().filter(|child| child.parent == Some(task.id)) {
+        add_subtree(tasks, child, depth + 1, added, rows);
+    }
+}
+
+/// Writes the tasks in aligned columns, with a heading. Columns no task
+/// has anything in are left out, and the start of the body is cut to fit
+/// the terminal.
+fn write_table(
+    out: &mut impl Write,
+    rows: &[(usize, &Task)],
+    data: &Storage,
+    now: DateTime<Local>,
+) -> Result<()> {
+    let today = now.date_naive();
+    let cells = |row: &(usize, &Task)| -> [String; 6] {
+        let (depth, task) = *row;
+        let progress = progress(task, data)
+            .map(|(done, total)| format!(" [{done}/{total}]"))
+            .unwrap_or_default();
+        let tags = task
+            .project
+            .iter()
+            .map(|p| format!("+{p}"))
+            .chain(task.tags.iter().map(|t| format!("#{t}")))
+            .chain(task.contexts.iter().map(|c| format!("@{c}")))
+            .collect::<Vec<_>>();
+        let due = task.due.map(|due| {
+            let overdue = if due < today && !task.done {
+                "overdue, "
+            } else {
+                ""
+            };
+            format!("{overdue}{}", show_day(due, today))
+        });
+        let preview = task.body.lines().find(|line| !line.trim().is_empty());
+        [
+            task.id.to_string(),
+            format!("{}{}{progress}", "  ".repeat(depth), task.head),
+            tags.join(" "),
+            due.unwrap_or_default(),
+            task.priority.map(|p| p.to_string()).unwrap_or_default(),
+            preview.unwrap_or_default().trim().to_string(),
+        ]
+    };
+    let table = rows.iter().map(cells).collect::<Vec<_>>();
+    let heading = ["ID", "TASK", "TAGS", "DUE", "PRIORITY", "BODY"];
+    let columns = (0..heading.len())
+        .filter(|&column| column < 2 || table.iter().any(|cells| !cells[column].is_empty()))
+        .collect::<Vec<_>>();
+    let widths = columns
+        .iter()
+        .map(|&column| {
+            table
+                .iter()
+                .map(|cells| cells[column].chars().count())
+                .chain([heading[column].len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    // The body takes what is left of the line, as it comes last
+    let used = widths
+        .iter()
+        .take(widths.len() - 1)
+        .map(|w| w + 2)
+        .sum::<usize>();
+    let screen = crossterm::terminal::size()
+        .ok()
+        .filter(|_| io::stdout().is_terminal())
+        .map_or(usize::MAX, |(width, _)| usize::from(width));
+    let preview_width = screen.saturating_sub(used).clamp(20, 60);
+
+    // The last cell of a line isn't padded, to leave no spaces at the end
+    let last = columns.len() - 1;
+    let heading_line = columns
+        .iter()
+        .zip(&widths)
+        .enumerate()
+        .map(|(i, (&column, &width))| {
+            let width = if i == last { 0 } else { width };
+            format!("{:<width$}", heading[column])
+        })
+        .collect::<Vec<_>>()
+        .join("  ");
+    writeln!(out, "{}", style::bold(&heading_line))?;
+
+    for ((_, task), cells) in rows.iter().zip(&table) {
+        let mut line = Vec::new();
+        for (i, &column) in columns.iter().enumerate() {
+            let rest_empty = |from: usize| columns[from..].iter().all(|&c| cells[c].is_empty());
+            if rest_empty(i) {
+                break;
+            }
+            let mut text = cells[column].clone();
+            if column == 5 && text.chars().count() > preview_width {
+                text = text.chars().take(preview_width - 1).collect::<String>() + "…";
+            }
+            let width = if rest_empty(i + 1) { 0 } else { widths[i] };
+            let text = format!("{text:<width$}");
+            line.push(match column {
+                _ if task.done => text,
+                1 => style::bold(&text),
+                2 => style::cyan(&text),
+                3 => task
+                    .due
+                    .map_or(text.clone(), |due| style_due(due, today, &text)),
+                4 if task.priority == Some(Priority::High) => style::red(&text),
+                5 => style::dim(&text),
+                _ => text,
+            });
+        }
+        let line = line.join("  ");
+        if task.done {
+            writeln!(out, "{}", style::dim(&line))?;
+        } else {
+            writeln!(out, "{line}")?;
+        }
+    }
+    Ok(())
+}
+
+/// The line listing a task: its id, head, project, tags, contexts, due date and the
+/// progress of its subtasks. Overdue tasks are marked, done tasks are
+/// dimmed.
+fn list_line(task: &Task, data: &Storage, now: DateTime<Local>) -> String {
+    let today = now.date_naive();
+    let label = if task.body.is_empty() { "" } else { "HEAD: " };
+    let tags = task
+        .project
+        .iter()
+        .map(|p| format!(" +{p}"))
+        .chain(task.tags.iter().map(|t| format!(" #{t}")))
+        .chain(task.contexts.iter().map(|c| format!(" @{c}")))
+        .collect::<String>();
+    let progress = progress(task, data)
+        .map(|(done, total)| format!(" [{done}/{total}]"))
+        .unwrap_or_default();
+    let priority = task.priority.map(|p| format!(" !{p}")).unwrap_or_default();
+    let blockers = data.open_blockers(task.id);
+    let mut notes = String::new();
+    if !blockers.is_empty() {
+        notes.push_str(&format!(" (blocked by {})", join_ids(&blockers)));
+    }
+    if task.is_timing() {
+        notes.push_str(" (timer running)");
+    }
+    if let Some(until) = task.snoozed_until.filter(|_| task.is_snoozed(now)) {
+        notes.push_str(&format!(" (snoozed until {})", until.format(MOMENT_FORMAT)));
+    }
+
+    if task.done {
+        let due = task
+            .due
+            .map(|d| format!(" (due {})", show_day(d, today)))
+            .unwrap_or_default();
+        return style::dim(&format!(
+            "{}. {label}{}{priority}{tags}{due}{progress} (done)",
+            task.id, task.head
+        ));
+    }
+
+    let due = task
+        .due
+        .map(|d| {
+            // Marked in words too, for output without colors
+            let overdue = if d < today { "overdue, " } else { "" };
+            let text = format!("({overdue}due {})", show_day(d, today));
+            format!(" {}", style_due(d, today, &text))
+        })
+        .unwrap_or_default();
+    let priority = match task.priority {
+        Some(Priority::High) => style::red(&priority),
+        _ => priority,
+    };
+    format!(
+        "{}. {label}{}{priority}{}{due}{progress}{}",
+        task.id,
+        style::bold(&task.head),
+        style::cyan(&tags),
+        style::dim(&notes)
+    )
+}
+
+/// Shows the `count` most pressing open tasks matching the filter, see
+/// `urgency`, with their score.
+fn suggest_next(data: &Storage, filter: &Filter, count: usize) -> Result<()> {
+    let now = Local::now();
+    let today = now.date_naive();
+    let ranked = urgency::rank(data, now)
+        .into_iter()
+        .filter(|(task, _)| filter.matches(task, today))
+        .take(count)
+        .collect::<Vec<_>>();
+    if ranked.is_empty() {
+        println!("No Tasks!");
+        return Ok(());
+    }
+
+    let mut out = Vec::new();
+    for (task, score) in ranked {
+        let score = format!("(urgency {score:.1})");
+        writeln!(out, "{} {}", list_line(task, data, now), style::dim(&score))?;
+    }
+    page(&out)
+}
+
+/// Lists the tasks matching `query`, done ones included, each with the
+/// lines that match and the matches highlighted.
+fn search(data: &Storage, filter: &Filter, query: &Query, format: OutputFormat) -> Result<()> {
+    let today = Local::now().date_naive();
+    let found = data
+        .list()
+        .filter(|task| filter.matches(task, today))
+        .filter_map(|task| query.search(task))
+        .collect::<Vec<_>>();
+    let mut out = Vec::new();
+    if let OutputFormat::Json = format {
+        let json = serde_json::to_string_pretty(&found)
+            .map_err(|err| TodoError::Serialize(err.to_string()))?;
+        writeln!(out, "{json}")?;
+        return page(&out);
+    }
+    if found.is_empty() {
+        writeln!(out, "No Tasks!")?;
+        return page(&out);
+    }
+
+    for task in &found {
+        let head = task.hits.iter().find(|hit| hit.field == Field::Head);
+        let head = head.map_or(task.head.clone(), highlight);
+        let line = format!("{}. {head}", task.id);
+        writeln!(out, "{}", if task.done { style::dim(&line) } else { line })?;
+        for hit in task.hits.iter().filter(|hit| hit.field != Field::Head) {
+            let label = match hit.field {
+                Field::Head | Field::Body => "body",
+                Field::Tag => "tag",
+                Field::Note => "note",
+            };
+            writeln!(
+                out,
+                "   {} {}",
+                style::dim(&format!("{label}:")),
+                highlight(hit)
+            )?;
+        }
+    }
+    page(&out)
+}
+
+/// The text of `hit` with its matches in bold.
+fn highlight(hit: &Hit) -> String {
+    let mut out = String::new();
+    let mut end = 0;
+    for range in &hit.matches {
+        out.push_str(&hit.text[end..range.start]);
+        out.push_str(&style::bold(&style::yellow(&hit.text[range.clone()])));
+        end = range.end;
+    }
+    out.push_str(&hit.text[end..]);
+    out
+}
+
+/// Shows an open task matching the filter, picked at random. Snoozed
+/// tasks are left out.
+fn pick_random(data: &Storage, filter: &Filter) -> Result<()> {
+    let now = Local::now();
+    let today = now.date_naive();
+    let tasks = data
+        .list()
+        .filter(|task| !(task.done || task.is_snoozed(now)))
+        .filter(|task| filter.matches(task, today))
+        .collect::<Vec<&Task>>();
+    match fastrand::choice(tasks) {
+        Some(task) => println!("{}", list_line(task, data, now)),
+        None => println!("No Tasks!"),
+    }
+    Ok(())
+}
+
+/// Shows the open tasks that are overdue or due in the `days` starting
+/// today, grouped by day. Snoozed tasks are left out.
+fn agenda(data: &Storage, days: u64) -> Result<()> {
+    let now = Local::now();
+    let today = now.date_naive();
+    let end = today + chrono::Days::new(days);
+
+    // Overdue tasks are grouped under `None`, which comes first
+    let mut by_day = BTreeMap::<Option<NaiveDate>, Vec<&Task>>::new();
+    for task in data.list() {
+        if let Some(due) = task.due.filter(|due| *due < end)
+            && !task.done
+            && !task.is_snoozed(now)
+        {
+            let day = (due >= today).then_some(due);
+            by_day.entry(day).or_default().push(task);
+        }
+    }
+    if by_day.is_empty() {
+        println!("No Tasks!");
+        return Ok(());
+    }
+
+    let mut out = Vec::new();
+    for (day, tasks) in by_day {
+        let heading = match day {
+            None => "Overdue".to_string(),
+            Some(day) if day == today => format!("{} (today)", day.format(DAY_FORMAT)),
+            Some(day) => day.format(DAY_FORMAT).to_string(),
+        };
+        writeln!(out, "{}", style::bold(&heading))?;
+        for task in tasks {
+            writeln!(out, "  {}", list_line(task, data, now))?;
+        }
+    }
+    page(&out)
+}
+
+/// Prints a calendar of the month starting on `first`, or of this
+/// month, with the number of open tasks due on each day.
+fn calendar(data: &Storage, first: Option<NaiveDate>) -> Result<()> {
+    let today = Local::now().date_naive();
+    let first = first.unwrap_or_else(|| today.with_day(1).unwrap_or(today));
+    let next_month = first + Months::new(1);
+
+    let mut due_counts = BTreeMap::<NaiveDate, usize>::new();
+    for task in data.list().filter(|task| !task.done) {
+        if let Some(due) = task.due.filter(|due| (first..next_month).contains(due)) {
+            *due_counts.entry(due).or_default() += 1;
+        }
+    }
+
+    let width = CALENDAR_CELL_WIDTH * 7;
+    let title = first.format("%B %Y").to_string();
+    println!("{}", style::bold(&format!("{title:^width$}")));
+    for weekday in ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"] {
+        print!("{weekday:<CALENDAR_CELL_WIDTH$}");
+    }
+    println!();
+
+    let offset = first.weekday().num_days_from_monday() as usize;
+    print!("{}", " ".repeat(offset * CALENDAR_CELL_WIDTH));
+    for day in first.iter_days().take_while(|day| *day < next_month) {
+        let count = due_counts.get(&day).copied().unwrap_or_default();
+        let overdue = day < today && count > 0;
+        let mut cell = format!("{:>2}", day.day());
+        if count > 0 {
+            cell.push_str(&format!("{}{count}", if overdue { "!" } else { ":" }));
+        }
+        let cell = format!("{cell:<CALENDAR_CELL_WIDTH$}");
+
+        if overdue {
+            print!("{}", style::red(&cell));
+        } else if day == today {
+            print!("{}", style::bold(&cell));
+        } else {
+            print!("{cell}");
+        }
+        if day.weekday() == Weekday::Sun {
+            println!();
+        }
+    }
+    if next_month.weekday() != Weekday::Mon {
+        println!();
+    }
+    Ok(())
+}
+
+/// Colors text about a due date: red if overdue, yellow if due today.
+fn style_due(due: NaiveDate, today: NaiveDate, text: &str) -> String {
+    match due.cmp(&today) {
+        Ordering::Less => style::red(text),
+        Ordering::Equal => style::yellow(text),
+        Ordering::Greater => text.to_string(),
+    }
+}
+
+/// Fails if any of the tasks is blocked by a task that isn't done.
+fn check_unblocked(indices: &[u64], data: &Storage) -> Result<()> {
+    for &id in indices {
+        let blockers = data.open_blockers(id);
+        if !blockers.is_empty() {
+            return Err(TodoError::Blocked { id, blockers });
+        }
+    }
+    Ok(())
+}
+
+/// Marks todos as done, or as not done, by their indices.
+fn mark_done(indices: &[u64], done: bool, data: &mut Storage) {
+    for id in data.set_done(indices, done) {
+        if done {
+            report!("Task {id} done!");
+        } else {
+            report!("Task {id} not done!");
+        }
+    }
+}
+
+/// Asks whether to go ahead when a command acts on more than
+/// `CONFIRM_ABOVE` tasks. `action` describes what happens to them.
+fn confirm_many(action: &str, count: usize) -> Result<bool> {
+    if count <= CONFIRM_ABOVE {
+        return Ok(true);
+    }
+    confirm_count(action, count)
+}
+
+/// Asks whether to go ahead with `action` on `count` tasks.
+fn confirm_count(action: &str, count: usize) -> Result<bool> {
+    let tasks = if count == 1 { "task" } else { "tasks" };
+    let confirmed = confirm(&format!("{action} {count} {tasks}?"))?;
+    if !confirmed {
+        report!("Nothing changed.");
+    }
+    Ok(confirmed)
+}
+
+/// Deletes todos by their indices.
+fn delete_todos(indices: &[u64], data: &mut Storage) {
+    for id in data.delete(indices) {
+        report!("Task {id} Deleted!");
+    }
+}
+
+/// Gets a task by its index and opens it in the default editor.
+/// If the task is modified, it updates the task.
+/// If the task is empty, it deletes the task.
+fn edit_task(index: u64, editor: &str, data: &mut Storage) -> Result<()> {
+    let current_task = data.get(index).ok_or(TodoError::TaskNotFound(index))?;
+
+    let content = edit_text(
+        editor,
+        &format!("{}\n{}", current_task.head, current_task.body),
+        EDIT_TASK_HELP,
+    )?;
+    let Some((new_head, new_body)) = split_head_body(&content) else {
+        if confirm(&format!("The task is empty. Delete task {index}?"))? {
+            delete_todos(&[index], data);
+        } else {
+            report!("Task {index} not updated!");
+        }
+        return Ok(());
+    };
+
+    let updated_task = Task {
+        head: new_head,
+        body: new_body,
+        ..current_task.clone()
+    };
+
+    if *current_task != updated_task {
+        report!("Task {} updated!", &updated_task.id);
+        if let Some(task) = data.get_mut(index) {
+            // Keep the modification time `get_mut` just set
+            *task = Task {
+                updated_at: task.updated_at,
+                ..updated_task
+            };
+        }
+    } else {
+        report!("Task {} not updated!", &updated_task.id);
+    }
+
+    Ok(())
+}
+
+/// Updates a task with the contents given on the command line,
+/// without an editor round-trip.
+fn update_task(id: Id, fields: EditFields, data: &mut Storage) -> Result<()> {
+    let current_task = data.get(id).ok_or(TodoError::TaskNotFound(id))?;
+    let mut updated_task = current_task.clone();
+
+    if let Some(head) = fields.head {
+        let head = head.trim();
+        if head.is_empty() {
+            return Err(TodoError::Usage("the head cannot be empty".to_string()));
+        }
+        updated_task.head = head.to_string();
+        take_tokens(&mut updated_task)?;
+    }
+    if let Some(body) = fields.body {
+        updated_task.body = body.trim().to_string();
+    }
+    if let Some(estimate) = fields.estimate {
+        updated_task.estimate = Some(estimate);
+    }
+    if let Some(priority) = fields.priority {
+        updated_task.priority = Some(priority);
+    }
+    if let Some(line) = fields.append_body.as_deref().map(str::trim)
+        && !line.is_empty()
+    {
+        if !updated_task.body.is_empty() {
+            updated_task.body.push('\n');
+        }
+        updated_task.body.push_str(line);
+    }
+
+    if *current_task != updated_task {
+        report!("Task {id} updated!");
+        if let Some(task) = data.get_mut(id) {
+            // Keep the modification time `get_mut` just set
+            *task = Task {
+                updated_at: task.updated_at,
+                ..updated_task
+            };
+        }
+    } else {
+        report!("Task {id} not updated!");
+    }
+    Ok(())
+}
+
+/// Adds a line to the start of a task's body.
+fn prepend_body(id: Id, text: &str, data: &mut Storage) -> Result<()> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(());
+    }
+
+    let task = data.get_mut(id).ok_or(TodoError::TaskNotFound(id))?;
+    task.body = if task.body.is_empty() {
+        text.to_string()
+    } else {
+        format!("{text}\n{}", task.body)
+    };
+    report!("Task {id} updated!");
+    Ok(())
+}
+
+/// Creates a task for every non-empty line of a file, or of stdin
+/// if the path is `-`.
+fn import_lines(path: &Path, data: &mut Storage) -> Result<()> {
+    let content = read_import(path)?;
+
+    let added = content
+        .lines()
+        .filter_map(|line| {
+            data.add(Task {
+                head: line.to_string(),
+                ..Default::default()
+            })
+        })
+        .count();
+    report!("{added} tasks added!");
+    Ok(())
+}
+
+/// Creates tasks from a Taskwarrior export in the file at `path`, `-`
+/// for stdin. Tasks imported before, known by their UUID, are skipped.
+fn import_taskwarrior(path: &Path, data: &mut Storage) -> Result<()> {
+    let imported = taskwarrior::parse_export(&read_import(path)?)?;
+
+    let mut ids: BTreeMap<String, Id> = data
+        .list()
+        .filter_map(|task| Some((task.uid.clone()?, task.id)))
+        .collect();
+    let mut added = Vec::new();
+    let mut skipped = 0;
+    for taskwarrior::Imported { task, depends } in imported {
+        let uuid = task.uid.clone().unwrap_or_default();
+        if ids.contains_key(&uuid) {
+            skipped += 1;
+            continue;
+        }
+        let created_at = task.created_at;
+        let Some(id) = data.add(task) else {
+            continue;
+        };
+        // Keep when the task was created in Taskwarrior
+        if let Some(task) = data.get_mut(id)
+            && created_at.is_some()
+        {
+            task.created_at = created_at;
+        }
+        ids.insert(uuid, id);
+        added.push((id, depends));
+    }
+
+    // Dependencies may point at tasks listed after them
+    for (id, depends) in &added {
+        for uuid in depends {
+            let Some(&on) = ids.get(uuid) else {
+                log::warn!("task {id} depends on unknown task {uuid}, ignored");
+                continue;
+            };
+            if let Err(err) = data.block(*id, on) {
+                log::warn!("{err}, dependency ignored");
+            }
+        }
+    }
+
+    report!("{} tasks added!", added.len());
+    if skipped > 0 {
+        report!("{skipped} tasks were imported before and skipped");
+    }
+    Ok(())
+}
+
+/// Reads a store exported as JSON from `path`, `-` for stdin, and puts
+/// it in place of the store with `replace`, after asking, or merges it
+/// into the store with `merge`, as syncing does.
+fn import_json(path: &Path, replace: bool, merge: bool, data: &mut Storage) -> Result<()> {
+    if !replace && !merge {
+        return Err(TodoError::Usage(
+            "importing JSON needs --replace or --merge".to_string(),
+        ));
+    }
+    let imported =
+        decode_storage(read_import(path)?.as_bytes()).map_err(|err| TodoError::InvalidImport {
+            format: "JSON".to_string(),
+            reason: err.to_string(),
+        })?;
+
+    if merge {
+        let reconciled = data.reconcile(None, &imported);
+        report!(
+            "{} tasks added, {} updated and {} deleted!",
+            reconciled.added,
+            reconciled.updated,
+            reconciled.deleted
+        );
+        return Ok(());
+    }
+    let question = format!(
+        "Replace the {} tasks of the store with the {} imported?",
+        data.len(),
+        imported.len()
+    );
+    if data.is_empty() || confirm(&question)? {
+        let count = imported.len();
+        data.replace(imported);
+        report!("{count} tasks imported!");
+    }
+    Ok(())
+}
+
+/// Creates tasks from the headlines of an Org file at `path`, `-` for
+/// stdin. Nested headlines become subtasks of the headline above them.
+fn import_org(path: &Path, data: &mut Storage) -> Result<()> {
+    // The levels and ids of the headlines enclosing the current one
+    let mut enclosing: Vec<(usize, Id)> = Vec::new();
+    let mut added = 0;
+    for org::Headline { level, mut task } in org::parse_org(&read_import(path)?) {
+        while enclosing.last().is_some_and(|&(above, _)| above >= level) {
+            enclosing.pop();
+        }
+        task.parent = enclosing.last().map(|&(_, id)| id);
+        if let Some(id) = data.add(task) {
+            enclosing.push((level, id));
+            added += 1;
+        }
+    }
+    report!("{added} tasks added!");
+    Ok(())
+}
+
+/// Creates tasks from the rows of a CSV file at `path`, `-` for stdin,
+/// filling their fields from the columns `map` gives.
+fn import_csv(path: &Path, map: &[(String, String)], data: &mut Storage) -> Result<()> {
+    let mut added = 0;
+    for task in todo::csv::parse_csv(&read_import(path)?, map)? {
+        let done = task.done;
+        if let Some(id) = data.add(task) {
+            // Adding records no completion
+            if done {
+                data.set_done(&[id], true);
+            }
+            added += 1;
+        }
+    }
+    report!("{added} tasks added!");
+    Ok(())
+}
+
+/// Adds a task for each of `messages` not imported before, as told by
+/// the `Message-ID` kept in the tasks.
+fn import_mail(messages: Vec<mail::Message>, data: &mut Storage) {
+    let mut imported = data
+        .list()
+        .filter_map(|task| task.fields.get(mail::MESSAGE_ID_FIELD).cloned())
+        .collect::<BTreeSet<_>>();
+    let mut added = 0;
+    let mut skipped = 0;
+    for message in messages {
+        if let Some(id) = &message.message_id
+            && !imported.insert(id.clone())
+        {
+            skipped += 1;
+            continue;
+        }
+        if data.add(message.to_task()).is_some() {
+            added += 1;
+        }
+    }
+
+    report!("{added} tasks added!");
+    if skipped > 0 {
+        report!("{skipped} tasks were imported before and skipped");
+    }
+}
+
+/// Creates a task for each TODO and FIXME comment under `root`, tagged
+/// with its keyword, with where it is in the `location` field. Tasks of
+/// comments found before are updated instead, and the open tasks of
+/// comments under `root` no longer there are marked done.
+/// Locations are absolute, so scanning one project leaves the tasks of
+/// others alone.
+fn scan_comments(root: &Path, data: &mut Storage) -> Result<()> {
+    let root = root.canonicalize().map_err(|source| TodoError::ReadFile {
+        path: root.to_path_buf(),
+        source,
+    })?;
+    let (mut added, mut updated) = (0, 0);
+    let mut found = BTreeSet::new();
+    for comment in scan::scan(&root)? {
+        let fingerprint = comment.fingerprint.clone();
+        let location = comment.location();
+        let existing = data
+            .list()
+            .find(|task| task.fields.get(scan::FINGERPRINT_FIELD) == Some(&fingerprint))
+            .map(|task| {
+                (
+                    task.id,
+                    task.fields.get(scan::LOCATION_FIELD) == Some(&location),
+                )
+            });
+        match existing {
+            Some((_, true)) => {}
+            Some((id, false)) => {
+                if let Some(task) = data.get_mut(id) {
+                    task.fields
+                        .insert(scan::LOCATION_FIELD.to_string(), location);
+                    updated += 1;
+                }
+            }
+            None => {
+                let head = if comment.text.is_empty() {
+                    format!("{} in {location}", comment.keyword)
+                } else {
+                    comment.text.clone()
+                };
+                let task = Task {
+                    head,
+                    tags: vec![comment.keyword.to_lowercase()],
+                    fields: BTreeMap::from([
+                        (scan::FINGERPRINT_FIELD.to_string(), fingerprint.clone()),
+                        (scan::LOCATION_FIELD.to_string(), location),
+                    ]),
+                    ..Default::default()
+                };
+                if data.add(task).is_some() {
+                    added += 1;
+                }
+            }
+        }
+        found.insert(fingerprint);
+    }
+
+    let gone: Vec<Id> = data
+        .list()
+        .filter(|task| !task.done)
+        .filter(|task| {
+            task.fields
+                .get(scan::FINGERPRINT_FIELD)
+                .is_some_and(|fingerprint| !found.contains(fingerprint))
+                && task
+                    .fields
+                    .get(scan::LOCATION_FIELD)
+                    .and_then(|location| location.rsplit_once(':'))
+                    .is_some_and(|(path, _)| Path::new(path).starts_with(&root))
+        })
+        .map(|task| task.id)
+        .collect();
+    let done = data.set_done(&gone, true).len();
+    report!("{added} tasks added, {updated} updated and {done} done!");
+    Ok(())
+}
+
+/// Reports the tasks whose same fields were changed on both sides of a
+/// sync, `elsewhere` telling where the other side is.
+fn report_conflicts(conflicts: &[Conflict], elsewhere: &str) {
+    for conflict in conflicts {
+        let kept = if conflict.kept_theirs {
+            elsewhere
+        } else {
+            "here"
+        };
+        let fields = if conflict.fields.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", conflict.fields.join(", "))
+        };
+        report!(
+            "\"{}\" changed on both sides{fields}, kept the changes made {kept}",
+            conflict.head
+        );
+    }
+}
+
+/// Reports what a sync with a server changed.
+fn report_sync(synced: &SyncReport) {
+    report_conflicts(&synced.conflicts, "on the server");
+    report!(
+        "Synced: {} pushed, {} pulled, {} deleted on the server, {} deleted here",
+        synced.pushed,
+        synced.pulled,
+        synced.deleted_remote,
+        synced.deleted_local
+    );
+}
+
+/// Creates tasks from the open issues of `repo` assigned to `assignee`,
+/// linking each task to its issue. Issues imported before are skipped,
+/// or with `update` their tasks are refreshed.
+fn import_github(
+    client: &github::Client,
+    repo: &str,
+    assignee: &str,
+    update: bool,
+    data: &mut Storage,
+) -> Result<()> {
+    if repo.split('/').filter(|part| !part.is_empty()).count() != 2 {
+        return Err(TodoError::Usage(format!(
+            "invalid repository `{repo}`, expected OWNER/NAME"
+        )));
+    }
+    let assignee = match assignee {
+        "me" => client.login()?,
+        login => login.to_string(),
+    };
+    let issues = client.assigned_issues(repo, &assignee)?;
+    log::debug!(
+        "{} open issue(s) in {repo} assigned to {assignee}",
+        issues.len()
+    );
+
+    let mut linked: BTreeMap<String, Id> = data
+        .list()
+        .filter_map(|task| Some((task.link.clone()?, task.id)))
+        .collect();
+    let (mut added, mut updated) = (0, 0);
+    for issue in &issues {
+        match linked.remove(&issue.html_url) {
+            Some(id) if update => {
+                let Some(task) = data.get(id) else {
+                    continue;
+                };
+                let body = issue.body.clone().unwrap_or_default();
+                let new_labels: Vec<String> = issue
+                    .labels
+                    .iter()
+                    .map(|label| label.name.clone())
+                    .filter(|label| !task.tags.contains(label))
+                    .collect();
+                if (task.head != issue.title || task.body != body || !new_labels.is_empty())
+                    && let Some(task) = data.get_mut(id)
+                {
+                    task.head = issue.title.clone();
+                    task.body = body;
+                    task.tags.extend(new_labels);
+                    updated += 1;
+                }
+            }
+            Some(_) => {}
+            None => {
+                let task = Task {
+                    head: issue.title.clone(),
+                    body: issue.body.clone().unwrap_or_default(),
+                    tags: issue
+                        .labels
+                        .iter()
+                        .map(|label| label.name.clone())
+                        .collect(),
+                    link: Some(issue.html_url.clone()),
+                    ..Default::default()
+                };
+                if data.add(task).is_some() {
+                    added += 1;
+                }
+            }
+        }
+    }
+
+    // Tasks of issues that are no longer open and assigned
+    if update {
+        let prefix = format!("https://github.com/{repo}/issues/");
+        for (link, id) in linked {
+            let Some(number) = link
+                .strip_prefix(&prefix)
+                .and_then(|number| number.parse().ok())
+            else {
+                continue;
+            };
+            let open = data.get(id).is_some_and(|task| !task.done);
+            if open && client.issue(repo, number)?.state == "closed" {
+                data.set_done(&[id], true);
+                report!("Task {id} done, its issue was closed!");
+                updated += 1;
+            }
+        }
+    }
+
+    report!("{added} tasks added!");
+    if update {
+        report!("{updated} tasks updated!");
+    }
+    Ok(())
+}
+
+/// Reads a file to import, `-` for stdin.
+fn read_import(path: &Path) -> Result<String> {
+    if path == Path::new("-") {
+        return Ok(io::read_to_string(io::stdin())?);
+    }
+    fs::read_to_string(path).map_err(|source| TodoError::ReadFile {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Reads a file to import as bytes, `-` for stdin, e.g. an email in
+/// whatever charset it was sent.
+fn read_import_bytes(path: &Path) -> Result<Vec<u8>> {
+    if path == Path::new("-") {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes)?;
+        return Ok(bytes);
+    }
+    fs::read(path).map_err(|source| TodoError::ReadFile {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Writes the tasks in `format` to `output`, or to stdout. With `all`
+/// the JSON format takes the trash and sync state too.
+fn export(data: &Storage, format: ExportFormat, all: bool, output: Option<&Path>) -> Result<()> {
+    let text = match format {
+        ExportFormat::Ics | ExportFormat::Org | ExportFormat::Html if all => {
+            return Err(TodoError::Usage(
+                "--all only applies to --format json".to_string(),
+            ));
+        }
+        ExportFormat::Ics => ics::to_ics(data.list(), Local::now()),
+        ExportFormat::Org => org::to_org(&data.ordered()),
+        ExportFormat::Json => data.to_json(all)?,
+        ExportFormat::Html => html::to_html(&data.ordered(), Local::now()),
+    };
+    match output {
+        Some(path) => {
+            fs::write(path, text).map_err(|source| TodoError::WriteFile {
+                path: path.to_path_buf(),
+                source,
+            })?;
+            report!("Tasks exported to {}", path.display());
+        }
+        None => io::stdout().write_all(text.as_bytes())?,
+    }
+    Ok(())
+}
+
+/// Checks the storage file for damage, and repairs it if `fix` is set.
+/// Returns whether the file is sound, or was made so.
+fn check_storage(storage_path: &Path, fix: bool, config: &Config) -> Result<bool> {
+    // Locking would create a file given by mistake
+    fs::metadata(storage_path).map_err(|source| TodoError::ReadFile {
+        path: storage_path.to_path_buf(),
+        source,
+    })?;
+    let _lock = lock_storage(storage_path)?;
+    let examination = doctor::examine(storage_path);
+    if examination.problems.is_empty() {
+        println!(
+            "{} is sound, {} tasks",
+            storage_path.display(),
+            examination.tasks
+        );
+        return Ok(true);
+    }
+
+    println!("Problems in {}:", storage_path.display());
+    for problem in &examination.problems {
+        println!("  {problem}");
+    }
+    if !examination.problems.iter().all(Problem::is_fixable) {
+        println!("It can't be repaired, `todo restore-backup` lists the backups to restore");
+        return Ok(false);
+    }
+    if !fix {
+        println!("Run `todo doctor --fix` to repair them");
+        return Ok(false);
+    }
+    let count = examination.problems.len();
+    let compression = (config.compression, config.compression_level);
+    let snapshot = doctor::repair(examination, config.format, compression)?;
+    report!(
+        "Repaired {count} problems, the file as it was is saved to {}",
+        snapshot.display()
+    );
+    Ok(true)
+}
+
+/// Lists the available backups, or restores the one numbered `index`
+/// in that listing into the active storage.
+/// The current storage is snapshotted before it is replaced.
+fn restore_backup(storage_path: &Path, index: Option<usize>) -> Result<()> {
+    let backups = list_backups(storage_path)?;
+    if backups.is_empty() {
+        println!("No Backups!");
+        return Ok(());
+    }
+
+    let Some(index) = index else {
+        for (i, backup) in backups.iter().enumerate() {
+            let meta = std::fs::metadata(backup)?;
+            let modified: DateTime<Local> = meta.modified()?.into();
+            println!(
+                "{}. {} ({}, {} bytes)",
+                i + 1,
+                backup.display(),
+                modified.format("%Y-%m-%d %H:%M:%S"),
+                meta.len()
+            );
+        }
+        return Ok(());
+    };
+
+    let backup = index
+        .checked_sub(1)
+        .and_then(|i| backups.get(i))
+        .ok_or(TodoError::BackupNotFound(index))?;
+
+    let snapshot = {
+        let _lock = lock_storage(storage_path)?;
+        restore(storage_path, backup)?
+    };
+    report!("Current data saved to {}", snapshot.display());
+    report!("Restored {}", backup.display());
+    Ok(())
+}
+
+/// Adds the tasks that can still be read from the storage file at
+/// `path`, a damaged one or a backup. Tasks already in the store are
+/// skipped, and the parents and blockers of the others point at their
+/// new ids.
+fn recover(path: &Path, data: &mut Storage) -> Result<()> {
+    let load_err = |source| TodoError::Load {
+        path: path.to_path_buf(),
+        source,
+    };
+    let salvaged = salvage(path).map_err(load_err)?;
+    if salvaged.is_empty() {
+        return Err(load_err(LoadError::Format(
+            "no task can be read from it".to_string(),
+        )));
+    }
+
+    // The ids of the tasks in the file, and theirs in the store
+    let mut ids: BTreeMap<Id, Id> = BTreeMap::new();
+    let mut added = Vec::new();
+    let mut skipped = 0;
+    for task in salvaged {
+        let old_id = task.id;
+        if let Some(kept) = data.list().find(|kept| is_copy(kept, &task)) {
+            ids.insert(old_id, kept.id);
+            skipped += 1;
+            continue;
+        }
+        let (parent, blocked_by) = (task.parent, task.blocked_by.clone());
+        let (created_at, updated_at) = (task.created_at, task.updated_at);
+        let Some(id) = data.add(Task {
+            parent: None,
+            blocked_by: Vec::new(),
+            ..task
+        }) else {
+            continue;
+        };
+        if let Some(task) = data.get_mut(id) {
+            task.created_at = created_at.or(task.created_at);
+            task.updated_at = updated_at.or(task.updated_at);
+        }
+        ids.insert(old_id, id);
+        added.push((id, parent, blocked_by));
+    }
+
+    for (id, parent, blocked_by) in &added {
+        if let Some(parent) = parent.and_then(|parent| ids.get(&parent))
+            && let Some(task) = data.get_mut(*id)
+        {
+            task.parent = Some(*parent);
+        }
+        for on in blocked_by.iter().filter_map(|on| ids.get(on)) {
+            if let Err(err) = data.block(*id, *on) {
+                log::warn!("{err}, dependency ignored");
+            }
+        }
+    }
+
+    report!("{} tasks recovered from {}!", added.len(), path.display());
+    if skipped > 0 {
+        report!("{skipped} tasks were in the store already and skipped");
+    }
+    Ok(())
+}
+
+/// Whether a recovered task is a copy of `kept`: the same task, known by
+/// its uid, or one written the same.
+fn is_copy(kept: &Task, task: &Task) -> bool {
+    // Tasks without a uid or creation time are only known by their id
+    let known = task.uid.is_some() || task.created_at.is_some();
+    (known && ics::uid(kept) == ics::uid(task))
+        || (kept.head == task.head && kept.body == task.body)
+}
+
+/// Applies `fields` to the tasks among `ids` that match `filter`, or to
+/// every task matching it if no ids are given.
+fn modify(ids: &[Id], filter: &Filter, fields: &ModifyFields, data: &mut Storage) -> Result<()> {
+    if fields.is_empty() {
+        return Err(TodoError::Usage("no changes given".to_string()));
+    }
+    for &id in ids {
+        data.get(id).ok_or(TodoError::TaskNotFound(id))?;
+    }
+    let today = Local::now().date_naive();
+    let selected = data
+        .list()
+        .filter(|task| ids.is_empty() || ids.contains(&task.id))
+        .filter(|task| filter.matches(task, today))
+        .map(|task| task.id)
+        .collect::<Vec<Id>>();
+    if selected.is_empty() {
+        println!("No Tasks!");
+        return Ok(());
+    }
+
+    for id in selected {
+        let Some(current) = data.get(id) else {
+            continue;
+        };
+        let mut updated = current.clone();
+        fields.apply(&mut updated);
+        if *current != updated
+            && let Some(task) = data.get_mut(id)
+        {
+            *task = Task {
+                updated_at: task.updated_at,
+                ..updated
+            };
+            report!("Task {id} updated!");
+        }
+    }
+    Ok(())
+}
+
+/// Merges task `duplicate` into task `id`, or previews the merged task
+/// if `confirm` isn't set.
+fn merge(id: Id, duplicate: Id, confirm: bool, data: &mut Storage) -> Result<()> {
+    if !confirm {
+        let merged = data.merged(id, duplicate)?;
+        let mut out = Vec::new();
+        write_task(&mut out, &merged, None, false, data)?;
+        page(&out)?;
+        eprintln!("Task {duplicate} would be deleted. Re-run with `--confirm` to proceed.");
+        return Ok(());
+    }
+
+    let new_id = data.merge(id, duplicate)?;
+    report!("Task {duplicate} merged into task {id}!");
+    if new_id != id {
+        report!("Task {id} is now task {new_id}.");
+    }
+    Ok(())
+}
+
+/// Lists the deleted tasks, numbered for `trash --restore`.
+fn list_trash(data: &Storage) -> Result<()> {
+    if data.trash().is_empty() {
+        println!("The trash is empty!");
+        return Ok(());
+    }
+
+    let mut out = Vec::new();
+    for (i, task) in data.trash().iter().enumerate() {
+        let deleted = task
+            .deleted_at
+            .map(|at| {
+                format!(
+                    " (deleted {}, was task {})",
+                    at.format(MOMENT_FORMAT),
+                    task.id
+                )
+            })
+            .unwrap_or_default();
+        writeln!(out, "{}. {}{}", i + 1, task.head, style::dim(&deleted))?;
+    }
+    page(&out)
+}
+
+/// Deletes the tasks that are done, or every task if `all` is set,
+/// after asking for confirmation.
+/// A snapshot of the storage file is taken first, unless it's `None`.
+fn clear(storage_path: Option<&Path>, all: bool, data: &mut Storage) -> Result<()> {
+    let ids = data
+        .list()
+        .filter(|task| all || task.done)
+        .map(|task| task.id)
+        .collect::<Vec<Id>>();
+    if ids.is_empty() {
+        println!("No Tasks!");
+        return Ok(());
+    }
+    if !confirm_count("Delete", ids.len())? {
+        return Ok(());
+    }
+
+    if let Some(storage_path) = storage_path {
+        let snapshot = snapshot_data(storage_path)?;
+        report!("Snapshot saved to {}", snapshot.display());
+    }
+    let deleted = data.delete(&ids);
+    report!("{} task(s) deleted!", deleted.len());
+    Ok(())
+}
+
+/// Clears every task from the store, if `confirm` is set.
+/// A snapshot of the storage file is taken first, unless it's `None`,
+/// so the reset can be undone by hand.
+fn reset(storage_path: Option<&Path>, confirm: bool, data: &mut Storage) -> Result<()> {
+    if !confirm {
+        return Err(TodoError::Usage(
+            "this deletes every task, re-run with `--confirm` to proceed".to_string(),
+        ));
+    }
+
+    if let Some(storage_path) = storage_path {
+        let snapshot = snapshot_data(storage_path)?;
+        report!("Snapshot saved to {}", snapshot.display());
+    }
+
+    data.clear();
+    report!("All tasks cleared!");
+    Ok(())
+}
+
+/// Writes a throwaway store filled with `DEMO_TASKS` to a new file in
+/// the temp directory, leaving the real store untouched.
+fn demo() -> Result<()> {
+    // A name of its own, as a predictable one could be a link planted
+    // by another user; kept once written, for the user to try
+    let demo_path = tempfile::Builder::new()
+        .prefix("todo-demo")
+        .suffix(".bin")
+        .tempfile()?
+        .into_temp_path()
+        .keep()
+        .map_err(|err| err.error)?;
+    let mut data = Storage::default();
+    for (head, body) in DEMO_TASKS {
+        add_one(
+            Task {
+                head: head.to_string(),
+                body: body.to_string(),
+                ..Default::default()
+            },
+            &mut data,
+        )?;
+    }
+    data.save(&demo_path, StorageFormat::default())?;
+
+    println!("Demo store written to {}", demo_path.display());
+    println!("Try: TODO_STORAGE={} todo list", demo_path.display());
+    Ok(())
+}
+
+/// Completes task ids, showing the head of each task.
+/// The store is read as it is, under the lock: a damaged one is left
+/// alone, without backups or messages to mix into the candidates.
+fn task_id_candidates() -> Vec<CompletionCandidate> {
+    let data = get_storage().ok().and_then(|path| {
+        let _lock = lock_storage(&path).ok()?;
+        read_document(&path).ok()?.into_storage().ok()
+    });
+    let Some(data) = data else {
+        return Vec::new();
+    };
+    data.list()
+        .map(|task| {
+            CompletionCandidate::new(task.id.to_string()).help(Some(task.head.clone().into()))
+        })
+        .collect()
+}
+
+/// Prints a completion script for the shell.
+/// The default script calls back into `todo` to complete task ids,
+/// the static one only knows about commands and flags.
+fn print_completions(shell: Shell, static_script: bool) -> Result<()> {
+    let mut cmd = Cli::command();
+    let mut stdout = io::stdout();
+    if static_script {
+        clap_complete::generate(shell, &mut cmd, "todo", &mut stdout);
+        return Ok(());
+    }
+
+    let completer = std::env::current_exe()?;
+    let shells = Shells::builtins();
+    let env_completer = shells
+        .completer(&shell.to_string())
+        .ok_or_else(|| TodoError::Usage(format!("no dynamic completion for {shell}")))?;
+    env_completer.write_registration(
+        COMPLETE_VAR,
+        "todo",
+        "todo",
+        &completer.to_string_lossy(),
+        &mut stdout,
+    )?;
+    Ok(())
+}
+
+/// Prints the man page, or writes pages for the command and each
+/// subcommand to `out_dir`.
+fn print_man(out_dir: Option<&Path>) -> Result<()> {
+    let cmd = Cli::command();
+    match out_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            clap_mangen::generate_to(cmd, dir)?;
+            println!("Man pages written to {}", dir.display());
+        }
+        None => clap_mangen::Man::new(cmd).render(&mut io::stdout())?,
+    }
+    Ok(())
+}
+
+/// Runs `todo-<name>` from the PATH for a command `todo` doesn't know,
+/// with the rest of `args`, and returns its exit code. The plugin is
+/// told where the store is in `TODO_STORAGE`, where the config is in
+/// `TODO_CONFIG` and how to run `todo` in `TODO_EXE`.
+fn run_external(args: &[String]) -> Result<u8> {
+    let (name, rest) = args
+        .split_first()
+        .ok_or_else(|| TodoError::Usage("no command given".to_string()))?;
+    let program = format!("todo-{name}");
+    let mut command = Command::new(&program);
+    command
+        .args(rest)
+        .env("TODO_STORAGE", get_storage()?)
+        .env("TODO_CONFIG", get_config_path());
+    if let Ok(exe) = std::env::current_exe() {
+        command.env("TODO_EXE", exe);
+    }
+    log::debug!("running {program}");
+    let status = command.status().map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => TodoError::Usage(format!(
+            "unknown command `{name}`, and no `{program}` on the PATH, see `todo --help`"
+        )),
+        _ => TodoError::Usage(format!("cannot run {program}: {err}")),
+    })?;
+    // Killed by a signal, most likely
+    Ok(status.code().map_or(1, |code| code as u8))
+}
+
+/// Hides a task from the list for `duration`, and moves its due date,
+/// if it has one, forward by as many whole days.
+fn snooze(id: Id, duration: TimeDelta, data: &mut Storage) -> Result<()> {
+    let task = data.get_mut(id).ok_or(TodoError::TaskNotFound(id))?;
+    // Past the last date there is
+    let too_far = || TodoError::InvalidDuration(format_duration(duration));
+    let until = Local::now()
+        .checked_add_signed(duration)
+        .ok_or_else(too_far)?;
+    let due = task
+        .due
+        .map(|due| {
+            due.checked_add_signed(TimeDelta::days(duration.num_days()))
+                .ok_or_else(too_far)
+        })
+        .transpose()?;
+    task.snoozed_until = Some(until);
+    task.due = due;
+    task.history.push(HistoryEntry {
+        at: Local::now(),
+        event: Event::Snooze { until },
+    });
+
+    report!("Task {id} snoozed until {}!", until.format(MOMENT_FORMAT));
+    if let Some(due) = task.due {
+        report!("Task {id} is now due {due}");
+    }
+    Ok(())
+}
+
+/// Goes over the open tasks matching `filter` unchanged for
+/// `older_than`, least recently changed first, asking what to do with
+/// each. Tasks deleted are deleted at the end, as deleting renumbers the
+/// others.
+fn review(data: &mut Storage, filter: &Filter, older_than: TimeDelta) -> Result<()> {
+    let now = Local::now();
+    let today = now.date_naive();
+    let mut stale = data
+        .list()
+        .filter(|task| !task.done && !task.is_snoozed(now) && filter.matches(task, today))
+        .filter_map(|task| Some((task.updated_at.or(task.created_at)?, task.id)))
+        .filter(|(changed, _)| *changed <= now - older_than)
+        .collect::<Vec<_>>();
+    stale.sort();
+    if stale.is_empty() {
+        println!(
+            "No task has gone unchanged for {}!",
+            format_duration(older_than)
+        );
+        return Ok(());
+    }
+
+    let total = stale.len();
+    let mut reviewed = 0;
+    let mut deleted = Vec::new();
+    'tasks: for (position, (changed, id)) in (1..).zip(stale) {
+        let Some(task) = data.get(id) else {
+            continue;
+        };
+        println!("[{position}/{total}] {}", list_line(task, data, now));
+        println!(
+            "{}",
+            style::dim(&format!("  last changed {}", show_moment(changed, now)))
+        );
+        loop {
+            let Some(action) = prompt::ask(
+                "[d]one, [s]nooze, [p]riority, [r]emove, [k]eep, Enter to skip, [q]uit",
+                None,
+            )?
+            else {
+                break 'tasks;
+            };
+            match action.to_lowercase().as_str() {
+                "d" | "done" => {
+                    if let Err(err) = check_unblocked(&[id], data) {
+                        eprintln!("{err}");
+                        continue;
+                    }
+                    mark_done(&[id], true, data);
+                }
+                "s" | "snooze" => {
+                    let default = Some(DEFAULT_REVIEW_SNOOZE.to_string());
+                    match ask_until("Snooze for", default, parse_duration)? {
+                        Some(duration) => snooze(id, duration, data)?,
+                        None => break 'tasks,
+                    }
+                }
+                "p" | "priority" => {
+                    let current = data
+                        .get(id)
+                        .and_then(|task| task.priority)
+                        .map(|priority| priority.to_string());
+                    let Some(priority) = ask_until(
+                        "Priority: low, medium or high, `-` for none",
+                        current,
+                        |priority| match priority {
+                            "" | "-" => Ok(None),
+                            priority => parse_priority(priority).map(Some),
+                        },
+                    )?
+                    else {
+                        break 'tasks;
+                    };
+                    if let Some(task) = data.get_mut(id) {
+                        task.priority = priority;
+                    }
+                    match priority {
+                        Some(priority) => report!("Task {id} is now {priority} priority!"),
+                        None => report!("Task {id} has no priority now!"),
+                    }
+                }
+                "r" | "remove" => deleted.push(id),
+                "k" | "keep" => {
+                    // Changing it marks it reviewed
+                    data.get_mut(id);
+                    report!("Task {id} kept!");
+                }
+                "" => {}
+                "q" | "quit" => break 'tasks,
+                _ => {
+                    eprintln!("Unknown answer `{action}`, try again");
+                    continue;
+                }
+            }
+            reviewed += 1;
+            break;
+        }
+    }
+
+    delete_todos(&deleted, data);
+    report!("{reviewed} of {total} tasks reviewed!");
+    Ok(())
+}
+
+/// Counts down `length` for a task, then logs it as time worked on the
+/// task and shows a notification. Nothing is logged if the countdown is
+/// interrupted.
+fn pomodoro(id: Id, length: TimeDelta, session: &mut Session) -> Result<()> {
+    let head = session
+        .data
+        .get(id)
+        .ok_or(TodoError::TaskNotFound(id))?
+        .head
+        .clone();
+    if let Some(running) = session.data.running_timer() {
+        return Err(TodoError::TimerRunning(running));
+    }
+
+    let start = Local::now();
+    let end = start + length;
+    let mut stdout = io::stdout();
+    let live = stdout.is_terminal();
+    println!("Pomodoro on task {id}: {}", style::bold(&head));
+    loop {
+        let left = end - Local::now();
+        if left <= TimeDelta::zero() {
+            break;
+        }
+        if live {
+            let seconds = left.num_seconds();
+            print!("\r{:02}:{:02} left ", seconds / 60, seconds % 60);
+            stdout.flush()?;
+        }
+        thread::sleep(Duration::from_secs(1).min(left.to_std().unwrap_or_default()));
+    }
+    if live {
+        println!();
+    }
+
+    // Other commands may have changed the store meanwhile
+    session.save()?;
+    session.reload()?;
+    let task = session
+        .data
+        .get_mut(id)
+        .ok_or(TodoError::TaskNotFound(id))?;
+    task.intervals.push(Interval {
+        start,
+        end: Some(Local::now()),
+    });
+
+    report!(
+        "Pomodoro done, {} logged on task {id}!",
+        format_time(length)
+    );
+    if let Err(err) = notify::send("Pomodoro done", &format!("Task {id}: {head}")) {
+        eprintln!("Error: {err}");
+    }
+    Ok(())
+}
+
+/// Prints the time worked on each task, and in total.
+/// With `week`, only this week counts and the time worked on each of
+/// its days is printed too.
+fn timesheet(data: &Storage, week: bool) -> Result<()> {
+    let now = Local::now();
+    let today = now.date_naive();
+    let monday = today - TimeDelta::days(i64::from(today.weekday().num_days_from_monday()));
+    let from = if week {
+        start_of_day(monday)
+    } else {
+        DateTime::<Local>::MIN_UTC.into()
+    };
+
+    let mut out = Vec::new();
+    let mut total = TimeDelta::zero();
+    for task in data.list() {
+        let spent = task.time_spent(from, now);
+        if spent.is_zero() {
+            continue;
+        }
+        total += spent;
+        let running = if task.is_timing() { " (running)" } else { "" };
+        writeln!(
+            out,
+            "{}. {}: {}{running}",
+            task.id,
+            style::bold(&task.head),
+            format_time(spent)
+        )?;
+    }
+    if total.is_zero() {
+        println!("No time tracked!");
+        return Ok(());
+    }
+    writeln!(out, "Total: {}", format_time(total))?;
+
+    if week {
+        writeln!(out, "Per day:")?;
+        for day in monday.iter_days().take_while(|day| *day <= today) {
+            let next_day = day.succ_opt().unwrap_or(day);
+            let spent = data
+                .list()
+                .map(|task| task.time_spent(start_of_day(day), start_of_day(next_day).min(now)))
+                .sum::<TimeDelta>();
+            writeln!(out, "  {}: {}", day.format(DAY_FORMAT), format_time(spent))?;
+        }
+    }
+    page(&out)
+}
+
+/// Prints the estimated work left on open tasks, per due day in the
+/// coming week and per tag. Days over `capacity` are marked.
+fn workload(data: &Storage, capacity: TimeDelta) -> Result<()> {
+    let now = Local::now();
+    let today = now.date_naive();
+    let week_end = today + chrono::Days::new(7);
+
+    // Overdue work is grouped under `Some(None)`, work without a due
+    // date or due after this week under `None`
+    let mut by_day = BTreeMap::<Option<Option<NaiveDate>>, TimeDelta>::new();
+    let mut by_tag = BTreeMap::<&str, TimeDelta>::new();
+    let mut total = TimeDelta::zero();
+    for task in data.list() {
+        let Some(left) = task.remaining_estimate(now).filter(|left| !left.is_zero()) else {
+            continue;
+        };
+        total += left;
+        let day = task
+            .due
+            .filter(|due| *due < week_end)
+            .map(|due| (due >= today).then_some(due));
+        *by_day.entry(day).or_default() += left;
+        for tag in &task.tags {
+            *by_tag.entry(tag).or_default() += left;
+        }
+    }
+    if total.is_zero() {
+        println!("No estimated work left!");
+        return Ok(());
+    }
+
+    let mut out = Vec::new();
+    writeln!(out, "By day:")?;
+    // Work without a day goes last
+    let (later, days): (Vec<_>, Vec<_>) = by_day.into_iter().partition(|(day, _)| day.is_none());
+    for (day, left) in days.into_iter().chain(later) {
+        let line = match day {
+            Some(None) => format!("Overdue: {}", format_time(left)),
+            Some(Some(day)) => format!("{}: {}", day.format(DAY_FORMAT), format_time(left)),
+            None => format!("Later or no due date: {}", format_time(left)),
+        };
+        if matches!(day, Some(Some(_))) && left > capacity {
+            let over = format!(" (over capacity by {})", format_time(left - capacity));
+            writeln!(out, "  {}", style::red(&(line + &over)))?;
+        } else {
+            writeln!(out, "  {line}")?;
+        }
+    }
+    if !by_tag.is_empty() {
+        writeln!(out, "By tag:")?;
+        for (tag, left) in by_tag {
+            writeln!(out, "  #{tag}: {}", format_time(left))?;
+        }
+    }
+    writeln!(out, "Total: {}", format_time(total))?;
+    page(&out)
+}
+
+/// The moment a day starts, in local time.
+fn start_of_day(day: NaiveDate) -> DateTime<Local> {
+    day.and_time(NaiveTime::MIN)
+        .and_local_timezone(Local)
+        .earliest()
+        .unwrap_or_else(|| day.and_time(NaiveTime::MIN).and_utc().into())
+}
+
+/// Formats time worked as hours and minutes, e.g. `2h05m`.
+fn format_time(time: TimeDelta) -> String {
+    format!("{}h{:02}m", time.num_hours(), time.num_minutes() % 60)
+}
+
+/// Lists the upcoming reminders, of all tasks or just the one with `id`.
+fn list_reminders(data: &Storage, leads: &[TimeDelta], id: Option<Id>) -> Result<()> {
+    if let Some(id) = id {
+        data.get(id).ok_or(TodoError::TaskNotFound(id))?;
+    }
+
+    let now = Local::now();
+    let upcoming = reminders(data, leads)
+        .into_iter()
+        .filter(|reminder| reminder.at > now && id.is_none_or(|id| reminder.id == id))
+        .collect::<Vec<_>>();
+    if upcoming.is_empty() {
+        println!("No Reminders!");
+    }
+    for reminder in upcoming {
+        println!(
+            "{} {}",
+            reminder.at.format(MOMENT_FORMAT),
+            reminder.message()
+        );
+    }
+    Ok(())
+}
+
+/// Shows a desktop notification for each open task that is overdue or
+/// due `within` from now.
+fn notify_due(data: &Storage, within: TimeDelta) -> Result<()> {
+    let now = Local::now();
+    let today = now.date_naive();
+    let horizon = now
+        .checked_add_signed(within)
+        .ok_or_else(|| {
+            TodoError::Usage(format!(
+                "cannot look {} ahead, that is past the last date there is",
+                format_duration(within)
+            ))
+        })?
+        .date_naive();
+
+    let mut shown = 0;
+    for task in data.list().filter(|task| !task.done) {
+        let Some(due) = task.due.filter(|due| *due <= horizon) else {
+            continue;
+        };
+        let summary = match due.cmp(&today) {
+            Ordering::Less => format!("Overdue since {due}"),
+            Ordering::Equal => "Due today".to_string(),
+            Ordering::Greater => format!("Due {due}"),
+        };
+        notify::send(&summary, &format!("Task {}: {}", task.id, task.head))?;
+        shown += 1;
+    }
+    report!("{shown} notifications shown!");
+    Ok(())
+}
+
+/// Checks for reminders every `interval` seconds and gives the ones
+/// that fell due since the last check, until interrupted.
+/// The store is reloaded for every check, to pick up changes made by
+/// other commands meanwhile.
+fn remind_daemon(storage_path: &Path, leads: &[TimeDelta], interval: u64) -> Result<()> {
+    println!("Checking for reminders every {interval}s, press Ctrl-C to stop");
+    let mut last_check = Local::now();
+    loop {
+        thread::sleep(Duration::from_secs(interval));
+        let now = Local::now();
+        let data = {
+            let _lock = lock_storage(storage_path)?;
+            Storage::load(storage_path)?
+        };
+        for reminder in reminders_between(&data, leads, last_check, now) {
+            println!(
+                "{} {}",
+                reminder.at.format(MOMENT_FORMAT),
+                reminder.message()
+            );
+            // The reminder is printed either way, so don't give up
+            if let Err(err) = notify::send("Reminder", &reminder.message()) {
+                eprintln!("Error: {err}");
+            }
+        }
+        last_check = now;
+    }
+}
+
+/// Prints statistics about the tasks in the store.
+fn show_stats(data: &Storage) {
+    let stats = Stats::compute(data, Local::now());
+
+    println!("Tasks: {}", stats.total);
+    println!("Open: {}", stats.open);
+    println!("Done: {}", stats.done);
+    println!("With due date: {}", stats.with_due);
+    println!("Overdue: {}", stats.overdue);
+    if let Some(age) = stats.average_open_age {
+        println!("Average age of open tasks: {} days", age.num_days());
+    }
+
+    println!("Activity:");
+    for activity in &stats.weeks {
+        println!(
+            "  {}-W{:02}: {} added, {} completed",
+            activity.week.year(),
+            activity.week.week(),
+            activity.added,
+            activity.completed
+        );
+    }
+
+    if !stats.tags.is_empty() {
+        println!("Busiest tags:");
+        for (tag, count) in stats.tags.iter().take(BUSIEST_TAGS) {
+            println!("  #{tag}: {count}");
+        }
+    }
+
+    if !stats.chronic.is_empty() {
+        println!("Chronic rollers:");
+        for task in &stats.chronic {
+            println!(
+                "  {}. {} (rolled over {} times)",
+                task.id,
+                style::bold(&task.head),
+                task.rollovers
+            );
+        }
+    }
+}
+
+/// Prints what was completed from `since` ago until now.
+fn show_report(data: &Storage, since: TimeDelta, format: OutputFormat) -> Result<()> {
+    let now = Local::now();
+    let report = Productivity::compute(data, now - since, now);
+    let mut out = Vec::new();
+    if let OutputFormat::Json = format {
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(|err| TodoError::Serialize(err.to_string()))?;
+        writeln!(out, "{json}")?;
+        return page(&out);
+    }
+
+    writeln!(
+        out,
+        "Completed from {} to {}: {} tasks",
+        report.since, report.until, report.completed
+    )?;
+    if let Some(turnaround) = report.average_turnaround {
+        writeln!(out, "Average turnaround: {}", format_turnaround(turnaround))?;
+    }
+
+    writeln!(out, "Per day:")?;
+    let most = report
+        .days
+        .iter()
+        .map(|day| day.completed)
+        .max()
+        .unwrap_or_default()
+        .max(1);
+    for day in &report.days {
+        // Days with completions get a bar however short
+        let width = (day.completed * REPORT_BAR_WIDTH).div_ceil(most);
+        let bar = if width == 0 {
+            String::new()
+        } else {
+            format!("{} ", style::cyan(&"█".repeat(width)))
+        };
+        writeln!(
+            out,
+            "  {} {}  {bar}{}",
+            day.date,
+            day.date.format("%a"),
+            day.completed
+        )?;
+    }
+
+    if !report.tags.is_empty() {
+        writeln!(out, "By tag:")?;
+        for activity in &report.tags {
+            let turnaround = activity
+                .average_turnaround
+                .map(|turnaround| format!(", {} on average", format_turnaround(turnaround)))
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "  #{}: {} completed{turnaround}",
+                activity.tag, activity.completed
+            )?;
+        }
+    }
+    page(&out)
+}
+
+/// Formats how long a task took, e.g. `3d 4h`, or `2h 05m` under a day.
+fn format_turnaround(turnaround: TimeDelta) -> String {
+    if turnaround.num_days() > 0 {
+        format!(
+            "{}d {}h",
+            turnaround.num_days(),
+            turnaround.num_hours() % 24
+        )
+    } else {
+        format!(
+            "{}h {:02}m",
+            turnaround.num_hours(),
+            turnaround.num_minutes() % 60
+        )
+    }
+}
+
+/// The main function for the task manager.
+/// The exit code tells scripts what went wrong, see `todo::error`.
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+/// Reports an invalid command line and exits.
+/// Help and version requests exit successfully.
+fn exit_on_clap_error(err: clap::Error) -> ! {
+    let _ = err.print();
+    let code = if err.use_stderr() { EXIT_USAGE } else { 0 };
+    std::process::exit(code.into())
+}
+
+/// Unwraps a `with` command into the command it wraps and its filter.
+/// The global options given inside `with` are added to those given
+/// before it, each taken explicitly so that none is dropped.
+fn resolve_with(args: Cli) -> std::result::Result<(Cli, Filter), clap::Error> {
+    let Commands::With { filter, command } = args.command else {
+        return Ok((args, Filter::default()));
+    };
+    let inner = Cli::try_parse_from(std::iter::once("todo".to_string()).chain(command))?;
+    let (quiet, verbose) = if inner.quiet || inner.verbose > 0 {
+        (inner.quiet, inner.verbose)
+    } else {
+        (args.quiet, args.verbose)
+    };
+    let cli = Cli {
+        command: inner.command,
+        color: if inner.color != ColorChoice::Auto {
+            inner.color
+        } else {
+            args.color
+        },
+        no_pager: args.no_pager || inner.no_pager,
+        absolute_dates: args.absolute_dates || inner.absolute_dates,
+        editor: inner.editor.or(args.editor),
+        global: args.global || inner.global,
+        yes: args.yes || inner.yes,
+        dry_run: args.dry_run || inner.dry_run,
+        quiet,
+        verbose,
+    };
+    Ok((cli, filter))
+}
+
+/// Runs the command given on the command line.
+fn run() -> Result<ExitCode> {
+    // Answer completion requests from the shell scripts
+    CompleteEnv::with_factory(Cli::command)
+        .var(COMPLETE_VAR)
+        .complete();
+
+    // Parse the cli arguments
+    let args = Cli::try_parse().unwrap_or_else(|err| exit_on_clap_error(err));
+    // `with` runs the wrapped command under a temporary filter
+    let (args, filter) = resolve_with(args).unwrap_or_else(|err| exit_on_clap_error(err));
+    style::set_color(args.color);
+    pager::set_paging(!args.no_pager);
+    date::set_absolute_dates(args.absolute_dates);
+    prompt::set_assume_yes(args.yes);
+    set_global_store(args.global);
+    report::init_logging();
+    report::set_verbosity(args.quiet, args.verbose);
+    let command = args.command;
+    if !filter.is_empty() && !command.accepts_filter() {
+        return Err(filter_not_accepted());
+    }
+
+    match command {
+        // These don't need the store
+        Commands::Demo | Commands::Completions { .. } | Commands::Man { .. } => {
+            return execute_standalone(command).map(|_| ExitCode::SUCCESS);
+        }
+        // Plugins load the store themselves
+        Commands::External(plugin_args) => {
+            if args.dry_run {
+                return Err(TodoError::Usage(
+                    "plugins cannot be run as a dry run".to_string(),
+                ));
+            }
+            return run_external(&plugin_args).map(ExitCode::from);
+        }
+        // Restoring replaces the storage file, so it must not be loaded
+        Commands::RestoreBackup { index } => {
+            if index.is_some() && args.dry_run {
+                return Err(TodoError::Usage(
+                    "restoring a backup cannot be a dry run".to_string(),
+                ));
+            }
+            let storage_path = get_storage()?;
+            return restore_backup(&storage_path, index).map(|_| ExitCode::SUCCESS);
+        }
+        // A damaged store must not be loaded, which would replace it
+        Commands::Doctor { fix, file } => {
+            if fix && args.dry_run {
+                return Err(TodoError::Usage(
+                    "repairing the store cannot be a dry run".to_string(),
+                ));
+            }
+            let storage_path = match file {
+                Some(file) => file,
+                None => get_storage()?,
+            };
+            let sound = check_storage(&storage_path, fix, &load_config())?;
+            return Ok(if sound {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::from(EXIT_STORAGE)
+            });
+        }
+        _ => {}
+    }
+
+    let mut session = Session::open(command.tasks_read_in_full(&filter))?;
+    session.dry_run = args.dry_run;
+    if args.editor.is_some() {
+        session.config.editor = args.editor;
+    }
+    match command {
+        Commands::Shell => shell(&mut session)?,
+        command => execute(command, &filter, &mut session)?,
+    }
+
+    // save the current state to disk
+    session.save()?;
+    if session.dry_run && session.data.is_dirty() {
+        eprintln!("Dry run: no changes were saved.");
+    }
+
+    // The command ran, but on an empty store
+    if session.data.was_recovered() {
+        return Ok(ExitCode::from(EXIT_STORAGE));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The store, and where and how it is saved.
+struct Session {
+    storage_path: PathBuf,
+    config: Config,
+    data: Storage,
+    /// What the storage file looked like as of loading it
+    loaded: Option<Fingerprint>,
+    /// The tasks as of loading the store, to run hooks for the changes
+    snapshot: Snapshot,
+    /// Changes are not saved, see `--dry-run`
+    dry_run: bool,
+}
+
+impl Session {
+    /// Loads the store and the config. Given the tasks to read in full,
+    /// only those and the index of the store are loaded if nothing needs
+    /// saving.
+    /// Tasks due yesterday are rolled over if the config asks for it.
+    fn open(full: Option<Vec<Id>>) -> Result<Self> {
+        // Get filepath for the storage
+        // Create one if it does not exist
+        let storage_path = get_storage()?;
+        let config = load_config();
+        // Rolling over saves the store, which takes all of it
+        let full = full.filter(|_| !config.rollover);
+
+        // Load data from the storage file
+        // If the data is corrupted, copy it to a backup file and start
+        // this session from a clean slate.
+        let (mut data, loaded) = {
+            let _lock = lock_storage(&storage_path)?;
+            let data = match &full {
+                Some(full) => Storage::load_partial(&storage_path, full)?,
+                None => Storage::load(&storage_path)?,
+            };
+            (data, fingerprint(&storage_path))
+        };
+        log::debug!(
+            "loaded {} tasks from {}",
+            data.len(),
+            storage_path.display()
+        );
+
+        let snapshot = Snapshot::of(&data);
+        data.set_trash_retention(config.trash_retention);
+        data.set_compression(config.compression, config.compression_level);
+        data.set_crdt(config.crdt);
+        if config.rollover {
+            let today = Local::now().date_naive();
+            for (id, due) in data.rollover_overdue(today) {
+                report!("Task {id} rolled over from {due} to {today}");
+            }
+        }
+
+        Ok(Session {
+            storage_path,
+            config,
+            data,
+            loaded,
+            snapshot,
+            dry_run: false,
+        })
+    }
+
+    /// Loads the store again, e.g. after other processes changed it.
+    fn reload(&mut self) -> Result<()> {
+        let _lock = lock_storage(&self.storage_path)?;
+        self.data = Storage::load(&self.storage_path)?;
+        self.loaded = fingerprint(&self.storage_path);
+        self.snapshot = Snapshot::of(&self.data);
+        self.configure();
+        Ok(())
+    }
+
+    /// Applies the settings of the config to the store, after loading it.
+    fn configure(&mut self) {
+        self.data.set_trash_retention(self.config.trash_retention);
+        self.data
+            .set_compression(self.config.compression, self.config.compression_level);
+        self.data.set_crdt(self.config.crdt);
+    }
+
+    /// Saves the store if it has unsaved changes, unless this is a dry
+    /// run. If another process, e.g. `todo serve`, saved the store since
+    /// it was loaded, its changes are merged in rather than overwritten.
+    fn save(&mut self) -> Result<()> {
+        if self.data.is_dirty() && !self.dry_run {
+            let lock = lock_storage(&self.storage_path)?;
+            self.save_locked()?;
+            // Hooks may run `todo`, which waits for the lock
+            drop(lock);
+            self.run_hooks();
+        }
+        Ok(())
+    }
+
+    /// Saves the store, changes or not, with the storage file locked by
+    /// the caller, merging in changes saved meanwhile as `save` does.
+    /// Hooks are left to `run_hooks`, once the lock is released.
+    fn save_locked(&mut self) -> Result<()> {
+        if fingerprint(&self.storage_path) != self.loaded {
+            let theirs = Storage::load(&self.storage_path)?;
+            if !theirs.was_recovered() {
+                self.resolve_conflicts(&theirs)?;
+                self.snapshot.absorb(&theirs);
+                let merged = self.data.reconcile(None, &theirs);
+                log::info!("merged changes saved meanwhile: {merged:?}");
+            }
+        }
+        self.data.save(&self.storage_path, self.config.format)?;
+        self.loaded = fingerprint(&self.storage_path);
+        log::debug!(
+            "saved {} tasks to {} as {:?}",
+            self.data.len(),
+            self.storage_path.display(),
+            self.config.format
+        );
+        Ok(())
+    }
+
+    /// Runs the hooks for the changes saved since the last time.
+    fn run_hooks(&mut self) {
+        hooks::run_after_save(&self.snapshot, &self.data, &self.storage_path);
+        self.snapshot = Snapshot::of(&self.data);
+    }
+
+    /// Asks which copy to keep of each task changed both in this session
+    /// and in `theirs`, the store saved meanwhile, when there is someone
+    /// to ask. Otherwise merging keeps the copy changed last.
+    fn resolve_conflicts(&mut self, theirs: &Storage) -> Result<()> {
+        let theirs: BTreeMap<String, &Task> =
+            theirs.list().map(|task| (ics::uid(task), task)).collect();
+        let conflicts: Vec<(Id, Task)> = self
+            .data
+            .list()
+            .filter_map(|task| {
+                let their = theirs.get(&ics::uid(task))?;
+                let both = self.snapshot.changed(task) && self.snapshot.changed(their);
+                (both && their.updated_at != task.updated_at).then(|| (task.id, (*their).clone()))
+            })
+            .collect();
+
+        for (id, their) in conflicts {
+            if !io::stdin().is_terminal() {
+                log::warn!("task {id} was also changed elsewhere, keeping the copy changed last");
+                continue;
+            }
+            let keep_theirs = confirm(&format!(
+                "Task {id} was also changed elsewhere since it was loaded. \
+                 Keep that copy, dropping the changes made here?"
+            ))?;
+            let Some(task) = self.data.get_mut(id) else {
+                continue;
+            };
+            // Merging keeps ours when both were changed at the same time
+            if keep_theirs {
+                *task = Task {
+                    id,
+                    parent: task.parent,
+                    blocked_by: std::mem::take(&mut task.blocked_by),
+                    position: task.position,
+                    ..their
+                };
+            } else {
+                task.updated_at = task.updated_at.max(their.updated_at);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs a command that doesn't need the store.
+fn execute_standalone(command: Commands) -> Result<()> {
+    match command {
+        Commands::Demo => demo(),
+        Commands::Completions {
+            shell,
+            static_script,
+        } => print_completions(shell, static_script),
+        Commands::Man { out_dir } => print_man(out_dir.as_deref()),
+        _ => unreachable!("the command needs the store"),
+    }
+}
+
+/// Runs a command against the store of the session.
+fn execute(command: Commands, filter: &Filter, session: &mut Session) -> Result<()> {
+    let data = &mut session.data;
+    let editor = resolve_editor(session.config.editor.as_deref());
+    match command {
+        Commands::List { options } => page(&render_list(data, &options.filter(filter), &options)?)?,
+
+        Commands::Watch { options } => watch(&options, filter, session)?,
+
+        Commands::Board { by_tag, all } => {
+            let definitions = match &session.config.board {
+                columns if columns.is_empty() => board::default_columns(),
+                columns => columns.clone(),
+            };
+            page(&render_board(data, filter, &definitions, by_tag, all)?)?
+        }
+
+        Commands::Next { contexts, count } => {
+            let filter = contexts.into_iter().fold(filter.clone(), Filter::context);
+            suggest_next(data, &filter, count)?
+        }
+
+        Commands::Random { contexts, tags } => {
+            let filter = tags.into_iter().fold(filter.clone(), Filter::tag);
+            let filter = contexts.into_iter().fold(filter, Filter::context);
+            pick_random(data, &filter)?
+        }
+
+        Commands::Search {
+            pattern,
+            regex,
+            ignore_case,
+            format,
+        } => {
+            let query = Query::new(&pattern, regex, ignore_case)?;
+            search(data, filter, &query, format)?
+        }
+
+        Commands::Today => agenda(data, 1)?,
+
+        Commands::Week => agenda(data, 7)?,
+
+        Commands::Cal { month } => calendar(data, month)?,
+
+        Commands::Edit { task, fields } => {
+            let Some(id) = find_task(&task, |_| true, data)? else {
+                return Ok(());
+            };
+            if fields.is_empty() {
+                edit_task(id, &editor, data)?;
+            } else {
+                update_task(id, fields, data)?;
+            }
+        }
+
+        Commands::Append { id, text } => {
+            let fields = EditFields {
+                append_body: Some(text),
+                ..Default::default()
+            };
+            update_task(id, fields, data)?;
+        }
+
+        Commands::Prepend { id, text } => prepend_body(id, &text, data)?,
+
+        Commands::Set { task, fields } => {
+            let Some(id) = find_task(&task, |_| true, data)? else {
+                return Ok(());
+            };
+            let task = data.get_mut(id).ok_or(TodoError::TaskNotFound(id))?;
+            for (key, value) in fields {
+                if value.is_empty() {
+                    task.fields.remove(&key);
+                } else {
+                    task.fields.insert(key, value);
+                }
+            }
+            report!("Task {id} updated!");
+        }
+
+        Commands::Show {
+            task,
+            pick,
+            section,
+            format,
+            raw,
+        } => {
+            let id = match task {
+                _ if pick => pick_task(data)?,
+                Some(task) => find_task(&task, |_| true, data)?,
+                None => None,
+            };
+            if let Some(id) = id {
+                show_task(id, section.as_deref(), format, raw, data)?;
+            }
+        }
+
+        Commands::Attach { copy: true, .. } if session.dry_run => {
+            return Err(TodoError::Usage(
+                "files cannot be copied in a dry run".to_string(),
+            ));
+        }
+
+        Commands::Attach {
+            id,
+            target,
+            copy,
+            remove,
+        } => {
+            let task = data.get(id).ok_or(TodoError::TaskNotFound(id))?;
+            let reference = match target {
+                Some(target) if copy => Some(attach::copy(&target, task, &session.storage_path)?),
+                Some(target) => Some(attach::reference(&target)?),
+                None => None,
+            };
+            let task = data.get_mut(id).ok_or(TodoError::TaskNotFound(id))?;
+            match (reference, remove) {
+                (Some(reference), _) => {
+                    if !task.attachments.contains(&reference) {
+                        task.attachments.push(reference);
+                    }
+                }
+                (None, Some(number)) => {
+                    let index = attachment_index(task, number)?;
+                    task.attachments.remove(index);
+                }
+                (None, None) => unreachable!("clap requires a target or --remove"),
+            }
+            report!("Task {id} updated!");
+        }
+
+        Commands::Open { id, attachment } => {
+            let task = data.get(id).ok_or(TodoError::TaskNotFound(id))?;
+            let urls = attach::urls(task);
+            let targets = match (attachment, task.attachments.as_slice()) {
+                (Some(number), _) => {
+                    vec![task.attachments[attachment_index(task, number)?].as_str()]
+                }
+                _ if !urls.is_empty() => urls,
+                (None, [only]) => vec![only.as_str()],
+                (None, []) => {
+                    return Err(TodoError::Usage(format!(
+                        "task {id} has no URLs or attachments"
+                    )));
+                }
+                (None, _) => {
+                    return Err(TodoError::Usage(format!(
+                        "task {id} has several attachments, choose one with --attachment"
+                    )));
+                }
+            };
+            for target in targets {
+                attach::open(target)?;
+            }
+        }
+
+        Commands::Note { id, text, section } => add_note(id, &text, section.as_deref(), data)?,
+
+        Commands::New {
+            head,
+            body,
+            stdin,
+            from_clipboard,
+            interactive,
+            template: template_name,
+            due,
+            urls,
+            tags,
+            contexts,
+            project,
+            parent,
+            estimate,
+            priority,
+        } => {
+            let mut project = project;
+            if let Some(parent) = parent {
+                let parent = data.get(parent).ok_or(TodoError::TaskNotFound(parent))?;
+                // Steps of a task are part of its project
+                if project.is_none() {
+                    project.clone_from(&parent.project);
+                }
+            }
+            let template = Task {
+                due,
+                attachments: urls,
+                tags,
+                contexts,
+                project,
+                parent,
+                estimate,
+                priority,
+                ..Default::default()
+            };
+            if let Some(name) = template_name {
+                let text = template::fill(&template::load(&name)?, Local::now());
+                let (template_head, template_body) =
+                    split_head_body(text.trim_start()).unwrap_or_default();
+                add_one(
+                    Task {
+                        head: head.unwrap_or(template_head),
+                        body: body.unwrap_or(template_body),
+                        ..template
+                    },
+                    data,
+                )?;
+            } else if interactive {
+                match ask_task(template)? {
+                    Some(task) => add_one(task, data)?,
+                    None => report!("New Task aborted!"),
+                }
+            } else if from_clipboard {
+                let text = clipboard::read()?;
+                let Some((head, body)) = split_head_body(text.trim()) else {
+                    report!("The clipboard is empty, New Task aborted!");
+                    return Ok(());
+                };
+                add_one(
+                    Task {
+                        head: head.trim().to_string(),
+                        body: body.trim().to_string(),
+                        ..template
+                    },
+                    data,
+                )?;
+            } else if head.as_deref() == Some("-") {
+                if body.is_some() {
+                    return Err(TodoError::Usage(
+                        "a body cannot be given when reading from stdin".to_string(),
+                    ));
+                }
+                add_new(template, true, &editor, data)?;
+            } else if head.is_none() && body.is_none() {
+                add_new(template, stdin, &editor, data)?;
+            } else {
+                add_one(
+                    Task {
+                        head: head.unwrap_or_default(),
+                        body: body.unwrap_or_default(),
+                        ..template
+                    },
+                    data,
+                )?;
+            }
+        }
+
+        Commands::Done {
+            indices,
+            pick,
+            undo,
+            force,
+        } => {
+            let mut indices = select_tasks(&indices, |task| task.done == undo, data)?;
+            if pick {
+                indices.extend(pick_task(data)?);
+            }
+            if !undo && !force {
+                check_unblocked(&indices, data)?;
+            }
+            if undo || confirm_many("Mark as done", indices.len())? {
+                mark_done(&indices, !undo, data);
+            }
+        }
+
+        Commands::Modify {
+            ids,
+            filter,
+            fields,
+        } => modify(
+            &select::flatten(&ids),
+            &filter.unwrap_or_default(),
+            &fields,
+            data,
+        )?,
+
+        Commands::Merge {
+            id,
+            duplicate,
+            confirm,
+        } => merge(id, duplicate, confirm || prompt::assume_yes(), data)?,
+
+        Commands::Move {
+            id, before, top, ..
+        } => {
+            let to = match before {
+                Some(other) => Placement::Before(other),
+                None if top => Placement::Top,
+                None => Placement::Bottom,
+            };
+            data.move_task(id, to)?;
+            report!("Task {id} moved!");
+        }
+
+        Commands::Block { id, on } => {
+            for other in on {
+                data.block(id, other)?;
+                report!("Task {id} waits for task {other}!");
+            }
+        }
+
+        Commands::Unblock { id, on } => {
+            for other in on {
+                if data.unblock(id, other)? {
+                    report!("Task {id} no longer waits for task {other}!");
+                }
+            }
+        }
+
+        Commands::Rm { indices, pick } => {
+            let mut indices = select_tasks(&indices, |_| true, data)?;
+            if pick {
+                indices.extend(pick_task(data)?);
+            }
+            if confirm_many("Delete", indices.len())? {
+                delete_todos(&indices, data);
+            }
+        }
+
+        Commands::Pick => {
+            if let Some(id) = pick_task(data)? {
+                println!("{id}");
+            }
+        }
+
+        Commands::Import {
+            source:
+                Some(ImportSource::Github {
+                    repo,
+                    assignee,
+                    update,
+                }),
+            ..
+        } => {
+            let token = session
+                .config
+                .github
+                .as_ref()
+                .map(|github| github.token.clone())
+                .or_else(|| std::env::var("GITHUB_TOKEN").ok());
+            import_github(&github::Client::new(token), &repo, &assignee, update, data)?;
+        }
+
+        Commands::Import {
+            format,
+            file,
+            lines,
+            maildir,
+            replace,
+            merge,
+            map,
+            source: None,
+        } => {
+            let format =
+                format.unwrap_or_else(|| match file.extension().and_then(|ext| ext.to_str()) {
+                    Some("csv") => ImportFormat::Csv,
+                    Some("org") => ImportFormat::Org,
+                    Some("eml") => ImportFormat::Eml,
+                    _ => ImportFormat::Lines,
+                });
+            if (replace || merge) && format != ImportFormat::Json {
+                return Err(TodoError::Usage(
+                    "--replace and --merge only apply to --format json".to_string(),
+                ));
+            }
+            if !map.is_empty() && format != ImportFormat::Csv {
+                return Err(TodoError::Usage(
+                    "--map only applies to --format csv".to_string(),
+                ));
+            }
+            if let Some(maildir) = maildir {
+                import_mail(mail::read_maildir(&maildir)?, data);
+                return Ok(());
+            }
+            match (lines, format) {
+                (Some(lines), _) => import_lines(&lines, data)?,
+                (None, ImportFormat::Lines) => import_lines(&file, data)?,
+                (None, ImportFormat::Taskwarrior) => import_taskwarrior(&file, data)?,
+                (None, ImportFormat::Org) => import_org(&file, data)?,
+                (None, ImportFormat::Csv) => import_csv(&file, &map, data)?,
+                (None, ImportFormat::Json) => import_json(&file, replace, merge, data)?,
+                (None, ImportFormat::Eml) => {
+                    let raw = read_import_bytes(&file)?;
+                    import_mail(
+                        vec![mail::parse_message(&String::from_utf8_lossy(&raw))],
+                        data,
+                    )
+                }
+            }
+        }
+
+        Commands::Export {
+            format,
+            output,
+            all,
+        } => export(data, format, all, output.as_deref())?,
+
+        Commands::Scan { path } => scan_comments(&path, data)?,
+
+        Commands::Template {
+            action: TemplateAction::Save { .. } | TemplateAction::Rm { .. },
+        } if session.dry_run => {
+            return Err(TodoError::Usage(
+                "templates cannot be changed in a dry run".to_string(),
+            ));
+        }
+
+        Commands::Tags => list_tags(data)?,
+
+        Commands::Tag {
+            action: TagAction::Rename { old, new },
+        } => {
+            let ids = data.rename_tag(&old, &new);
+            if ids.is_empty() {
+                println!("No task is tagged #{old}!");
+            } else {
+                report!("Tag #{old} renamed to #{new} on tasks {}!", join_ids(&ids));
+            }
+        }
+
+        Commands::Template { action } => manage_template(action, &editor, data)?,
+
+        Commands::Sync { .. } if session.dry_run => {
+            return Err(TodoError::Usage("syncing cannot be a dry run".to_string()));
+        }
+
+        Commands::Sync {
+            target: SyncTarget::Git,
+        } => {
+            // The file is committed, so it must be up to date, and stay
+            // so until the merged store is saved
+            let lock = lock_storage(&session.storage_path)?;
+            session.save_locked()?;
+            let mut synced = git::sync(
+                &session.storage_path,
+                &mut session.data,
+                session.config.format,
+            )?;
+            // Pulling rewrote the file, these aren't changes saved meanwhile
+            session.loaded = fingerprint(&session.storage_path);
+            if synced.fast_forwarded {
+                session.configure();
+            }
+            if synced.merged.is_some() {
+                session.save_locked()?;
+            }
+            git::finish(&session.storage_path, &mut synced)?;
+            drop(lock);
+            session.run_hooks();
+            if synced.committed {
+                report!("Changes committed!");
+            }
+            if synced.fast_forwarded {
+                report!("Changes pulled!");
+            }
+            if let Some(merged) = synced.merged {
+                report_conflicts(&merged.conflicts, "elsewhere");
+                report!(
+                    "Changes merged: {} added, {} updated, {} deleted",
+                    merged.added,
+                    merged.updated,
+                    merged.deleted
+                );
+            }
+            if synced.pushed {
+                report!("Changes pushed!");
+            }
+            if synced.no_remote {
+                report!("The repository has no remote, nothing was pulled or pushed");
+            } else if !synced.committed && !synced.fast_forwarded && !synced.pushed {
+                report!("Already up to date!");
+            }
+        }
+
+        Commands::Sync {
+            target: SyncTarget::Remote { destination },
+        } => {
+            let destination = remote::parse_destination(&destination)?;
+            // The merged store is sent as saved, so it must stay so until
+            // it is sent
+            let lock = lock_storage(&session.storage_path)?;
+            session.save_locked()?;
+            let pulled = remote::pull(&destination, &session.storage_path, &mut session.data)?;
+            session.save_locked()?;
+            let synced = remote::finish(&destination, &session.storage_path, pulled)?;
+            drop(lock);
+            session.run_hooks();
+            match &synced.merged {
+                None => report!("No copy on {} yet, creating it", destination.host),
+                Some(merged) if *merged != Reconciled::default() => {
+                    report_conflicts(&merged.conflicts, "elsewhere");
+                    report!(
+                        "Changes merged: {} added, {} updated, {} deleted",
+                        merged.added,
+                        merged.updated,
+                        merged.deleted
+                    );
+                }
+                Some(_) => {}
+            }
+            if synced.pushed {
+                report!("Changes pushed!");
+            } else if synced
+                .merged
+                .is_some_and(|merged| merged == Reconciled::default())
+            {
+                report!("Already up to date!");
+            }
+        }
+
+        Commands::Sync {
+            target: SyncTarget::Caldav,
+        } => {
+            let Some(caldav) = &session.config.caldav else {
+                return Err(TodoError::Usage(
+                    "no CalDAV server is set up, add a [caldav] table to the config".to_string(),
+                ));
+            };
+            report_sync(&caldav::sync(data, caldav)?);
+        }
+
+        #[cfg(feature = "todoist")]
+        Commands::Sync {
+            target: SyncTarget::Todoist,
+        } => {
+            let Some(todoist) = &session.config.todoist else {
+                return Err(TodoError::Usage(
+                    "no Todoist account is set up, add a [todoist] table to the config".to_string(),
+                ));
+            };
+            report_sync(&todoist::sync(data, todoist)?);
+        }
+
+        Commands::Trash { restore } => match restore {
+            Some(index) => {
+                let id = index
+                    .checked_sub(1)
+                    .and_then(|index| data.restore_deleted(index))
+                    .ok_or_else(|| TodoError::Usage(format!("there is no deleted task {index}")))?;
+                report!("Task {id} restored!");
+            }
+            None => list_trash(data)?,
+        },
+
+        Commands::Purge => {
+            let purged = data.purge();
+            report!("{purged} deleted task(s) purged!");
+        }
+
+        Commands::Clear { all, .. } => {
+            // The snapshot is taken of the file, so it must be up to date
+            session.save()?;
+            // A dry run leaves no snapshot behind
+            let snapshot = (!session.dry_run).then_some(session.storage_path.as_path());
+            clear(snapshot, all, &mut session.data)?;
+        }
+
+        Commands::Reset { confirm } => {
+            // The snapshot is taken of the file, so it must be up to date
+            session.save()?;
+            let snapshot = (!session.dry_run).then_some(session.storage_path.as_path());
+            reset(snapshot, confirm || prompt::assume_yes(), &mut session.data)?;
+        }
+
+        Commands::RestoreBackup { index } => {
+            if index.is_some() && session.dry_run {
+                return Err(TodoError::Usage(
+                    "restoring a backup cannot be a dry run".to_string(),
+                ));
+            }
+            session.save()?;
+            restore_backup(&session.storage_path, index)?;
+            session.reload()?;
+        }
+
+        Commands::Doctor { fix, file } => {
+            if fix && session.dry_run {
+                return Err(TodoError::Usage(
+                    "repairing the store cannot be a dry run".to_string(),
+                ));
+            }
+            session.save()?;
+            let storage_path = file.unwrap_or_else(|| session.storage_path.clone());
+            check_storage(&storage_path, fix, &session.config)?;
+            if fix {
+                session.reload()?;
+            }
+        }
+
+        Commands::Recover { file } => recover(&file, data)?,
+
+        Commands::Snooze { id, duration } => snooze(id, duration, data)?,
+
+        Commands::Start { task } => {
+            let Some(id) = find_task(&task, |task| !task.done, data)? else {
+                return Ok(());
+            };
+            data.start_timer(id)?;
+            report!("Timer started on task {id}!");
+        }
+
+        Commands::Stop => match data.stop_timer() {
+            Some((id, elapsed)) => {
+                report!("Timer stopped on task {id} after {}!", format_time(elapsed));
+            }
+            None => println!("No timer running!"),
+        },
+
+        Commands::Pomo { id, length } => pomodoro(id, length, session)?,
+
+        Commands::Timesheet { week } => timesheet(data, week)?,
+
+        Commands::Workload => workload(data, session.config.daily_capacity)?,
+
+        Commands::Remind {
+            id,
+            at,
+            clear,
+            daemon,
+            interval,
+        } => {
+            if daemon {
+                remind_daemon(
+                    &session.storage_path,
+                    &session.config.reminder_leads,
+                    interval,
+                )?;
+            } else if let (Some(id), Some(at)) = (id, at) {
+                let task = data.get_mut(id).ok_or(TodoError::TaskNotFound(id))?;
+                task.reminders.push(at);
+                task.reminders.sort();
+                report!(
+                    "Task {id} will be reminded of at {}!",
+                    at.format(MOMENT_FORMAT)
+                );
+            } else if let (Some(id), true) = (id, clear) {
+                let task = data.get_mut(id).ok_or(TodoError::TaskNotFound(id))?;
+                task.reminders.clear();
+                report!("Task {id} reminders cleared!");
+            } else {
+                list_reminders(data, &session.config.reminder_leads, id)?;
+            }
+        }
+
+        Commands::Notify { within } => notify_due(data, within)?,
+
+        Commands::Stats => show_stats(data),
+
+        Commands::Review { older_than } => review(data, filter, older_than)?,
+
+        Commands::Report { since, format } => show_report(data, since, format)?,
+
+        Commands::Serve { .. } if session.dry_run => {
+            return Err(TodoError::Usage("serving cannot be a dry run".to_string()));
+        }
+
+        #[cfg(unix)]
+        Commands::Daemon { .. } if session.dry_run => {
+            return Err(TodoError::Usage("serving cannot be a dry run".to_string()));
+        }
+
+        #[cfg(unix)]
+        Commands::Daemon { socket } => {
+            // Requests load the store from disk, so it must be up to date
+            session.save()?;
+            let socket = socket.unwrap_or_else(daemon::socket_path);
+            let listener = daemon::listen(&socket)?;
+            println!("Listening on {}, press Ctrl-C to stop", socket.display());
+            daemon::serve(listener, &session.storage_path, &session.config)?;
+        }
+
+        Commands::Serve { port, bind } => {
+            // Requests load the store from disk, so it must be up to date
+            session.save()?;
+            // IPv6 addresses are bracketed, e.g. `[::1]:8080`
+            let address = if bind.contains(':') {
+                format!("[{bind}]:{port}")
+            } else {
+                format!("{bind}:{port}")
+            };
+            let listener = server::listen(&address)?;
+            println!("Serving the tasks on http://{address}, press Ctrl-C to stop");
+            server::serve(listener, &session.storage_path, &session.config)?;
+        }
+
+        Commands::Demo | Commands::Completions { .. } | Commands::Man { .. } => {
+            execute_standalone(command)?
+        }
+
+        Commands::Shell => {
+            return Err(TodoError::Usage("already in a shell".to_string()));
+        }
+
+        Commands::With { .. } => {
+            return Err(TodoError::Usage("`with` cannot be nested".to_string()));
+        }
+
+        Commands::External(_) if session.dry_run => {
+            return Err(TodoError::Usage(
+                "plugins cannot be run as a dry run".to_string(),
+            ));
+        }
+
+        Commands::External(args) => {
+            // The plugin loads the store from disk
+            session.save()?;
+            let code = run_external(&args)?;
+            if code != 0 {
+                eprintln!("todo-{} exited with {code}", args[0]);
+            }
+            session.reload()?;
+        }
+    };
+    Ok(())
+}
+
+/// Reads commands from stdin and runs them against the session,
+/// keeping the store loaded in between.
+/// The store is saved on `save`, and when the shell is left with
+/// `exit`, `quit` or end of input.
+fn shell(session: &mut Session) -> Result<()> {
+    println!("todo {VERSION}: `help` lists the commands, `save` saves, `exit` quits.");
+    let stdin = io::stdin();
+    let mut line = String::new();
+    loop {
+        print!("todo> ");
+        io::stdout().flush()?;
+
+        line.clear();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            println!();
+            break;
+        }
+
+        let Some(words) = shlex::split(&line) else {
+            eprintln!("Error: unbalanced quotes");
+            continue;
+        };
+        match words.first().map(String::as_str) {
+            None => continue,
+            Some("exit" | "quit") => break,
+            Some("save") => {
+                match session.save() {
+                    Ok(()) if session.dry_run => println!("Dry run: nothing was saved."),
+                    Ok(()) => println!("Saved!"),
+                    Err(err) => eprintln!("Error: {err}"),
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let parsed = Cli::try_parse_from(std::iter::once("todo".to_string()).chain(words))
+            .and_then(resolve_with)
+            .map(|(cli, filter)| {
+                style::set_color(cli.color);
+                pager::set_paging(!cli.no_pager);
+                date::set_absolute_dates(cli.absolute_dates);
+                prompt::set_assume_yes(cli.yes);
+                report::set_verbosity(cli.quiet, cli.verbose);
+                (cli.command, filter, cli.dry_run, cli.editor, cli.global)
+            });
+        let (command, filter, dry_run, editor, global) = match parsed {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                let _ = err.print();
+                continue;
+            }
+        };
+        // The store of the session is already loaded
+        if global {
+            eprintln!(
+                "Error: `--global` cannot be given in the shell, run `todo -g shell` instead"
+            );
+            continue;
+        }
+
+        // A dry run of a single command works on a copy of the store that
+        // is thrown away afterwards
+        let before = (dry_run && !session.dry_run).then(|| session.data.clone());
+        if before.is_some() {
+            session.dry_run = true;
+        }
+        // An editor given with the command is used for it alone
+        let config_editor = editor
+            .is_some()
+            .then(|| std::mem::replace(&mut session.config.editor, editor));
+        let result = if !filter.is_empty() && !command.accepts_filter() {
+            Err(filter_not_accepted())
+        } else {
+            execute(command, &filter, session)
+        };
+        if let Some(config_editor) = config_editor {
+            session.config.editor = config_editor;
+        }
+        if let Err(err) = result {
+            eprintln!("Error: {err}");
+        }
+        if let Some(before) = before {
+            session.data = before;
+            session.dry_run = false;
+            eprintln!("Dry run: no changes were made.");
+        }
+    }
+    session.save()
 }
@@ -0,0 +1,106 @@
+//! Rendering task bodies written in Markdown for the terminal, see
+//! `todo show`.
+//!
+//! Lines are rendered one by one: headings, bullet lists, checkboxes,
+//! block quotes and fenced code blocks, and within lines bold text,
+//! emphasis, code and links. Anything else is printed as written.
+
+use crate::style;
+
+/// Renders `text`, line by line.
+pub fn render(text: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_code = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        lines.push(if in_code {
+            format!("  {}", style::cyan(line))
+        } else {
+            render_line(line)
+        });
+    }
+    lines.join("\n")
+}
+
+/// Renders a line outside code blocks.
+fn render_line(line: &str) -> String {
+    let trimmed = line.trim_start();
+    let indent = &line[..line.len() - trimmed.len()];
+
+    let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&hashes)
+        && let Some(heading) = trimmed[hashes..].strip_prefix(' ')
+    {
+        return format!("{indent}{}", style::bold(&inline(heading.trim())));
+    }
+    if let Some(quote) = trimmed.strip_prefix('>') {
+        return format!(
+            "{indent}{}",
+            style::dim(&format!("│ {}", quote.trim_start()))
+        );
+    }
+
+    let Some(item) = ["- ", "* ", "+ "]
+        .iter()
+        .find_map(|bullet| trimmed.strip_prefix(bullet))
+    else {
+        return format!("{indent}{}", inline(trimmed));
+    };
+    if let Some(open) = item.strip_prefix("[ ] ") {
+        format!("{indent}☐ {}", inline(open))
+    } else if let Some(done) = item
+        .strip_prefix("[x] ")
+        .or_else(|| item.strip_prefix("[X] "))
+    {
+        format!("{indent}☑ {}", style::dim(done))
+    } else {
+        format!("{indent}• {}", inline(item))
+    }
+}
+
+/// Renders the spans within a line: `**bold**`, `*emphasis*`, `` `code` ``
+/// and `[links](url)`. Markers left open are printed as written.
+fn inline(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(['*', '`', '[']) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let (rendered, len) = if let Some(inner) = enclosed(rest, "**") {
+            (style::bold(inner), inner.len() + 4)
+        } else if let Some(inner) = enclosed(rest, "`") {
+            (style::cyan(inner), inner.len() + 2)
+        } else if let Some(inner) = enclosed(rest, "*") {
+            (style::italic(inner), inner.len() + 2)
+        } else if let Some((label, url)) = link(rest) {
+            let rendered = format!("{label} {}", style::dim(&format!("<{url}>")));
+            (rendered, label.len() + url.len() + 4)
+        } else {
+            (rest[..1].to_string(), 1)
+        };
+        out.push_str(&rendered);
+        rest = &rest[len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The text between `marker` at the start of `text` and the next one,
+/// if it isn't empty.
+fn enclosed<'a>(text: &'a str, marker: &str) -> Option<&'a str> {
+    let after = text.strip_prefix(marker)?;
+    let end = after.find(marker)?;
+    let inner = &after[..end];
+    (!inner.is_empty() && !inner.starts_with(' ')).then_some(inner)
+}
+
+/// The label and URL of a `[label](url)` link at the start of `text`.
+fn link(text: &str) -> Option<(&str, &str)> {
+    let after = text.strip_prefix('[')?;
+    let (label, after) = after.split_once("](")?;
+    let end = after.find(')')?;
+    (!label.contains(']')).then_some((label, &after[..end]))
+}
@@ -0,0 +1,158 @@
+//! Merging two copies of a task changed apart, given the copy both
+//! started from, as the syncs do.
+//!
+//! Each field is merged on its own. A field changed in one copy takes
+//! that change, and a field changed in both to different values is a
+//! conflict, settled by the copy changed last. Lists, such as the tags
+//! and the notes, take what either copy added and lose what either
+//! removed, so they don't conflict. Without the copy both started from
+//! nothing tells which copy changed a field, and every field that
+//! differs is a conflict.
+
+use std::collections::BTreeSet;
+
+use crate::task::Task;
+
+/// A task changed in both copies in the same fields, and which copy was
+/// kept for them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conflict {
+    /// The head of the merged task
+    pub head: String,
+    /// The fields given different values in each copy, custom fields by
+    /// their key
+    pub fields: Vec<String>,
+    /// The other copy's values were kept, as it was changed last
+    pub kept_theirs: bool,
+}
+
+/// A merged task, and the conflict met merging it, if any.
+#[derive(Debug)]
+pub struct Merged {
+    pub task: Task,
+    pub conflict: Option<Conflict>,
+}
+
+/// Merges `ours` and `theirs`, two copies of a task, field by field.
+/// `base` is the copy both started from, `None` if unknown. The merged
+/// task keeps the id of ours, and the ids `theirs` and `base` point at,
+/// e.g. their parent, are taken to be those of the tasks of ours.
+pub fn merge(base: Option<&Task>, ours: &Task, theirs: &Task) -> Merged {
+    let mut copies = Fields {
+        base,
+        ours,
+        theirs,
+        theirs_later: theirs.updated_at > ours.updated_at,
+        conflicts: Vec::new(),
+    };
+    let (done, completed_at) = copies.pick("done", |task| (task.done, task.completed_at));
+    let mut task = Task {
+        id: ours.id,
+        head: copies.pick("head", |task| task.head.clone()),
+        body: copies.pick("body", |task| task.body.clone()),
+        due: copies.pick("due", |task| task.due),
+        tags: copies.combine("tags", |task| &task.tags),
+        contexts: copies.combine("contexts", |task| &task.contexts),
+        project: copies.pick("project", |task| task.project.clone()),
+        parent: copies.pick("parent", |task| task.parent),
+        priority: copies.pick("priority", |task| task.priority),
+        position: copies.pick("position", |task| task.position),
+        done,
+        completed_at,
+        link: copies.pick("link", |task| task.link.clone()),
+        attachments: copies.combine("attachments", |task| &task.attachments),
+        blocked_by: copies.combine("blocked by", |task| &task.blocked_by),
+        reminders: copies.combine("reminders", |task| &task.reminders),
+        snoozed_until: copies.pick("snoozed", |task| task.snoozed_until),
+        intervals: copies.combine("time tracked", |task| &task.intervals),
+        estimate: copies.pick("estimate", |task| task.estimate),
+        rollovers: ours.rollovers.max(theirs.rollovers),
+        history: copies.combine("history", |task| &task.history),
+        notes: copies.combine("notes", |task| &task.notes),
+        created_at: [ours.created_at, theirs.created_at]
+            .into_iter()
+            .flatten()
+            .min(),
+        updated_at: ours.updated_at.max(theirs.updated_at),
+        ..ours.clone()
+    };
+
+    let keys = ours
+        .fields
+        .keys()
+        .chain(theirs.fields.keys())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    task.fields.clear();
+    for key in keys {
+        if let Some(value) = copies.pick(&key, |task| task.fields.get(&key).cloned()) {
+            task.fields.insert(key, value);
+        }
+    }
+
+    let conflict = (!copies.conflicts.is_empty()).then(|| Conflict {
+        head: task.head.clone(),
+        fields: copies.conflicts,
+        kept_theirs: copies.theirs_later,
+    });
+    Merged { task, conflict }
+}
+
+/// The three copies of a task being merged, and the conflicts met so
+/// far.
+struct Fields<'a> {
+    base: Option<&'a Task>,
+    ours: &'a Task,
+    theirs: &'a Task,
+    /// Conflicts are settled by their copy
+    theirs_later: bool,
+    conflicts: Vec<String>,
+}
+
+impl Fields<'_> {
+    /// The merged value of the field `get` reads.
+    fn pick<T: PartialEq>(&mut self, name: &str, get: impl Fn(&Task) -> T) -> T {
+        let ours = get(self.ours);
+        let theirs = get(self.theirs);
+        if ours == theirs {
+            return ours;
+        }
+        match self.base.map(&get) {
+            Some(base) if base == ours => theirs,
+            Some(base) if base == theirs => ours,
+            _ => {
+                self.conflicts.push(name.to_string());
+                if self.theirs_later { theirs } else { ours }
+            }
+        }
+    }
+
+    /// The merged list `get` reads: the items of ours that their copy
+    /// didn't remove, and those their copy added.
+    fn combine<T: Clone + PartialEq>(
+        &mut self,
+        name: &str,
+        get: impl Fn(&Task) -> &Vec<T>,
+    ) -> Vec<T> {
+        let ours = get(self.ours);
+        let theirs = get(self.theirs);
+        if ours == theirs {
+            return ours.clone();
+        }
+        let Some(base) = self.base.map(&get) else {
+            self.conflicts.push(name.to_string());
+            return if self.theirs_later { theirs } else { ours }.clone();
+        };
+        let mut merged = ours
+            .iter()
+            .filter(|item| theirs.contains(item) || !base.contains(item))
+            .cloned()
+            .collect::<Vec<_>>();
+        for item in theirs {
+            if !base.contains(item) && !merged.contains(item) {
+                merged.push(item.clone());
+            }
+        }
+        merged
+    }
+}
@@ -0,0 +1,17 @@
+//! Desktop notifications.
+
+use crate::error::{Result, TodoError};
+
+/// The application name notifications are sent under.
+const APP_NAME: &str = "todo";
+
+/// Shows a desktop notification.
+pub fn send(summary: &str, body: &str) -> Result<()> {
+    notify_rust::Notification::new()
+        .appname(APP_NAME)
+        .summary(summary)
+        .body(body)
+        .show()
+        .map(|_| ())
+        .map_err(|err| TodoError::Notify(err.to_string()))
+}
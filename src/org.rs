@@ -0,0 +1,237 @@
+//! Writing and reading tasks as Org-mode headlines.
+//!
+//! A task is a headline with a `TODO` or `DONE` keyword, followed by a
+//! planning line with its `DEADLINE` and the `CLOSED` time, and its body
+//! as content. Priorities are cookies, `[#A]` to `[#C]`, tags are
+//! headline tags and subtasks are nested headlines.
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+
+use crate::task::{Id, Priority, Task};
+
+/// A task read from an Org file.
+pub struct Headline {
+    /// The number of stars, 1 for top-level headlines
+    pub level: usize,
+    /// The task, without an id or parent
+    pub task: Task,
+}
+
+/// Writes `tasks` as Org headlines, in the order given, with subtasks
+/// nested under their parents.
+pub fn to_org(tasks: &[&Task]) -> String {
+    let mut out = String::new();
+    let is_listed = |id: Id| tasks.iter().any(|task| task.id == id);
+    for task in tasks {
+        if task.parent.is_none_or(|parent| !is_listed(parent)) {
+            push_tree(&mut out, tasks, task, 1);
+        }
+    }
+    out
+}
+
+/// Appends a task and its subtasks.
+fn push_tree(out: &mut String, tasks: &[&Task], task: &Task, level: usize) {
+    push_headline(out, task, level);
+    // Bounded, in case the parents form a cycle
+    if level > tasks.len() {
+        return;
+    }
+    for child in tasks.iter().filter(|child| child.parent == Some(task.id)) {
+        push_tree(out, tasks, child, level + 1);
+    }
+}
+
+/// Appends the headline, planning line and content of a task.
+fn push_headline(out: &mut String, task: &Task, level: usize) {
+    out.push_str(&"*".repeat(level));
+    out.push_str(if task.done { " DONE " } else { " TODO " });
+    if let Some(priority) = task.priority {
+        let cookie = match priority {
+            Priority::High => "A",
+            Priority::Medium => "B",
+            Priority::Low => "C",
+        };
+        out.push_str(&format!("[#{cookie}] "));
+    }
+    out.push_str(task.head.lines().next().unwrap_or_default());
+    if !task.tags.is_empty() {
+        out.push_str(&format!(" :{}:", task.tags.join(":")));
+    }
+    out.push('\n');
+
+    let mut planning = Vec::new();
+    if let Some(due) = task.due {
+        planning.push(format!("DEADLINE: <{}>", due.format("%Y-%m-%d %a")));
+    }
+    if let Some(completed_at) = task.completed_at.filter(|_| task.done) {
+        planning.push(format!(
+            "CLOSED: [{}]",
+            completed_at.format("%Y-%m-%d %a %H:%M")
+        ));
+    }
+    if !planning.is_empty() {
+        out.push_str(&planning.join(" "));
+        out.push('\n');
+    }
+
+    for line in task.body.lines() {
+        // Lines starting with a star would be read as headlines
+        if line.starts_with('*') {
+            out.push(',');
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+}
+
+/// Reads the headlines of an Org file as tasks, in order.
+/// Headlines without a keyword are open tasks; text before the first
+/// headline, property drawers and timestamps other than `DEADLINE` and
+/// `CLOSED` are ignored.
+pub fn parse_org(text: &str) -> Vec<Headline> {
+    let mut headlines: Vec<Headline> = Vec::new();
+    // Whether the line after a headline, which may be its planning line,
+    // is next
+    let mut after_headline = false;
+    let mut in_drawer = false;
+
+    for line in text.lines() {
+        if let Some(headline) = parse_headline(line) {
+            headlines.push(headline);
+            after_headline = true;
+            in_drawer = false;
+            continue;
+        }
+        let Some(current) = headlines.last_mut() else {
+            continue;
+        };
+
+        let trimmed = line.trim();
+        if after_headline && parse_planning(trimmed, &mut current.task) {
+            after_headline = false;
+            continue;
+        }
+        after_headline = false;
+
+        if trimmed.eq_ignore_ascii_case(":PROPERTIES:") || trimmed.eq_ignore_ascii_case(":LOGBOOK:")
+        {
+            in_drawer = true;
+            continue;
+        }
+        if in_drawer {
+            in_drawer = !trimmed.eq_ignore_ascii_case(":END:");
+            continue;
+        }
+
+        // Content is often indented to line up with the headline's title
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        let line = &line[indent.min(current.level + 1)..];
+        let line = line
+            .strip_prefix(',')
+            .filter(|rest| rest.starts_with('*'))
+            .unwrap_or(line);
+        let body = &mut current.task.body;
+        if !body.is_empty() {
+            body.push('\n');
+        }
+        body.push_str(line.trim_end());
+    }
+
+    for headline in &mut headlines {
+        headline.task.body = headline.task.body.trim().to_string();
+    }
+    headlines
+}
+
+/// Reads a headline, e.g. `** TODO [#A] Call the bank :work:phone:`.
+fn parse_headline(line: &str) -> Option<Headline> {
+    let level = line.chars().take_while(|&c| c == '*').count();
+    if level == 0 {
+        return None;
+    }
+    let rest = line[level..].strip_prefix(' ')?;
+    let mut task = Task::default();
+
+    let mut rest = rest.trim();
+    if let Some(title) = rest
+        .strip_prefix("TODO ")
+        .or((rest == "TODO").then_some(""))
+    {
+        rest = title.trim_start();
+    } else if let Some(title) = rest
+        .strip_prefix("DONE ")
+        .or((rest == "DONE").then_some(""))
+    {
+        task.done = true;
+        rest = title.trim_start();
+    }
+
+    for (cookie, priority) in [
+        ("[#A]", Priority::High),
+        ("[#B]", Priority::Medium),
+        ("[#C]", Priority::Low),
+    ] {
+        if let Some(title) = rest.strip_prefix(cookie) {
+            task.priority = Some(priority);
+            rest = title.trim_start();
+        }
+    }
+
+    // Tags end the headline, e.g. `:work:phone:`
+    if let Some((title, tags)) = rest.rsplit_once(' ')
+        && tags.len() > 2
+        && tags.starts_with(':')
+        && tags.ends_with(':')
+        && !tags.contains(char::is_whitespace)
+    {
+        task.tags = tags
+            .split(':')
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect();
+        rest = title.trim_end();
+    }
+
+    task.head = rest.to_string();
+    Some(Headline { level, task })
+}
+
+/// Reads a planning line, e.g. `DEADLINE: <2025-03-01 Sat> CLOSED: [...]`,
+/// into `task`. Returns whether the line is one.
+fn parse_planning(line: &str, task: &mut Task) -> bool {
+    let keywords = ["DEADLINE:", "SCHEDULED:", "CLOSED:"];
+    if !keywords.iter().any(|keyword| line.starts_with(keyword)) {
+        return false;
+    }
+    if let Some(deadline) = timestamp_after(line, "DEADLINE:") {
+        task.due =
+            NaiveDate::parse_from_str(deadline.get(..10).unwrap_or_default(), "%Y-%m-%d").ok();
+    }
+    if let Some(closed) = timestamp_after(line, "CLOSED:") {
+        task.completed_at = parse_moment(closed);
+    }
+    true
+}
+
+/// The inside of the timestamp following `keyword` on a planning line.
+fn timestamp_after<'a>(line: &'a str, keyword: &str) -> Option<&'a str> {
+    let rest = line[line.find(keyword)? + keyword.len()..].trim_start();
+    let close = match rest.chars().next()? {
+        '<' => '>',
+        '[' => ']',
+        _ => return None,
+    };
+    let end = rest.find(close)?;
+    Some(&rest[1..end])
+}
+
+/// Reads the date and time of a timestamp, e.g. `2025-03-01 Sat 10:00`,
+/// as local time.
+fn parse_moment(timestamp: &str) -> Option<DateTime<Local>> {
+    let mut words = timestamp.split_whitespace();
+    let date = words.next()?;
+    let time = words.find(|word| word.contains(':')).unwrap_or("00:00");
+    let naive = NaiveDateTime::parse_from_str(&format!("{date} {time}"), "%Y-%m-%d %H:%M").ok()?;
+    Local.from_local_datetime(&naive).earliest()
+}
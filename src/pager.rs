@@ -0,0 +1,67 @@
+//! Showing long output through a pager.
+
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::Result;
+
+/// The pager used when `PAGER` is unset.
+const DEFAULT_PAGER: &str = "less -R";
+
+/// Whether long output is paged, see `set_paging`.
+static PAGING: AtomicBool = AtomicBool::new(true);
+
+/// Turns paging of long output on or off.
+pub fn set_paging(enabled: bool) {
+    PAGING.store(enabled, Ordering::Relaxed);
+}
+
+/// Writes `output` to stdout, through `$PAGER` if stdout is a terminal
+/// and the output doesn't fit on the screen.
+/// If the pager cannot be started the output is written directly.
+pub fn page(output: &[u8]) -> Result<()> {
+    let mut stdout = io::stdout();
+    if !PAGING.load(Ordering::Relaxed) || !stdout.is_terminal() || fits_screen(output) {
+        stdout.write_all(output)?;
+        return Ok(());
+    }
+
+    let pager = std::env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_PAGER.to_string());
+    let words = shlex::split(&pager).unwrap_or_default();
+    let Some((program, args)) = words.split_first() else {
+        stdout.write_all(output)?;
+        return Ok(());
+    };
+
+    let Ok(mut child) = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+    else {
+        stdout.write_all(output)?;
+        return Ok(());
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager closes its input when quit before reaching the end
+        match stdin.write_all(output) {
+            Err(err) if err.kind() != io::ErrorKind::BrokenPipe => return Err(err.into()),
+            _ => {}
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Whether `output` fits on the screen, leaving a line for the prompt.
+fn fits_screen(output: &[u8]) -> bool {
+    let Ok((_, height)) = crossterm::terminal::size() else {
+        return true;
+    };
+    let lines = output.iter().filter(|&&b| b == b'\n').count();
+    lines < usize::from(height)
+}
@@ -0,0 +1,156 @@
+//! Picking an item with a built-in fuzzy finder.
+
+use std::cmp::Reverse;
+use std::io::{self, IsTerminal, Write};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue};
+
+use crate::error::{Result, TodoError};
+
+/// Scores how well `candidate` matches `query`, ignoring case.
+/// Every character of the query must appear in the candidate, in order.
+/// Runs of consecutive characters and matches at the start of words
+/// score higher. Returns `None` if the candidate doesn't match.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<u32> {
+    let candidate: Vec<char> = candidate.chars().flat_map(char::to_lowercase).collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut last_match = None;
+
+    for wanted in query.chars().flat_map(char::to_lowercase) {
+        let offset = candidate[next..].iter().position(|&c| c == wanted)?;
+        let at = next + offset;
+
+        score += 1;
+        if at > 0 && last_match == Some(at - 1) {
+            score += 4;
+        }
+        if at == 0 || !candidate[at - 1].is_alphanumeric() {
+            score += 3;
+        }
+
+        last_match = Some(at);
+        next = at + 1;
+    }
+    Some(score)
+}
+
+/// Returns the indices of the `items` matching `query`, best match first.
+/// Items that match equally well keep their order.
+pub fn rank(query: &str, items: &[String]) -> Vec<usize> {
+    let mut scored: Vec<(u32, usize)> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(index, item)| Some((fuzzy_score(query, item)?, index)))
+        .collect();
+    scored.sort_by_key(|&(score, _)| Reverse(score));
+    scored.into_iter().map(|(_, index)| index).collect()
+}
+
+/// Lets the user narrow down `items` by typing and pick one of them.
+/// Returns the index of the picked item, or `None` if picking was
+/// cancelled with Esc or Ctrl-C.
+///
+/// The finder is drawn on stderr, so the output of the command can be
+/// captured, e.g. `todo done $(todo pick)`.
+pub fn pick(items: &[String]) -> Result<Option<usize>> {
+    let mut tty = io::stderr();
+    if !io::stdin().is_terminal() || !tty.is_terminal() {
+        return Err(TodoError::Usage(
+            "picking a task needs a terminal".to_string(),
+        ));
+    }
+
+    terminal::enable_raw_mode()?;
+    execute!(tty, EnterAlternateScreen, cursor::Hide)?;
+    let picked = run_finder(&mut tty, items);
+    // Restore the terminal even if the finder failed
+    let _ = execute!(tty, LeaveAlternateScreen, cursor::Show);
+    let _ = terminal::disable_raw_mode();
+    picked
+}
+
+/// Reads keys and redraws the finder until an item is picked.
+fn run_finder(tty: &mut impl Write, items: &[String]) -> Result<Option<usize>> {
+    let mut query = String::new();
+    let mut selected = 0;
+
+    loop {
+        let matches = rank(&query, items);
+        selected = selected.min(matches.len().saturating_sub(1));
+        draw(tty, &query, items, &matches, selected)?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Enter => return Ok(matches.get(selected).copied()),
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if ctrl => return Ok(None),
+            KeyCode::Up => selected = selected.saturating_sub(1),
+            KeyCode::Char('p') if ctrl => selected = selected.saturating_sub(1),
+            KeyCode::Down => selected += 1,
+            KeyCode::Char('n') if ctrl => selected += 1,
+            KeyCode::Backspace => {
+                query.pop();
+                selected = 0;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                query.push(c);
+                selected = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Draws the query line, a match count and as many matches as fit,
+/// keeping the selected one in view.
+fn draw(
+    tty: &mut impl Write,
+    query: &str,
+    items: &[String],
+    matches: &[usize],
+    selected: usize,
+) -> io::Result<()> {
+    let (width, height) = terminal::size()?;
+    let width = usize::from(width).saturating_sub(2);
+    let rows = usize::from(height).saturating_sub(2).max(1);
+    let first = selected.saturating_sub(rows - 1);
+
+    queue!(
+        tty,
+        Clear(ClearType::All),
+        cursor::MoveTo(0, 0),
+        Print(format!("> {query}")),
+        cursor::MoveTo(0, 1),
+        Print(format!("  {}/{}", matches.len(), items.len())),
+    )?;
+
+    for (row, &index) in matches.iter().enumerate().skip(first).take(rows) {
+        let line: String = items[index].chars().take(width).collect();
+        // Rows 0 and 1 hold the query and the count
+        let y = u16::try_from(row - first + 2).unwrap_or(u16::MAX);
+        queue!(tty, cursor::MoveTo(0, y))?;
+        if row == selected {
+            queue!(
+                tty,
+                SetAttribute(Attribute::Reverse),
+                Print(format!("> {line}")),
+                SetAttribute(Attribute::Reset),
+            )?;
+        } else {
+            queue!(tty, Print(format!("  {line}")))?;
+        }
+    }
+
+    tty.flush()
+}
@@ -0,0 +1,57 @@
+//! Asking questions on the terminal: for confirmation before
+//! destructive changes, and for the fields of a task with `todo new -i`.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::Result;
+
+/// Whether every question is answered yes, see `set_assume_yes`.
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+/// Answers every question yes without asking, e.g. in scripts.
+pub fn set_assume_yes(enabled: bool) {
+    ASSUME_YES.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether every question is answered yes, e.g. for commands that
+/// take `--yes` as their confirmation.
+pub fn assume_yes() -> bool {
+    ASSUME_YES.load(Ordering::Relaxed)
+}
+
+/// Asks a yes/no question on stderr and reads the answer from stdin.
+/// Anything but `y` or `yes` is taken as no, including the end of input.
+pub fn confirm(question: &str) -> Result<bool> {
+    if assume_yes() {
+        return Ok(true);
+    }
+
+    eprint!("{question} [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Asks for a line of text on stderr, showing `default` if any, and reads
+/// the answer from stdin, trimmed. An empty answer gives the default, or
+/// an empty string without one. Returns `None` at the end of input.
+pub fn ask(question: &str, default: Option<&str>) -> Result<Option<String>> {
+    match default {
+        Some(default) if !default.is_empty() => eprint!("{question} [{default}]: "),
+        _ => eprint!("{question}: "),
+    }
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        eprintln!();
+        return Ok(None);
+    }
+    let answer = answer.trim();
+    Ok(Some(if answer.is_empty() {
+        default.unwrap_or_default().to_string()
+    } else {
+        answer.to_string()
+    }))
+}
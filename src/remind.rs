@@ -0,0 +1,104 @@
+//! Reminders of tasks that are coming due.
+
+use chrono::{DateTime, Local, TimeDelta};
+
+use crate::storage::Storage;
+use crate::task::Id;
+
+/// The hour of the day a task is considered due on its due date.
+pub const DUE_HOUR: u32 = 9;
+
+/// A moment to remind the user of a task.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reminder {
+    /// The task to remind of
+    pub id: Id,
+    /// The head of the task
+    pub head: String,
+    /// When to remind of it
+    pub at: DateTime<Local>,
+    /// Why the reminder is given
+    pub kind: ReminderKind,
+}
+
+/// Why a reminder is given.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReminderKind {
+    /// The user asked to be reminded at this moment
+    Set,
+    /// The task is due this long after the reminder
+    Due { lead: TimeDelta },
+}
+
+impl Reminder {
+    /// A message describing the reminder.
+    pub fn message(&self) -> String {
+        match self.kind {
+            ReminderKind::Set => format!("Task {}: {}", self.id, self.head),
+            ReminderKind::Due { lead } if lead.is_zero() => {
+                format!("Task {} is due now: {}", self.id, self.head)
+            }
+            ReminderKind::Due { lead } => format!(
+                "Task {} is due in {}: {}",
+                self.id,
+                format_duration(lead),
+                self.head
+            ),
+        }
+    }
+}
+
+/// Returns the reminders of the open tasks, earliest first: the ones
+/// set on the tasks, and one for each of the `leads` before a due date.
+pub fn reminders(data: &Storage, leads: &[TimeDelta]) -> Vec<Reminder> {
+    let mut reminders = Vec::new();
+    for task in data.list().filter(|task| !task.done) {
+        let reminder = |at, kind| Reminder {
+            id: task.id,
+            head: task.head.clone(),
+            at,
+            kind,
+        };
+
+        for at in &task.reminders {
+            reminders.push(reminder(*at, ReminderKind::Set));
+        }
+        let due = task
+            .due
+            .and_then(|due| due.and_hms_opt(DUE_HOUR, 0, 0))
+            .and_then(|due| due.and_local_timezone(Local).earliest());
+        if let Some(due) = due {
+            for lead in leads {
+                reminders.push(reminder(due - *lead, ReminderKind::Due { lead: *lead }));
+            }
+        }
+    }
+    reminders.sort_by_key(|reminder| reminder.at);
+    reminders
+}
+
+/// Returns the reminders falling after `from`, up to and including `to`.
+pub fn reminders_between(
+    data: &Storage,
+    leads: &[TimeDelta],
+    from: DateTime<Local>,
+    to: DateTime<Local>,
+) -> Vec<Reminder> {
+    reminders(data, leads)
+        .into_iter()
+        .filter(|reminder| reminder.at > from && reminder.at <= to)
+        .collect()
+}
+
+/// Formats a duration in its largest whole unit, e.g. `2h` or `1d`.
+pub fn format_duration(duration: TimeDelta) -> String {
+    if duration.num_weeks() > 0 && duration == TimeDelta::weeks(duration.num_weeks()) {
+        format!("{}w", duration.num_weeks())
+    } else if duration.num_days() > 0 && duration == TimeDelta::days(duration.num_days()) {
+        format!("{}d", duration.num_days())
+    } else if duration.num_hours() > 0 && duration == TimeDelta::hours(duration.num_hours()) {
+        format!("{}h", duration.num_hours())
+    } else {
+        format!("{}m", duration.num_minutes())
+    }
+}
@@ -0,0 +1,164 @@
+//! Syncing the store with a copy on another machine over SSH, see
+//! `todo sync remote`.
+
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::error::{Result, TodoError};
+use crate::storage::{Reconciled, Storage, decode_storage};
+
+/// The exit code of the remote command when there is no copy yet.
+const MISSING: i32 = 3;
+
+/// What a remote sync did.
+#[derive(Debug, Default)]
+pub struct RemoteReport {
+    /// Changes made to the remote copy were merged in, `None` if there
+    /// was no remote copy yet
+    pub merged: Option<Reconciled>,
+    /// The remote copy was replaced by the merged store
+    pub pushed: bool,
+}
+
+/// A place on another machine, e.g. `me@example.com:tasks.json`.
+#[derive(Debug, PartialEq)]
+pub struct Destination {
+    /// What `ssh` connects to, e.g. `me@example.com`
+    pub host: String,
+    /// The file on the host, relative to the home directory unless
+    /// absolute
+    pub path: String,
+}
+
+/// Reads a destination given as `[USER@]HOST:PATH`.
+pub fn parse_destination(text: &str) -> Result<Destination> {
+    match text.split_once(':') {
+        // ssh starts in the home directory, and `~` would be quoted
+        Some((host, path)) if !host.is_empty() && !path.is_empty() => Ok(Destination {
+            host: host.to_string(),
+            path: path.strip_prefix("~/").unwrap_or(path).to_string(),
+        }),
+        _ => Err(TodoError::Usage(format!(
+            "invalid destination `{text}`, expected [USER@]HOST:PATH"
+        ))),
+    }
+}
+
+/// The remote copy, as fetched by `pull`.
+#[derive(Debug)]
+pub struct Pulled {
+    /// The copy, `None` if there was none yet
+    theirs: Option<Vec<u8>>,
+    /// What merging the copy in did
+    report: RemoteReport,
+}
+
+/// Syncs the store at `storage_path` with the copy at `destination`,
+/// for `finish` to send the merged store back.
+///
+/// The remote copy is fetched and merged in task by task, see
+/// `Storage::reconcile`, so changes made on either machine since the
+/// last sync are kept. `data` is the store as saved, and is reconciled
+/// in place. The store should stay locked until `finish` is done.
+/// `ssh` may ask for a password.
+pub fn pull(destination: &Destination, storage_path: &Path, data: &mut Storage) -> Result<Pulled> {
+    let mut report = RemoteReport::default();
+    let theirs = fetch(&destination.host, &quote(destination)?)?;
+    if let Some(bytes) = &theirs {
+        let copy = tempfile::NamedTempFile::new()?;
+        std::fs::write(copy.path(), bytes)?;
+        let other = Storage::load(copy.path())?;
+        if other.was_recovered() {
+            return Err(TodoError::Sync(format!(
+                "{}:{} is not a valid store",
+                destination.host, destination.path
+            )));
+        }
+        let base = load_base(storage_path);
+        report.merged = Some(data.reconcile(base.as_ref(), &other));
+    }
+    Ok(Pulled { theirs, report })
+}
+
+/// Sends the store saved to `storage_path` to `destination` if it
+/// differs from the copy `pull` fetched. The store as synced is kept
+/// next to it as the base of the next merge.
+pub fn finish(
+    destination: &Destination,
+    storage_path: &Path,
+    pulled: Pulled,
+) -> Result<RemoteReport> {
+    let Pulled { theirs, mut report } = pulled;
+    let ours = std::fs::read(storage_path).map_err(|source| TodoError::ReadFile {
+        path: storage_path.to_path_buf(),
+        source,
+    })?;
+    if theirs.as_ref() != Some(&ours) {
+        push(&destination.host, &quote(destination)?, &ours)?;
+        report.pushed = true;
+    }
+    std::fs::write(base_path(storage_path), &ours)?;
+    Ok(report)
+}
+
+/// The path of the remote copy, quoted for the shell on the host.
+fn quote(destination: &Destination) -> Result<String> {
+    shlex::try_quote(&destination.path)
+        .map(|path| path.into_owned())
+        .map_err(|_| TodoError::Usage(format!("invalid path `{}`", destination.path)))
+}
+
+/// Where the store as last synced is kept, next to the store, e.g.
+/// `tasks.json.synced`.
+fn base_path(storage_path: &Path) -> PathBuf {
+    let mut name = storage_path
+        .file_name()
+        .map(OsString::from)
+        .unwrap_or_default();
+    name.push(".synced");
+    storage_path.with_file_name(name)
+}
+
+/// Reads the store as last synced, `None` if it was never synced or the
+/// copy can't be read.
+fn load_base(storage_path: &Path) -> Option<Storage> {
+    let bytes = std::fs::read(base_path(storage_path)).ok()?;
+    decode_storage(&bytes).ok()
+}
+
+/// Reads the remote copy, `None` if there is none yet.
+fn fetch(host: &str, path: &str) -> Result<Option<Vec<u8>>> {
+    let script = format!("if [ -e {path} ]; then cat {path}; else exit {MISSING}; fi");
+    log::debug!("ssh {host} {script}");
+    let output = Command::new("ssh")
+        .args([host, &script])
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| TodoError::Sync(format!("cannot run ssh: {err}")))?;
+    match output.status.code() {
+        Some(0) => Ok(Some(output.stdout)),
+        Some(MISSING) => Ok(None),
+        _ => Err(TodoError::Sync(format!("cannot read {path} on {host}"))),
+    }
+}
+
+/// Replaces the remote copy with `bytes`, all at once.
+fn push(host: &str, path: &str, bytes: &[u8]) -> Result<()> {
+    let script = format!("cat > {path}.tmp && mv {path}.tmp {path}");
+    log::debug!("ssh {host} {script}");
+    let mut child = Command::new("ssh")
+        .args([host, &script])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|err| TodoError::Sync(format!("cannot run ssh: {err}")))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(bytes)?;
+    }
+    if !child.wait()?.success() {
+        return Err(TodoError::Sync(format!("cannot write {path} on {host}")));
+    }
+    Ok(())
+}
@@ -0,0 +1,49 @@
+//! How much is reported about what a command does: the messages
+//! confirming changes, and the log.
+
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::{Level, LevelFilter};
+
+/// Whether messages confirming changes are left out, see `set_verbosity`.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Sets up the log, written to stderr. Its level is set with
+/// `set_verbosity`, unless `RUST_LOG` is set.
+pub fn init_logging() {
+    let _ = env_logger::Builder::new()
+        .filter_level(LevelFilter::Trace)
+        .parse_default_env()
+        .format(|buf, record| {
+            let level = match record.level() {
+                Level::Warn => "warning".to_string(),
+                level => level.as_str().to_lowercase(),
+            };
+            writeln!(buf, "{level}: {}", record.args())
+        })
+        .try_init();
+}
+
+/// Sets how much is reported. `quiet` leaves out the messages
+/// confirming changes and logs errors only. Otherwise warnings are
+/// logged, and each `verbose` step adds a level of detail.
+pub fn set_verbosity(quiet: bool, verbose: u8) {
+    QUIET.store(quiet, Ordering::Relaxed);
+    if std::env::var_os("RUST_LOG").is_some() {
+        return;
+    }
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Info,
+        (false, 2) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    log::set_max_level(level);
+}
+
+/// Whether messages confirming changes are left out.
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
@@ -0,0 +1,145 @@
+//! Finding `TODO` and `FIXME` comments in source code, see `todo scan`.
+//!
+//! A comment is told from other text by the comment marker before it,
+//! e.g. `//`, `#` or `--`. Each comment found has a fingerprint, taken
+//! from the file it is in and its text but not its line, so the task made
+//! from it is found again after the lines around it change. Comments
+//! alike in a file are told apart by their order.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use crate::error::{Result, TodoError};
+
+/// The custom field of the tasks made from comments holding the
+/// fingerprint of their comment.
+pub const FINGERPRINT_FIELD: &str = "scan";
+
+/// The custom field of the tasks made from comments holding where their
+/// comment is, see `Comment::location`.
+pub const LOCATION_FIELD: &str = "location";
+
+/// Files larger than this are skipped, as generated or data files.
+const MAX_FILE_LEN: u64 = 1024 * 1024;
+
+/// Directories holding build output and dependencies rather than code.
+const SKIPPED_DIRS: [&str; 2] = ["target", "node_modules"];
+
+/// A comment marker, then the keyword, an optional `(author)` and colon,
+/// and the text of the comment.
+static COMMENT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?://+|#+|--|/\*+|^\s*\*|;+|<!--)\s*(TODO|FIXME)\b(?:\([^)]*\))?:?\s*(.*)")
+        .expect("valid regular expression")
+});
+
+/// A `TODO` or `FIXME` comment.
+#[derive(Debug)]
+pub struct Comment {
+    /// The file the comment is in
+    pub path: PathBuf,
+    /// The line of the comment, from 1
+    pub line: usize,
+    /// `TODO` or `FIXME`
+    pub keyword: String,
+    /// What the comment says after the keyword
+    pub text: String,
+    /// Tells the comment apart from the others wherever its line, see
+    /// `fingerprint`
+    pub fingerprint: String,
+}
+
+impl Comment {
+    /// Where the comment is, e.g. `/home/me/code/src/main.rs:42` when
+    /// scanning an absolute root.
+    pub fn location(&self) -> String {
+        format!("{}:{}", self.path.display(), self.line)
+    }
+}
+
+/// Finds the comments in the files under `root`, or in `root` if it is a
+/// file. Hidden files and directories, and those in `SKIPPED_DIRS`, are
+/// passed over, and so are files that aren't text.
+pub fn scan(root: &Path) -> Result<Vec<Comment>> {
+    let mut comments = Vec::new();
+    if root.is_file() {
+        scan_file(root, &mut comments);
+    } else {
+        scan_dir(root, &mut comments)?;
+    }
+    Ok(comments)
+}
+
+/// Finds the comments in the files under `dir`, in the order of their
+/// names.
+fn scan_dir(dir: &Path, comments: &mut Vec<Comment>) -> Result<()> {
+    let read_err = |source| TodoError::ReadFile {
+        path: dir.to_path_buf(),
+        source,
+    };
+    let mut entries = fs::read_dir(dir)
+        .map_err(read_err)?
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(read_err)?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() && !SKIPPED_DIRS.contains(&name.as_ref()) {
+            scan_dir(&path, comments)?;
+        } else if file_type.is_file() {
+            scan_file(&path, comments);
+        }
+    }
+    Ok(())
+}
+
+/// Finds the comments in the file at `path`, if it is text.
+fn scan_file(path: &Path, comments: &mut Vec<Comment>) {
+    if fs::metadata(path).is_ok_and(|metadata| metadata.len() > MAX_FILE_LEN) {
+        log::debug!("{} is too large, skipped", path.display());
+        return;
+    }
+    let Ok(text) = fs::read_to_string(path) else {
+        return;
+    };
+    // How many comments alike came before, to tell them apart
+    let mut seen = BTreeMap::<String, usize>::new();
+    for (line, content) in (1..).zip(text.lines()) {
+        let Some(captures) = COMMENT.captures(content) else {
+            continue;
+        };
+        let text = captures[2]
+            .trim()
+            .trim_end_matches("*/")
+            .trim_end_matches("-->")
+            .trim();
+        let keyword = &captures[1];
+        let key = format!("{}\0{keyword}\0{text}", path.display());
+        let count = seen.entry(key.clone()).or_default();
+        *count += 1;
+        let key = if *count > 1 {
+            format!("{key}\0{count}")
+        } else {
+            key
+        };
+        comments.push(Comment {
+            path: path.to_path_buf(),
+            line,
+            keyword: keyword.to_string(),
+            text: text.to_string(),
+            fingerprint: format!("{:08x}", crc32fast::hash(key.as_bytes())),
+        });
+    }
+}
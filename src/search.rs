@@ -0,0 +1,100 @@
+//! Searching the text of tasks, see `todo search`.
+//!
+//! The head, the tags and each line of the body and of the notes are
+//! searched apart, and every one that matches is a hit. The text searched for is
+//! taken as written unless it is a regular expression.
+
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+
+use crate::error::{Result, TodoError};
+use crate::task::{Id, Task};
+
+/// The part of a task a hit is in.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Field {
+    Head,
+    Body,
+    Tag,
+    Note,
+}
+
+/// Text of a task that matches.
+#[derive(Debug, Serialize)]
+pub struct Hit {
+    pub field: Field,
+    /// The head, the tag, or the line of the body or of a note
+    pub text: String,
+    /// Where the matches are in `text`, in bytes
+    #[serde(skip)]
+    pub matches: Vec<Range<usize>>,
+}
+
+/// A task that matches, and where.
+#[derive(Debug, Serialize)]
+pub struct Found {
+    pub id: Id,
+    pub head: String,
+    pub done: bool,
+    pub hits: Vec<Hit>,
+}
+
+/// What to search for.
+#[derive(Debug)]
+pub struct Query {
+    regex: Regex,
+}
+
+impl Query {
+    /// Builds a query for `pattern`, read as a regular expression if
+    /// `regex` is set. Fails on a regular expression that can't be read.
+    pub fn new(pattern: &str, regex: bool, ignore_case: bool) -> Result<Self> {
+        let pattern = if regex {
+            pattern.to_string()
+        } else {
+            regex::escape(pattern)
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|err| TodoError::Usage(format!("invalid regular expression: {err}")))?;
+        Ok(Query { regex })
+    }
+
+    /// Where `task` matches, `None` if it doesn't.
+    pub fn search(&self, task: &Task) -> Option<Found> {
+        let texts = [(Field::Head, task.head.as_str())]
+            .into_iter()
+            .chain(task.body.lines().map(|line| (Field::Body, line)))
+            .chain(task.tags.iter().map(|tag| (Field::Tag, tag.as_str())))
+            .chain(
+                task.notes
+                    .iter()
+                    .flat_map(|note| note.text.lines().map(|line| (Field::Note, line))),
+            );
+        let hits = texts
+            .filter_map(|(field, text)| {
+                let matches = self
+                    .regex
+                    .find_iter(text)
+                    .map(|m| m.range())
+                    .filter(|range| !range.is_empty())
+                    .collect::<Vec<_>>();
+                (!matches.is_empty()).then(|| Hit {
+                    field,
+                    text: text.to_string(),
+                    matches,
+                })
+            })
+            .collect::<Vec<_>>();
+        (!hits.is_empty()).then(|| Found {
+            id: task.id,
+            head: task.head.clone(),
+            done: task.done,
+            hits,
+        })
+    }
+}
@@ -0,0 +1,71 @@
+//! Named sections inside task bodies.
+//!
+//! Bodies are split into sections by `## Name` heading lines,
+//! the text before the first heading has no name.
+
+/// A part of a task body.
+pub struct Section<'a> {
+    /// The heading of the section, `None` for the text before the
+    /// first heading
+    pub name: Option<&'a str>,
+    /// The lines of the section, without its heading
+    pub lines: Vec<&'a str>,
+}
+
+/// Returns the heading name if the line starts a body section.
+fn section_heading(line: &str) -> Option<&str> {
+    line.strip_prefix("## ")
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+}
+
+/// Splits a task body into its sections.
+pub fn parse_sections(body: &str) -> Vec<Section<'_>> {
+    let mut sections = vec![Section {
+        name: None,
+        lines: Vec::new(),
+    }];
+    for line in body.lines() {
+        match section_heading(line) {
+            Some(name) => sections.push(Section {
+                name: Some(name),
+                lines: Vec::new(),
+            }),
+            None => sections.last_mut().unwrap().lines.push(line),
+        }
+    }
+    sections
+}
+
+/// Appends a line of text to the named section of the body.
+/// The section is created at the end of the body if it is missing.
+pub fn append_to_section(body: &str, section: &str, text: &str) -> String {
+    let mut lines = body.lines().collect::<Vec<&str>>();
+    let start = lines.iter().position(|line| {
+        section_heading(line).is_some_and(|name| name.eq_ignore_ascii_case(section))
+    });
+
+    match start {
+        Some(start) => {
+            let end = lines[start + 1..]
+                .iter()
+                .position(|line| section_heading(line).is_some())
+                .map_or(lines.len(), |i| start + 1 + i);
+            // Keep the blank lines separating this section from the next
+            let mut at = end;
+            while at > start + 1 && lines[at - 1].trim().is_empty() {
+                at -= 1;
+            }
+            lines.insert(at, text);
+            lines.join("\n")
+        }
+        None => {
+            let mut body = body.trim_end().to_string();
+            if !body.is_empty() {
+                body.push_str("\n\n");
+            }
+            body.push_str(&format!("## {section}\n{text}"));
+            body
+        }
+    }
+}
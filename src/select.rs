@@ -0,0 +1,137 @@
+//! Selecting tasks by id on the command line, or by words of their
+//! head, e.g. `todo done groceri`.
+
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use crate::error::TodoError;
+use crate::pick::fuzzy_score;
+use crate::task::{Id, Task};
+
+/// The most ids a single range may select, so a typo like `1-99999999`
+/// doesn't exhaust memory.
+const MAX_RANGE_LEN: u64 = 10_000;
+
+/// Task ids given as ids and inclusive ranges separated by commas,
+/// e.g. `2-6,9`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IdSet(Vec<Id>);
+
+impl IdSet {
+    /// The ids in the order given.
+    pub fn ids(&self) -> &[Id] {
+        &self.0
+    }
+}
+
+impl FromStr for IdSet {
+    type Err = TodoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_ids(s)
+    }
+}
+
+/// Parses task ids given on the command line, see `IdSet`.
+pub fn parse_ids(s: &str) -> Result<IdSet, TodoError> {
+    let invalid = || TodoError::InvalidIds(s.to_string());
+    let parse_id = |id: &str| id.trim().parse::<Id>().map_err(|_| invalid());
+
+    let mut ids = Vec::new();
+    for part in s.split(',') {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse_id(first)?, parse_id(last)?);
+                if first > last || last - first >= MAX_RANGE_LEN {
+                    return Err(invalid());
+                }
+                ids.extend(first..=last);
+            }
+            None => ids.push(parse_id(part)?),
+        }
+    }
+    Ok(IdSet(ids))
+}
+
+/// Tasks given on the command line: ids, see `IdSet`, or words of the
+/// head of a task.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Selection {
+    Ids(IdSet),
+    Head(String),
+}
+
+/// Parses tasks given on the command line, see `Selection`. Anything
+/// but digits, commas and dashes is taken as words of a head.
+pub fn parse_selection(s: &str) -> Result<Selection, TodoError> {
+    if is_ids(s) {
+        parse_ids(s).map(Selection::Ids)
+    } else {
+        Ok(Selection::Head(s.trim().to_string()))
+    }
+}
+
+/// A task given on the command line: its id, or words of its head.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TaskRef {
+    Id(Id),
+    Head(String),
+}
+
+/// Parses a task given on the command line, see `TaskRef`.
+pub fn parse_task_ref(s: &str) -> Result<TaskRef, TodoError> {
+    if s.trim().chars().all(|c| c.is_ascii_digit()) {
+        s.trim()
+            .parse()
+            .map(TaskRef::Id)
+            .map_err(|_| TodoError::InvalidIds(s.to_string()))
+    } else {
+        Ok(TaskRef::Head(s.trim().to_string()))
+    }
+}
+
+/// Whether `s` is written as ids and ranges rather than words.
+fn is_ids(s: &str) -> bool {
+    s.chars()
+        .all(|c| c.is_ascii_digit() || c.is_whitespace() || c == ',' || c == '-')
+}
+
+/// The tasks whose head `query` matches, ignoring case: those containing
+/// it, or if none does, those matching it fuzzily, best match first. A
+/// head equal to the query is its only match.
+pub fn match_head<'a>(query: &str, tasks: impl Iterator<Item = &'a Task>) -> Vec<&'a Task> {
+    let tasks = tasks.collect::<Vec<_>>();
+    let query_lower = query.to_lowercase();
+    if let Some(task) = tasks
+        .iter()
+        .find(|task| task.head.trim().to_lowercase() == query_lower)
+    {
+        return vec![task];
+    }
+    let containing = tasks
+        .iter()
+        .filter(|task| task.head.to_lowercase().contains(&query_lower))
+        .copied()
+        .collect::<Vec<_>>();
+    if !containing.is_empty() {
+        return containing;
+    }
+
+    let mut scored = tasks
+        .into_iter()
+        .filter_map(|task| Some((fuzzy_score(query, &task.head)?, task)))
+        .collect::<Vec<_>>();
+    scored.sort_by_key(|&(score, _)| Reverse(score));
+    scored.into_iter().map(|(_, task)| task).collect()
+}
+
+/// Joins the ids of several sets, dropping repeated ids.
+pub fn flatten(sets: &[IdSet]) -> Vec<Id> {
+    let mut seen = BTreeSet::new();
+    sets.iter()
+        .flat_map(IdSet::ids)
+        .copied()
+        .filter(|id| seen.insert(*id))
+        .collect()
+}
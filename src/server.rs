@@ -0,0 +1,391 @@
+//! Serving the tasks over HTTP, see `todo serve`.
+//!
+//! The API speaks JSON, tasks being written as by `todo show --format
+//! json`:
+//!
+//! - `GET /tasks` lists the tasks, `?filter=` selects some, written as
+//!   for `--where` or `with`, e.g. `due:overdue tag:work`
+//! - `POST /tasks` creates a task from `head`, and optionally `body`,
+//!   `due`, `tags` and `priority`
+//! - `GET /tasks/{id}` reads a task
+//! - `PATCH /tasks/{id}` changes the fields given, and `done`; `null`
+//!   clears `due` and `priority`
+//! - `DELETE /tasks/{id}` moves a task to the trash
+//!
+//! Errors are `{"error": "..."}`. The store is loaded for every request
+//! while holding the lock on the storage file, so the CLI can be used
+//! alongside.
+//!
+//! Every request carries a token as `Authorization: Bearer <token>`: the
+//! one in the config, else one made up when the server starts. Bodies
+//! are sent as `application/json`, and the `Host` must be the address
+//! listened on, so that web pages open in a browser can't reach the
+//! tasks.
+
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use chrono::{Local, NaiveDate};
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::config::Config;
+use crate::error::{EXIT_NOT_FOUND, EXIT_USAGE, Result, TodoError};
+use crate::filter::parse_filter;
+use crate::storage::{Storage, update_locked};
+use crate::task::{Id, Priority, Task};
+
+/// The largest request body read, in bytes.
+const MAX_BODY: u64 = 1024 * 1024;
+
+/// The number of random bytes in a token made up by `serve`.
+const TOKEN_BYTES: usize = 16;
+
+/// A task to create, as sent to `POST /tasks`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NewTask {
+    head: String,
+    #[serde(default)]
+    body: String,
+    due: Option<NaiveDate>,
+    #[serde(default)]
+    tags: Vec<String>,
+    priority: Option<Priority>,
+}
+
+/// Changes to a task, as sent to `PATCH /tasks/{id}`. Missing fields
+/// are left as they are.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TaskPatch {
+    head: Option<String>,
+    body: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    due: Option<Option<NaiveDate>>,
+    tags: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "present")]
+    priority: Option<Option<Priority>>,
+    done: Option<bool>,
+}
+
+/// Reads a field that may be `null`, telling it apart from a missing
+/// one.
+fn present<'de, T, D>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// A request that could not be answered.
+struct Failure {
+    status: u16,
+    message: String,
+}
+
+impl Failure {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<TodoError> for Failure {
+    fn from(err: TodoError) -> Self {
+        let status = match err {
+            TodoError::Blocked { .. } | TodoError::DependencyCycle { .. } => 409,
+            _ => match err.exit_code() {
+                EXIT_NOT_FOUND => 404,
+                EXIT_USAGE => 400,
+                _ => 500,
+            },
+        };
+        Self::new(status, err.to_string())
+    }
+}
+
+/// The answer to a request: a status and a JSON body, if any.
+type Answer = std::result::Result<(u16, Option<serde_json::Value>), Failure>;
+
+/// Listens on `address`, e.g. `127.0.0.1:8080`.
+pub fn listen(address: &str) -> Result<Server> {
+    Server::http(address)
+        .map_err(|err| TodoError::Usage(format!("cannot listen on {address}: {err}")))
+}
+
+/// Serves the store at `storage_path` with `server`, until the process
+/// is stopped. Without a token in the config, one is made up and
+/// printed.
+pub fn serve(server: Server, storage_path: &Path, config: &Config) -> Result<()> {
+    let address = server.server_addr();
+    let token = match config
+        .server
+        .as_ref()
+        .map(|server| server.token.trim())
+        .filter(|token| !token.is_empty())
+    {
+        Some(token) => token.to_string(),
+        None => {
+            let token = make_token()?;
+            println!("Send `Authorization: Bearer {token}` with every request");
+            token
+        }
+    };
+    log::info!("serving {} on {address}", storage_path.display());
+    let address = address.to_ip();
+
+    for mut request in server.incoming_requests() {
+        let answer = if !host_allowed(&request, address) {
+            Err(Failure::new(421, "the Host is not the address served on"))
+        } else if !authorized(&request, &token) {
+            Err(Failure::new(401, "missing or wrong token"))
+        } else {
+            handle(&mut request, storage_path, config)
+        };
+        let (status, body) = match answer {
+            Ok(answer) => answer,
+            Err(failure) => (failure.status, Some(json!({ "error": failure.message }))),
+        };
+        log::info!("{} {} {status}", request.method(), request.url());
+        let response = match body {
+            Some(body) => Response::from_string(body.to_string()).with_header(
+                Header::from_bytes("Content-Type", "application/json")
+                    .expect("the header is valid"),
+            ),
+            None => Response::from_string(String::new()),
+        };
+        if let Err(err) = request.respond(response.with_status_code(status)) {
+            log::warn!("cannot answer a request: {err}");
+        }
+    }
+    Ok(())
+}
+
+/// Makes up a random token, as hex.
+fn make_token() -> Result<String> {
+    let mut bytes = [0; TOKEN_BYTES];
+    getrandom::fill(&mut bytes)
+        .map_err(|err| io::Error::other(format!("cannot make a token: {err}")))?;
+    Ok(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+/// The value of the header `name` of the request, if it has one.
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+/// Whether the request carries the token.
+fn authorized(request: &Request, token: &str) -> bool {
+    header(request, "Authorization").and_then(|value| value.strip_prefix("Bearer ")) == Some(token)
+}
+
+/// Whether the `Host` of the request is the address served on, as a
+/// page whose name was rebound to it would send its own name. Any
+/// host goes when serving every network, the token keeping others out.
+fn host_allowed(request: &Request, address: Option<SocketAddr>) -> bool {
+    let Some(address) = address else {
+        return true;
+    };
+    if address.ip().is_unspecified() {
+        return true;
+    }
+    let Some(host) = header(request, "Host") else {
+        return false;
+    };
+    let ip = match address {
+        SocketAddr::V4(address) => address.ip().to_string(),
+        SocketAddr::V6(address) => format!("[{}]", address.ip()),
+    };
+    let localhost = address
+        .ip()
+        .is_loopback()
+        .then_some("localhost".to_string());
+    // Clients leave out the default port
+    [Some(ip), localhost].into_iter().flatten().any(|name| {
+        host.eq_ignore_ascii_case(&format!("{name}:{}", address.port()))
+            || (address.port() == 80 && host.eq_ignore_ascii_case(&name))
+    })
+}
+
+/// Answers a request, changing the store as asked.
+fn handle(request: &mut Request, storage_path: &Path, config: &Config) -> Answer {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let id = match segments.as_slice() {
+        ["tasks"] => None,
+        ["tasks", id] => Some(
+            id.parse::<Id>()
+                .map_err(|_| Failure::new(404, format!("no task {id}")))?,
+        ),
+        _ => return Err(Failure::new(404, format!("no such endpoint {path}"))),
+    };
+    let method = request.method().clone();
+    let body = match method {
+        Method::Post | Method::Patch => {
+            // Forms can't send JSON across sites without asking first
+            let json = header(request, "Content-Type").is_some_and(|value| {
+                value
+                    .split(';')
+                    .next()
+                    .is_some_and(|kind| kind.trim().eq_ignore_ascii_case("application/json"))
+            });
+            if !json {
+                return Err(Failure::new(
+                    415,
+                    "the body must be sent as application/json",
+                ));
+            }
+            read_body(request)?
+        }
+        _ => String::new(),
+    };
+
+    update_locked(storage_path, config, |data| match (method, id) {
+        (Method::Get, None) => list(data, query),
+        (Method::Post, None) => create(data, &body),
+        (Method::Get, Some(id)) => {
+            let task = data.get(id).ok_or(TodoError::TaskNotFound(id))?;
+            Ok((200, Some(to_json(task)?)))
+        }
+        (Method::Patch, Some(id)) => update(data, id, &body),
+        (Method::Delete, Some(id)) => {
+            data.get(id).ok_or(TodoError::TaskNotFound(id))?;
+            data.delete(&[id]);
+            Ok((204, None))
+        }
+        (method, _) => Err(Failure::new(
+            405,
+            format!("{method} is not allowed on {path}"),
+        )),
+    })
+}
+
+/// Lists the tasks matching the `filter` of the query, if any.
+fn list(data: &Storage, query: &str) -> Answer {
+    let filter = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == "filter")
+        .map(|(_, value)| parse_filter(&percent_decode(value)))
+        .transpose()?
+        .unwrap_or_default();
+    let today = Local::now().date_naive();
+    let tasks = data
+        .list()
+        .filter(|task| filter.matches(task, today))
+        .map(to_json)
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok((200, Some(serde_json::Value::Array(tasks))))
+}
+
+/// Creates a task.
+fn create(data: &mut Storage, body: &str) -> Answer {
+    let new: NewTask = from_json(body)?;
+    let id = data
+        .add(Task {
+            head: new.head,
+            body: new.body,
+            due: new.due,
+            tags: new.tags,
+            priority: new.priority,
+            ..Default::default()
+        })
+        .ok_or_else(|| Failure::new(400, "the task is empty"))?;
+    let task = data.get(id).ok_or(TodoError::TaskNotFound(id))?;
+    Ok((201, Some(to_json(task)?)))
+}
+
+/// Changes the fields of a task given in the body.
+fn update(data: &mut Storage, id: Id, body: &str) -> Answer {
+    let patch: TaskPatch = from_json(body)?;
+    data.get(id).ok_or(TodoError::TaskNotFound(id))?;
+    if patch.done == Some(true) {
+        let blockers = data.open_blockers(id);
+        if !blockers.is_empty() {
+            return Err(TodoError::Blocked { id, blockers }.into());
+        }
+    }
+    if patch
+        .head
+        .as_deref()
+        .is_some_and(|head| head.trim().is_empty())
+    {
+        return Err(Failure::new(400, "the head cannot be empty"));
+    }
+
+    let task = data.get_mut(id).ok_or(TodoError::TaskNotFound(id))?;
+    if let Some(head) = patch.head {
+        task.head = head.trim().to_string();
+    }
+    if let Some(body) = patch.body {
+        task.body = body.trim().to_string();
+    }
+    if let Some(due) = patch.due {
+        task.due = due;
+    }
+    if let Some(tags) = patch.tags {
+        task.tags = tags;
+    }
+    if let Some(priority) = patch.priority {
+        task.priority = priority;
+    }
+    if let Some(done) = patch.done {
+        data.set_done(&[id], done);
+    }
+    let task = data.get(id).ok_or(TodoError::TaskNotFound(id))?;
+    Ok((200, Some(to_json(task)?)))
+}
+
+/// Reads the body of a request.
+fn read_body(request: &mut Request) -> std::result::Result<String, Failure> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY)
+        .read_to_string(&mut body)
+        .map_err(|err| Failure::new(400, format!("cannot read the request: {err}")))?;
+    Ok(body)
+}
+
+/// Reads the JSON of a request body.
+fn from_json<T: for<'de> Deserialize<'de>>(body: &str) -> std::result::Result<T, Failure> {
+    serde_json::from_str(body).map_err(|err| Failure::new(400, format!("invalid JSON: {err}")))
+}
+
+/// Writes a task as JSON.
+fn to_json(task: &Task) -> std::result::Result<serde_json::Value, Failure> {
+    serde_json::to_value(task).map_err(|err| TodoError::Serialize(err.to_string()).into())
+}
+
+/// Decodes a query parameter, e.g. `tag%3Awork+report`.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if let Some(byte) = value
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()) =>
+            {
+                decoded.push(byte);
+                i += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,216 @@
+//! Statistics about the tasks in a store.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Datelike, IsoWeek, Local, NaiveDate, TimeDelta};
+use serde::Serialize;
+
+use crate::storage::Storage;
+use crate::task::Task;
+
+/// Tasks rolled over at least this many times are chronic rollers.
+pub const CHRONIC_ROLLOVER_THRESHOLD: u32 = 3;
+
+/// The number of weeks, counting back from this one, that activity is
+/// reported for.
+pub const ACTIVITY_WEEKS: usize = 4;
+
+/// The number of tasks added and completed in a week.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeekActivity {
+    pub week: IsoWeek,
+    pub added: usize,
+    pub completed: usize,
+}
+
+/// Totals and trends over the tasks of a store.
+#[derive(Clone, Debug)]
+pub struct Stats<'a> {
+    /// All tasks
+    pub total: usize,
+    /// Tasks that aren't done
+    pub open: usize,
+    /// Tasks that are done
+    pub done: usize,
+    /// Tasks with a due date
+    pub with_due: usize,
+    /// Open tasks past their due date
+    pub overdue: usize,
+    /// Tasks added and completed per week, this week first
+    pub weeks: Vec<WeekActivity>,
+    /// The average age of the open tasks that record when they were
+    /// created
+    pub average_open_age: Option<TimeDelta>,
+    /// Tags with the number of tasks carrying them, most used first
+    pub tags: Vec<(String, usize)>,
+    /// Tasks that keep getting rolled over
+    pub chronic: Vec<&'a Task>,
+}
+
+impl<'a> Stats<'a> {
+    /// Computes the statistics of the tasks in `data` as of `now`.
+    pub fn compute(data: &'a Storage, now: DateTime<Local>) -> Self {
+        let today = now.date_naive();
+        let tasks = data.list().collect::<Vec<&Task>>();
+        let done = tasks.iter().filter(|t| t.done).count();
+
+        let mut weeks = (0..ACTIVITY_WEEKS)
+            .map(|weeks_back| WeekActivity {
+                week: (today - TimeDelta::weeks(weeks_back as i64)).iso_week(),
+                added: 0,
+                completed: 0,
+            })
+            .collect::<Vec<_>>();
+        let in_week = |at: Option<DateTime<Local>>, week: IsoWeek| {
+            at.is_some_and(|at| at.date_naive().iso_week() == week)
+        };
+        for task in &tasks {
+            for activity in &mut weeks {
+                activity.added += usize::from(in_week(task.created_at, activity.week));
+                activity.completed += usize::from(in_week(task.completed_at, activity.week));
+            }
+        }
+
+        let ages = tasks
+            .iter()
+            .filter(|t| !t.done)
+            .filter_map(|t| t.created_at.map(|created| now - created))
+            .collect::<Vec<_>>();
+        let average_open_age =
+            (!ages.is_empty()).then(|| ages.iter().sum::<TimeDelta>() / ages.len() as i32);
+
+        let mut tag_counts = BTreeMap::<&str, usize>::new();
+        for tag in tasks.iter().flat_map(|t| &t.tags) {
+            *tag_counts.entry(tag).or_default() += 1;
+        }
+        let mut tags = tag_counts
+            .into_iter()
+            .map(|(tag, count)| (tag.to_string(), count))
+            .collect::<Vec<_>>();
+        tags.sort_by_key(|(_, count)| Reverse(*count));
+
+        Stats {
+            total: tasks.len(),
+            open: tasks.len() - done,
+            done,
+            with_due: tasks.iter().filter(|t| t.due.is_some()).count(),
+            overdue: tasks
+                .iter()
+                .filter(|t| !t.done && t.due.is_some_and(|due| due < today))
+                .count(),
+            weeks,
+            average_open_age,
+            tags,
+            chronic: tasks
+                .iter()
+                .filter(|t| t.rollovers >= CHRONIC_ROLLOVER_THRESHOLD)
+                .copied()
+                .collect(),
+        }
+    }
+}
+
+/// Tasks completed over a period and how long they took, see
+/// `todo report`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Productivity {
+    /// The first day of the period
+    pub since: NaiveDate,
+    /// The last day of the period, today
+    pub until: NaiveDate,
+    /// Tasks completed in the period
+    pub completed: usize,
+    /// Tasks completed each day of the period, from the first
+    pub days: Vec<DayActivity>,
+    /// The average time from creation to completion of the tasks
+    /// completed that record when they were created
+    #[serde(
+        rename = "average_turnaround_minutes",
+        with = "crate::date::option_minutes"
+    )]
+    pub average_turnaround: Option<TimeDelta>,
+    /// The tasks completed by tag, most completed first
+    pub tags: Vec<TagActivity>,
+}
+
+/// The number of tasks completed in a day.
+#[derive(Clone, Debug, Serialize)]
+pub struct DayActivity {
+    pub date: NaiveDate,
+    pub completed: usize,
+}
+
+/// The tasks carrying a tag completed over a period.
+#[derive(Clone, Debug, Serialize)]
+pub struct TagActivity {
+    pub tag: String,
+    pub completed: usize,
+    #[serde(
+        rename = "average_turnaround_minutes",
+        with = "crate::date::option_minutes"
+    )]
+    pub average_turnaround: Option<TimeDelta>,
+}
+
+impl Productivity {
+    /// Computes what was completed in `data` from the start of the day
+    /// of `since` until `now`, so that the first day is counted whole.
+    pub fn compute(data: &Storage, since: DateTime<Local>, now: DateTime<Local>) -> Self {
+        let (first, last) = (since.date_naive(), now.date_naive());
+        let completed = data
+            .list()
+            .filter(|task| task.done)
+            .filter_map(|task| Some((task, task.completed_at?)))
+            .filter(|(_, at)| at.date_naive() >= first && *at <= now)
+            .collect::<Vec<_>>();
+
+        let days = first
+            .iter_days()
+            .take_while(|date| *date <= last)
+            .map(|date| DayActivity {
+                date,
+                completed: completed
+                    .iter()
+                    .filter(|(_, at)| at.date_naive() == date)
+                    .count(),
+            })
+            .collect();
+
+        let mut by_tag = BTreeMap::<&str, Vec<(&Task, DateTime<Local>)>>::new();
+        for (task, at) in &completed {
+            for tag in &task.tags {
+                by_tag.entry(tag).or_default().push((task, *at));
+            }
+        }
+        let mut tags = by_tag
+            .into_iter()
+            .map(|(tag, completed)| TagActivity {
+                tag: tag.to_string(),
+                completed: completed.len(),
+                average_turnaround: average_turnaround(&completed),
+            })
+            .collect::<Vec<_>>();
+        tags.sort_by_key(|activity| Reverse(activity.completed));
+
+        Productivity {
+            since: first,
+            until: last,
+            completed: completed.len(),
+            days,
+            average_turnaround: average_turnaround(&completed),
+            tags,
+        }
+    }
+}
+
+/// The average time from creation to completion of `completed`, tasks
+/// with when they were completed, `None` if none records its creation.
+fn average_turnaround(completed: &[(&Task, DateTime<Local>)]) -> Option<TimeDelta> {
+    let turnarounds = completed
+        .iter()
+        .filter_map(|(task, at)| task.created_at.map(|created| *at - created))
+        .collect::<Vec<_>>();
+    (!turnarounds.is_empty())
+        .then(|| turnarounds.iter().sum::<TimeDelta>() / turnarounds.len() as i32)
+}
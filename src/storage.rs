@@ -0,0 +1,1957 @@
+//! Persistence of the task store.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local, NaiveDate, TimeDelta};
+use flate2::write::{ZlibDecoder, ZlibEncoder};
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::backup::backup_data;
+use crate::config::{Config, app_data_dir};
+use crate::crdt::{self, Clock};
+use crate::error::{LoadError, Result, TodoError};
+use crate::hooks::{self, Snapshot};
+use crate::ics::uid;
+use crate::merge::{Conflict, merge};
+use crate::task::{Event, HistoryEntry, Id, Interval, Task};
+
+/// How long deleted tasks are kept in the trash when the config doesn't
+/// say otherwise.
+pub const DEFAULT_TRASH_RETENTION: TimeDelta = TimeDelta::days(30);
+
+/// The version of the on-disk schema written by this build.
+/// Bump it together with a new entry in `MIGRATIONS`.
+const SCHEMA_VERSION: u32 = 5;
+
+/// Marks a versioned binary storage file.
+/// Files without it predate versioning and are read as schema 0.
+/// It is followed by the schema version, since schema 5 by the
+/// `Compression` of the file, since schema 4 by the index of the tasks,
+/// see `Storage::load_partial`, and since schema 2 by the compressed
+/// payload. The index and the payload are each preceded by their length
+/// and CRC32 checksum.
+const STORAGE_MAGIC: &[u8; 4] = b"TODO";
+
+/// Marks a mapped storage file, see `StorageFormat::Mapped`.
+/// It is followed by the schema version and by sections like those of
+/// binary stores, uncompressed: the index, the trash and sync state,
+/// where each task is in the records, and the records of the tasks.
+const MAPPED_MAGIC: &[u8; 4] = b"TODM";
+
+/// The name of a project's own store, a file or a directory holding
+/// `PROJECT_STORE_FILE`, see `find_project_store`.
+const PROJECT_STORE: &str = ".todo";
+
+/// The store in a project's `.todo` directory.
+const PROJECT_STORE_FILE: &str = "tasks.bin";
+
+/// Whether the stores of projects are passed over, see
+/// `set_global_store`.
+static GLOBAL_STORE: AtomicBool = AtomicBool::new(false);
+
+/// The length of the length and checksum fields that precede the index
+/// and the payload of binary stores.
+const SECTION_HEADER_LEN: usize = 12;
+
+/// Migrations between schema versions, `MIGRATIONS[n]` upgrades a
+/// document from version `n` to `n + 1`.
+/// Fields added to `Task` default when missing, so a migration is
+/// only needed when existing data has to be reshaped.
+const MIGRATIONS: &[fn(&mut Value)] = &[
+    migrate_v0_to_v1,
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+    migrate_v4_to_v5,
+];
+
+/// The on-disk encodings of the store.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageFormat {
+    /// Compressed, with a versioned and checksummed header
+    #[default]
+    Binary,
+    /// Pretty printed JSON, suitable for versioning and hand-editing
+    Json,
+    /// TOML, suitable for versioning and hand-editing
+    Toml,
+    /// Uncompressed and read in place, each task on its own, so
+    /// commands reading a few tasks of a large store don't decode the
+    /// others
+    Mapped,
+}
+
+/// How binary stores are compressed, recorded in their header.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Quick to save, as stores were before the choice was given
+    #[default]
+    Zlib,
+    /// Smaller and quicker to load, better for large stores
+    Zstd,
+    /// Not compressed, quickest for small stores
+    None,
+}
+
+impl Compression {
+    /// The byte recording the compression in the header.
+    fn code(self) -> u8 {
+        match self {
+            Compression::Zlib => 0,
+            Compression::Zstd => 1,
+            Compression::None => 2,
+        }
+    }
+
+    /// The compression recorded by a byte of the header.
+    fn from_code(code: u8) -> Result<Self, LoadError> {
+        match code {
+            0 => Ok(Compression::Zlib),
+            1 => Ok(Compression::Zstd),
+            2 => Ok(Compression::None),
+            _ => Err(LoadError::Format(format!("unknown compression {code}"))),
+        }
+    }
+}
+
+/// Where `Storage::move_task` puts a task in the list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placement {
+    /// Right before another task
+    Before(Id),
+    /// First
+    Top,
+    /// Last
+    Bottom,
+}
+
+/// The task store.
+#[derive(Clone, Debug)]
+pub struct Storage {
+    /// The tasks by their id, which runs from 1 to the number of tasks
+    tasks: BTreeMap<Id, Task>,
+    /// Deleted tasks, oldest deletion first. They are dropped for good
+    /// once they are older than `trash_retention` when the store is saved.
+    trash: Vec<Task>,
+    /// What was last synced with CalDAV, by the `ics::uid` of the task
+    caldav: BTreeMap<String, SyncRecord>,
+    /// What was last synced with Todoist
+    todoist: TodoistState,
+    /// How long deleted tasks are kept
+    trash_retention: TimeDelta,
+    /// How the store is compressed when saved as binary, as it was
+    /// loaded unless set otherwise
+    compression: Compression,
+    /// The level of `compression`, its default if `None`
+    compression_level: Option<i32>,
+    /// The fields of the tasks are stamped when saved and merged, see
+    /// `crdt`
+    crdt: bool,
+    /// The In-Memory storage has unsynched changes to the disk
+    is_dirty: bool,
+    /// The storage file could not be read and was replaced by an
+    /// empty store
+    recovered: bool,
+    /// The store was loaded from its index, without all of the tasks,
+    /// so it can't be saved
+    partial: bool,
+}
+
+/// The state of a task on a sync server when it was last synced.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SyncRecord {
+    /// Where the task is stored on the server
+    pub href: String,
+    /// The version of the task on the server, empty if unknown
+    pub etag: String,
+    /// When the task was synced
+    pub synced_at: DateTime<Local>,
+    /// The fields of the task the server holds, as synced, to merge the
+    /// changes made on both sides since. `None` for records kept before
+    /// they were
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<Task>,
+}
+
+/// What `Storage::reconcile` took from the other copy of the store.
+#[derive(Debug, Default, PartialEq)]
+pub struct Reconciled {
+    /// Tasks only in the other copy
+    pub added: usize,
+    /// Tasks changed later in the other copy
+    pub updated: usize,
+    /// Tasks deleted in the other copy
+    pub deleted: usize,
+    /// Tasks whose same fields were changed in both copies
+    pub conflicts: Vec<Conflict>,
+}
+
+/// What was last synced with Todoist.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct TodoistState {
+    /// Where the next sync picks up the changes on the server, empty
+    /// for a full sync
+    pub sync_token: String,
+    /// The names of the projects, by their Todoist id
+    pub projects: BTreeMap<String, String>,
+    /// The synced tasks, by the `ics::uid` of the task
+    pub items: BTreeMap<String, TodoistRecord>,
+}
+
+impl TodoistState {
+    /// Whether nothing was ever synced.
+    pub fn is_empty(&self) -> bool {
+        self.sync_token.is_empty() && self.items.is_empty()
+    }
+}
+
+/// The state of a task on Todoist when it was last synced.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct TodoistRecord {
+    /// The Todoist id of the task
+    pub id: String,
+    /// Whether the task was done
+    pub done: bool,
+    /// When the task was synced
+    pub synced_at: DateTime<Local>,
+}
+
+/// What the storage file looked like when it was read, see
+/// `fingerprint`. The contents are compared along with the time it was
+/// written, which misses writes made within the resolution of the file
+/// system's clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    modified: Option<SystemTime>,
+    len: u64,
+    checksum: u32,
+}
+
+/// The representation of the store on disk: its tasks in id order,
+/// without the free slots of the in-memory array.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct PlainStorage {
+    /// The schema version the file was written with
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    tasks: Vec<Task>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    trash: Vec<Task>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    caldav: BTreeMap<String, SyncRecord>,
+    #[serde(default, skip_serializing_if = "TodoistState::is_empty")]
+    todoist: TodoistState,
+}
+
+/// The layout of a task before the schema was versioned (todo 0.3.0).
+#[derive(Debug, Deserialize, Serialize)]
+struct LegacyTask {
+    id: Id,
+    head: String,
+    body: String,
+}
+
+/// The layout of the store before the schema was versioned (todo 0.3.0).
+#[derive(Debug, Deserialize, Serialize)]
+struct LegacyStorage {
+    store: Vec<LegacyTask>,
+    id_to_slot: BTreeMap<Id, usize>,
+    is_dirty: bool,
+}
+
+/// Where a task is in the records of a mapped store.
+#[derive(Debug, Deserialize, Serialize)]
+struct Record {
+    /// The offset of the task from the start of the records
+    start: usize,
+    len: usize,
+    checksum: u32,
+}
+
+/// A decoded storage file that has not been migrated yet.
+#[derive(Clone, Debug)]
+pub struct Document {
+    /// The format the file was written in
+    pub format: StorageFormat,
+    /// How the file was compressed, if binary
+    pub compression: Compression,
+    /// The schema version the file was written with
+    pub version: u32,
+    /// The contents of the file
+    pub value: Value,
+}
+
+impl Document {
+    /// Migrates the document and turns it into a `Storage`.
+    pub fn into_storage(mut self) -> Result<Storage, LoadError> {
+        migrate(&mut self);
+        into_storage(self)
+    }
+
+    /// The tasks of the document as written, once migrated.
+    pub fn tasks(&self) -> Result<Vec<Task>, LoadError> {
+        let mut doc = self.clone();
+        migrate(&mut doc);
+        serde_json::from_value::<PlainStorage>(doc.value)
+            .map(|plain| plain.tasks)
+            .map_err(|err| LoadError::Format(err.to_string()))
+    }
+}
+
+impl Storage {
+    /// Loads the store from a file, see `load_from_storage`.
+    pub fn load(storage_path: &Path) -> Result<Self> {
+        load_from_storage(storage_path)
+    }
+
+    /// Loads what listing the tasks takes from the index at the front of
+    /// binary and mapped stores, skipping the rest of the file: the tasks
+    /// with only the first line of their body and without their notes
+    /// and history. The trash and sync state are left out. The tasks
+    /// `full` are loaded in full, which takes all of a binary store but
+    /// only their own records of a mapped one. Other stores are loaded in
+    /// full. The store can't be saved.
+    ///
+    /// The storage file must be locked, see `lock_storage`.
+    pub fn load_partial(storage_path: &Path, full: &[Id]) -> Result<Self> {
+        let partial = map_storage(storage_path).and_then(|map| match map {
+            Some(map) => decode_partial(&map, full),
+            None => Ok(None),
+        });
+        match partial {
+            Ok(Some(data)) => Ok(Storage {
+                partial: true,
+                ..data
+            }),
+            Ok(None) => load_from_storage(storage_path),
+            Err(err) => {
+                log::debug!("cannot read the index of the store: {err}");
+                load_from_storage(storage_path)
+            }
+        }
+    }
+
+    /// Saves the store to a file, see `save_to_storage`.
+    /// The store has no unsaved changes afterwards.
+    pub fn save(&mut self, storage_path: &Path, format: StorageFormat) -> Result<()> {
+        if self.partial {
+            return Err(TodoError::Serialize(
+                "the store was loaded from its index and cannot be saved".to_string(),
+            ));
+        }
+        if self.crdt {
+            self.stamp_clocks(Local::now());
+        }
+        save_to_storage(storage_path, self, format)?;
+        self.is_dirty = false;
+        Ok(())
+    }
+
+    /// Whether the storage file could not be read, so the store
+    /// started out empty. The unreadable file is kept as a backup.
+    pub fn was_recovered(&self) -> bool {
+        self.recovered
+    }
+
+    /// Whether the store has changes that haven't been saved.
+    pub fn is_dirty(&self) -> bool {
+        self.is_dirty
+    }
+
+    /// Returns the tasks in id order.
+    pub fn list(&self) -> impl Iterator<Item = &Task> {
+        self.tasks.values()
+    }
+
+    /// The number of tasks in the store.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Whether the store has no tasks.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Returns the tasks in list order, see `Task::position`.
+    pub fn ordered(&self) -> Vec<&Task> {
+        let mut tasks = self.list().collect::<Vec<&Task>>();
+        tasks.sort_by_key(|task| task.position);
+        tasks
+    }
+
+    /// Returns the task with the given id.
+    pub fn get(&self, id: Id) -> Option<&Task> {
+        self.tasks.get(&id)
+    }
+
+    /// Returns the task with the given id for modification.
+    /// The store, and the task, are considered modified afterwards.
+    pub fn get_mut(&mut self, id: Id) -> Option<&mut Task> {
+        let task = self.tasks.get_mut(&id)?;
+        self.is_dirty = true;
+        task.updated_at = Some(Local::now());
+        Some(task)
+    }
+
+    /// Adds a new task and returns its id.
+    /// The head and body are trimmed, a task with neither is not
+    /// added. The id of `task` is ignored, new tasks get the next id.
+    /// The task is recorded as created, and updated, now, and goes to
+    /// the bottom of the list.
+    pub fn add(&mut self, task: Task) -> Option<Id> {
+        let new_id = (self.tasks.len() + 1) as u64;
+        let head = task.head.trim().to_string();
+        let body = task.body.trim().to_string();
+        if head.is_empty() && body.is_empty() {
+            return None;
+        }
+
+        let now = Local::now();
+        let position = self
+            .list()
+            .map(|task| task.position)
+            .max()
+            .map_or(0, |max| max + 1);
+        let new_task = Task {
+            id: new_id,
+            position,
+            head,
+            body,
+            created_at: Some(now),
+            updated_at: Some(now),
+            ..task
+        };
+        self.tasks.insert(new_id, new_task);
+        self.is_dirty = true;
+        Some(new_id)
+    }
+
+    /// Moves a task to another place in the list.
+    /// Every task gets a distinct position afterwards.
+    pub fn move_task(&mut self, id: Id, to: Placement) -> Result<()> {
+        if self.get(id).is_none() {
+            return Err(TodoError::TaskNotFound(id));
+        }
+        if to == Placement::Before(id) {
+            return Err(TodoError::Usage(format!(
+                "task {id} cannot be moved before itself"
+            )));
+        }
+
+        let mut order = self
+            .ordered()
+            .iter()
+            .map(|task| task.id)
+            .filter(|other| *other != id)
+            .collect::<Vec<Id>>();
+        let at = match to {
+            Placement::Top => 0,
+            Placement::Bottom => order.len(),
+            Placement::Before(other) => order
+                .iter()
+                .position(|task| *task == other)
+                .ok_or(TodoError::TaskNotFound(other))?,
+        };
+        order.insert(at, id);
+
+        for (position, task) in order.into_iter().enumerate() {
+            if let Some(task) = self.tasks.get_mut(&task) {
+                task.position = position as u64 + 1;
+            }
+        }
+        if let Some(task) = self.tasks.get_mut(&id) {
+            task.updated_at = Some(Local::now());
+        }
+        self.is_dirty = true;
+        Ok(())
+    }
+
+    /// Deletes tasks by their ids and returns the ids that existed.
+    /// If a task is deleted, the remaining tasks are renumbered so
+    /// the ids have no gaps.
+    /// Subtasks of a deleted task move up to the deleted task's parent.
+    /// Deleted tasks are kept in the trash, see `trash`.
+    pub fn delete(&mut self, ids: &[Id]) -> Vec<Id> {
+        let deleted = ids
+            .iter()
+            .filter(|id| self.tasks.contains_key(id))
+            .cloned()
+            .collect::<Vec<Id>>();
+        if deleted.is_empty() {
+            return deleted;
+        }
+
+        let now = Local::now();
+        let mut parent_of_deleted = BTreeMap::new();
+        for id in &deleted {
+            if let Some(task) = self.tasks.remove(id) {
+                parent_of_deleted.insert(*id, task.parent);
+                self.trash.push(Task {
+                    deleted_at: Some(now),
+                    ..task
+                });
+            }
+        }
+        for task in self.tasks.values_mut() {
+            // Bounded, in case the deleted tasks' parents form a cycle
+            for _ in 0..parent_of_deleted.len() {
+                match task.parent.and_then(|p| parent_of_deleted.get(&p)) {
+                    Some(&grandparent) => task.parent = grandparent,
+                    None => break,
+                }
+            }
+        }
+        self.is_dirty = true;
+
+        // Fill in the gaps from deleted tasks.
+        self.renumber();
+        deleted
+    }
+
+    /// Mends what a damaged store can't hold together, see `todo
+    /// doctor`: tasks with neither head nor body are dropped, and parents
+    /// that form a cycle and tasks blocking themselves are cleared.
+    pub fn repair(&mut self) {
+        let before = self.tasks.len();
+        self.tasks
+            .retain(|_, task| !task.head.trim().is_empty() || !task.body.trim().is_empty());
+        if self.tasks.len() != before {
+            self.renumber();
+        }
+
+        let ids = self.tasks.keys().copied().collect::<Vec<Id>>();
+        for id in ids {
+            let mut parent = self.tasks[&id].parent;
+            for _ in 0..self.tasks.len() {
+                match parent {
+                    Some(p) if p == id => {
+                        if let Some(task) = self.tasks.get_mut(&id) {
+                            task.parent = None;
+                        }
+                        break;
+                    }
+                    Some(p) => parent = self.tasks.get(&p).and_then(|task| task.parent),
+                    None => break,
+                }
+            }
+        }
+        for task in self.tasks.values_mut() {
+            let id = task.id;
+            task.blocked_by.retain(|blocker| *blocker != id);
+        }
+        self.is_dirty = true;
+    }
+
+    /// Gives the tasks the ids from 1 up, in id order, and points the
+    /// references between them at the new ids.
+    fn renumber(&mut self) {
+        let old_tasks = std::mem::take(&mut self.tasks);
+        let mut new_ids = BTreeMap::new();
+        for (i, (old_id, mut task)) in old_tasks.into_iter().enumerate() {
+            let new_id = i as u64 + 1;
+            new_ids.insert(old_id, new_id);
+            task.id = new_id;
+            self.tasks.insert(new_id, task);
+        }
+        self.renumber_refs(&new_ids);
+    }
+
+    /// Returns task `keep` as it would be after merging task `duplicate`
+    /// into it, see `Task::merged_with`.
+    pub fn merged(&self, keep: Id, duplicate: Id) -> Result<Task> {
+        if keep == duplicate {
+            return Err(TodoError::Usage(format!(
+                "task {keep} cannot be merged with itself"
+            )));
+        }
+        let other = self
+            .get(duplicate)
+            .ok_or(TodoError::TaskNotFound(duplicate))?;
+        let mut merged = self
+            .get(keep)
+            .ok_or(TodoError::TaskNotFound(keep))?
+            .merged_with(other);
+        // Dependencies that would make the merged task wait for itself
+        merged.blocked_by.retain(|blocker| {
+            !self.waits_for(*blocker, keep) && !self.waits_for(*blocker, duplicate)
+        });
+        Ok(merged)
+    }
+
+    /// Merges task `duplicate` into task `keep` and deletes it, see
+    /// `merged`. Its subtasks and the tasks waiting for it move over to
+    /// `keep`. Returns the id `keep` has after the deletion.
+    pub fn merge(&mut self, keep: Id, duplicate: Id) -> Result<Id> {
+        let merged = self.merged(keep, duplicate)?;
+
+        for task in self.tasks.values_mut() {
+            if task.id == keep {
+                continue;
+            }
+            if task.parent == Some(duplicate) {
+                task.parent = Some(keep);
+            }
+            if task.blocked_by.contains(&duplicate) {
+                task.blocked_by.retain(|blocker| *blocker != duplicate);
+                if !task.blocked_by.contains(&keep) {
+                    task.blocked_by.push(keep);
+                    task.blocked_by.sort_unstable();
+                }
+            }
+        }
+        if let Some(task) = self.get_mut(keep) {
+            *task = Task {
+                updated_at: task.updated_at,
+                ..merged
+            };
+        }
+
+        self.delete(&[duplicate]);
+        Ok(if duplicate < keep { keep - 1 } else { keep })
+    }
+
+    /// Marks tasks as done, or as not done, and returns the ids that
+    /// existed. A timer running on a task that is done is stopped.
+    pub fn set_done(&mut self, ids: &[Id], done: bool) -> Vec<Id> {
+        ids.iter()
+            .filter_map(|&id| {
+                let task = self.get_mut(id)?;
+                if task.done != done {
+                    task.done = done;
+                    task.completed_at = done.then(Local::now);
+                }
+                if done {
+                    stop_intervals(task);
+                }
+                Some(id)
+            })
+            .collect()
+    }
+
+    /// Renames tag `old`, in any case, to `new` on every task carrying
+    /// it, and returns the ids of those tasks. A task carrying both keeps
+    /// `new` once.
+    pub fn rename_tag(&mut self, old: &str, new: &str) -> Vec<Id> {
+        let ids = self
+            .list()
+            .filter(|task| task.tags.iter().any(|tag| tag.eq_ignore_ascii_case(old)))
+            .map(|task| task.id)
+            .collect::<Vec<Id>>();
+        for &id in &ids {
+            let Some(task) = self.get_mut(id) else {
+                continue;
+            };
+            let mut tags = Vec::new();
+            for tag in std::mem::take(&mut task.tags) {
+                let tag = if tag.eq_ignore_ascii_case(old) {
+                    new.to_string()
+                } else {
+                    tag
+                };
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+            task.tags = tags;
+        }
+        ids
+    }
+
+    /// Starts a timer on a task.
+    /// Fails if a timer is already running, on any task.
+    pub fn start_timer(&mut self, id: Id) -> Result<()> {
+        if let Some(running) = self.running_timer() {
+            return Err(TodoError::TimerRunning(running));
+        }
+        let task = self.get_mut(id).ok_or(TodoError::TaskNotFound(id))?;
+        task.intervals.push(Interval {
+            start: Local::now(),
+            end: None,
+        });
+        Ok(())
+    }
+
+    /// Stops the running timer, returning the task it ran on and for how
+    /// long.
+    pub fn stop_timer(&mut self) -> Option<(Id, TimeDelta)> {
+        let id = self.running_timer()?;
+        let task = self.get_mut(id)?;
+        stop_intervals(task).map(|elapsed| (id, elapsed))
+    }
+
+    /// Returns the task a timer is running on.
+    pub fn running_timer(&self) -> Option<Id> {
+        self.list()
+            .find(|task| task.is_timing())
+            .map(|task| task.id)
+    }
+
+    /// Records that task `id` can't be done before task `on`.
+    /// Fails if either task doesn't exist, or if `on` already waits for
+    /// `id`, directly or through other tasks.
+    pub fn block(&mut self, id: Id, on: Id) -> Result<()> {
+        for task in [id, on] {
+            if self.get(task).is_none() {
+                return Err(TodoError::TaskNotFound(task));
+            }
+        }
+        if self.waits_for(on, id) {
+            return Err(TodoError::DependencyCycle { id, on });
+        }
+
+        if let Some(task) = self.get_mut(id)
+            && !task.blocked_by.contains(&on)
+        {
+            task.blocked_by.push(on);
+            task.blocked_by.sort_unstable();
+        }
+        Ok(())
+    }
+
+    /// Removes the dependency of task `id` on task `on`.
+    /// Returns whether there was one.
+    pub fn unblock(&mut self, id: Id, on: Id) -> Result<bool> {
+        let task = self.get_mut(id).ok_or(TodoError::TaskNotFound(id))?;
+        let before = task.blocked_by.len();
+        task.blocked_by.retain(|blocker| *blocker != on);
+        Ok(task.blocked_by.len() != before)
+    }
+
+    /// Returns the ids of the tasks blocking `id` that aren't done.
+    pub fn open_blockers(&self, id: Id) -> Vec<Id> {
+        self.get(id)
+            .map(|task| {
+                task.blocked_by
+                    .iter()
+                    .copied()
+                    .filter(|blocker| self.get(*blocker).is_some_and(|b| !b.done))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Whether task `id` has to wait for task `other`, directly or
+    /// through the tasks blocking it.
+    fn waits_for(&self, id: Id, other: Id) -> bool {
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![id];
+        while let Some(current) = pending.pop() {
+            if current == other {
+                return true;
+            }
+            if seen.insert(current)
+                && let Some(task) = self.get(current)
+            {
+                pending.extend(&task.blocked_by);
+            }
+        }
+        false
+    }
+
+    /// Returns the subtasks of a task, in id order.
+    pub fn children(&self, id: Id) -> impl Iterator<Item = &Task> {
+        self.list().filter(move |task| task.parent == Some(id))
+    }
+
+    /// Returns the deleted tasks, oldest deletion first.
+    /// They keep the id they had when they were deleted.
+    pub fn trash(&self) -> &[Task] {
+        &self.trash
+    }
+
+    /// Returns what was last synced with CalDAV, by the `ics::uid` of
+    /// each task.
+    pub fn caldav_records(&self) -> &BTreeMap<String, SyncRecord> {
+        &self.caldav
+    }
+
+    /// Returns what was last synced with CalDAV for modification.
+    /// The store is considered modified afterwards.
+    pub fn caldav_records_mut(&mut self) -> &mut BTreeMap<String, SyncRecord> {
+        self.is_dirty = true;
+        &mut self.caldav
+    }
+
+    /// Returns what was last synced with Todoist.
+    pub fn todoist_state(&self) -> &TodoistState {
+        &self.todoist
+    }
+
+    /// Returns what was last synced with Todoist for modification.
+    /// The store is considered modified afterwards.
+    pub fn todoist_state_mut(&mut self) -> &mut TodoistState {
+        self.is_dirty = true;
+        &mut self.todoist
+    }
+
+    /// Sets how long deleted tasks are kept in the trash.
+    pub fn set_trash_retention(&mut self, retention: TimeDelta) {
+        self.trash_retention = retention;
+    }
+
+    /// Sets whether the fields of the tasks are stamped when the store is
+    /// saved and merged, so that copies merge as CRDTs, see `crdt`.
+    pub fn set_crdt(&mut self, crdt: bool) {
+        self.crdt = crdt;
+    }
+
+    /// Stamps the fields of the tasks changed since they were last
+    /// stamped, see `crdt::stamp`.
+    fn stamp_clocks(&mut self, now: DateTime<Local>) {
+        let uids: BTreeMap<Id, String> = self.list().map(|task| (task.id, uid(task))).collect();
+        let uid_of = |id: Id| uids.get(&id).cloned().unwrap_or_default();
+        for task in self.tasks.values_mut() {
+            crdt::stamp(task, &uid_of, now);
+        }
+    }
+
+    /// Sets how the store is compressed when saved as binary: the
+    /// algorithm, the one it was loaded with being kept if `None`, and its
+    /// level, the default of the algorithm if `None`.
+    pub fn set_compression(&mut self, compression: Option<Compression>, level: Option<i32>) {
+        if let Some(compression) = compression {
+            self.compression = compression;
+        }
+        self.compression_level = level;
+    }
+
+    /// Adds the deleted task at `index` in the trash back to the store,
+    /// as a new task. Returns its new id.
+    pub fn restore_deleted(&mut self, index: usize) -> Option<Id> {
+        if index >= self.trash.len() {
+            return None;
+        }
+        let task = self.trash.remove(index);
+        let created_at = task.created_at;
+        self.is_dirty = true;
+        let id = self.add(Task {
+            parent: None,
+            blocked_by: Vec::new(),
+            deleted_at: None,
+            ..task
+        })?;
+        if let Some(task) = self.tasks.get_mut(&id) {
+            task.created_at = created_at;
+        }
+        Some(id)
+    }
+
+    /// Empties the trash, returning how many tasks were in it.
+    pub fn purge(&mut self) -> usize {
+        let purged = self.trash.len();
+        if purged > 0 {
+            self.trash.clear();
+            self.is_dirty = true;
+        }
+        purged
+    }
+
+    /// The store without the deleted tasks that have been in the trash
+    /// longer than the retention at `now`.
+    fn without_expired(&self, now: DateTime<Local>) -> Cow<'_, Storage> {
+        let expired = |task: &Task| {
+            task.deleted_at
+                .is_some_and(|deleted_at| now - deleted_at > self.trash_retention)
+        };
+        if !self.trash.iter().any(expired) {
+            return Cow::Borrowed(self);
+        }
+        let mut data = self.clone();
+        data.trash.retain(|task| !expired(task));
+        Cow::Owned(data)
+    }
+
+    /// Brings in the changes made to another copy of the store, e.g. on
+    /// another machine. Tasks are matched by their `ics::uid`.
+    ///
+    /// A task changed in both copies is merged field by field against its
+    /// copy in `base`, the store both copies started from, see `merge`,
+    /// or by the clocks of its fields if the store is kept as CRDTs, see
+    /// `crdt`.
+    /// Without a base, or if the task isn't in it, the fields that differ
+    /// are taken from the copy changed last. A task deleted in one copy is
+    /// deleted, unless it was changed in the other after it was deleted.
+    /// The sync records of this copy are kept.
+    pub fn reconcile(&mut self, base: Option<&Storage>, other: &Storage) -> Reconciled {
+        if self.crdt {
+            self.stamp_clocks(Local::now());
+        }
+        let mut reconciled = Reconciled::default();
+        let ours: BTreeMap<String, Id> = self.list().map(|task| (uid(task), task.id)).collect();
+        let theirs: BTreeMap<String, &Task> = other.list().map(|task| (uid(task), task)).collect();
+        let deleted_at = |trash: &[Task], uid_of: &str| {
+            trash
+                .iter()
+                .filter(|task| uid(task) == uid_of)
+                .filter_map(|task| task.deleted_at)
+                .max()
+        };
+        let changed_after =
+            |task: &Task, at: DateTime<Local>| task.updated_at.is_some_and(|updated| updated > at);
+
+        // Their tasks get ids past ours until the store is renumbered
+        let offset = self.list().map(|task| task.id).max().unwrap_or(0);
+        let their_id = |id: Id| -> Id {
+            other
+                .get(id)
+                .and_then(|task| ours.get(&uid(task)).copied())
+                .unwrap_or(offset + id)
+        };
+        let from_theirs = |task: &Task, id: Id| Task {
+            id,
+            parent: task.parent.map(their_id),
+            blocked_by: task.blocked_by.iter().map(|&id| their_id(id)).collect(),
+            uid: Some(uid(task)),
+            ..task.clone()
+        };
+        // The base points at tasks that may since be gone from both
+        let base_id = |base: &Storage, id: Id| -> Id {
+            base.get(id)
+                .map(uid)
+                .and_then(|key| {
+                    let their = theirs.get(&key).map(|task| offset + task.id);
+                    ours.get(&key).copied().or(their)
+                })
+                .unwrap_or(Id::MAX)
+        };
+        let bases: BTreeMap<String, Task> = base
+            .into_iter()
+            .flat_map(|base| {
+                base.list().map(move |task| {
+                    let task = Task {
+                        parent: task.parent.map(|id| base_id(base, id)),
+                        blocked_by: task
+                            .blocked_by
+                            .iter()
+                            .map(|&id| base_id(base, id))
+                            .collect(),
+                        ..task.clone()
+                    };
+                    (uid(&task), task)
+                })
+            })
+            .collect();
+
+        let mut tasks = Vec::new();
+        let mut trash = self.trash.clone();
+        for task in self.list() {
+            let key = uid(task);
+            match theirs.get(key.as_str()) {
+                Some(their) if self.crdt && !task.clock.is_empty() && !their.clock.is_empty() => {
+                    let merged = crdt::merge(task, &from_theirs(their, task.id));
+                    if merged != *task {
+                        reconciled.updated += 1;
+                    }
+                    tasks.push(merged);
+                }
+                Some(their) if their.updated_at == task.updated_at => tasks.push(task.clone()),
+                Some(their) => {
+                    let merged = merge(bases.get(&key), task, &from_theirs(their, task.id));
+                    if merged.task != *task {
+                        reconciled.updated += 1;
+                    }
+                    reconciled.conflicts.extend(merged.conflict);
+                    tasks.push(merged.task);
+                }
+                None => match deleted_at(&other.trash, &key) {
+                    Some(at) if !changed_after(task, at) => {
+                        trash.push(Task {
+                            deleted_at: Some(at),
+                            ..task.clone()
+                        });
+                        reconciled.deleted += 1;
+                    }
+                    _ => tasks.push(task.clone()),
+                },
+            }
+        }
+        for (key, their) in &theirs {
+            if ours.contains_key(key) {
+                continue;
+            }
+            let deleted_here =
+                deleted_at(&self.trash, key).is_some_and(|at| !changed_after(their, at));
+            if !deleted_here {
+                tasks.push(from_theirs(their, offset + their.id));
+                reconciled.added += 1;
+            }
+        }
+        for task in &other.trash {
+            let key = uid(task);
+            if !trash.iter().any(|kept| uid(kept) == key) {
+                trash.push(task.clone());
+            }
+        }
+        trash.sort_by_key(|task| task.deleted_at);
+
+        tasks.sort_by_key(|task| task.id);
+        *self = Storage {
+            caldav: std::mem::take(&mut self.caldav),
+            todoist: std::mem::take(&mut self.todoist),
+            trash_retention: self.trash_retention,
+            compression: self.compression,
+            compression_level: self.compression_level,
+            crdt: self.crdt,
+            is_dirty: true,
+            ..Storage::from_tasks(tasks, trash)
+        };
+        reconciled
+    }
+
+    /// Takes the tasks, trash and sync state of `other` in place of
+    /// those of the store, keeping its settings.
+    pub fn replace(&mut self, other: Storage) {
+        *self = Storage {
+            trash_retention: self.trash_retention,
+            compression: self.compression,
+            compression_level: self.compression_level,
+            crdt: self.crdt,
+            is_dirty: true,
+            ..other
+        };
+    }
+
+    /// Writes the store as the JSON format saves it, with only the tasks
+    /// unless `all` is set, see `decode_storage`. Deleted tasks that have
+    /// been in the trash longer than the retention are left out.
+    pub fn to_json(&self, all: bool) -> Result<String> {
+        let data = self.without_expired(Local::now());
+        let mut plain = PlainStorage {
+            version: SCHEMA_VERSION,
+            tasks: data.tasks(),
+            ..PlainStorage::default()
+        };
+        if all {
+            plain.trash = data.trash.clone();
+            plain.caldav = data.caldav.clone();
+            plain.todoist = data.todoist.clone();
+        }
+        let mut text = serde_json::to_string_pretty(&plain)
+            .map_err(|err| TodoError::Serialize(err.to_string()))?;
+        text.push('\n');
+        Ok(text)
+    }
+
+    /// Removes every task.
+    pub fn clear(&mut self) {
+        // The sync records stay, so the tasks are deleted remotely too
+        *self = Storage {
+            caldav: std::mem::take(&mut self.caldav),
+            todoist: std::mem::take(&mut self.todoist),
+            trash_retention: self.trash_retention,
+            compression: self.compression,
+            compression_level: self.compression_level,
+            crdt: self.crdt,
+            is_dirty: true,
+            ..Storage::default()
+        };
+    }
+
+    /// Moves the due date of open tasks due yesterday forward to `today`,
+    /// recording the rollover in each task's history.
+    /// Returns the ids of the rolled over tasks with their old due date.
+    pub fn rollover_overdue(&mut self, today: NaiveDate) -> Vec<(Id, NaiveDate)> {
+        let mut rolled = Vec::new();
+        let yesterday = today.pred_opt();
+        for task in self.tasks.values_mut() {
+            let Some(due) = task.due.filter(|due| Some(*due) == yesterday && !task.done) else {
+                continue;
+            };
+
+            task.due = Some(today);
+            task.rollovers += 1;
+            task.history.push(HistoryEntry {
+                at: Local::now(),
+                event: Event::Rollover {
+                    from: due,
+                    to: today,
+                },
+            });
+            rolled.push((task.id, due));
+        }
+        if !rolled.is_empty() {
+            self.is_dirty = true;
+        }
+        rolled
+    }
+
+    /// Builds a store from a list of tasks.
+    /// Tasks are re-numbered in order, so hand-edited files don't
+    /// need to keep the ids consistent.
+    fn from_tasks(tasks: Vec<Task>, trash: Vec<Task>) -> Self {
+        let mut data = Storage {
+            trash,
+            ..Storage::default()
+        };
+        let mut new_ids = BTreeMap::new();
+        for (i, mut task) in tasks.into_iter().enumerate() {
+            let new_id = i as u64 + 1;
+            new_ids.insert(task.id, new_id);
+            task.id = new_id;
+            data.tasks.insert(new_id, task);
+        }
+        data.renumber_refs(&new_ids);
+        data
+    }
+
+    /// Points the parent and blockers of every task at their new ids,
+    /// after the ids were renumbered. Tasks that no longer exist are
+    /// dropped.
+    fn renumber_refs(&mut self, new_ids: &BTreeMap<Id, Id>) {
+        for task in self.tasks.values_mut() {
+            task.parent = task.parent.and_then(|parent| new_ids.get(&parent).copied());
+            task.blocked_by = task
+                .blocked_by
+                .iter()
+                .filter_map(|blocker| new_ids.get(blocker).copied())
+                .collect();
+        }
+    }
+
+    /// Returns a copy of every task in id order.
+    fn tasks(&self) -> Vec<Task> {
+        self.list().cloned().collect()
+    }
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self {
+            tasks: BTreeMap::new(),
+            trash: Vec::new(),
+            caldav: BTreeMap::new(),
+            todoist: TodoistState::default(),
+            trash_retention: DEFAULT_TRASH_RETENTION,
+            compression: Compression::default(),
+            compression_level: None,
+            crdt: false,
+            is_dirty: false,
+            recovered: false,
+            partial: false,
+        }
+    }
+}
+
+/// Returns the path to the storage file: `TODO_STORAGE`, else the store
+/// of the project the current directory is in, see `find_project_store`,
+/// else `~/.tasks.bin`, or on Windows `%APPDATA%\todo\tasks.bin` unless
+/// there is a store in the home directory already.
+/// If the storage file does not exist, it creates it.
+pub fn get_storage() -> Result<PathBuf> {
+    // An explicit storage file takes precedence over the project's store,
+    // which takes precedence over the home directory.
+    let project = || {
+        let dir = std::env::current_dir().ok()?;
+        find_project_store(&dir).filter(|_| !GLOBAL_STORE.load(Ordering::Relaxed))
+    };
+    let explicit = std::env::var("TODO_STORAGE").ok().map(PathBuf::from);
+    let storage = match explicit.or_else(project) {
+        Some(path) => path,
+        None => {
+            let home = home::home_dir().unwrap_or(PathBuf::from("."));
+            // Supports development mode;
+            let t_path = match std::env::var("ENVIRONMENT") {
+                Ok(p) => match p.to_uppercase().as_str() {
+                    "DEVELOPMENT" => ".dev_tasks.bin",
+                    _ => ".tasks.bin",
+                },
+                _ => ".tasks.bin",
+            };
+            let in_home = home.join(t_path);
+            match app_data_dir() {
+                Some(dir) if !in_home.exists() => {
+                    fs::create_dir_all(&dir).map_err(|source| TodoError::CreateFile {
+                        path: dir.clone(),
+                        source,
+                    })?;
+                    dir.join(t_path.trim_start_matches('.'))
+                }
+                _ => in_home,
+            }
+        }
+    };
+
+    if !storage.exists() {
+        File::create(&storage).map_err(|source| TodoError::CreateFile {
+            path: storage.clone(),
+            source,
+        })?;
+    }
+    Ok(storage)
+}
+
+/// Finds the store of the project `dir` is in: a `.todo` file in `dir`
+/// or the nearest directory above it holding one, as git finds
+/// repositories. A `.todo` directory holds the store as `tasks.bin`.
+pub fn find_project_store(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().find_map(|dir| {
+        let path = dir.join(PROJECT_STORE);
+        if path.is_dir() {
+            Some(path.join(PROJECT_STORE_FILE))
+        } else {
+            path.is_file().then_some(path)
+        }
+    })
+}
+
+/// Uses the store in the home directory even inside a project with a
+/// store of its own, see `find_project_store`.
+pub fn set_global_store(enabled: bool) {
+    GLOBAL_STORE.store(enabled, Ordering::Relaxed);
+}
+
+/// An exclusive lock on the storage file, so that no process reads it
+/// while another writes it. Released when dropped.
+pub struct StorageLock {
+    _file: File,
+}
+
+/// Waits for an exclusive lock on the storage file.
+pub fn lock_storage(storage_path: &Path) -> Result<StorageLock> {
+    let file = open_for_lock(storage_path)?;
+    file.lock()?;
+    Ok(StorageLock { _file: file })
+}
+
+/// Opens the storage file to lock it, creating it if needed.
+fn open_for_lock(storage_path: &Path) -> Result<File> {
+    File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(storage_path)
+        .map_err(|source| TodoError::ReadFile {
+            path: storage_path.to_path_buf(),
+            source,
+        })
+}
+
+/// Loads the store while holding the lock on the storage file, lets
+/// `change` read or change it, and saves it as `config` says if it
+/// changed, running the hooks. For long-running processes serving many
+/// changes, e.g. `todo serve`.
+pub fn update_locked<T, E: From<TodoError>>(
+    storage_path: &Path,
+    config: &Config,
+    change: impl FnOnce(&mut Storage) -> std::result::Result<T, E>,
+) -> std::result::Result<T, E> {
+    let lock = lock_storage(storage_path)?;
+    let mut data = Storage::load(storage_path)?;
+    data.set_trash_retention(config.trash_retention);
+    data.set_compression(config.compression, config.compression_level);
+    data.set_crdt(config.crdt);
+    let snapshot = Snapshot::of(&data);
+    let result = change(&mut data)?;
+    if data.is_dirty() {
+        data.save(storage_path, config.format)?;
+        // Hooks may run `todo`, which waits for the lock
+        drop(lock);
+        hooks::run_after_save(&snapshot, &data, storage_path);
+    }
+    Ok(result)
+}
+
+/// Takes the fingerprint of the storage file, to tell whether another
+/// process saved it since it was loaded. `None` if it can't be read.
+pub fn fingerprint(storage_path: &Path) -> Option<Fingerprint> {
+    let data = fs::read(storage_path).ok()?;
+    let modified = fs::metadata(storage_path)
+        .and_then(|meta| meta.modified())
+        .ok();
+    Some(Fingerprint {
+        modified,
+        len: data.len() as u64,
+        checksum: crc32fast::hash(&data),
+    })
+}
+
+/// Loads tasks from the storage file.
+/// The format of the file is detected from its contents and older
+/// schema versions are migrated.
+/// If the storage file is empty or the storage file is corrupted,
+/// it returns `Storage::default()`.
+/// A file written by a newer version of the program is an error,
+/// rather than something to be overwritten.
+pub fn load_from_storage(storage_path: &Path) -> Result<Storage> {
+    let loaded = fs::read(storage_path)
+        .map_err(LoadError::from)
+        .and_then(|data| {
+            if data.is_empty() {
+                return Ok(Storage::default());
+            }
+            let doc = decode_document(&data)?;
+            log::trace!("decoded a {:?} store of schema {}", doc.format, doc.version);
+            doc.into_storage()
+        });
+
+    match loaded {
+        Ok(data) => Ok(data),
+        Err(source @ LoadError::NewerVersion { .. }) => Err(TodoError::Load {
+            path: storage_path.to_path_buf(),
+            source,
+        }),
+        Err(err) => {
+            log::error!("Failed to read {}: {err}", storage_path.display());
+            // save the old data to a backup file
+            match backup_data(storage_path) {
+                Ok(backup) => {
+                    eprintln!("Data saved to a backup file: {}", backup.display());
+                    eprintln!(
+                        "Run `todo doctor {}` to see what is wrong with it, \
+                         or `todo recover {0}` to get its tasks back",
+                        backup.display()
+                    );
+                }
+                Err(err) => log::error!("Failed to save data to a backup file: {err}"),
+            }
+            Ok(Storage {
+                recovered: true,
+                ..Storage::default()
+            })
+        }
+    }
+}
+
+/// Reads the storage file as written, without migrating it or starting
+/// from an empty store if it can't be read, see `todo doctor`.
+pub fn read_document(storage_path: &Path) -> Result<Document, LoadError> {
+    decode_document(&fs::read(storage_path)?)
+}
+
+/// Reads a store from the contents of a storage file, of any format and
+/// schema, e.g. written by `Storage::to_json`.
+pub fn decode_storage(data: &[u8]) -> Result<Storage, LoadError> {
+    decode_document(data)?.into_storage()
+}
+
+/// Reads the tasks in the index at the front of the storage file, see
+/// `Storage::load_partial`. `None` if the file has no index of the
+/// current schema.
+pub fn read_index(storage_path: &Path) -> Result<Option<Vec<Task>>, LoadError> {
+    let index = decode_partial(&fs::read(storage_path)?, &[])?;
+    Ok(index.map(|data| data.tasks.into_values().collect()))
+}
+
+/// Reads every task that can still be read from a damaged storage file,
+/// or a backup, see `todo recover`. A file that can be read gives its
+/// tasks as loading it would. Otherwise the file is searched for tasks,
+/// decompressed as far as it goes, and the index of binary and mapped
+/// stores gives the tasks missing from the rest, their bodies cut to
+/// the first line. Deleted tasks are left out.
+pub fn salvage(storage_path: &Path) -> Result<Vec<Task>, LoadError> {
+    let data = fs::read(storage_path)?;
+    if let Ok(tasks) = decode_document(&data).and_then(|doc| doc.tasks()) {
+        return Ok(tasks);
+    }
+
+    let mut tasks: Vec<Task> = Vec::new();
+    for task in salvage_tasks(&data) {
+        let readable = task.deleted_at.is_none()
+            && !(task.head.trim().is_empty() && task.body.trim().is_empty());
+        if readable && !tasks.iter().any(|kept| uid(kept) == uid(&task)) {
+            tasks.push(task);
+        }
+    }
+    tasks.sort_by_key(|task| task.id);
+    Ok(tasks)
+}
+
+/// The tasks found in a damaged storage file, the most complete copies
+/// first.
+fn salvage_tasks(data: &[u8]) -> Vec<Task> {
+    if let Some(rest) = data.strip_prefix(MAPPED_MAGIC) {
+        let (index, rest) = salvage_section(rest.get(4..).unwrap_or_default());
+        let (_, rest) = salvage_section(rest);
+        // The records follow the table of where they are
+        let (_, records) = salvage_section(rest);
+        return [records, index].into_iter().flat_map(scan_json).collect();
+    }
+
+    if let Some(rest) = data.strip_prefix(STORAGE_MAGIC) {
+        let Some((version, rest)) = rest.split_first_chunk::<4>() else {
+            return Vec::new();
+        };
+        let version = u32::from_le_bytes(*version);
+        let (compression, rest) = split_compression(version, rest)
+            .unwrap_or((Compression::Zlib, rest.get(1..).unwrap_or_default()));
+        let sections = match version {
+            0 | 1 => vec![rest],
+            2 | 3 => vec![salvage_section(rest).0],
+            _ => {
+                let (index, rest) = salvage_section(rest);
+                vec![salvage_section(rest).0, index]
+            }
+        };
+        return sections
+            .into_iter()
+            .flat_map(|section| scan_json(&decompress_partial(section, compression)))
+            .collect();
+    }
+
+    // Stores from before versioning are bincode, of which nothing can be
+    // read once damaged
+    let mut tasks = scan_json(data);
+    tasks.extend(scan_toml(&String::from_utf8_lossy(data)));
+    tasks
+}
+
+/// Takes a section of a damaged binary store as far as it goes, whatever
+/// its checksum, and returns it with the data after it.
+fn salvage_section(data: &[u8]) -> (&[u8], &[u8]) {
+    let Some((header, payload)) = data.split_first_chunk::<SECTION_HEADER_LEN>() else {
+        return (&[], &[]);
+    };
+    let len = u64::from_le_bytes(header[..8].try_into().expect("8 byte length"));
+    let len = usize::try_from(len)
+        .unwrap_or(usize::MAX)
+        .min(payload.len());
+    payload.split_at(len)
+}
+
+/// Decompresses `data` up to where it is damaged.
+fn decompress_partial(data: &[u8], compression: Compression) -> Vec<u8> {
+    let mut reader: Box<dyn Read> = match compression {
+        Compression::Zlib => Box::new(flate2::read::ZlibDecoder::new(data)),
+        Compression::Zstd => match zstd::stream::read::Decoder::new(data) {
+            Ok(decoder) => Box::new(decoder),
+            Err(_) => return Vec::new(),
+        },
+        Compression::None => return data.to_vec(),
+    };
+    let mut out = Vec::new();
+    let mut buf = [0; 8192];
+    while let Ok(len @ 1..) = reader.read(&mut buf) {
+        out.extend_from_slice(&buf[..len]);
+    }
+    out
+}
+
+/// Finds the tasks written as JSON objects in `data`, skipping what
+/// can't be read. Objects with a head are taken for tasks.
+fn scan_json(data: &[u8]) -> Vec<Task> {
+    let mut tasks = Vec::new();
+    let mut at = 0;
+    while let Some(start) = data[at..].iter().position(|byte| *byte == b'{') {
+        let start = at + start;
+        let mut values = serde_json::Deserializer::from_slice(&data[start..]).into_iter::<Value>();
+        match values.next() {
+            Some(Ok(value)) if value.get("head").is_some() => {
+                tasks.extend(serde_json::from_value::<Task>(value).ok());
+                at = start + values.byte_offset();
+            }
+            // Tasks may be further in, e.g. in a store cut short
+            _ => at = start + 1,
+        }
+    }
+    tasks
+}
+
+/// Finds the tasks written as TOML tables in `text`, each read on its
+/// own so that a damaged one doesn't take the others with it.
+fn scan_toml(text: &str) -> Vec<Task> {
+    let mut chunks: Vec<String> = Vec::new();
+    let mut in_task = false;
+    for line in text.lines() {
+        let header = line.trim();
+        if header == "[[tasks]]" {
+            chunks.push(String::new());
+            in_task = true;
+        } else if header.starts_with('[') && !header.starts_with("[[tasks.") {
+            in_task = header.starts_with("[tasks.");
+        }
+        if in_task && let Some(chunk) = chunks.last_mut() {
+            chunk.push_str(line);
+            chunk.push('\n');
+        }
+    }
+    chunks
+        .iter()
+        .filter_map(|chunk| toml::from_str::<PlainStorage>(chunk).ok())
+        .flat_map(|plain| plain.tasks)
+        .collect()
+}
+
+/// Decodes a storage file into a `Document` of whichever format and
+/// schema version it was written in.
+fn decode_document(data: &[u8]) -> Result<Document, LoadError> {
+    let format_err = |err: String| LoadError::Format(err);
+
+    if let Some(rest) = data.strip_prefix(MAPPED_MAGIC) {
+        let (version, rest) = split_version(rest)?;
+        let (_index, rest) = read_section(rest)?;
+        let (rest, records) = read_section(rest)?;
+        let mut value: Value =
+            serde_json::from_slice(rest).map_err(|err| format_err(err.to_string()))?;
+        let tasks = read_records(records)?
+            .map(|record| {
+                serde_json::from_slice::<Value>(record?).map_err(|err| format_err(err.to_string()))
+            })
+            .collect::<Result<Vec<Value>, LoadError>>()?;
+        if let Some(storage) = value.as_object_mut() {
+            storage.insert("tasks".to_string(), Value::Array(tasks));
+        }
+        return Ok(Document {
+            format: StorageFormat::Mapped,
+            compression: Compression::None,
+            version,
+            value,
+        });
+    }
+
+    if let Some(rest) = data.strip_prefix(STORAGE_MAGIC) {
+        let (version, rest) = split_version(rest)?;
+        let (compression, rest) = split_compression(version, rest)?;
+
+        let payload = match version {
+            0 | 1 => rest,
+            2 | 3 => read_section(rest)?.0,
+            _ => read_section(read_section(rest)?.1)?.0,
+        };
+        let payload =
+            decompress(payload, compression).map_err(|err| format_err(err.to_string()))?;
+        let value = serde_json::from_slice(&payload).map_err(|err| format_err(err.to_string()))?;
+        return Ok(Document {
+            format: StorageFormat::Binary,
+            compression,
+            version,
+            value,
+        });
+    }
+
+    match detect_format(data) {
+        StorageFormat::Binary => {
+            let payload =
+                decompress(data, Compression::Zlib).map_err(|err| format_err(err.to_string()))?;
+            let legacy: LegacyStorage =
+                bincode2::deserialize(&payload).map_err(|err| format_err(err.to_string()))?;
+            let value = serde_json::to_value(legacy).map_err(|err| format_err(err.to_string()))?;
+            Ok(Document {
+                format: StorageFormat::Binary,
+                compression: Compression::Zlib,
+                version: 0,
+                value,
+            })
+        }
+        format => {
+            let text = String::from_utf8_lossy(data);
+            let value: Value = match format {
+                StorageFormat::Json => serde_json::from_str(&text).map_err(|e| e.to_string()),
+                _ => toml::from_str::<toml::Value>(&text)
+                    .map_err(|e| e.to_string())
+                    .and_then(|v| serde_json::to_value(v).map_err(|e| e.to_string())),
+            }
+            .map_err(format_err)?;
+            // Hand-written files may leave out the version
+            let version = value
+                .get("version")
+                .and_then(Value::as_u64)
+                .map_or(SCHEMA_VERSION, |v| v as u32);
+            if version > SCHEMA_VERSION {
+                return Err(LoadError::NewerVersion {
+                    found: version,
+                    supported: SCHEMA_VERSION,
+                });
+            }
+            Ok(Document {
+                format,
+                compression: Compression::default(),
+                version,
+                value,
+            })
+        }
+    }
+}
+
+/// Reads the schema version at the start of a binary or mapped store,
+/// after the magic, and returns it with the data after it.
+fn split_version(data: &[u8]) -> Result<(u32, &[u8]), LoadError> {
+    let (version, rest) = data.split_first_chunk::<4>().ok_or(LoadError::Truncated {
+        expected: 4,
+        found: data.len(),
+    })?;
+    let version = u32::from_le_bytes(*version);
+    if version > SCHEMA_VERSION {
+        return Err(LoadError::NewerVersion {
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    Ok((version, rest))
+}
+
+/// Reads the compression that follows the schema version of a binary
+/// store since schema 5, and returns it with the data after it. Older
+/// stores are compressed with zlib.
+fn split_compression(version: u32, data: &[u8]) -> Result<(Compression, &[u8]), LoadError> {
+    if version < 5 {
+        return Ok((Compression::Zlib, data));
+    }
+    let (code, rest) = data.split_first().ok_or(LoadError::Truncated {
+        expected: 1,
+        found: 0,
+    })?;
+    Ok((Compression::from_code(*code)?, rest))
+}
+
+/// Reads the index at the front of a binary or mapped store written with
+/// the current schema, and the tasks `full` in full, see
+/// `Storage::load_partial`. `None` for other stores, and for binary
+/// stores if tasks are wanted in full.
+fn decode_partial(data: &[u8], full: &[Id]) -> Result<Option<Storage>, LoadError> {
+    let format_err = |err: String| LoadError::Format(err);
+    let (mapped, rest) = if let Some(rest) = data.strip_prefix(MAPPED_MAGIC) {
+        (true, rest)
+    } else if let Some(rest) = data.strip_prefix(STORAGE_MAGIC) {
+        (false, rest)
+    } else {
+        return Ok(None);
+    };
+    let Some((version, rest)) = rest.split_first_chunk::<4>() else {
+        return Ok(None);
+    };
+    // Older indexes would need migrating, those stores are loaded in full
+    if u32::from_le_bytes(*version) != SCHEMA_VERSION || (!mapped && !full.is_empty()) {
+        return Ok(None);
+    }
+
+    let (compression, rest) = if mapped {
+        (Compression::None, rest)
+    } else {
+        split_compression(SCHEMA_VERSION, rest)?
+    };
+
+    let (index, rest) = read_section(rest)?;
+    let index = decompress(index, compression).map_err(|err| format_err(err.to_string()))?;
+    let mut tasks = serde_json::from_slice::<PlainStorage>(&index)
+        .map_err(|err| format_err(err.to_string()))?
+        .tasks;
+    if !full.is_empty() {
+        let (_, records) = read_section(rest)?;
+        let mut records = read_records(records)?.collect::<Vec<_>>();
+        for id in full {
+            // The index lists the tasks in id order, from 1
+            let at = (*id as usize).saturating_sub(1);
+            if let (Some(task), Some(record)) = (tasks.get_mut(at), records.get_mut(at)) {
+                let record = std::mem::replace(record, Ok(&[]))?;
+                *task =
+                    serde_json::from_slice(record).map_err(|err| format_err(err.to_string()))?;
+            }
+        }
+    }
+    Ok(Some(Storage::from_tasks(tasks, Vec::new())))
+}
+
+/// Reads where each task is in the records of a mapped store, from the
+/// section before them, and returns the record of each task, checked
+/// against its checksum.
+fn read_records(data: &[u8]) -> Result<impl Iterator<Item = Result<&[u8], LoadError>>, LoadError> {
+    let (table, records) = read_section(data)?;
+    let table: Vec<Record> =
+        serde_json::from_slice(table).map_err(|err| LoadError::Format(err.to_string()))?;
+    Ok(table.into_iter().map(move |record| {
+        let task =
+            records
+                .get(record.start..record.start + record.len)
+                .ok_or(LoadError::Truncated {
+                    expected: record.start + record.len,
+                    found: records.len(),
+                })?;
+        let found = crc32fast::hash(task);
+        if found != record.checksum {
+            return Err(LoadError::Corrupted {
+                expected: record.checksum,
+                found,
+            });
+        }
+        Ok(task)
+    }))
+}
+
+/// Maps the storage file into memory, `None` if it is empty.
+fn map_storage(storage_path: &Path) -> Result<Option<Mmap>, LoadError> {
+    let file = File::open(storage_path)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    // SAFETY: the file is only written while it is locked, and the
+    // callers hold the lock while they read the map
+    let map = unsafe { Mmap::map(&file)? };
+    Ok(Some(map))
+}
+
+/// Checks the length and checksum that precede a section of a binary
+/// store, and returns the section and the data after it.
+fn read_section(data: &[u8]) -> Result<(&[u8], &[u8]), LoadError> {
+    let (header, payload) =
+        data.split_first_chunk::<SECTION_HEADER_LEN>()
+            .ok_or(LoadError::Truncated {
+                expected: SECTION_HEADER_LEN,
+                found: data.len(),
+            })?;
+    let (len, checksum) = header.split_at(8);
+    let len = u64::from_le_bytes(len.try_into().expect("8 byte length")) as usize;
+    let checksum = u32::from_le_bytes(checksum.try_into().expect("4 byte checksum"));
+
+    if payload.len() < len {
+        return Err(LoadError::Truncated {
+            expected: len,
+            found: payload.len(),
+        });
+    }
+    let (section, rest) = payload.split_at(len);
+    let found = crc32fast::hash(section);
+    if found != checksum {
+        return Err(LoadError::Corrupted {
+            expected: checksum,
+            found,
+        });
+    }
+    Ok((section, rest))
+}
+
+/// Runs every migration from the document's version up to
+/// `SCHEMA_VERSION`.
+fn migrate(doc: &mut Document) {
+    if doc.version < SCHEMA_VERSION {
+        log::info!(
+            "migrating the store from schema {} to {SCHEMA_VERSION}",
+            doc.version
+        );
+    }
+    for migration in &MIGRATIONS[doc.version as usize..] {
+        migration(&mut doc.value);
+    }
+    doc.version = SCHEMA_VERSION;
+}
+
+/// Schema 5 records the compression of binary stores in their header,
+/// the document itself is unchanged.
+fn migrate_v4_to_v5(_value: &mut Value) {}
+
+/// Schema 4 adds the index of the tasks in front of the payload of
+/// binary stores, the document itself is unchanged.
+fn migrate_v3_to_v4(_value: &mut Value) {}
+
+/// Schema 3 writes the binary store as its tasks in id order, like the
+/// plaintext formats, rather than the array of slots the tasks were kept
+/// in with the empty ones.
+fn migrate_v2_to_v3(value: &mut Value) {
+    let Some(storage) = value.as_object_mut() else {
+        return;
+    };
+    let (Some(Value::Array(mut store)), Some(Value::Object(id_to_slot))) =
+        (storage.remove("store"), storage.remove("id_to_slot"))
+    else {
+        return;
+    };
+    let mut slots = id_to_slot
+        .iter()
+        .filter_map(|(id, slot)| Some((id.parse::<Id>().ok()?, slot.as_u64()? as usize)))
+        .collect::<Vec<_>>();
+    slots.sort();
+    let tasks = slots
+        .into_iter()
+        .filter_map(|(_, slot)| store.get_mut(slot).map(Value::take))
+        // A slot pointed at twice was taken the first time
+        .filter(|task| !task.is_null())
+        .collect();
+    storage.insert("tasks".to_string(), Value::Array(tasks));
+}
+
+/// Schema 2 only adds a checksum to the binary header,
+/// the document itself is unchanged.
+fn migrate_v1_to_v2(_value: &mut Value) {}
+
+/// Schema 1 stops persisting the in-memory dirty flag.
+/// The task fields added in schema 1 default when missing.
+fn migrate_v0_to_v1(value: &mut Value) {
+    if let Some(storage) = value.as_object_mut() {
+        storage.remove("is_dirty");
+    }
+}
+
+/// Turns a migrated document into a `Storage`.
+fn into_storage(doc: Document) -> Result<Storage, LoadError> {
+    serde_json::from_value::<PlainStorage>(doc.value)
+        .map(|plain| Storage {
+            caldav: plain.caldav,
+            todoist: plain.todoist,
+            compression: doc.compression,
+            ..Storage::from_tasks(plain.tasks, plain.trash)
+        })
+        .map_err(|err| LoadError::Format(err.to_string()))
+}
+
+/// Guesses the format of the storage file from its contents.
+/// Compressed data is never valid UTF-8, JSON starts with an object.
+fn detect_format(data: &[u8]) -> StorageFormat {
+    match std::str::from_utf8(data) {
+        Ok(text) if text.trim_start().starts_with('{') => StorageFormat::Json,
+        Ok(_) => StorageFormat::Toml,
+        Err(_) => StorageFormat::Binary,
+    }
+}
+
+/// Decompress the data from storage before deserialization
+fn decompress(data: &[u8], compression: Compression) -> io::Result<Cow<'_, [u8]>> {
+    match compression {
+        Compression::Zlib => {
+            let mut decoder = ZlibDecoder::new(Vec::new());
+            decoder.write_all(data)?;
+            decoder.finish().map(Cow::Owned)
+        }
+        Compression::Zstd => zstd::decode_all(data).map(Cow::Owned),
+        Compression::None => Ok(Cow::Borrowed(data)),
+    }
+}
+
+/// Saves tasks to the storage file in the given format.
+/// Deleted tasks that have been in the trash longer than the retention
+/// are left out, and so are gone for good.
+pub fn save_to_storage(storage_path: &Path, data: &Storage, format: StorageFormat) -> Result<()> {
+    let invalid = |err: String| TodoError::Serialize(err);
+    let data = data.without_expired(Local::now());
+    let plain = PlainStorage {
+        version: SCHEMA_VERSION,
+        tasks: data.tasks(),
+        trash: data.trash.clone(),
+        caldav: data.caldav.clone(),
+        todoist: data.todoist.clone(),
+    };
+
+    let data = match format {
+        StorageFormat::Binary => {
+            let index = PlainStorage {
+                version: SCHEMA_VERSION,
+                tasks: plain.tasks.iter().map(index_entry).collect(),
+                ..PlainStorage::default()
+            };
+            let index = serde_json::to_vec(&index).map_err(|err| invalid(err.to_string()))?;
+            let encoded = serde_json::to_vec(&plain).map_err(|err| invalid(err.to_string()))?;
+            let compress =
+                |bytes: &[u8]| compress_data(bytes, data.compression, data.compression_level);
+            let mut bytes = STORAGE_MAGIC.to_vec();
+            bytes.extend_from_slice(&SCHEMA_VERSION.to_le_bytes());
+            bytes.push(data.compression.code());
+            write_section(&mut bytes, &compress(&index)?);
+            write_section(&mut bytes, &compress(&encoded)?);
+            bytes
+        }
+        StorageFormat::Json => {
+            let mut text =
+                serde_json::to_string_pretty(&plain).map_err(|err| invalid(err.to_string()))?;
+            text.push('\n');
+            text.into_bytes()
+        }
+        StorageFormat::Toml => toml::to_string_pretty(&plain)
+            .map_err(|err| invalid(err.to_string()))?
+            .into_bytes(),
+        StorageFormat::Mapped => {
+            let index = PlainStorage {
+                version: SCHEMA_VERSION,
+                tasks: plain.tasks.iter().map(index_entry).collect(),
+                ..PlainStorage::default()
+            };
+            let mut table = Vec::new();
+            let mut records = Vec::new();
+            for task in &plain.tasks {
+                let record = serde_json::to_vec(task).map_err(|err| invalid(err.to_string()))?;
+                table.push(Record {
+                    start: records.len(),
+                    len: record.len(),
+                    checksum: crc32fast::hash(&record),
+                });
+                records.extend(record);
+            }
+            let rest = PlainStorage {
+                tasks: Vec::new(),
+                ..plain
+            };
+
+            let index = serde_json::to_vec(&index).map_err(|err| invalid(err.to_string()))?;
+            let rest = serde_json::to_vec(&rest).map_err(|err| invalid(err.to_string()))?;
+            let table = serde_json::to_vec(&table).map_err(|err| invalid(err.to_string()))?;
+            let mut bytes = MAPPED_MAGIC.to_vec();
+            bytes.extend_from_slice(&SCHEMA_VERSION.to_le_bytes());
+            write_section(&mut bytes, &index);
+            write_section(&mut bytes, &rest);
+            write_section(&mut bytes, &table);
+            // Not a section, each record has its own checksum
+            bytes.extend(records);
+            bytes
+        }
+    };
+    fs::write(storage_path, data).map_err(|source| TodoError::WriteFile {
+        path: storage_path.to_path_buf(),
+        source,
+    })
+}
+
+/// The task as written in the index of binary stores, with what listing
+/// tasks doesn't show left out. The first line of the body is kept, to
+/// tell tasks with a body.
+fn index_entry(task: &Task) -> Task {
+    Task {
+        body: task.body.lines().next().unwrap_or_default().to_string(),
+        history: Vec::new(),
+        notes: Vec::new(),
+        clock: Clock::default(),
+        ..task.clone()
+    }
+}
+
+/// Appends a section of a binary store, preceded by its length and
+/// checksum.
+fn write_section(bytes: &mut Vec<u8>, section: &[u8]) {
+    bytes.extend_from_slice(&(section.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(section).to_le_bytes());
+    bytes.extend_from_slice(section);
+}
+
+/// Compress the data before saving to the storage file, at `level` if
+/// given. Levels out of the range of the algorithm are clamped to it.
+fn compress_data(data: &[u8], compression: Compression, level: Option<i32>) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::Zlib => {
+            let level = level.map_or(flate2::Compression::default(), |level| {
+                flate2::Compression::new(level.clamp(0, 9) as u32)
+            });
+            let mut encoder = ZlibEncoder::new(Vec::new(), level);
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        Compression::Zstd => {
+            let range = zstd::compression_level_range();
+            let level = level.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |level| {
+                level.clamp(*range.start(), *range.end())
+            });
+            zstd::encode_all(data, level)
+        }
+        Compression::None => Ok(data.to_vec()),
+    }
+}
+
+/// Ends the running intervals of a task now, returning how long the
+/// last of them ran.
+fn stop_intervals(task: &mut Task) -> Option<TimeDelta> {
+    let now = Local::now();
+    let mut elapsed = None;
+    for interval in task.intervals.iter_mut().filter(|i| i.end.is_none()) {
+        interval.end = Some(now);
+        elapsed = Some(now - interval.start);
+    }
+    elapsed
+}
@@ -0,0 +1,72 @@
+//! ANSI styling of the output.
+
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether output is styled, see `set_color`.
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// When to style the output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Style the output if stdout is a terminal and `NO_COLOR` is unset
+    #[default]
+    Auto,
+    /// Always style the output
+    Always,
+    /// Never style the output
+    Never,
+}
+
+/// Turns styling of the output on or off.
+pub fn set_color(choice: ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        // See https://no-color.org
+        ColorChoice::Auto => {
+            io::stdout().is_terminal()
+                && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        }
+    };
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+/// Wraps `text` in the SGR `codes` if styling is on.
+fn paint(text: &str, codes: &str) -> String {
+    if COLOR.load(Ordering::Relaxed) && !text.is_empty() {
+        format!("\x1b[{codes}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+/// Bold text, used for task heads.
+pub fn bold(text: &str) -> String {
+    paint(text, "1")
+}
+
+/// Italic text, used for emphasis in task bodies.
+pub fn italic(text: &str) -> String {
+    paint(text, "3")
+}
+
+/// Faint text, used for details of less interest.
+pub fn dim(text: &str) -> String {
+    paint(text, "2")
+}
+
+/// Red text, used for overdue tasks.
+pub fn red(text: &str) -> String {
+    paint(text, "31")
+}
+
+/// Yellow text, used for tasks due today.
+pub fn yellow(text: &str) -> String {
+    paint(text, "33")
+}
+
+/// Cyan text, used for tags.
+pub fn cyan(text: &str) -> String {
+    paint(text, "36")
+}
@@ -0,0 +1,301 @@
+//! The task model.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Local, NaiveDate, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::Clock;
+
+/// An alias for the task id's type
+pub type Id = u64;
+
+/// Joins task ids into a list for messages, e.g. `2, 3`.
+pub fn join_ids(ids: &[Id]) -> String {
+    ids.iter().map(Id::to_string).collect::<Vec<_>>().join(", ")
+}
+
+/// A task with an id, head, and body.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Task {
+    /// A unique identifier for the task
+    pub id: Id,
+    /// The head of the task.
+    pub head: String,
+    /// The body of the task.
+    pub body: String,
+    /// The day the task is due
+    pub due: Option<NaiveDate>,
+    /// Labels used to group and filter tasks
+    pub tags: Vec<String>,
+    /// Where or with what the task can be done, e.g. `home` or `phone`,
+    /// written `@home` in the head
+    pub contexts: Vec<String>,
+    /// The project the task is part of, written `+name` in the head
+    pub project: Option<String>,
+    /// The task this one is a step of
+    pub parent: Option<Id>,
+    /// How important the task is
+    pub priority: Option<Priority>,
+    /// Where the task comes in the list, lower first. Tasks in the same
+    /// position are listed in id order.
+    pub position: u64,
+    /// Whether the task has been completed
+    pub done: bool,
+    /// The id of the task in calendars, see `ics::uid`. Set once the task
+    /// has been synced, or if it came from a calendar
+    pub uid: Option<String>,
+    /// Where the task comes from, e.g. the URL of an issue
+    pub link: Option<String>,
+    /// URLs and paths of files attached to the task, see `attach`
+    pub attachments: Vec<String>,
+    /// When the task was added, unknown for tasks added by old versions
+    pub created_at: Option<DateTime<Local>>,
+    /// When the task was last modified
+    pub updated_at: Option<DateTime<Local>>,
+    /// When the task was completed
+    pub completed_at: Option<DateTime<Local>>,
+    /// When the task was deleted, for tasks kept in the trash
+    pub deleted_at: Option<DateTime<Local>>,
+    /// Tasks that have to be done before this one
+    pub blocked_by: Vec<Id>,
+    /// Moments to be reminded of the task at
+    pub reminders: Vec<DateTime<Local>>,
+    /// The task is left out of the list until then
+    pub snoozed_until: Option<DateTime<Local>>,
+    /// The periods worked on the task
+    pub intervals: Vec<Interval>,
+    /// How long the task is expected to take, stored in minutes
+    #[serde(with = "crate::date::option_minutes")]
+    pub estimate: Option<TimeDelta>,
+    /// Number of times the due date was rolled forward
+    pub rollovers: u32,
+    /// Notable events in the life of the task
+    pub history: Vec<HistoryEntry>,
+    /// Comments added over time, oldest first
+    pub notes: Vec<Note>,
+    /// Fields of the user's own, e.g. `client` set to `acme`, see
+    /// `todo set`
+    pub fields: BTreeMap<String, String>,
+    /// When the fields last changed, kept for stores merged as CRDTs
+    #[serde(skip_serializing_if = "Clock::is_empty")]
+    pub clock: Clock,
+}
+
+impl Task {
+    /// Whether the task is snoozed at `now`.
+    pub fn is_snoozed(&self, now: DateTime<Local>) -> bool {
+        self.snoozed_until.is_some_and(|until| until > now)
+    }
+
+    /// Whether a timer is running on the task.
+    pub fn is_timing(&self) -> bool {
+        self.intervals.iter().any(|interval| interval.end.is_none())
+    }
+
+    /// The estimated time still needed for the task: the estimate
+    /// less the time worked on it, none once it is done.
+    pub fn remaining_estimate(&self, now: DateTime<Local>) -> Option<TimeDelta> {
+        let estimate = self.estimate.filter(|_| !self.done)?;
+        let spent = self.time_spent(DateTime::<Local>::MIN_UTC.into(), now);
+        Some((estimate - spent).max(TimeDelta::zero()))
+    }
+
+    /// Combines the task with a duplicate of it: the bodies are joined,
+    /// the duplicate's head going into the body if it differs, and tags,
+    /// contexts, attachments, dependencies, reminders, time worked, history
+    /// and notes are combined.
+    /// The earliest due date and creation time are kept, and the task's
+    /// project unless it has none, and its fields over the duplicate's.
+    pub fn merged_with(&self, other: &Task) -> Task {
+        let mut merged = self.clone();
+
+        let mut extra = Vec::new();
+        if other.head != self.head {
+            extra.push(other.head.as_str());
+        }
+        if !other.body.is_empty() && other.body != self.body {
+            extra.push(other.body.as_str());
+        }
+        if !extra.is_empty() {
+            if !merged.body.is_empty() {
+                merged.body.push_str("\n\n");
+            }
+            merged.body.push_str(&extra.join("\n"));
+        }
+
+        for tag in &other.tags {
+            if !merged.tags.contains(tag) {
+                merged.tags.push(tag.clone());
+            }
+        }
+        for context in &other.contexts {
+            if !merged.contexts.contains(context) {
+                merged.contexts.push(context.clone());
+            }
+        }
+        merged.due = [self.due, other.due].into_iter().flatten().min();
+        merged.created_at = [self.created_at, other.created_at]
+            .into_iter()
+            .flatten()
+            .min();
+        if merged.parent == Some(other.id) {
+            merged.parent = other.parent;
+        }
+        merged.done = self.done && other.done;
+        if !merged.done {
+            merged.completed_at = None;
+        }
+        merged.estimate = self.estimate.or(other.estimate);
+        merged.link = self.link.clone().or_else(|| other.link.clone());
+        for attachment in &other.attachments {
+            if !merged.attachments.contains(attachment) {
+                merged.attachments.push(attachment.clone());
+            }
+        }
+        merged.project = self.project.clone().or_else(|| other.project.clone());
+        merged.priority = self.priority.max(other.priority);
+        merged.position = self.position.min(other.position);
+
+        for blocker in &other.blocked_by {
+            if *blocker != self.id && !merged.blocked_by.contains(blocker) {
+                merged.blocked_by.push(*blocker);
+            }
+        }
+        merged.blocked_by.retain(|blocker| *blocker != other.id);
+        merged.blocked_by.sort_unstable();
+        merged.reminders.extend(&other.reminders);
+        merged.reminders.sort();
+        merged.reminders.dedup();
+        merged.intervals.extend(other.intervals.iter().cloned());
+        merged.intervals.sort_by_key(|interval| interval.start);
+        merged.history.extend(other.history.iter().cloned());
+        merged.history.sort_by_key(|entry| entry.at);
+        for note in &other.notes {
+            if !merged.notes.contains(note) {
+                merged.notes.push(note.clone());
+            }
+        }
+        merged.notes.sort_by_key(|note| note.at);
+        for (key, value) in &other.fields {
+            merged.fields.entry(key.clone()).or_insert(value.clone());
+        }
+        merged.rollovers = self.rollovers.max(other.rollovers);
+        merged
+    }
+
+    /// The time worked on the task between `from` and `to`, counting a
+    /// running timer up to `to`.
+    pub fn time_spent(&self, from: DateTime<Local>, to: DateTime<Local>) -> TimeDelta {
+        self.intervals
+            .iter()
+            .map(|interval| {
+                let start = interval.start.max(from);
+                let end = interval.end.unwrap_or(to).min(to);
+                (end - start).max(TimeDelta::zero())
+            })
+            .sum()
+    }
+}
+
+/// The name of a context written `@name`, e.g. `home` for `@home`.
+/// Names start with a letter, then letters, digits, `-` and `_`, so that
+/// e.g. `@3pm` stays in the head.
+pub fn context_name(word: &str) -> Option<&str> {
+    marked_name(word, '@', &['-', '_'])
+}
+
+/// The name of a tag written `#name`, e.g. `bills` for `#bills`.
+/// Names are made like those of contexts, so that e.g. `#1` stays in
+/// the head.
+pub fn tag_name(word: &str) -> Option<&str> {
+    marked_name(word, '#', &['-', '_'])
+}
+
+/// The name of a project written `+name`, e.g. `house` for `+house`.
+/// Names are made like those of contexts, with `.` for subprojects as
+/// in `+house.garden`.
+pub fn project_name(word: &str) -> Option<&str> {
+    marked_name(word, '+', &['-', '_', '.'])
+}
+
+/// The key and value of a field written `key=value`, e.g. `client=acme`.
+/// Keys are made like the names of contexts, the value may be empty.
+pub fn field(word: &str) -> Option<(&str, &str)> {
+    let (key, value) = word.split_once('=')?;
+    let valid = key.starts_with(char::is_alphabetic)
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    valid.then_some((key, value))
+}
+
+/// The name following `mark` in `word`, if it is a valid name made of
+/// letters, digits and `extra`.
+fn marked_name<'a>(word: &'a str, mark: char, extra: &[char]) -> Option<&'a str> {
+    word.strip_prefix(mark).filter(|name| {
+        name.starts_with(char::is_alphabetic)
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || extra.contains(&c))
+    })
+}
+
+/// How important a task is.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    Medium,
+    High,
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Priority::Low => "low",
+            Priority::Medium => "medium",
+            Priority::High => "high",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A period of work on a task.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Interval {
+    /// When the timer was started
+    pub start: DateTime<Local>,
+    /// When the timer was stopped, `None` while it runs
+    pub end: Option<DateTime<Local>>,
+}
+
+/// A timestamped event in a task's history.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct HistoryEntry {
+    /// When the event happened
+    pub at: DateTime<Local>,
+    /// What happened
+    pub event: Event,
+}
+
+/// A timestamped comment on a task, see `todo note`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Note {
+    /// When the note was added
+    pub at: DateTime<Local>,
+    /// What it says
+    pub text: String,
+}
+
+/// The kinds of events recorded in a task's history.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Event {
+    /// The overdue due date was moved forward
+    Rollover { from: NaiveDate, to: NaiveDate },
+    /// The task was put out of sight
+    Snooze { until: DateTime<Local> },
+}
@@ -0,0 +1,127 @@
+//! Reading the tasks written by Taskwarrior's `task export`.
+
+use serde::Deserialize;
+
+use crate::error::{Result, TodoError};
+use crate::ics::parse_date_time;
+use crate::task::{Priority, Task};
+
+/// A task as exported by Taskwarrior. Attributes without a counterpart,
+/// e.g. `urgency` or `recur`, are ignored.
+#[derive(Deserialize)]
+struct Exported {
+    #[serde(default)]
+    uuid: Option<String>,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    entry: Option<String>,
+    #[serde(default)]
+    due: Option<String>,
+    #[serde(default)]
+    end: Option<String>,
+    #[serde(default)]
+    wait: Option<String>,
+    #[serde(default)]
+    project: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    priority: Option<String>,
+    #[serde(default)]
+    annotations: Vec<Annotation>,
+    #[serde(default)]
+    depends: Option<Depends>,
+}
+
+/// A note added to a Taskwarrior task.
+#[derive(Deserialize)]
+struct Annotation {
+    description: String,
+}
+
+/// The UUIDs of the tasks a task depends on: a list since Taskwarrior
+/// 2.6, a comma separated string before.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Depends {
+    List(Vec<String>),
+    Joined(String),
+}
+
+/// A task read from a Taskwarrior export.
+pub struct Imported {
+    /// The task, with the Taskwarrior UUID as its UID
+    pub task: Task,
+    /// The UUIDs of the tasks it depends on
+    pub depends: Vec<String>,
+}
+
+/// Reads the JSON array written by `task export`.
+///
+/// The project carries over, annotations become the body, and `wait`
+/// snoozes the task. Deleted tasks and the templates of recurring
+/// tasks are left out; their instances are imported like other tasks.
+pub fn parse_export(text: &str) -> Result<Vec<Imported>> {
+    let exported: Vec<Exported> =
+        serde_json::from_str(text).map_err(|err| TodoError::InvalidImport {
+            format: "taskwarrior".to_string(),
+            reason: err.to_string(),
+        })?;
+
+    let imported = exported
+        .into_iter()
+        .filter(|exported| !matches!(exported.status.as_str(), "deleted" | "recurring"))
+        .map(|exported| {
+            let body = exported
+                .annotations
+                .into_iter()
+                .map(|annotation| annotation.description)
+                .collect::<Vec<_>>()
+                .join("\n");
+            let done = exported.status == "completed";
+
+            let task = Task {
+                head: exported.description,
+                body,
+                due: exported
+                    .due
+                    .as_deref()
+                    .and_then(parse_date_time)
+                    .map(|due| due.date_naive()),
+                tags: exported.tags,
+                project: exported.project.filter(|project| !project.is_empty()),
+                priority: match exported.priority.as_deref() {
+                    Some("H") => Some(Priority::High),
+                    Some("M") => Some(Priority::Medium),
+                    Some("L") => Some(Priority::Low),
+                    _ => None,
+                },
+                done,
+                uid: exported.uuid,
+                created_at: exported.entry.as_deref().and_then(parse_date_time),
+                completed_at: exported
+                    .end
+                    .as_deref()
+                    .and_then(parse_date_time)
+                    .filter(|_| done),
+                snoozed_until: exported.wait.as_deref().and_then(parse_date_time),
+                ..Default::default()
+            };
+            let depends = match exported.depends {
+                Some(Depends::List(uuids)) => uuids,
+                Some(Depends::Joined(joined)) => joined
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|uuid| !uuid.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+                None => Vec::new(),
+            };
+            Imported { task, depends }
+        })
+        .collect();
+    Ok(imported)
+}
@@ -0,0 +1,95 @@
+//! Tasks kept as templates in `~/.config/todo/templates/`, see
+//! `todo template`.
+//!
+//! A template is a markdown file named after it, its first line being
+//! the head and the rest the body, as when composing a task in the
+//! editor. Placeholders are filled in when a task is made from it:
+//! `{{date}}` with today's date, `{{time}}` with the time and
+//! `{{weekday}}` with the name of the day.
+
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local};
+
+use crate::config::get_config_path;
+use crate::error::{Result, TodoError};
+
+/// The extension of template files, for editors to highlight them.
+const EXTENSION: &str = "md";
+
+/// Where the templates are.
+pub fn templates_dir() -> PathBuf {
+    get_config_path().with_file_name("templates")
+}
+
+/// The names of the saved templates, sorted.
+pub fn names() -> Result<Vec<String>> {
+    let dir = templates_dir();
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(source) => return Err(TodoError::ReadFile { path: dir, source }),
+    };
+    let mut names = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+        .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+        .collect::<Vec<_>>();
+    names.sort();
+    Ok(names)
+}
+
+/// Reads the template `name`, as saved.
+pub fn load(name: &str) -> Result<String> {
+    let path = path(name)?;
+    fs::read_to_string(&path).map_err(|source| match source.kind() {
+        std::io::ErrorKind::NotFound => TodoError::TemplateNotFound(name.to_string()),
+        _ => TodoError::ReadFile { path, source },
+    })
+}
+
+/// Saves `text` as the template `name`, replacing any template of that
+/// name, and returns where it went.
+pub fn save(name: &str, text: &str) -> Result<PathBuf> {
+    let path = path(name)?;
+    let dir = templates_dir();
+    fs::create_dir_all(&dir).map_err(|source| TodoError::CreateFile { path: dir, source })?;
+    fs::write(&path, format!("{}\n", text.trim_end())).map_err(|source| TodoError::WriteFile {
+        path: path.clone(),
+        source,
+    })?;
+    Ok(path)
+}
+
+/// Deletes the template `name`.
+pub fn remove(name: &str) -> Result<()> {
+    let path = path(name)?;
+    fs::remove_file(&path).map_err(|source| match source.kind() {
+        std::io::ErrorKind::NotFound => TodoError::TemplateNotFound(name.to_string()),
+        _ => TodoError::WriteFile { path, source },
+    })
+}
+
+/// Fills in the placeholders of a template as of `now`.
+pub fn fill(text: &str, now: DateTime<Local>) -> String {
+    text.replace("{{date}}", &now.format("%Y-%m-%d").to_string())
+        .replace("{{time}}", &now.format("%H:%M").to_string())
+        .replace("{{weekday}}", &now.format("%A").to_string())
+}
+
+/// The file of the template `name`. Names are made of letters, digits,
+/// `-` and `_`, so they can't point outside the directory.
+fn path(name: &str) -> Result<PathBuf> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(TodoError::Usage(format!(
+            "invalid template name `{name}`, use letters, digits, `-` and `_`"
+        )));
+    }
+    Ok(templates_dir().join(format!("{name}.{EXTENSION}")))
+}
@@ -0,0 +1,472 @@
+//! Two-way sync of the tasks with Todoist, through its sync API.
+//!
+//! Todoist only sends what changed since the last sync, so the sync
+//! token and the Todoist ids of the tasks are kept in the store, see
+//! `TodoistState`. Projects are tags: a task in a project is tagged
+//! with its name, and a task tagged with the name of a project is put
+//! in it. Tasks outside a project go to the Inbox.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local, NaiveDate};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::caldav::SyncReport;
+use crate::config::TodoistConfig;
+use crate::error::{Result, TodoError};
+use crate::ics;
+use crate::merge::{Conflict, merge};
+use crate::storage::{Storage, TodoistRecord};
+use crate::task::{Id, Priority, Task};
+
+/// Where the sync API is.
+const SYNC_URL: &str = "https://api.todoist.com/api/v1/sync";
+
+/// How long to wait for the server.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Tells apart the ids made within the same nanosecond.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The answer to a sync request.
+#[derive(Deserialize)]
+struct Response {
+    sync_token: String,
+    #[serde(default)]
+    items: Vec<Item>,
+    #[serde(default)]
+    projects: Vec<Project>,
+    #[serde(default)]
+    sync_status: BTreeMap<String, Value>,
+    #[serde(default)]
+    temp_id_mapping: BTreeMap<String, String>,
+}
+
+/// A Todoist task.
+#[derive(Deserialize)]
+struct Item {
+    id: String,
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    project_id: String,
+    #[serde(default)]
+    checked: bool,
+    #[serde(default)]
+    is_deleted: bool,
+    #[serde(default)]
+    due: Option<Due>,
+    /// From 1, normal, to 4, urgent
+    #[serde(default)]
+    priority: u8,
+    #[serde(default)]
+    labels: Vec<String>,
+    #[serde(default)]
+    updated_at: Option<DateTime<Local>>,
+    #[serde(default)]
+    completed_at: Option<DateTime<Local>>,
+}
+
+/// When a Todoist task is due.
+#[derive(Deserialize)]
+struct Due {
+    /// A date, or a date and time, e.g. `2025-03-01T10:00:00`
+    date: String,
+}
+
+/// A Todoist project.
+#[derive(Deserialize)]
+struct Project {
+    id: String,
+    name: String,
+    #[serde(default)]
+    is_deleted: bool,
+    #[serde(default)]
+    inbox_project: bool,
+}
+
+/// Syncs the tasks with the Todoist account in `config`.
+///
+/// Changes made on one side since the last sync are made on the other.
+/// A task changed on both sides keeps the copy changed last. A task
+/// deleted on one side is deleted on the other, unless it was changed
+/// there since. Done tasks are only sent once they were synced before.
+pub fn sync(data: &mut Storage, config: &TodoistConfig) -> Result<SyncReport> {
+    let client = Client::new(config);
+    let mut state = data.todoist_state().clone();
+    let changes = client.request(&state.sync_token, &[])?;
+
+    for project in &changes.projects {
+        if project.is_deleted {
+            state.projects.remove(&project.id);
+        } else if !project.inbox_project {
+            state
+                .projects
+                .insert(project.id.clone(), project.name.clone());
+        }
+    }
+
+    let mut report = SyncReport::default();
+    let mut commands = Vec::new();
+    let mut to_delete: Vec<Id> = Vec::new();
+    let mut to_add: Vec<&Item> = Vec::new();
+    let mut synced: BTreeMap<String, TodoistRecord> = BTreeMap::new();
+    // The temporary ids of the added items, by uid
+    let mut added: BTreeMap<String, String> = BTreeMap::new();
+
+    let uid_of: BTreeMap<&str, &str> = state
+        .items
+        .iter()
+        .map(|(uid, record)| (record.id.as_str(), uid.as_str()))
+        .collect();
+    let mut remote_changes: BTreeMap<&str, &Item> = BTreeMap::new();
+    for item in &changes.items {
+        match uid_of.get(item.id.as_str()) {
+            Some(uid) => {
+                remote_changes.insert(*uid, item);
+            }
+            // Done or deleted tasks never seen before don't matter
+            None if !item.checked && !item.is_deleted => to_add.push(item),
+            None => {}
+        }
+    }
+
+    let locals: Vec<(Id, String)> = data.list().map(|task| (task.id, ics::uid(task))).collect();
+    let mut seen = Vec::new();
+    for (id, uid) in locals {
+        let Some(task) = data.get(id) else {
+            continue;
+        };
+        let Some(record) = state.items.get(&uid) else {
+            if !task.done {
+                let temp_id = command_id();
+                commands.push(command(
+                    "item_add",
+                    Some(&temp_id),
+                    item_args(task, &state.projects, None),
+                ));
+                added.insert(uid, temp_id);
+                report.pushed += 1;
+            }
+            continue;
+        };
+        seen.push(uid.clone());
+        let changed_here = task.updated_at.is_none_or(|at| at > record.synced_at);
+
+        let keep_remote = match (remote_changes.get(uid.as_str()), changed_here) {
+            (None, false) => {
+                synced.insert(uid, record.clone());
+                continue;
+            }
+            (None, true) => false,
+            (Some(item), false) if item.is_deleted => {
+                to_delete.push(id);
+                report.deleted_local += 1;
+                continue;
+            }
+            (Some(_), false) => true,
+            (Some(item), true) if item.is_deleted => {
+                // Changed since it was deleted on Todoist
+                let temp_id = command_id();
+                commands.push(command(
+                    "item_add",
+                    Some(&temp_id),
+                    item_args(task, &state.projects, None),
+                ));
+                added.insert(uid, temp_id);
+                report.pushed += 1;
+                continue;
+            }
+            (Some(item), true) => {
+                let keep_remote = match (item.updated_at, task.updated_at) {
+                    (Some(there), Some(here)) => there > here,
+                    (there, _) => there.is_some(),
+                };
+                // Todoist keeps no copy as synced to merge against, so
+                // the fields that differ are those changed on both sides
+                let mut theirs = task.clone();
+                apply_item(item, &mut theirs, &state.projects);
+                let fields = merge(None, task, &theirs)
+                    .conflict
+                    .map(|conflict| conflict.fields)
+                    .unwrap_or_default();
+                report.conflicts.push(Conflict {
+                    head: task.head.clone(),
+                    fields,
+                    kept_theirs: keep_remote,
+                });
+                keep_remote
+            }
+        };
+
+        if keep_remote {
+            let item = remote_changes[uid.as_str()];
+            if let Some(task) = data.get_mut(id) {
+                apply_item(item, task, &state.projects);
+            }
+            synced.insert(
+                uid,
+                TodoistRecord {
+                    id: item.id.clone(),
+                    done: item.checked,
+                    synced_at: Local::now(),
+                },
+            );
+            report.pulled += 1;
+        } else {
+            let args = item_args(task, &state.projects, Some(&record.id));
+            commands.push(command("item_update", None, args));
+            if task.done != record.done {
+                let kind = if task.done {
+                    "item_close"
+                } else {
+                    "item_uncomplete"
+                };
+                commands.push(command(kind, None, json!({ "id": record.id })));
+            }
+            synced.insert(
+                uid,
+                TodoistRecord {
+                    id: record.id.clone(),
+                    done: task.done,
+                    synced_at: Local::now(),
+                },
+            );
+            report.pushed += 1;
+        }
+    }
+
+    // Tasks synced before that are gone here were deleted here
+    for (uid, record) in &state.items {
+        if seen.contains(uid) {
+            continue;
+        }
+        match remote_changes.get(uid.as_str()) {
+            Some(item) if item.is_deleted => {}
+            // Changed on Todoist since it was deleted here
+            Some(item) => to_add.push(item),
+            None => {
+                commands.push(command("item_delete", None, json!({ "id": record.id })));
+                report.deleted_remote += 1;
+            }
+        }
+    }
+
+    let response = if commands.is_empty() {
+        None
+    } else {
+        Some(client.request(&changes.sync_token, &commands)?)
+    };
+    if let Some(response) = &response {
+        for (id, status) in &response.sync_status {
+            if status != "ok" {
+                let error = status
+                    .get("error")
+                    .and_then(Value::as_str)
+                    .unwrap_or("unknown error");
+                return Err(TodoError::Sync(format!(
+                    "Todoist refused a change: {error}"
+                )));
+            }
+            log::debug!("command {id} done");
+        }
+    }
+
+    // Deleting renumbers the tasks, so it is done once everything else is
+    data.delete(&to_delete);
+    for item in to_add {
+        let mut task = Task::default();
+        apply_item(item, &mut task, &state.projects);
+        let uid = format!("{}@todoist", item.id);
+        task.uid = Some(uid.clone());
+        if data.add(task).is_some() {
+            synced.insert(
+                uid,
+                TodoistRecord {
+                    id: item.id.clone(),
+                    done: item.checked,
+                    synced_at: Local::now(),
+                },
+            );
+            report.pulled += 1;
+        }
+    }
+
+    let synced_at = Local::now();
+    for (uid, temp_id) in added {
+        let real_id = response
+            .as_ref()
+            .and_then(|response| response.temp_id_mapping.get(&temp_id))
+            .cloned()
+            .unwrap_or(temp_id);
+        synced.insert(
+            uid,
+            TodoistRecord {
+                id: real_id,
+                done: false,
+                synced_at,
+            },
+        );
+    }
+    for record in synced.values_mut() {
+        record.synced_at = synced_at;
+    }
+    let sync_token = response.map_or(changes.sync_token, |response| response.sync_token);
+
+    let state_mut = data.todoist_state_mut();
+    *state_mut = state;
+    state_mut.items = synced;
+    state_mut.sync_token = sync_token;
+    Ok(report)
+}
+
+/// The arguments of an `item_add`, or of an `item_update` of item `id`.
+fn item_args(task: &Task, projects: &BTreeMap<String, String>, id: Option<&str>) -> Value {
+    let project_id = projects
+        .iter()
+        .find(|(_, name)| task.tags.contains(name))
+        .map(|(id, _)| id.clone());
+    let labels: Vec<&String> = task
+        .tags
+        .iter()
+        .filter(|tag| !projects.values().any(|name| name == *tag))
+        .collect();
+    let mut args = json!({
+        "content": task.head,
+        "description": task.body,
+        "priority": match task.priority {
+            Some(Priority::High) => 4,
+            Some(Priority::Medium) => 3,
+            Some(Priority::Low) => 2,
+            None => 1,
+        },
+        "labels": labels,
+        "due": task.due.map(|due| json!({ "date": due.format("%Y-%m-%d").to_string() })),
+    });
+    match id {
+        Some(id) => args["id"] = json!(id),
+        // Moving between projects takes an `item_move`, so only new
+        // items are put in one
+        None => {
+            if let Some(project_id) = project_id {
+                args["project_id"] = json!(project_id);
+            }
+        }
+    }
+    args
+}
+
+/// Copies the fields of a Todoist task onto a task.
+fn apply_item(item: &Item, task: &mut Task, projects: &BTreeMap<String, String>) {
+    task.head = item.content.clone();
+    task.body = item.description.clone();
+    task.due = item
+        .due
+        .as_ref()
+        .and_then(|due| NaiveDate::parse_from_str(due.date.get(..10)?, "%Y-%m-%d").ok());
+    task.priority = match item.priority {
+        4 => Some(Priority::High),
+        3 => Some(Priority::Medium),
+        2 => Some(Priority::Low),
+        _ => None,
+    };
+    // The project replaces any other project tag
+    task.tags
+        .retain(|tag| !projects.values().any(|name| name == tag));
+    if let Some(project) = projects.get(&item.project_id) {
+        task.tags.push(project.clone());
+    }
+    for label in &item.labels {
+        if !task.tags.contains(label) {
+            task.tags.push(label.clone());
+        }
+    }
+    if task.done != item.checked {
+        task.done = item.checked;
+        task.completed_at = if item.checked {
+            item.completed_at.or(Some(Local::now()))
+        } else {
+            None
+        };
+    }
+}
+
+/// A command of a sync request.
+fn command(kind: &str, temp_id: Option<&str>, args: Value) -> Value {
+    let mut command = json!({
+        "type": kind,
+        "uuid": command_id(),
+        "args": args,
+    });
+    if let Some(temp_id) = temp_id {
+        command["temp_id"] = json!(temp_id);
+    }
+    command
+}
+
+/// A unique id for a command or a new item.
+fn command_id() -> String {
+    let index = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!("todo-{nanos:x}-{index}")
+}
+
+/// Talks to the Todoist sync API.
+struct Client {
+    agent: ureq::Agent,
+    /// The value of the `Authorization` header
+    auth: String,
+}
+
+impl Client {
+    fn new(config: &TodoistConfig) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build(),
+            auth: format!("Bearer {}", config.token.trim()),
+        }
+    }
+
+    /// Sends `commands` and reads the items and projects changed since
+    /// `sync_token`, everything if it is empty.
+    fn request(&self, sync_token: &str, commands: &[Value]) -> Result<Response> {
+        let sync_token = if sync_token.is_empty() {
+            "*"
+        } else {
+            sync_token
+        };
+        let commands = Value::from(commands).to_string();
+        let response = self
+            .agent
+            .post(SYNC_URL)
+            .set("Authorization", &self.auth)
+            .send_form(&[
+                ("sync_token", sync_token),
+                ("resource_types", r#"["items","projects"]"#),
+                ("commands", &commands),
+            ])
+            .map_err(|err| match err {
+                ureq::Error::Status(401 | 403, _) => {
+                    TodoError::Sync("Todoist refused the API token".to_string())
+                }
+                ureq::Error::Status(code, response) => {
+                    TodoError::Sync(format!("Todoist: {code} {}", response.status_text()))
+                }
+                ureq::Error::Transport(transport) => {
+                    TodoError::Sync(format!("Todoist: {transport}"))
+                }
+            })?;
+        let body = response
+            .into_string()
+            .map_err(|err| TodoError::Sync(format!("Todoist: {err}")))?;
+        serde_json::from_str(&body)
+            .map_err(|err| TodoError::Sync(format!("invalid response from Todoist: {err}")))
+    }
+}
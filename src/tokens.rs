@@ -0,0 +1,103 @@
+//! Reading the details of a task written in its head, for quick
+//! capture, e.g. `Pay rent due:friday +finance @home p:high #bills`.
+//!
+//! - `due:<date>` sets the due date, as `--due` does; `_` stands for a
+//!   space, as in `due:next_week`
+//! - `+<project>` sets the project, the last one given winning
+//! - `@<context>` adds a context
+//! - `p:<priority>` or `priority:<priority>` sets the priority: `low`,
+//!   `medium`, `high`, or their first letter
+//! - `#<tag>` adds a tag
+//!
+//! The words read are taken out of the head. A head made only of them
+//! is left as is.
+
+use chrono::NaiveDate;
+use clap::ValueEnum;
+
+use crate::date::parse_date;
+use crate::error::{Result, TodoError};
+use crate::task::{Priority, Task, context_name, project_name, tag_name};
+
+/// A detail written in the head.
+#[derive(Debug)]
+enum Token {
+    Due(NaiveDate),
+    Project(String),
+    Context(String),
+    Priority(Priority),
+    Tag(String),
+}
+
+/// Moves the details written in the head of `task` to their fields.
+/// Fails on a `due:` or `p:` word that can't be read.
+pub fn take_tokens(task: &mut Task) -> Result<()> {
+    let mut tokens = Vec::new();
+    let mut rest = Vec::new();
+    for word in task.head.split_whitespace() {
+        match token(word)? {
+            Some(token) => tokens.push(token),
+            None => rest.push(word),
+        }
+    }
+    if tokens.is_empty() || rest.is_empty() {
+        return Ok(());
+    }
+    task.head = rest.join(" ");
+
+    for token in tokens {
+        match token {
+            Token::Due(due) => task.due = Some(due),
+            Token::Project(project) => task.project = Some(project),
+            Token::Priority(priority) => task.priority = Some(priority),
+            Token::Context(context) => {
+                if !task.contexts.contains(&context) {
+                    task.contexts.push(context);
+                }
+            }
+            Token::Tag(tag) => {
+                if !task.tags.contains(&tag) {
+                    task.tags.push(tag);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads a word of the head, `None` if it is plain text.
+fn token(word: &str) -> Result<Option<Token>> {
+    if let Some(context) = context_name(word) {
+        return Ok(Some(Token::Context(context.to_string())));
+    }
+    if let Some(project) = project_name(word) {
+        return Ok(Some(Token::Project(project.to_string())));
+    }
+    if let Some(tag) = tag_name(word) {
+        return Ok(Some(Token::Tag(tag.to_string())));
+    }
+    match word.split_once(':') {
+        Some(("due", date)) if !date.is_empty() => {
+            parse_date(&date.replace('_', " ")).map(|due| Some(Token::Due(due)))
+        }
+        Some(("p" | "priority", priority)) if !priority.is_empty() => {
+            parse_priority(priority).map(|priority| Some(Token::Priority(priority)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Reads a priority, written in full or by its first letter.
+pub fn parse_priority(s: &str) -> Result<Priority> {
+    let priority = match s.to_ascii_lowercase().as_str() {
+        "l" => Ok(Priority::Low),
+        "m" => Ok(Priority::Medium),
+        "h" => Ok(Priority::High),
+        _ => Priority::from_str(s, true),
+    };
+    priority.map_err(|_| {
+        TodoError::Usage(format!(
+            "unknown priority `{s}`, expected low, medium or high"
+        ))
+    })
+}
@@ -0,0 +1,93 @@
+//! Scoring open tasks by how pressing they are, see `todo next`.
+//!
+//! The urgency of a task adds up a term for each thing that makes it
+//! pressing: a near or past due date, a priority, its age, a running
+//! timer, other tasks waiting on it and being part of a project. Being
+//! blocked counts against it. The weights follow Taskwarrior's.
+
+use std::cmp::Ordering;
+
+use chrono::{DateTime, Local};
+
+use crate::storage::Storage;
+use crate::task::{Priority, Task};
+
+/// The weight of a due date, reached once it is a week overdue.
+const DUE: f64 = 12.0;
+/// Due dates further off than this many days count as little as they can.
+const DUE_HORIZON: f64 = 14.0;
+/// Tasks overdue by this many days count in full.
+const DUE_OVERDUE: f64 = 7.0;
+/// The share of `DUE` of a due date beyond the horizon.
+const DUE_FLOOR: f64 = 0.2;
+/// The weights of the priorities.
+const HIGH: f64 = 6.0;
+const MEDIUM: f64 = 3.9;
+const LOW: f64 = 1.8;
+/// The weight of age, reached once a task is `AGE_DAYS` old.
+const AGE: f64 = 2.0;
+const AGE_DAYS: f64 = 365.0;
+/// The weight of a running timer.
+const ACTIVE: f64 = 4.0;
+/// The weight of open tasks waiting on this one.
+const BLOCKING: f64 = 8.0;
+/// The weight of having open blockers, which is negative.
+const BLOCKED: f64 = -5.0;
+/// The weight of being part of a project.
+const PROJECT: f64 = 1.0;
+
+/// How pressing `task` is at `now`, higher first. Done tasks score 0.
+pub fn urgency(task: &Task, data: &Storage, now: DateTime<Local>) -> f64 {
+    if task.done {
+        return 0.0;
+    }
+    let mut score = 0.0;
+
+    if let Some(due) = task.due {
+        let overdue = (now.date_naive() - due).num_days() as f64;
+        // From the floor two weeks ahead up to the full weight a week late
+        let share = ((overdue + DUE_HORIZON) / (DUE_HORIZON + DUE_OVERDUE))
+            .clamp(0.0, 1.0)
+            .max(DUE_FLOOR);
+        score += DUE * share;
+    }
+    score += match task.priority {
+        Some(Priority::High) => HIGH,
+        Some(Priority::Medium) => MEDIUM,
+        Some(Priority::Low) => LOW,
+        None => 0.0,
+    };
+    if let Some(created_at) = task.created_at {
+        let days = (now - created_at).num_days() as f64;
+        score += AGE * (days / AGE_DAYS).clamp(0.0, 1.0);
+    }
+    if task.is_timing() {
+        score += ACTIVE;
+    }
+    let blocking = data
+        .list()
+        .any(|other| !other.done && other.blocked_by.contains(&task.id));
+    if blocking {
+        score += BLOCKING;
+    }
+    if !data.open_blockers(task.id).is_empty() {
+        score += BLOCKED;
+    }
+    if task.project.is_some() {
+        score += PROJECT;
+    }
+    score
+}
+
+/// The open tasks of `data` that aren't snoozed, with their urgency,
+/// most pressing first. Ties keep the order of the list.
+pub fn rank(data: &Storage, now: DateTime<Local>) -> Vec<(&Task, f64)> {
+    let mut ranked = data
+        .ordered()
+        .into_iter()
+        .filter(|task| !task.done && !task.is_snoozed(now))
+        .map(|task| (task, urgency(task, data, now)))
+        .collect::<Vec<_>>();
+    ranked.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+    ranked
+}
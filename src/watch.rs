@@ -0,0 +1,78 @@
+//! Waiting for the storage file to change, see `todo watch`.
+//!
+//! Saving replaces the storage file, so the directory it is in is
+//! watched, and the events are narrowed down to those about the file.
+
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::error::{Result, TodoError};
+
+/// How long a burst of events from a single save may last.
+const SETTLE: Duration = Duration::from_millis(100);
+
+/// Watches the storage file for changes.
+pub struct StorageWatcher {
+    /// Stops watching when dropped
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    file_name: OsString,
+}
+
+impl StorageWatcher {
+    /// Starts watching the storage file at `storage_path`.
+    pub fn new(storage_path: &Path) -> Result<Self> {
+        let watch_err = |err: notify::Error| TodoError::Watch(err.to_string());
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(watch_err)?;
+        let dir = match storage_path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(watch_err)?;
+        log::debug!("watching {}", dir.display());
+        Ok(StorageWatcher {
+            _watcher: watcher,
+            events,
+            file_name: storage_path.file_name().unwrap_or_default().to_owned(),
+        })
+    }
+
+    /// Waits up to `timeout` for the storage file to be written, and
+    /// returns whether it was. Reading the file, as loading it does, is
+    /// no change.
+    pub fn wait(&self, timeout: Duration) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.events.recv_timeout(left) {
+                Ok(Ok(event)) if self.is_write(&event) => break,
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => return Err(TodoError::Watch(err.to_string())),
+                Err(RecvTimeoutError::Timeout) => return Ok(false),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(TodoError::Watch("the watcher stopped".to_string()));
+                }
+            }
+        }
+        // Let the rest of the save go by
+        while self.events.recv_timeout(SETTLE).is_ok() {}
+        Ok(true)
+    }
+
+    /// Whether `event` is the storage file being written, created or
+    /// replaced.
+    fn is_write(&self, event: &Event) -> bool {
+        let about_file = event
+            .paths
+            .iter()
+            .any(|path| path.file_name() == Some(self.file_name.as_os_str()));
+        about_file && (event.kind.is_create() || event.kind.is_modify())
+    }
+}